@@ -0,0 +1,168 @@
+// everything the pages need beyond htmx. there are no inline scripts, the
+// Content-Security-Policy only lets scripts load from files like this one (see security_headers.rs)
+document.addEventListener("DOMContentLoaded", () => {
+    // https://htmx.org/events/
+    // to see all the events as they happen
+    // htmx.logAll()
+    document.body.addEventListener("htmx:beforeSwap", (event) => {
+        if (event.detail.xhr.status === 422) {
+            event.detail.shouldSwap = true;
+            // suppresses error logging in the console
+            event.detail.isError = false;
+        }
+        // other errors come back as a toast for #error-toast (see error.rs)
+        if (event.detail.xhr.status >= 400 && event.detail.xhr.getResponseHeader('HX-Retarget') === '#error-toast') {
+            event.detail.shouldSwap = true;
+            event.detail.isError = false;
+        }
+    })
+    // sends back the token from the csrf cookie, or anything but a GET gets turned away (see csrf.rs)
+    document.body.addEventListener("htmx:configRequest", (event) => {
+        const token = document.cookie.split('; ').find((cookie) => cookie.startsWith('top_doggo_csrf='));
+        if (token) {
+            event.detail.headers['X-CSRF-Token'] = token.split('=')[1];
+        }
+    })
+    // the upload form (see upload_dog_form), when the photo is over the body limit
+    // htmx.on('#upload-form', 'htmx:xhr:progress', function(evt) {
+    //     htmx.find('#progress').setAttribute('value', evt.detail.loaded/evt.detail.total * 100)
+    // });
+    // set by the shortcuts below, read by the focus handling after them
+    let usedShortcut = false;
+    let focusAfterSwap = null;
+    // the game board's buttons say which keys press them in aria-keyshortcuts (see game_board)
+    const gameBoardKey = (event) => {
+        if (event.key === ' ') return 'Space';
+        return event.key.length === 1 ? event.key.toUpperCase() : event.key;
+    }
+    document.addEventListener('keydown', (event) => {
+        const board = document.getElementById('game-board');
+        if (!board || event.repeat || event.ctrlKey || event.metaKey || event.altKey) return;
+        if (event.target.closest('input, textarea, select, [contenteditable]')) return;
+        // space and enter already press whatever button or link has focus
+        if ((event.key === ' ' || event.key === 'Enter') && event.target.closest('button, a')) return;
+        const key = gameBoardKey(event);
+        const button = [...board.querySelectorAll('button[aria-keyshortcuts]')]
+            .find((button) => button.getAttribute('aria-keyshortcuts').split(' ').includes(key));
+        if (!button) return;
+        event.preventDefault();
+        // one pick at a time, the next pairing is on its way
+        if (board.querySelector('.htmx-request') || board.classList.contains('htmx-request')) return;
+        usedShortcut = true;
+        button.click();
+    })
+    // the boards get swapped out from under whatever had focus, so put it back on the same button
+    // (or on the heading, so screen readers start from the top of the new dogs)
+    const boards = ['game-board', 'ranking-board'];
+    let focusFallback = null;
+    document.body.addEventListener('htmx:beforeRequest', (event) => {
+        const board = event.detail.target?.id;
+        if (!boards.includes(board)) return;
+        const focused = document.activeElement;
+        focusFallback = `${board}-heading`;
+        focusAfterSwap = !usedShortcut && focused?.id && event.detail.target.contains(focused) ? focused.id : focusFallback;
+        usedShortcut = false;
+    })
+    document.body.addEventListener('htmx:afterSettle', () => {
+        if (!focusAfterSwap) return;
+        const element = document.getElementById(focusAfterSwap) ?? document.getElementById(focusFallback);
+        element?.focus({ preventScroll: true });
+        focusAfterSwap = null;
+    })
+    // ranking mode (see ranking_board): the list's order is the ranking, favorite first. tapping a
+    // dog moves it up behind the ones tapped before it, and dragging one moves it anywhere
+    const rankingChanged = (list) => {
+        const items = [...list.children];
+        items.forEach((item, i) => {
+            item.querySelector('.ranking-place').textContent = i + 1;
+        });
+        document.getElementById('ranking-order').value = items.map((item) => item.dataset.dogId).join(',');
+    }
+    document.body.addEventListener('click', (event) => {
+        const list = document.getElementById('ranking');
+        if (!list) return;
+        const dog = event.target.closest('.ranking-dog');
+        if (dog) {
+            const item = dog.closest('li');
+            if (item.dataset.placed) return;
+            const placed = list.querySelectorAll('li[data-placed]');
+            placed.length ? placed[placed.length - 1].after(item) : list.prepend(item);
+            item.dataset.placed = 'true';
+            // the last one left has nowhere else to go
+            const unplaced = list.querySelectorAll('li:not([data-placed])');
+            if (unplaced.length === 1) {
+                unplaced[0].dataset.placed = 'true';
+            }
+            rankingChanged(list);
+            dog.focus();
+        } else if (event.target.closest('#ranking-reset')) {
+            // back to the order the dogs came in
+            const order = document.getElementById('ranking-order').defaultValue.split(',');
+            const items = [...list.children];
+            order.forEach((dogId) => list.append(items.find((item) => item.dataset.dogId === dogId)));
+            items.forEach((item) => delete item.dataset.placed);
+            rankingChanged(list);
+        }
+    })
+    let dragged = null;
+    document.body.addEventListener('dragstart', (event) => {
+        dragged = event.target.closest?.('#ranking li') ?? null;
+        if (dragged) {
+            event.dataTransfer.effectAllowed = 'move';
+        }
+    })
+    document.body.addEventListener('dragover', (event) => {
+        const over = event.target.closest?.('#ranking li');
+        if (!dragged || !over) return;
+        event.preventDefault();
+        if (over === dragged) return;
+        const items = [...over.parentElement.children];
+        items.indexOf(dragged) < items.indexOf(over) ? over.after(dragged) : over.before(dragged);
+        dragged.dataset.placed = 'true';
+        rankingChanged(over.parentElement);
+    })
+    document.body.addEventListener('drop', (event) => {
+        if (dragged) event.preventDefault();
+    })
+    document.body.addEventListener('dragend', () => {
+        dragged = null;
+    })
+    // toasts and the undo button go away on their own after data-fade-after seconds
+    const fadeAfter = (element) => {
+        if (element.dataset.fading) return;
+        element.dataset.fading = 'true';
+        setTimeout(() => {
+            element.style.transition = 'opacity 0.5s';
+            element.style.opacity = '0';
+            setTimeout(() => element.remove(), 500);
+        }, Number(element.dataset.fadeAfter) * 1000);
+    }
+    const fadeAll = (root) => {
+        if (root.matches?.('[data-fade-after]')) fadeAfter(root);
+        root.querySelectorAll?.('[data-fade-after]').forEach(fadeAfter);
+    }
+    // htmx may have loaded the page before this ran
+    fadeAll(document.body);
+    document.body.addEventListener('htmx:load', (event) => fadeAll(event.detail.elt));
+    // coming back to the tab sends data-on-visible's event to the element, e.g. to refresh /me
+    document.addEventListener('visibilitychange', () => {
+        if (document.visibilityState !== 'visible') return;
+        document.querySelectorAll('[data-on-visible]').forEach((element) => {
+            htmx.trigger(element, element.dataset.onVisible);
+        });
+    })
+    document.body.addEventListener('htmx:beforeTransition', (event) => {
+        if (window.matchMedia('(prefers-reduced-motion: reduce)').matches) {
+            event.preventDefault();
+        }
+    })
+    document.body.addEventListener('htmx:error', (event) => {
+        if (event.detail.errorInfo.pathInfo.requestPath === '/upload') {
+            document.getElementById("new_dog_photo")?.classList.add("file-input-error");
+            const error = document.getElementById("new_dog_photo_error");
+            if (error) {
+                error.innerHTML = "That file is too big!";
+            }
+        }
+    })
+})
@@ -0,0 +1,3 @@
+-- magic links are also used to confirm deleting an account, 'log-in' or 'delete-account'
+ALTER TABLE email_token
+ADD COLUMN purpose TEXT NOT NULL DEFAULT 'log-in';
@@ -0,0 +1,35 @@
+-- log notes become details, the whole event as JSON (see db/log.rs).
+-- old notes are converted where their meaning is known, anything else is left as it was
+ALTER TABLE log RENAME COLUMN notes TO details;
+
+-- a dog id
+UPDATE log SET details = json_object('action', action, 'dog_id', CAST(details AS INTEGER))
+WHERE action IN ('name-dog', 'upload') AND details <> '' AND details NOT GLOB '*[^0-9]*';
+
+-- an email
+UPDATE log SET details = json_object('action', action, 'email', details)
+WHERE action IN ('send-magic-link', 'sign-up') AND details IS NOT NULL;
+
+-- 'email device_user_id'
+UPDATE log SET details = json_object(
+    'action', action,
+    'email', substr(details, 1, instr(details, ' ') - 1),
+    'device_user_id', CAST(substr(details, instr(details, ' ') + 1) AS INTEGER)
+)
+WHERE action = 'log-in' AND instr(details, ' ') > 0;
+
+-- a backup's file name
+UPDATE log SET details = json_object('action', action, 'file_name', details)
+WHERE action = 'backup' AND details IS NOT NULL;
+
+-- 'archive imported/files', the archive name can have spaces in it
+UPDATE log SET details = json_object(
+    'action', action,
+    'archive', rtrim(rtrim(details, '0123456789/'), ' '),
+    'imported', CAST(substr(details, length(rtrim(details, '0123456789/')) + 1) AS INTEGER),
+    'files', CAST(substr(substr(details, length(rtrim(details, '0123456789/')) + 1), instr(substr(details, length(rtrim(details, '0123456789/')) + 1), '/') + 1) AS INTEGER)
+)
+WHERE action = 'import' AND details GLOB '* *[0-9]/[0-9]*';
+
+CREATE INDEX log_action ON log (action);
+CREATE INDEX log_user_id ON log (user_id);
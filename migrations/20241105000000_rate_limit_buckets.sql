@@ -0,0 +1,7 @@
+-- token buckets for rate limiting, only used when RATE_LIMIT_PERSIST is on (see rate_limit.rs).
+-- updated_at is unix milliseconds, buckets refill continuously between requests
+CREATE TABLE rate_limit_bucket (
+    key TEXT PRIMARY KEY,
+    tokens REAL NOT NULL,
+    updated_at INTEGER NOT NULL
+);
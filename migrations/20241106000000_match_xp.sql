@@ -0,0 +1,2 @@
+-- the xp a pick was worth, so undoing it can take the same amount back
+ALTER TABLE match ADD COLUMN xp INTEGER NULL;
@@ -0,0 +1,2 @@
+-- how many times the pairing was skipped before it was decided, to spot dogs with bad photos
+ALTER TABLE match ADD COLUMN skips INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,33 @@
+-- ranking mode: the user puts 3 to 5 dogs in order at once (see routers/doggo/ranking.rs).
+-- a ranking's status is '…' while it's on screen and '✓' once it's been submitted, and its
+-- placements are turned into ordinary matches, which point back at the ranking they came from
+CREATE TABLE ranking (
+    id INTEGER PRIMARY KEY NOT NULL,
+    created_at DATETIME NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at DATETIME NULL DEFAULT CURRENT_TIMESTAMP,
+    user_id INTEGER NOT NULL,
+    status TEXT NOT NULL DEFAULT '…',
+    FOREIGN KEY(user_id) REFERENCES "user"(id)
+);
+CREATE TRIGGER update_updated_at_ranking
+AFTER UPDATE ON ranking
+WHEN OLD.updated_at <> CURRENT_TIMESTAMP
+BEGIN
+    UPDATE ranking
+    SET updated_at = CURRENT_TIMESTAMP
+    WHERE id = OLD.id;
+END;
+
+-- place is 1 for the favorite, and NULL until the ranking is submitted
+CREATE TABLE ranking_place (
+    ranking_id INTEGER NOT NULL,
+    dog_id INTEGER NOT NULL,
+    place INTEGER NULL,
+    FOREIGN KEY(ranking_id) REFERENCES ranking(id),
+    FOREIGN KEY(dog_id) REFERENCES dog(id),
+    PRIMARY KEY(ranking_id, dog_id)
+);
+
+ALTER TABLE match ADD COLUMN ranking_id INTEGER NULL REFERENCES ranking(id);
+
+CREATE INDEX ranking_user_id ON ranking (user_id);
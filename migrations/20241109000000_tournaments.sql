@@ -0,0 +1,75 @@
+-- bracket tournaments between the top rated dogs (see routers/tournaments). an admin starts one,
+-- every user votes on the open matches, and a match goes to whichever dog has more votes when its
+-- voting window closes. champion_id is NULL until the final is decided.
+CREATE TABLE tournament (
+    id INTEGER PRIMARY KEY NOT NULL,
+    created_at DATETIME NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at DATETIME NULL DEFAULT CURRENT_TIMESTAMP,
+    name TEXT NOT NULL,
+    -- 'single' or 'double' elimination
+    format TEXT NOT NULL,
+    window_hours INTEGER NOT NULL,
+    champion_id INTEGER NULL,
+    FOREIGN KEY(champion_id) REFERENCES dog(id)
+);
+CREATE TRIGGER update_updated_at_tournament
+AFTER UPDATE ON tournament
+WHEN OLD.updated_at <> CURRENT_TIMESTAMP
+BEGIN
+    UPDATE tournament
+    SET updated_at = CURRENT_TIMESTAMP
+    WHERE id = OLD.id;
+END;
+
+-- seed 1 is the dog that was rated highest when the tournament started
+CREATE TABLE tournament_entry (
+    tournament_id INTEGER NOT NULL,
+    dog_id INTEGER NOT NULL,
+    seed INTEGER NOT NULL,
+    FOREIGN KEY(tournament_id) REFERENCES tournament(id),
+    FOREIGN KEY(dog_id) REFERENCES dog(id),
+    PRIMARY KEY(tournament_id, dog_id)
+);
+
+-- number is the match's place in the bracket's layout, which also says where its winner and loser
+-- go next. the dogs are NULL until the matches feeding this one are decided, and the voting window
+-- opens once both are known
+CREATE TABLE bracket_match (
+    id INTEGER PRIMARY KEY NOT NULL,
+    created_at DATETIME NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at DATETIME NULL DEFAULT CURRENT_TIMESTAMP,
+    tournament_id INTEGER NOT NULL,
+    number INTEGER NOT NULL,
+    dog_a_id INTEGER NULL,
+    dog_b_id INTEGER NULL,
+    winner_id INTEGER NULL,
+    opens_at TEXT NULL,
+    closes_at TEXT NULL,
+    FOREIGN KEY(tournament_id) REFERENCES tournament(id),
+    FOREIGN KEY(dog_a_id) REFERENCES dog(id),
+    FOREIGN KEY(dog_b_id) REFERENCES dog(id),
+    FOREIGN KEY(winner_id) REFERENCES dog(id),
+    UNIQUE(tournament_id, number)
+);
+CREATE TRIGGER update_updated_at_bracket_match
+AFTER UPDATE ON bracket_match
+WHEN OLD.updated_at <> CURRENT_TIMESTAMP
+BEGIN
+    UPDATE bracket_match
+    SET updated_at = CURRENT_TIMESTAMP
+    WHERE id = OLD.id;
+END;
+
+-- one vote per user per match, which they can change until the window closes
+CREATE TABLE bracket_vote (
+    bracket_match_id INTEGER NOT NULL,
+    user_id INTEGER NOT NULL,
+    dog_id INTEGER NOT NULL,
+    created_at DATETIME NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY(bracket_match_id) REFERENCES bracket_match(id),
+    FOREIGN KEY(user_id) REFERENCES "user"(id),
+    FOREIGN KEY(dog_id) REFERENCES dog(id),
+    PRIMARY KEY(bracket_match_id, user_id)
+);
+
+CREATE INDEX bracket_vote_user_id ON bracket_vote (user_id);
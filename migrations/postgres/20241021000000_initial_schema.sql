@@ -0,0 +1,111 @@
+-- postgres gets the whole schema as it stands after the sqlite migrations one level up,
+-- any later change needs a migration in both places.
+-- timestamps are text in the same 'YYYY-MM-DD HH:MM:SS' (UTC) shape as sqlite's CURRENT_TIMESTAMP
+-- so the same queries can compare them on either database
+
+CREATE FUNCTION utc_now() RETURNS TEXT AS $$
+    SELECT to_char(now() AT TIME ZONE 'utc', 'YYYY-MM-DD HH24:MI:SS')
+$$ LANGUAGE SQL;
+
+CREATE FUNCTION set_updated_at() RETURNS TRIGGER AS $$
+BEGIN
+    NEW.updated_at = utc_now();
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TABLE "user" (
+    id BIGSERIAL PRIMARY KEY,
+    created_at TEXT NULL DEFAULT utc_now(),
+    updated_at TEXT NULL DEFAULT utc_now(),
+    email TEXT UNIQUE NULL,
+    total_xp BIGINT NOT NULL DEFAULT 0
+);
+CREATE TRIGGER update_updated_at_user
+BEFORE UPDATE ON "user"
+FOR EACH ROW EXECUTE FUNCTION set_updated_at();
+
+CREATE TABLE session (
+    created_at TEXT NULL DEFAULT utc_now(),
+    updated_at TEXT NULL DEFAULT utc_now(),
+    token TEXT PRIMARY KEY,
+    user_id BIGINT NOT NULL REFERENCES "user" (id)
+);
+CREATE TRIGGER update_updated_at_session
+BEFORE UPDATE ON session
+FOR EACH ROW EXECUTE FUNCTION set_updated_at();
+
+CREATE TABLE dog (
+    id BIGSERIAL PRIMARY KEY,
+    created_at TEXT NULL DEFAULT utc_now(),
+    updated_at TEXT NULL DEFAULT utc_now(),
+    image_url TEXT UNIQUE NOT NULL,
+    name TEXT UNIQUE NULL,
+    namer_id BIGINT NULL REFERENCES "user" (id),
+    approved BOOLEAN NOT NULL DEFAULT TRUE
+);
+CREATE TRIGGER update_updated_at_dog
+BEFORE UPDATE ON dog
+FOR EACH ROW EXECUTE FUNCTION set_updated_at();
+
+CREATE TABLE match (
+    id BIGSERIAL PRIMARY KEY,
+    created_at TEXT NULL DEFAULT utc_now(),
+    updated_at TEXT NULL DEFAULT utc_now(),
+    user_id BIGINT NOT NULL REFERENCES "user" (id),
+    dog_a_id BIGINT NOT NULL REFERENCES dog (id),
+    dog_b_id BIGINT NOT NULL REFERENCES dog (id),
+    status TEXT NOT NULL DEFAULT '…', -- '>', '<', '=', or '…'
+    elo_change_overall_a BIGINT NULL,
+    elo_change_overall_b BIGINT NULL,
+    elo_change_personal_a BIGINT NULL,
+    elo_change_personal_b BIGINT NULL,
+    UNIQUE (user_id, dog_a_id, dog_b_id)
+);
+CREATE TRIGGER update_updated_at_match
+BEFORE UPDATE ON match
+FOR EACH ROW EXECUTE FUNCTION set_updated_at();
+
+-- overall ratings have no user_id, which postgres won't allow in a primary key
+CREATE TABLE rating (
+    created_at TEXT NULL DEFAULT utc_now(),
+    updated_at TEXT NULL DEFAULT utc_now(),
+    type TEXT NOT NULL DEFAULT 'overall', -- 'overall' or 'personal'
+    user_id BIGINT NULL REFERENCES "user" (id), -- used if type = 'personal'
+    dog_id BIGINT NOT NULL REFERENCES dog (id),
+    value BIGINT NOT NULL DEFAULT 1000,
+    UNIQUE NULLS NOT DISTINCT (type, user_id, dog_id)
+);
+CREATE TRIGGER update_updated_at_rating
+BEFORE UPDATE ON rating
+FOR EACH ROW EXECUTE FUNCTION set_updated_at();
+
+CREATE TABLE user_finished_with_dog (
+    created_at TEXT NULL DEFAULT utc_now(),
+    updated_at TEXT NULL DEFAULT utc_now(),
+    user_id BIGINT NOT NULL REFERENCES "user" (id),
+    dog_id BIGINT NOT NULL REFERENCES dog (id),
+    PRIMARY KEY (user_id, dog_id)
+);
+CREATE TRIGGER update_updated_at_user_finished_with_dog
+BEFORE UPDATE ON user_finished_with_dog
+FOR EACH ROW EXECUTE FUNCTION set_updated_at();
+
+CREATE TABLE log (
+    id BIGSERIAL PRIMARY KEY,
+    created_at TEXT NULL DEFAULT utc_now(),
+    action TEXT NOT NULL,
+    user_id BIGINT NULL REFERENCES "user" (id),
+    client_ip TEXT NULL,
+    notes TEXT NULL
+);
+
+CREATE TABLE email_token (
+    created_at TEXT NULL DEFAULT utc_now(),
+    token TEXT PRIMARY KEY,
+    email TEXT NOT NULL,
+    used BOOLEAN NOT NULL DEFAULT FALSE,
+    sender_id BIGINT NULL REFERENCES "user" (id),
+    -- magic links are also used to confirm deleting an account, 'log-in' or 'delete-account'
+    purpose TEXT NOT NULL DEFAULT 'log-in'
+);
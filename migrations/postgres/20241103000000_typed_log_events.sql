@@ -0,0 +1,35 @@
+-- log notes become details, the whole event as JSON (see db/log.rs).
+-- old notes are converted where their meaning is known, anything else is left as it was
+ALTER TABLE log RENAME COLUMN notes TO details;
+
+-- a dog id
+UPDATE log SET details = json_build_object('action', action, 'dog_id', details::BIGINT)::TEXT
+WHERE action IN ('name-dog', 'upload') AND details ~ '^[0-9]+$';
+
+-- an email
+UPDATE log SET details = json_build_object('action', action, 'email', details)::TEXT
+WHERE action IN ('send-magic-link', 'sign-up') AND details IS NOT NULL;
+
+-- 'email device_user_id'
+UPDATE log SET details = json_build_object(
+    'action', action,
+    'email', split_part(details, ' ', 1),
+    'device_user_id', split_part(details, ' ', 2)::BIGINT
+)::TEXT
+WHERE action = 'log-in' AND details ~ '^\S+ [0-9]+$';
+
+-- a backup's file name
+UPDATE log SET details = json_build_object('action', action, 'file_name', details)::TEXT
+WHERE action = 'backup' AND details IS NOT NULL;
+
+-- 'archive imported/files', the archive name can have spaces in it
+UPDATE log SET details = json_build_object(
+    'action', action,
+    'archive', substring(details from '^(.*) [0-9]+/[0-9]+$'),
+    'imported', substring(details from ' ([0-9]+)/[0-9]+$')::BIGINT,
+    'files', substring(details from '/([0-9]+)$')::BIGINT
+)::TEXT
+WHERE action = 'import' AND details ~ '^.* [0-9]+/[0-9]+$';
+
+CREATE INDEX log_action ON log (action);
+CREATE INDEX log_user_id ON log (user_id);
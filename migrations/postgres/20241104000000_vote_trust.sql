@@ -0,0 +1,12 @@
+-- vote fraud detection (see fraud.rs).
+-- trust is a percentage that scales how much a user's votes move the overall ratings,
+-- and each match keeps the trust its vote was counted with so replays come out the same
+ALTER TABLE "user" ADD COLUMN client_ip TEXT NULL;
+ALTER TABLE "user" ADD COLUMN trust BIGINT NOT NULL DEFAULT 100;
+ALTER TABLE "user" ADD COLUMN quarantined BOOLEAN NOT NULL DEFAULT FALSE;
+-- the outcomes of a user's first few picks, e.g. '>><=>>><>=', to spot scripted voters
+ALTER TABLE "user" ADD COLUMN first_picks TEXT NULL;
+ALTER TABLE match ADD COLUMN trust BIGINT NULL;
+
+CREATE INDEX user_client_ip ON "user" (client_ip);
+CREATE INDEX user_first_picks ON "user" (first_picks);
@@ -0,0 +1,25 @@
+-- ranking mode: the user puts 3 to 5 dogs in order at once (see routers/doggo/ranking.rs).
+-- a ranking's status is '…' while it's on screen and '✓' once it's been submitted, and its
+-- placements are turned into ordinary matches, which point back at the ranking they came from
+CREATE TABLE ranking (
+    id BIGSERIAL PRIMARY KEY,
+    created_at TEXT NULL DEFAULT utc_now(),
+    updated_at TEXT NULL DEFAULT utc_now(),
+    user_id BIGINT NOT NULL REFERENCES "user" (id),
+    status TEXT NOT NULL DEFAULT '…'
+);
+CREATE TRIGGER update_updated_at_ranking
+BEFORE UPDATE ON ranking
+FOR EACH ROW EXECUTE FUNCTION set_updated_at();
+
+-- place is 1 for the favorite, and NULL until the ranking is submitted
+CREATE TABLE ranking_place (
+    ranking_id BIGINT NOT NULL REFERENCES ranking (id),
+    dog_id BIGINT NOT NULL REFERENCES dog (id),
+    place BIGINT NULL,
+    PRIMARY KEY (ranking_id, dog_id)
+);
+
+ALTER TABLE match ADD COLUMN ranking_id BIGINT NULL REFERENCES ranking (id);
+
+CREATE INDEX ranking_user_id ON ranking (user_id);
@@ -0,0 +1,55 @@
+-- bracket tournaments between the top rated dogs (see routers/tournaments). an admin starts one,
+-- every user votes on the open matches, and a match goes to whichever dog has more votes when its
+-- voting window closes. champion_id is NULL until the final is decided.
+CREATE TABLE tournament (
+    id BIGSERIAL PRIMARY KEY,
+    created_at TEXT NULL DEFAULT utc_now(),
+    updated_at TEXT NULL DEFAULT utc_now(),
+    name TEXT NOT NULL,
+    -- 'single' or 'double' elimination
+    format TEXT NOT NULL,
+    window_hours BIGINT NOT NULL,
+    champion_id BIGINT NULL REFERENCES dog (id)
+);
+CREATE TRIGGER update_updated_at_tournament
+BEFORE UPDATE ON tournament
+FOR EACH ROW EXECUTE FUNCTION set_updated_at();
+
+-- seed 1 is the dog that was rated highest when the tournament started
+CREATE TABLE tournament_entry (
+    tournament_id BIGINT NOT NULL REFERENCES tournament (id),
+    dog_id BIGINT NOT NULL REFERENCES dog (id),
+    seed BIGINT NOT NULL,
+    PRIMARY KEY (tournament_id, dog_id)
+);
+
+-- number is the match's place in the bracket's layout, which also says where its winner and loser
+-- go next. the dogs are NULL until the matches feeding this one are decided, and the voting window
+-- opens once both are known
+CREATE TABLE bracket_match (
+    id BIGSERIAL PRIMARY KEY,
+    created_at TEXT NULL DEFAULT utc_now(),
+    updated_at TEXT NULL DEFAULT utc_now(),
+    tournament_id BIGINT NOT NULL REFERENCES tournament (id),
+    number BIGINT NOT NULL,
+    dog_a_id BIGINT NULL REFERENCES dog (id),
+    dog_b_id BIGINT NULL REFERENCES dog (id),
+    winner_id BIGINT NULL REFERENCES dog (id),
+    opens_at TEXT NULL,
+    closes_at TEXT NULL,
+    UNIQUE (tournament_id, number)
+);
+CREATE TRIGGER update_updated_at_bracket_match
+BEFORE UPDATE ON bracket_match
+FOR EACH ROW EXECUTE FUNCTION set_updated_at();
+
+-- one vote per user per match, which they can change until the window closes
+CREATE TABLE bracket_vote (
+    bracket_match_id BIGINT NOT NULL REFERENCES bracket_match (id),
+    user_id BIGINT NOT NULL REFERENCES "user" (id),
+    dog_id BIGINT NOT NULL REFERENCES dog (id),
+    created_at TEXT NULL DEFAULT utc_now(),
+    PRIMARY KEY (bracket_match_id, user_id)
+);
+
+CREATE INDEX bracket_vote_user_id ON bracket_vote (user_id);
@@ -0,0 +1,3 @@
+-- where each dog was put up on screen, 1 for the first, so a ranking comes back in the order it
+-- was shown rather than by id. rankings from before this keep 0 and fall back to dog_id.
+ALTER TABLE ranking_place ADD COLUMN position BIGINT NOT NULL DEFAULT 0;
@@ -0,0 +1,636 @@
+use crate::db::{self, Backend};
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use sqlx::{Any, AnyConnection, Pool};
+use std::io::{BufRead, Write};
+
+// a JSON Lines dump of everything except secrets (sessions and email tokens),
+// so an instance can be moved, staging can be seeded, and analytics can have a copy.
+// the first line is a `Header`, every line after it is a `Record`.
+// dog photos aren't in here, copy the images folder alongside it.
+
+pub const FORMAT: &str = "top-doggo-export";
+/// Bump this whenever a `Record` changes shape.
+/// 2: log notes became details, the event as JSON (version 1 notes import as they are)
+/// 3: users and matches gained trust, and users quarantined (older exports trust everybody)
+/// 4: matches gained the xp their pick was worth
+/// 5: matches gained how many times they were skipped
+/// 6: rankings and their places, and matches gained the ranking they came out of
+/// 7: tournaments, their entrants, bracket matches and votes
+/// 8: ranking places gained the position they were shown in
+pub const VERSION: u32 = 8;
+
+// log actions whose details hold an email address
+const LOG_ACTIONS_WITH_EMAILS: [&str; 3] = ["send-magic-link", "sign-up", "log-in"];
+
+// what to select for each row type, with timestamps and booleans cast so the Any driver can read them
+pub const USER_COLUMNS: &str = "id, email, total_xp, trust, CASE WHEN quarantined THEN 1 ELSE 0 END AS quarantined, CAST(created_at AS TEXT) AS created_at, CAST(updated_at AS TEXT) AS updated_at";
+pub const DOG_COLUMNS: &str = "id, image_url, name, namer_id, CASE WHEN approved THEN 1 ELSE 0 END AS approved, CAST(created_at AS TEXT) AS created_at, CAST(updated_at AS TEXT) AS updated_at";
+pub const MATCH_COLUMNS: &str = "id, user_id, dog_a_id, dog_b_id, status, elo_change_overall_a, elo_change_overall_b, elo_change_personal_a, elo_change_personal_b, trust, xp, skips, ranking_id, CAST(created_at AS TEXT) AS created_at, CAST(updated_at AS TEXT) AS updated_at";
+pub const RANKING_COLUMNS: &str = "id, user_id, status, CAST(created_at AS TEXT) AS created_at, CAST(updated_at AS TEXT) AS updated_at";
+pub const RANKING_PLACE_COLUMNS: &str = "ranking_id, dog_id, place, position";
+pub const TOURNAMENT_COLUMNS: &str = "id, name, format, window_hours, champion_id, CAST(created_at AS TEXT) AS created_at, CAST(updated_at AS TEXT) AS updated_at";
+pub const TOURNAMENT_ENTRY_COLUMNS: &str = "tournament_id, dog_id, seed";
+pub const BRACKET_MATCH_COLUMNS: &str = "id, tournament_id, number, dog_a_id, dog_b_id, winner_id, opens_at, closes_at, CAST(created_at AS TEXT) AS created_at, CAST(updated_at AS TEXT) AS updated_at";
+pub const BRACKET_VOTE_COLUMNS: &str =
+    "bracket_match_id, user_id, dog_id, CAST(created_at AS TEXT) AS created_at";
+pub const RATING_COLUMNS: &str = "type, user_id, dog_id, value, CAST(created_at AS TEXT) AS created_at, CAST(updated_at AS TEXT) AS updated_at";
+pub const USER_FINISHED_WITH_DOG_COLUMNS: &str = "user_id, dog_id, CAST(created_at AS TEXT) AS created_at, CAST(updated_at AS TEXT) AS updated_at";
+pub const LOG_COLUMNS: &str =
+    "id, action, user_id, client_ip, details, CAST(created_at AS TEXT) AS created_at";
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Header {
+    pub format: String,
+    pub version: u32,
+    pub exported_at: String,
+    pub anonymized: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "table", content = "row", rename_all = "snake_case")]
+pub enum Record {
+    User(UserRow),
+    Dog(DogRow),
+    Ranking(RankingRow),
+    RankingPlace(RankingPlaceRow),
+    Match(MatchRow),
+    Tournament(TournamentRow),
+    TournamentEntry(TournamentEntryRow),
+    BracketMatch(BracketMatchRow),
+    BracketVote(BracketVoteRow),
+    Rating(RatingRow),
+    UserFinishedWithDog(UserFinishedWithDogRow),
+    Log(LogRow),
+}
+
+#[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
+pub struct UserRow {
+    pub id: i64,
+    pub email: Option<String>,
+    pub total_xp: i64,
+    #[serde(default = "full_trust")]
+    pub trust: i64,
+    #[serde(default)]
+    #[sqlx(try_from = "db::Flag")]
+    pub quarantined: bool,
+    pub created_at: Option<String>,
+    pub updated_at: Option<String>,
+}
+
+fn full_trust() -> i64 {
+    100
+}
+
+#[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
+pub struct DogRow {
+    pub id: i64,
+    pub image_url: String,
+    pub name: Option<String>,
+    pub namer_id: Option<i64>,
+    #[sqlx(try_from = "db::Flag")]
+    pub approved: bool,
+    pub created_at: Option<String>,
+    pub updated_at: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
+pub struct MatchRow {
+    pub id: i64,
+    pub user_id: i64,
+    pub dog_a_id: i64,
+    pub dog_b_id: i64,
+    pub status: String,
+    pub elo_change_overall_a: Option<i64>,
+    pub elo_change_overall_b: Option<i64>,
+    pub elo_change_personal_a: Option<i64>,
+    pub elo_change_personal_b: Option<i64>,
+    #[serde(default)]
+    pub trust: Option<i64>,
+    #[serde(default)]
+    pub xp: Option<i64>,
+    #[serde(default)]
+    pub skips: i64,
+    #[serde(default)]
+    pub ranking_id: Option<i64>,
+    pub created_at: Option<String>,
+    pub updated_at: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
+pub struct RankingRow {
+    pub id: i64,
+    pub user_id: i64,
+    pub status: String,
+    pub created_at: Option<String>,
+    pub updated_at: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
+pub struct RankingPlaceRow {
+    pub ranking_id: i64,
+    pub dog_id: i64,
+    pub place: Option<i64>,
+    #[serde(default)]
+    pub position: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
+pub struct TournamentRow {
+    pub id: i64,
+    pub name: String,
+    pub format: String,
+    pub window_hours: i64,
+    pub champion_id: Option<i64>,
+    pub created_at: Option<String>,
+    pub updated_at: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
+pub struct TournamentEntryRow {
+    pub tournament_id: i64,
+    pub dog_id: i64,
+    pub seed: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
+pub struct BracketMatchRow {
+    pub id: i64,
+    pub tournament_id: i64,
+    pub number: i64,
+    pub dog_a_id: Option<i64>,
+    pub dog_b_id: Option<i64>,
+    pub winner_id: Option<i64>,
+    pub opens_at: Option<String>,
+    pub closes_at: Option<String>,
+    pub created_at: Option<String>,
+    pub updated_at: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
+pub struct BracketVoteRow {
+    pub bracket_match_id: i64,
+    pub user_id: i64,
+    pub dog_id: i64,
+    pub created_at: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
+pub struct RatingRow {
+    #[sqlx(rename = "type")]
+    pub r#type: String,
+    pub user_id: Option<i64>,
+    pub dog_id: i64,
+    pub value: i64,
+    pub created_at: Option<String>,
+    pub updated_at: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
+pub struct UserFinishedWithDogRow {
+    pub user_id: i64,
+    pub dog_id: i64,
+    pub created_at: Option<String>,
+    pub updated_at: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
+pub struct LogRow {
+    pub id: i64,
+    pub action: String,
+    pub user_id: Option<i64>,
+    pub client_ip: Option<String>,
+    #[serde(alias = "notes")]
+    pub details: Option<String>,
+    pub created_at: Option<String>,
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct Counts {
+    pub users: usize,
+    pub dogs: usize,
+    pub rankings: usize,
+    pub matches: usize,
+    pub tournaments: usize,
+    pub bracket_votes: usize,
+    pub ratings: usize,
+    pub finished: usize,
+    pub logs: usize,
+}
+
+impl Counts {
+    fn add(&mut self, record: &Record) {
+        match record {
+            Record::User(_) => self.users += 1,
+            Record::Dog(_) => self.dogs += 1,
+            Record::Ranking(_) => self.rankings += 1,
+            // counted along with their rankings
+            Record::RankingPlace(_) => {}
+            Record::Match(_) => self.matches += 1,
+            Record::Tournament(_) => self.tournaments += 1,
+            // counted along with their tournaments
+            Record::TournamentEntry(_) | Record::BracketMatch(_) => {}
+            Record::BracketVote(_) => self.bracket_votes += 1,
+            Record::Rating(_) => self.ratings += 1,
+            Record::UserFinishedWithDog(_) => self.finished += 1,
+            Record::Log(_) => self.logs += 1,
+        }
+    }
+}
+
+/// Writes the whole database as JSON Lines.
+/// `anonymize` drops emails and IP addresses, including the emails in log details.
+pub async fn export(pool: &Pool<Any>, out: &mut impl Write, anonymize: bool) -> Result<Counts> {
+    // one read transaction so the tables agree with each other
+    let mut transaction = pool.begin().await?;
+    let header = Header {
+        format: FORMAT.to_string(),
+        version: VERSION,
+        exported_at: chrono::Utc::now().to_rfc3339(),
+        anonymized: anonymize,
+    };
+    writeln!(out, "{}", serde_json::to_string(&header)?)?;
+
+    let mut counts = Counts::default();
+    for record in read_records(&mut transaction).await? {
+        let record = if anonymize {
+            anonymized(record)
+        } else {
+            record
+        };
+        counts.add(&record);
+        writeln!(out, "{}", serde_json::to_string(&record)?)?;
+    }
+    out.flush()?;
+    transaction.commit().await?;
+    Ok(counts)
+}
+
+// parents before children, so foreign keys are satisfied when importing in file order
+async fn read_records(conn: &mut AnyConnection) -> Result<Vec<Record>> {
+    let mut records = vec![];
+    records.extend(
+        sqlx::query_as::<_, UserRow>(&format!(
+            r#"SELECT {} FROM "user" ORDER BY id"#,
+            USER_COLUMNS
+        ))
+        .fetch_all(&mut *conn)
+        .await?
+        .into_iter()
+        .map(Record::User),
+    );
+    records.extend(
+        sqlx::query_as::<_, DogRow>(&format!("SELECT {} FROM dog ORDER BY id", DOG_COLUMNS))
+            .fetch_all(&mut *conn)
+            .await?
+            .into_iter()
+            .map(Record::Dog),
+    );
+    records.extend(
+        sqlx::query_as::<_, RankingRow>(&format!(
+            "SELECT {} FROM ranking ORDER BY id",
+            RANKING_COLUMNS
+        ))
+        .fetch_all(&mut *conn)
+        .await?
+        .into_iter()
+        .map(Record::Ranking),
+    );
+    records.extend(
+        sqlx::query_as::<_, RankingPlaceRow>(&format!(
+            "SELECT {} FROM ranking_place ORDER BY ranking_id, dog_id",
+            RANKING_PLACE_COLUMNS
+        ))
+        .fetch_all(&mut *conn)
+        .await?
+        .into_iter()
+        .map(Record::RankingPlace),
+    );
+    records.extend(
+        sqlx::query_as::<_, MatchRow>(&format!("SELECT {} FROM match ORDER BY id", MATCH_COLUMNS))
+            .fetch_all(&mut *conn)
+            .await?
+            .into_iter()
+            .map(Record::Match),
+    );
+    records.extend(
+        sqlx::query_as::<_, TournamentRow>(&format!(
+            "SELECT {} FROM tournament ORDER BY id",
+            TOURNAMENT_COLUMNS
+        ))
+        .fetch_all(&mut *conn)
+        .await?
+        .into_iter()
+        .map(Record::Tournament),
+    );
+    records.extend(
+        sqlx::query_as::<_, TournamentEntryRow>(&format!(
+            "SELECT {} FROM tournament_entry ORDER BY tournament_id, seed",
+            TOURNAMENT_ENTRY_COLUMNS
+        ))
+        .fetch_all(&mut *conn)
+        .await?
+        .into_iter()
+        .map(Record::TournamentEntry),
+    );
+    records.extend(
+        sqlx::query_as::<_, BracketMatchRow>(&format!(
+            "SELECT {} FROM bracket_match ORDER BY id",
+            BRACKET_MATCH_COLUMNS
+        ))
+        .fetch_all(&mut *conn)
+        .await?
+        .into_iter()
+        .map(Record::BracketMatch),
+    );
+    records.extend(
+        sqlx::query_as::<_, BracketVoteRow>(&format!(
+            "SELECT {} FROM bracket_vote ORDER BY bracket_match_id, user_id",
+            BRACKET_VOTE_COLUMNS
+        ))
+        .fetch_all(&mut *conn)
+        .await?
+        .into_iter()
+        .map(Record::BracketVote),
+    );
+    records.extend(
+        sqlx::query_as::<_, RatingRow>(&format!(
+            "SELECT {} FROM rating ORDER BY type, user_id, dog_id",
+            RATING_COLUMNS
+        ))
+        .fetch_all(&mut *conn)
+        .await?
+        .into_iter()
+        .map(Record::Rating),
+    );
+    records.extend(
+        sqlx::query_as::<_, UserFinishedWithDogRow>(&format!(
+            "SELECT {} FROM user_finished_with_dog ORDER BY user_id, dog_id",
+            USER_FINISHED_WITH_DOG_COLUMNS
+        ))
+        .fetch_all(&mut *conn)
+        .await?
+        .into_iter()
+        .map(Record::UserFinishedWithDog),
+    );
+    records.extend(
+        sqlx::query_as::<_, LogRow>(&format!("SELECT {} FROM log ORDER BY id", LOG_COLUMNS))
+            .fetch_all(&mut *conn)
+            .await?
+            .into_iter()
+            .map(Record::Log),
+    );
+    Ok(records)
+}
+
+fn anonymized(record: Record) -> Record {
+    match record {
+        Record::User(user) => Record::User(UserRow {
+            email: None,
+            ..user
+        }),
+        Record::Log(log) => Record::Log(LogRow {
+            client_ip: None,
+            details: if LOG_ACTIONS_WITH_EMAILS.contains(&log.action.as_str()) {
+                None
+            } else {
+                log.details
+            },
+            ..log
+        }),
+        record => record,
+    }
+}
+
+/// Loads an export into a database that doesn't have any users, dogs or matches yet.
+/// Everything goes in one transaction, so a bad line leaves the database empty.
+pub async fn import(pool: &Pool<Any>, input: impl BufRead) -> Result<Counts> {
+    let mut lines = input.lines();
+    let header_line = lines.next().context("the export is empty")??;
+    let header: Header =
+        serde_json::from_str(&header_line).context("the first line isn't an export header")?;
+    if header.format != FORMAT {
+        bail!("this is a {:?} file, not a {:?}", header.format, FORMAT);
+    }
+    if header.version > VERSION {
+        bail!(
+            "this export is version {}, but this build only understands up to version {}",
+            header.version,
+            VERSION
+        );
+    }
+
+    let mut transaction = pool.begin().await?;
+    let existing: i64 = sqlx::query_scalar(
+        r#"SELECT (SELECT COUNT(*) FROM "user") + (SELECT COUNT(*) FROM dog) + (SELECT COUNT(*) FROM match)"#,
+    )
+    .fetch_one(&mut *transaction)
+    .await?;
+    if existing > 0 {
+        bail!("the database already has data in it, import into an empty one");
+    }
+
+    let mut counts = Counts::default();
+    for (i, line) in lines.enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        // +2 for the header and 1-based line numbers
+        let record: Record =
+            serde_json::from_str(&line).with_context(|| format!("line {} is invalid", i + 2))?;
+        insert_record(&mut transaction, &record)
+            .await
+            .with_context(|| format!("couldn't insert line {}", i + 2))?;
+        counts.add(&record);
+    }
+    // the ids came from the export, so postgres's sequences have to catch up with them
+    if Backend::of(pool) == Backend::Postgres {
+        for table in [
+            "\"user\"",
+            "dog",
+            "ranking",
+            "match",
+            "tournament",
+            "bracket_match",
+            "log",
+        ] {
+            sqlx::query(&format!(
+                "SELECT setval(pg_get_serial_sequence('{0}', 'id'), COALESCE(MAX(id), 0) + 1, false) FROM {0}",
+                table
+            ))
+            .execute(&mut *transaction)
+            .await?;
+        }
+    }
+    transaction.commit().await?;
+    Ok(counts)
+}
+
+async fn insert_record(conn: &mut AnyConnection, record: &Record) -> Result<()> {
+    match record {
+        Record::User(user) => {
+            sqlx::query(
+                r#"INSERT INTO "user" (id, email, total_xp, trust, quarantined, created_at, updated_at) VALUES ($1, $2, $3, $4, $5, $6, $7)"#,
+            )
+            .bind(user.id)
+            .bind(&user.email)
+            .bind(user.total_xp)
+            .bind(user.trust)
+            .bind(user.quarantined)
+            .bind(&user.created_at)
+            .bind(&user.updated_at)
+            .execute(conn)
+            .await?;
+        }
+        Record::Dog(dog) => {
+            sqlx::query(
+                "INSERT INTO dog (id, image_url, name, namer_id, approved, created_at, updated_at) VALUES ($1, $2, $3, $4, $5, $6, $7)",
+            )
+            .bind(dog.id)
+            .bind(&dog.image_url)
+            .bind(&dog.name)
+            .bind(dog.namer_id)
+            .bind(dog.approved)
+            .bind(&dog.created_at)
+            .bind(&dog.updated_at)
+            .execute(conn)
+            .await?;
+        }
+        Record::Ranking(ranking) => {
+            sqlx::query(
+                "INSERT INTO ranking (id, user_id, status, created_at, updated_at) VALUES ($1, $2, $3, $4, $5)",
+            )
+            .bind(ranking.id)
+            .bind(ranking.user_id)
+            .bind(&ranking.status)
+            .bind(&ranking.created_at)
+            .bind(&ranking.updated_at)
+            .execute(conn)
+            .await?;
+        }
+        Record::RankingPlace(place) => {
+            sqlx::query(
+                "INSERT INTO ranking_place (ranking_id, dog_id, place, position) VALUES ($1, $2, $3, $4)",
+            )
+            .bind(place.ranking_id)
+            .bind(place.dog_id)
+            .bind(place.place)
+            .bind(place.position)
+            .execute(conn)
+            .await?;
+        }
+        Record::Match(m) => {
+            sqlx::query(
+                "INSERT INTO match (id, user_id, dog_a_id, dog_b_id, status, elo_change_overall_a, elo_change_overall_b, elo_change_personal_a, elo_change_personal_b, trust, xp, skips, ranking_id, created_at, updated_at) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15)",
+            )
+            .bind(m.id)
+            .bind(m.user_id)
+            .bind(m.dog_a_id)
+            .bind(m.dog_b_id)
+            .bind(&m.status)
+            .bind(m.elo_change_overall_a)
+            .bind(m.elo_change_overall_b)
+            .bind(m.elo_change_personal_a)
+            .bind(m.elo_change_personal_b)
+            .bind(m.trust)
+            .bind(m.xp)
+            .bind(m.skips)
+            .bind(m.ranking_id)
+            .bind(&m.created_at)
+            .bind(&m.updated_at)
+            .execute(conn)
+            .await?;
+        }
+        Record::Tournament(tournament) => {
+            sqlx::query(
+                "INSERT INTO tournament (id, name, format, window_hours, champion_id, created_at, updated_at) VALUES ($1, $2, $3, $4, $5, $6, $7)",
+            )
+            .bind(tournament.id)
+            .bind(&tournament.name)
+            .bind(&tournament.format)
+            .bind(tournament.window_hours)
+            .bind(tournament.champion_id)
+            .bind(&tournament.created_at)
+            .bind(&tournament.updated_at)
+            .execute(conn)
+            .await?;
+        }
+        Record::TournamentEntry(entry) => {
+            sqlx::query(
+                "INSERT INTO tournament_entry (tournament_id, dog_id, seed) VALUES ($1, $2, $3)",
+            )
+            .bind(entry.tournament_id)
+            .bind(entry.dog_id)
+            .bind(entry.seed)
+            .execute(conn)
+            .await?;
+        }
+        Record::BracketMatch(m) => {
+            sqlx::query(
+                "INSERT INTO bracket_match (id, tournament_id, number, dog_a_id, dog_b_id, winner_id, opens_at, closes_at, created_at, updated_at) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)",
+            )
+            .bind(m.id)
+            .bind(m.tournament_id)
+            .bind(m.number)
+            .bind(m.dog_a_id)
+            .bind(m.dog_b_id)
+            .bind(m.winner_id)
+            .bind(&m.opens_at)
+            .bind(&m.closes_at)
+            .bind(&m.created_at)
+            .bind(&m.updated_at)
+            .execute(conn)
+            .await?;
+        }
+        Record::BracketVote(vote) => {
+            sqlx::query(
+                "INSERT INTO bracket_vote (bracket_match_id, user_id, dog_id, created_at) VALUES ($1, $2, $3, $4)",
+            )
+            .bind(vote.bracket_match_id)
+            .bind(vote.user_id)
+            .bind(vote.dog_id)
+            .bind(&vote.created_at)
+            .execute(conn)
+            .await?;
+        }
+        Record::Rating(rating) => {
+            sqlx::query(
+                "INSERT INTO rating (type, user_id, dog_id, value, created_at, updated_at) VALUES ($1, $2, $3, $4, $5, $6)",
+            )
+            .bind(&rating.r#type)
+            .bind(rating.user_id)
+            .bind(rating.dog_id)
+            .bind(rating.value)
+            .bind(&rating.created_at)
+            .bind(&rating.updated_at)
+            .execute(conn)
+            .await?;
+        }
+        Record::UserFinishedWithDog(finished) => {
+            sqlx::query(
+                "INSERT INTO user_finished_with_dog (user_id, dog_id, created_at, updated_at) VALUES ($1, $2, $3, $4)",
+            )
+            .bind(finished.user_id)
+            .bind(finished.dog_id)
+            .bind(&finished.created_at)
+            .bind(&finished.updated_at)
+            .execute(conn)
+            .await?;
+        }
+        Record::Log(log) => {
+            sqlx::query(
+                "INSERT INTO log (id, action, user_id, client_ip, details, created_at) VALUES ($1, $2, $3, $4, $5, $6)",
+            )
+            .bind(log.id)
+            .bind(&log.action)
+            .bind(log.user_id)
+            .bind(&log.client_ip)
+            .bind(&log.details)
+            .bind(&log.created_at)
+            .execute(conn)
+            .await?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,289 @@
+use crate::routers::{
+    doggo::name_dog::validate_dog_name,
+    upload::{validate_dog_photo, MAX_DOG_PHOTO_BYTES},
+};
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use sqlx::{Any, Pool};
+use std::{
+    collections::HashMap,
+    fs,
+    io::{Cursor, Read},
+    path::Path,
+};
+
+// bulk imports skip the approval queue, since an admin is the one doing them
+
+pub struct ImportFile {
+    // path inside the archive or folder, e.g. "dogs/rex.jpg"
+    pub name: String,
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug)]
+pub struct ImportResult {
+    pub file: String,
+    pub outcome: Result<ImportedDog, String>,
+}
+
+#[derive(Debug)]
+pub struct ImportedDog {
+    pub dog_id: i64,
+    pub name: Option<String>,
+}
+
+/// File name -> dog name
+pub type Manifest = HashMap<String, String>;
+
+const MANIFEST_FILE_NAMES: [&str; 2] = ["manifest.json", "manifest.csv"];
+
+/// The most an archive can unpack to. Archives of a few hundred phone photos get big.
+pub const MAX_ARCHIVE_BYTES: usize = 1024 * 1024 * 500;
+
+/// Reads a folder or a .zip/.tar/.tar.gz archive
+pub fn read_path(path: &Path) -> Result<Vec<ImportFile>> {
+    if path.is_dir() {
+        return read_dir(path);
+    }
+    let data = fs::read(path).with_context(|| format!("couldn't read {:?}", path))?;
+    read_archive(&path.to_string_lossy(), &data)
+}
+
+pub fn read_dir(dir: &Path) -> Result<Vec<ImportFile>> {
+    let mut files = vec![];
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if !path.is_file() {
+            continue;
+        }
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        if should_skip(&name) {
+            continue;
+        }
+        files.push(ImportFile {
+            name,
+            data: fs::read(&path)?,
+        });
+    }
+    files.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(files)
+}
+
+pub fn read_archive(file_name: &str, data: &[u8]) -> Result<Vec<ImportFile>> {
+    let lowercase = file_name.to_lowercase();
+    let mut files = if lowercase.ends_with(".zip") {
+        read_zip(data)?
+    } else if lowercase.ends_with(".tar") {
+        read_tar(data)?
+    } else if lowercase.ends_with(".tar.gz") || lowercase.ends_with(".tgz") {
+        read_tar(flate2::read::GzDecoder::new(data))?
+    } else {
+        bail!("{} isn't a .zip, .tar, or .tar.gz", file_name);
+    };
+    files.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(files)
+}
+
+fn read_zip(data: &[u8]) -> Result<Vec<ImportFile>> {
+    let mut archive = zip::ZipArchive::new(Cursor::new(data)).context("not a valid zip")?;
+    let mut files = vec![];
+    let mut unpacked = 0;
+    for i in 0..archive.len() {
+        let entry = archive.by_index(i)?;
+        if !entry.is_file() || should_skip(entry.name()) {
+            continue;
+        }
+        let name = entry.name().to_string();
+        let data = read_entry(&name, entry, &mut unpacked)?;
+        files.push(ImportFile { name, data });
+    }
+    Ok(files)
+}
+
+fn read_tar(reader: impl Read) -> Result<Vec<ImportFile>> {
+    let mut archive = tar::Archive::new(reader);
+    let mut files = vec![];
+    let mut unpacked = 0;
+    for entry in archive.entries().context("not a valid tar")? {
+        let entry = entry?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let name = entry.path()?.to_string_lossy().to_string();
+        if should_skip(&name) {
+            continue;
+        }
+        let data = read_entry(&name, entry, &mut unpacked)?;
+        files.push(ImportFile { name, data });
+    }
+    Ok(files)
+}
+
+/// Stops reading as soon as the file is bigger than a dog photo can be or the archive has
+/// unpacked to more than `MAX_ARCHIVE_BYTES`, rather than trusting the sizes the archive claims
+fn read_entry(name: &str, entry: impl Read, unpacked: &mut usize) -> Result<Vec<u8>> {
+    let mut data = vec![];
+    entry
+        .take(MAX_DOG_PHOTO_BYTES as u64 + 1)
+        .read_to_end(&mut data)?;
+    if data.len() > MAX_DOG_PHOTO_BYTES {
+        bail!(
+            "{} is bigger than {} MiB",
+            name,
+            MAX_DOG_PHOTO_BYTES / 1024 / 1024
+        );
+    }
+    *unpacked += data.len();
+    if *unpacked > MAX_ARCHIVE_BYTES {
+        bail!(
+            "the archive unpacks to more than {} MiB",
+            MAX_ARCHIVE_BYTES / 1024 / 1024
+        );
+    }
+    Ok(data)
+}
+
+// hidden files and the junk macOS adds to zips
+fn should_skip(name: &str) -> bool {
+    name.contains("__MACOSX/") || base_name(name).starts_with('.')
+}
+
+fn base_name(name: &str) -> &str {
+    name.rsplit('/').next().unwrap_or(name)
+}
+
+/// Pulls a manifest.json or manifest.csv out of the files, if there is one
+pub fn take_manifest(files: &mut Vec<ImportFile>) -> Result<Option<Manifest>> {
+    let Some(index) = files
+        .iter()
+        .position(|file| MANIFEST_FILE_NAMES.contains(&base_name(&file.name)))
+    else {
+        return Ok(None);
+    };
+    let file = files.remove(index);
+    parse_manifest(&file.name, &file.data).map(Some)
+}
+
+/// JSON can be `{"rex.jpg": "Rex"}` or `[{"file": "rex.jpg", "name": "Rex"}]`,
+/// CSV is `file,name` rows (with or without that header)
+pub fn parse_manifest(file_name: &str, data: &[u8]) -> Result<Manifest> {
+    if file_name.to_lowercase().ends_with(".json") {
+        #[derive(Deserialize)]
+        struct Entry {
+            file: String,
+            name: String,
+        }
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum JsonManifest {
+            Map(Manifest),
+            List(Vec<Entry>),
+        }
+        let manifest: JsonManifest =
+            serde_json::from_slice(data).context("manifest isn't valid JSON")?;
+        return Ok(match manifest {
+            JsonManifest::Map(map) => map,
+            JsonManifest::List(entries) => entries
+                .into_iter()
+                .map(|entry| (entry.file, entry.name))
+                .collect(),
+        });
+    }
+
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .trim(csv::Trim::All)
+        .from_reader(data);
+    let mut manifest = Manifest::new();
+    for record in reader.records() {
+        let record = record.context("manifest isn't valid CSV")?;
+        let (Some(file), Some(name)) = (record.get(0), record.get(1)) else {
+            continue;
+        };
+        if file == "file" && name == "name" {
+            continue;
+        }
+        manifest.insert(file.to_string(), name.to_string());
+    }
+    Ok(manifest)
+}
+
+/// Adds each file as an approved dog, named from the manifest if it's listed there.
+/// One bad file doesn't stop the rest.
+pub async fn import_dogs(
+    pool: &Pool<Any>,
+    files: Vec<ImportFile>,
+    manifest: &Manifest,
+    images_dir: &Path,
+) -> Vec<ImportResult> {
+    let mut results = vec![];
+    for file in files {
+        let name = manifest
+            .get(&file.name)
+            .or_else(|| manifest.get(base_name(&file.name)))
+            .map(|name| name.trim())
+            .filter(|name| !name.is_empty());
+        let outcome = import_dog(pool, &file, name, images_dir).await;
+        results.push(ImportResult {
+            file: file.name,
+            outcome,
+        });
+    }
+    results
+}
+
+async fn import_dog(
+    pool: &Pool<Any>,
+    file: &ImportFile,
+    name: Option<&str>,
+    images_dir: &Path,
+) -> Result<ImportedDog, String> {
+    validate_dog_photo(&file.data)?;
+    if let Some(name) = name {
+        validate_dog_name(name)?;
+    }
+
+    let mut transaction = pool.begin().await.map_err(|error| error.to_string())?;
+    let dog_id: i64 = match sqlx::query_scalar(
+        "INSERT INTO dog (image_url, name) VALUES ('temp', $1) RETURNING id",
+    )
+    .bind(name)
+    .fetch_one(&mut *transaction)
+    .await
+    {
+        Ok(dog_id) => dog_id,
+        Err(sqlx::Error::Database(error)) if error.is_unique_violation() => {
+            return Err(format!("{} is already taken", name.unwrap_or_default()));
+        }
+        Err(error) => return Err(error.to_string()),
+    };
+
+    let file_name = format!("{}.jpg", dog_id);
+    let file_path = images_dir.join(&file_name);
+    fs::write(&file_path, &file.data).map_err(|error| error.to_string())?;
+
+    let image_url = format!("/images/{}", file_name);
+    let saved = async {
+        sqlx::query("UPDATE dog SET image_url = $1 WHERE id = $2")
+            .bind(image_url)
+            .bind(dog_id)
+            .execute(&mut *transaction)
+            .await?;
+        transaction.commit().await
+    }
+    .await;
+    if let Err(error) = saved {
+        // the dog's id is free again, so don't leave its photo behind
+        let _ = fs::remove_file(&file_path);
+        return Err(error.to_string());
+    }
+
+    Ok(ImportedDog {
+        dog_id,
+        name: name.map(|name| name.to_string()),
+    })
+}
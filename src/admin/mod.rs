@@ -0,0 +1,641 @@
+use crate::{
+    db::{self, ratings::RatingType},
+    rate_limit,
+    routers::{
+        doggo::elo::recompute_ratings,
+        tournaments::bracket::{self, Format},
+    },
+};
+use anyhow::{bail, Context, Result};
+use sqlx::{Any, AnyConnection, Pool};
+use std::{fs, path::Path};
+
+// the operations behind the top-doggo-admin binary, so they can run against any DATABASE_URL
+
+pub mod export;
+pub mod import;
+
+pub async fn approve_dog(
+    pool: &Pool<Any>,
+    dog_id: i64,
+    unapproved_dir: &Path,
+    images_dir: &Path,
+) -> Result<()> {
+    let approved = is_approved(pool, dog_id).await?;
+    if approved {
+        bail!("dog {} is already approved", dog_id);
+    }
+
+    let file_name = format!("{}.jpg", dog_id);
+    move_file(
+        &unapproved_dir.join(&file_name),
+        &images_dir.join(&file_name),
+    )?;
+
+    sqlx::query("UPDATE dog SET approved = TRUE WHERE id = $1")
+        .bind(dog_id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+async fn is_approved(pool: &Pool<Any>, dog_id: i64) -> Result<bool> {
+    let approved: i64 =
+        sqlx::query_scalar("SELECT CASE WHEN approved THEN 1 ELSE 0 END FROM dog WHERE id = $1")
+            .bind(dog_id)
+            .fetch_optional(pool)
+            .await?
+            .with_context(|| format!("dog {} doesn't exist", dog_id))?;
+    Ok(approved == 1)
+}
+
+pub async fn reject_dog(pool: &Pool<Any>, dog_id: i64, unapproved_dir: &Path) -> Result<()> {
+    let approved = is_approved(pool, dog_id).await?;
+    if approved {
+        bail!(
+            "dog {} has already been approved, so it might have matches",
+            dog_id
+        );
+    }
+
+    let mut transaction = pool.begin().await?;
+    sqlx::query("DELETE FROM user_finished_with_dog WHERE dog_id = $1")
+        .bind(dog_id)
+        .execute(&mut *transaction)
+        .await?;
+    sqlx::query("DELETE FROM dog WHERE id = $1")
+        .bind(dog_id)
+        .execute(&mut *transaction)
+        .await?;
+    transaction.commit().await?;
+
+    let file_path = unapproved_dir.join(format!("{}.jpg", dog_id));
+    if let Err(error) = fs::remove_file(&file_path) {
+        eprintln!("Couldn't delete {:?}: {}", file_path, error);
+    }
+    Ok(())
+}
+
+pub async fn rename_dog(pool: &Pool<Any>, dog_id: i64, name: Option<&str>) -> Result<()> {
+    let result = sqlx::query("UPDATE dog SET name = $1 WHERE id = $2")
+        .bind(name)
+        .bind(dog_id)
+        .execute(pool)
+        .await
+        .context("that name is probably taken")?;
+    if result.rows_affected() == 0 {
+        bail!("dog {} doesn't exist", dog_id);
+    }
+    Ok(())
+}
+
+/// Moves everything `from_user_id` has done over to `into_user_id` and deletes `from_user_id`.
+/// Votes on pairings that both users judged are kept from `into_user_id`, and the overall
+/// ratings are replayed without the ones that were dropped. The merged user keeps the lower
+/// trust of the two, and is quarantined if either was.
+pub async fn merge_users(pool: &Pool<Any>, from_user_id: i64, into_user_id: i64) -> Result<()> {
+    if from_user_id == into_user_id {
+        bail!("can't merge a user into themselves");
+    }
+    let mut transaction = pool.begin().await?;
+
+    let (from_email, from_total_xp) = sqlx::query_as::<_, (Option<String>, i64)>(
+        r#"SELECT email, total_xp FROM "user" WHERE id = $1"#,
+    )
+    .bind(from_user_id)
+    .fetch_optional(&mut *transaction)
+    .await?
+    .with_context(|| format!("user {} doesn't exist", from_user_id))?;
+    let into_email: Option<String> =
+        sqlx::query_scalar(r#"SELECT email FROM "user" WHERE id = $1"#)
+            .bind(into_user_id)
+            .fetch_optional(&mut *transaction)
+            .await?
+            .with_context(|| format!("user {} doesn't exist", into_user_id))?;
+    if from_email.is_some() && into_email.is_some() {
+        bail!("both users have an email, so it's not clear which one to keep");
+    }
+
+    sqlx::query("DELETE FROM match WHERE user_id = $1 AND status = '…'")
+        .bind(from_user_id)
+        .execute(&mut *transaction)
+        .await?;
+    db::rankings::withdraw_current(&mut transaction, from_user_id).await?;
+    // a pairing both users have voted on keeps into's vote, and one into has only been shown or
+    // skipped gives way to from's
+    sqlx::query(
+        "DELETE FROM match WHERE user_id = $1 AND id IN (
+            SELECT theirs.id FROM match AS theirs JOIN match AS ours
+            ON ours.user_id = $2 AND ours.status IN ('>', '<', '=') AND (
+                (ours.dog_a_id = theirs.dog_a_id AND ours.dog_b_id = theirs.dog_b_id)
+                OR (ours.dog_a_id = theirs.dog_b_id AND ours.dog_b_id = theirs.dog_a_id)
+            )
+            WHERE theirs.user_id = $1
+        )",
+    )
+    .bind(from_user_id)
+    .bind(into_user_id)
+    .execute(&mut *transaction)
+    .await?;
+    sqlx::query(
+        "DELETE FROM match WHERE user_id = $1 AND status NOT IN ('>', '<', '=') AND id IN (
+            SELECT ours.id FROM match AS ours JOIN match AS theirs
+            ON theirs.user_id = $2 AND (
+                (ours.dog_a_id = theirs.dog_a_id AND ours.dog_b_id = theirs.dog_b_id)
+                OR (ours.dog_a_id = theirs.dog_b_id AND ours.dog_b_id = theirs.dog_a_id)
+            )
+            WHERE ours.user_id = $1
+        )",
+    )
+    .bind(into_user_id)
+    .bind(from_user_id)
+    .execute(&mut *transaction)
+    .await?;
+    sqlx::query("UPDATE match SET user_id = $1 WHERE user_id = $2")
+        .bind(into_user_id)
+        .bind(from_user_id)
+        .execute(&mut *transaction)
+        .await?;
+    sqlx::query("UPDATE ranking SET user_id = $1 WHERE user_id = $2")
+        .bind(into_user_id)
+        .bind(from_user_id)
+        .execute(&mut *transaction)
+        .await?;
+    // a user only gets one vote per tournament match, so the one they'd end up with keeps theirs
+    sqlx::query(
+        "DELETE FROM bracket_vote WHERE user_id = $1 AND bracket_match_id IN (SELECT bracket_match_id FROM bracket_vote WHERE user_id = $2)",
+    )
+    .bind(from_user_id)
+    .bind(into_user_id)
+    .execute(&mut *transaction)
+    .await?;
+    sqlx::query("UPDATE bracket_vote SET user_id = $1 WHERE user_id = $2")
+        .bind(into_user_id)
+        .bind(from_user_id)
+        .execute(&mut *transaction)
+        .await?;
+
+    // recomputed below
+    sqlx::query("DELETE FROM rating WHERE type = 'personal' AND user_id = $1")
+        .bind(from_user_id)
+        .execute(&mut *transaction)
+        .await?;
+    // which dogs they're finished with gets worked out again by get_dog_match
+    sqlx::query("DELETE FROM user_finished_with_dog WHERE user_id = $1 OR user_id = $2")
+        .bind(from_user_id)
+        .bind(into_user_id)
+        .execute(&mut *transaction)
+        .await?;
+
+    sqlx::query("UPDATE session SET user_id = $1 WHERE user_id = $2")
+        .bind(into_user_id)
+        .bind(from_user_id)
+        .execute(&mut *transaction)
+        .await?;
+    sqlx::query("UPDATE dog SET namer_id = $1 WHERE namer_id = $2")
+        .bind(into_user_id)
+        .bind(from_user_id)
+        .execute(&mut *transaction)
+        .await?;
+    sqlx::query("UPDATE log SET user_id = $1 WHERE user_id = $2")
+        .bind(into_user_id)
+        .bind(from_user_id)
+        .execute(&mut *transaction)
+        .await?;
+    sqlx::query("UPDATE email_token SET sender_id = $1 WHERE sender_id = $2")
+        .bind(into_user_id)
+        .bind(from_user_id)
+        .execute(&mut *transaction)
+        .await?;
+
+    // whatever the fraud checks held against from carries over, so merging doesn't clear it
+    let from_standing = db::users::standing(&mut *transaction, from_user_id).await?;
+    if from_standing.quarantined {
+        sqlx::query(r#"UPDATE "user" SET quarantined = TRUE WHERE id = $1"#)
+            .bind(into_user_id)
+            .execute(&mut *transaction)
+            .await?;
+    }
+    sqlx::query(
+        r#"UPDATE "user" SET trust = CASE WHEN trust > $1 THEN $1 ELSE trust END, first_picks = COALESCE(first_picks, CAST($2 AS TEXT)) WHERE id = $3"#,
+    )
+    .bind(from_standing.trust)
+    .bind(from_standing.first_picks)
+    .bind(into_user_id)
+    .execute(&mut *transaction)
+    .await?;
+
+    sqlx::query(r#"DELETE FROM "user" WHERE id = $1"#)
+        .bind(from_user_id)
+        .execute(&mut *transaction)
+        .await?;
+    sqlx::query(
+        r#"UPDATE "user" SET total_xp = total_xp + $1, email = COALESCE(email, CAST($2 AS TEXT)) WHERE id = $3"#,
+    )
+    .bind(from_total_xp)
+    .bind(from_email)
+    .bind(into_user_id)
+    .execute(&mut *transaction)
+    .await?;
+
+    recompute_ratings(&mut transaction, None).await?;
+
+    transaction.commit().await?;
+    Ok(())
+}
+
+/// Removes a user and everything tied to them. Their votes are deleted and the overall
+/// ratings are replayed without them, dogs they named keep the name, and their log
+/// entries keep the action and time but lose the user id, IP address and details.
+pub async fn delete_user(pool: &Pool<Any>, user_id: i64) -> Result<()> {
+    let mut transaction = pool.begin().await?;
+    remove_user(&mut transaction, user_id).await?;
+    recompute_ratings(&mut transaction, None).await?;
+    transaction.commit().await?;
+    Ok(())
+}
+
+/// Removes a user like `delete_user`, but takes their votes out of the overall ratings by
+/// reversing the changes stored on their matches rather than replaying every match, so it's
+/// quick enough for people deleting their own account (see routers/me/account.rs). Votes cast
+/// after theirs were worked out from ratings that included them, until `recompute-ratings` runs.
+pub async fn delete_account(pool: &Pool<Any>, user_id: i64) -> Result<()> {
+    let mut transaction = pool.begin().await?;
+    let changes: Vec<(i64, Option<i64>, i64, Option<i64>)> = sqlx::query_as(
+        "SELECT dog_a_id, elo_change_overall_a, dog_b_id, elo_change_overall_b FROM match WHERE user_id = $1 AND status IN ('>', '<', '=')",
+    )
+    .bind(user_id)
+    .fetch_all(&mut *transaction)
+    .await?;
+    for (dog_a_id, change_a, dog_b_id, change_b) in changes {
+        for (dog_id, change) in [(dog_a_id, change_a), (dog_b_id, change_b)] {
+            if let Some(change) = change {
+                db::ratings::shift(
+                    &mut *transaction,
+                    dog_id,
+                    RatingType::Overall,
+                    user_id,
+                    -(change as i32),
+                )
+                .await?;
+            }
+        }
+    }
+    remove_user(&mut transaction, user_id).await?;
+    transaction.commit().await?;
+    Ok(())
+}
+
+/// Everything `delete_user` and `delete_account` have in common, short of the overall ratings
+async fn remove_user(conn: &mut AnyConnection, user_id: i64) -> Result<()> {
+    let email: Option<String> = sqlx::query_scalar(r#"SELECT email FROM "user" WHERE id = $1"#)
+        .bind(user_id)
+        .fetch_optional(&mut *conn)
+        .await?
+        .with_context(|| format!("user {} doesn't exist", user_id))?;
+
+    sqlx::query("DELETE FROM session WHERE user_id = $1")
+        .bind(user_id)
+        .execute(&mut *conn)
+        .await?;
+    sqlx::query("DELETE FROM match WHERE user_id = $1")
+        .bind(user_id)
+        .execute(&mut *conn)
+        .await?;
+    delete_rankings(&mut *conn, user_id).await?;
+    db::tournaments::delete_votes_by(&mut *conn, user_id).await?;
+    sqlx::query("DELETE FROM rating WHERE type = 'personal' AND user_id = $1")
+        .bind(user_id)
+        .execute(&mut *conn)
+        .await?;
+    sqlx::query("DELETE FROM user_finished_with_dog WHERE user_id = $1")
+        .bind(user_id)
+        .execute(&mut *conn)
+        .await?;
+    sqlx::query("DELETE FROM email_token WHERE sender_id = $1 OR email = CAST($2 AS TEXT)")
+        .bind(user_id)
+        .bind(&email)
+        .execute(&mut *conn)
+        .await?;
+    sqlx::query("UPDATE dog SET namer_id = NULL WHERE namer_id = $1")
+        .bind(user_id)
+        .execute(&mut *conn)
+        .await?;
+
+    // sign-ups and log-ins on other devices are logged under the other device's user
+    sqlx::query(
+        r"UPDATE log SET details = NULL WHERE user_id = $1 OR details LIKE CAST($2 AS TEXT) ESCAPE '\'",
+    )
+    .bind(user_id)
+    .bind(email.as_deref().map(db::containing))
+    .execute(&mut *conn)
+    .await?;
+    sqlx::query("UPDATE log SET user_id = NULL, client_ip = NULL WHERE user_id = $1")
+        .bind(user_id)
+        .execute(&mut *conn)
+        .await?;
+
+    sqlx::query(r#"DELETE FROM "user" WHERE id = $1"#)
+        .bind(user_id)
+        .execute(&mut *conn)
+        .await?;
+    Ok(())
+}
+
+/// Deletes every ranking a user has made, once the matches that came out of them are gone
+async fn delete_rankings(conn: &mut AnyConnection, user_id: i64) -> Result<()> {
+    sqlx::query(
+        "DELETE FROM ranking_place WHERE ranking_id IN (SELECT id FROM ranking WHERE user_id = $1)",
+    )
+    .bind(user_id)
+    .execute(&mut *conn)
+    .await?;
+    sqlx::query("DELETE FROM ranking WHERE user_id = $1")
+        .bind(user_id)
+        .execute(&mut *conn)
+        .await?;
+    Ok(())
+}
+
+async fn user_exists(conn: &mut AnyConnection, user_id: i64) -> Result<()> {
+    sqlx::query_scalar::<_, i64>(r#"SELECT id FROM "user" WHERE id = $1"#)
+        .bind(user_id)
+        .fetch_optional(conn)
+        .await?
+        .with_context(|| format!("user {} doesn't exist", user_id))?;
+    Ok(())
+}
+
+/// Stops a user's votes, past and future, from moving the overall ratings (see fraud.rs)
+pub async fn quarantine_user(pool: &Pool<Any>, user_id: i64) -> Result<()> {
+    let mut transaction = pool.begin().await?;
+    user_exists(&mut transaction, user_id).await?;
+
+    sqlx::query(r#"UPDATE "user" SET quarantined = TRUE WHERE id = $1"#)
+        .bind(user_id)
+        .execute(&mut *transaction)
+        .await?;
+
+    recompute_ratings(&mut transaction, None).await?;
+
+    transaction.commit().await?;
+    Ok(())
+}
+
+/// Vouches for a quarantined or flagged user, so all of their votes count in full again
+pub async fn release_user(pool: &Pool<Any>, user_id: i64) -> Result<()> {
+    let mut transaction = pool.begin().await?;
+    user_exists(&mut transaction, user_id).await?;
+
+    sqlx::query(r#"UPDATE "user" SET quarantined = FALSE, trust = 100 WHERE id = $1"#)
+        .bind(user_id)
+        .execute(&mut *transaction)
+        .await?;
+    sqlx::query("UPDATE match SET trust = NULL WHERE user_id = $1")
+        .bind(user_id)
+        .execute(&mut *transaction)
+        .await?;
+
+    recompute_ratings(&mut transaction, None).await?;
+
+    transaction.commit().await?;
+    Ok(())
+}
+
+/// Deletes every vote a user has cast and replays the overall ratings without them.
+/// The user, their xp and their log entries stay. Returns how many votes were deleted.
+pub async fn roll_back_votes(pool: &Pool<Any>, user_id: i64) -> Result<u64> {
+    let mut transaction = pool.begin().await?;
+    user_exists(&mut transaction, user_id).await?;
+
+    let votes = sqlx::query("DELETE FROM match WHERE user_id = $1 AND status IN ('>', '<', '=')")
+        .bind(user_id)
+        .execute(&mut *transaction)
+        .await?
+        .rows_affected();
+    sqlx::query("DELETE FROM match WHERE user_id = $1")
+        .bind(user_id)
+        .execute(&mut *transaction)
+        .await?;
+    delete_rankings(&mut transaction, user_id).await?;
+    // tournament matches they voted in that are already decided stay decided
+    let votes = votes + db::tournaments::delete_votes_by(&mut *transaction, user_id).await?;
+    sqlx::query("DELETE FROM rating WHERE type = 'personal' AND user_id = $1")
+        .bind(user_id)
+        .execute(&mut *transaction)
+        .await?;
+    sqlx::query("DELETE FROM user_finished_with_dog WHERE user_id = $1")
+        .bind(user_id)
+        .execute(&mut *transaction)
+        .await?;
+
+    recompute_ratings(&mut transaction, None).await?;
+
+    transaction.commit().await?;
+    Ok(votes)
+}
+
+/// Starts a tournament between the `size` highest rated approved dogs, seeded by their overall
+/// rating, with `window_hours` to vote on each round. Returns the tournament's id.
+pub async fn start_tournament(
+    pool: &Pool<Any>,
+    name: &str,
+    format: Format,
+    size: usize,
+    window_hours: i64,
+) -> Result<i64> {
+    let name = name.trim();
+    if name.is_empty() {
+        bail!("the tournament needs a name");
+    }
+    if !bracket::SIZES.contains(&size) {
+        bail!(
+            "a tournament can have {:?} dogs, not {}",
+            bracket::SIZES,
+            size
+        );
+    }
+    if window_hours < 1 {
+        bail!("voting has to stay open for at least an hour");
+    }
+    let mut transaction = pool.begin().await?;
+    let dog_ids = db::ratings::top_dog_ids(&mut *transaction, size as i64).await?;
+    if dog_ids.len() < size {
+        bail!(
+            "there are only {} approved dogs, not enough for {}",
+            dog_ids.len(),
+            size
+        );
+    }
+    let tournament_id =
+        bracket::start(&mut transaction, name, format, window_hours, &dog_ids).await?;
+    transaction.commit().await?;
+    Ok(tournament_id)
+}
+
+#[derive(Debug, Default)]
+pub struct PruneReport {
+    pub users: u64,
+    pub email_tokens: u64,
+    pub rate_limit_buckets: u64,
+}
+
+/// Deletes anonymous users older than `older_than_days` who never voted or named a dog,
+/// magic link tokens that can't be used anymore, and saved rate limit buckets that have refilled
+pub async fn prune(pool: &Pool<Any>, older_than_days: u32) -> Result<PruneReport> {
+    let mut report = PruneReport::default();
+    let mut transaction = pool.begin().await?;
+
+    let user_ids: Vec<i64> = sqlx::query_scalar(
+        r#"SELECT id FROM "user" WHERE email IS NULL AND created_at < $1
+            AND id NOT IN (SELECT user_id FROM match WHERE status IN ('>', '<', '='))
+            AND id NOT IN (SELECT user_id FROM bracket_vote)
+            AND id NOT IN (SELECT namer_id FROM dog WHERE namer_id IS NOT NULL)"#,
+    )
+    .bind(db::days_ago(older_than_days.into()))
+    .fetch_all(&mut *transaction)
+    .await?;
+
+    for user_id in user_ids {
+        sqlx::query("DELETE FROM session WHERE user_id = $1")
+            .bind(user_id)
+            .execute(&mut *transaction)
+            .await?;
+        sqlx::query("DELETE FROM match WHERE user_id = $1")
+            .bind(user_id)
+            .execute(&mut *transaction)
+            .await?;
+        delete_rankings(&mut transaction, user_id).await?;
+        sqlx::query("DELETE FROM user_finished_with_dog WHERE user_id = $1")
+            .bind(user_id)
+            .execute(&mut *transaction)
+            .await?;
+        sqlx::query("DELETE FROM email_token WHERE sender_id = $1")
+            .bind(user_id)
+            .execute(&mut *transaction)
+            .await?;
+        sqlx::query("UPDATE log SET user_id = NULL WHERE user_id = $1")
+            .bind(user_id)
+            .execute(&mut *transaction)
+            .await?;
+        sqlx::query(r#"DELETE FROM "user" WHERE id = $1"#)
+            .bind(user_id)
+            .execute(&mut *transaction)
+            .await?;
+        report.users += 1;
+    }
+
+    // magic links are only good for 30 minutes (see /login)
+    report.email_tokens = sqlx::query("DELETE FROM email_token WHERE created_at < $1")
+        .bind(db::minutes_ago(30))
+        .execute(&mut *transaction)
+        .await?
+        .rows_affected();
+
+    report.rate_limit_buckets = db::rate_limits::prune(
+        &mut *transaction,
+        chrono::Utc::now().timestamp_millis() - rate_limit::IDLE_MILLIS,
+    )
+    .await?;
+
+    transaction.commit().await?;
+    Ok(report)
+}
+
+#[derive(Debug)]
+pub struct Stats {
+    pub approved_dogs: i64,
+    pub unapproved_dogs: i64,
+    pub named_dogs: i64,
+    pub users: i64,
+    pub users_with_email: i64,
+    pub resolved_matches: i64,
+    pub ties: i64,
+    /// Times a pairing was passed on, including ones decided since
+    pub skips: i64,
+    pub pending_matches: i64,
+    pub total_xp: i64,
+    pub top_dogs: Vec<(String, i64)>,
+    /// (dog, times skipped, times shown), most often skipped first
+    pub most_skipped: Vec<(String, i64, i64)>,
+}
+
+pub async fn stats(pool: &Pool<Any>) -> Result<Stats> {
+    let (approved_dogs, unapproved_dogs, named_dogs) = sqlx::query_as::<_, (i64, i64, i64)>(
+        "SELECT
+            COUNT(*) FILTER (WHERE approved),
+            COUNT(*) FILTER (WHERE NOT approved),
+            COUNT(name)
+        FROM dog",
+    )
+    .fetch_one(pool)
+    .await?;
+    let (users, users_with_email, total_xp) = sqlx::query_as::<_, (i64, i64, i64)>(
+        r#"SELECT COUNT(*), COUNT(email), CAST(COALESCE(SUM(total_xp), 0) AS BIGINT) FROM "user""#,
+    )
+    .fetch_one(pool)
+    .await?;
+    let (resolved_matches, ties, skips, pending_matches) =
+        sqlx::query_as::<_, (i64, i64, i64, i64)>(
+            "SELECT
+                COUNT(*) FILTER (WHERE status IN ('>', '<', '=')),
+                COUNT(*) FILTER (WHERE status = '='),
+                CAST(COALESCE(SUM(skips), 0) AS BIGINT),
+                COUNT(*) FILTER (WHERE status = '…')
+            FROM match",
+        )
+        .fetch_one(pool)
+        .await?;
+    let top_dogs = sqlx::query_as::<_, (Option<String>, String, i64)>(
+        "SELECT name, image_url, value FROM rating JOIN dog ON rating.dog_id = dog.id WHERE type = 'overall' ORDER BY value DESC LIMIT 5",
+    )
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(|(name, image_url, value)| (name.unwrap_or(image_url), value))
+    .collect();
+
+    // dogs people keep passing on, which usually means a blurry or confusing photo
+    let most_skipped = sqlx::query_as::<_, (Option<String>, String, i64, i64)>(
+        "SELECT name, image_url, skips, skips + decided FROM (
+            SELECT name, image_url, dog.id AS dog_id,
+                CAST(SUM(skips) AS BIGINT) AS skips,
+                COUNT(*) FILTER (WHERE status IN ('>', '<', '=')) AS decided
+            FROM match JOIN dog ON dog.id = match.dog_a_id OR dog.id = match.dog_b_id
+            GROUP BY dog.id, name, image_url
+        ) AS shown
+        WHERE skips > 0
+        ORDER BY CAST(skips AS REAL) / (skips + decided) DESC, dog_id
+        LIMIT 5",
+    )
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(|(name, image_url, skips, shown)| (name.unwrap_or(image_url), skips, shown))
+    .collect();
+
+    Ok(Stats {
+        approved_dogs,
+        unapproved_dogs,
+        named_dogs,
+        users,
+        users_with_email,
+        resolved_matches,
+        ties,
+        skips,
+        pending_matches,
+        total_xp,
+        top_dogs,
+        most_skipped,
+    })
+}
+
+// fs::rename doesn't work across the docker volume mounts
+fn move_file(from: &Path, to: &Path) -> Result<()> {
+    if fs::rename(from, to).is_ok() {
+        return Ok(());
+    }
+    fs::copy(from, to).with_context(|| format!("couldn't copy {:?} to {:?}", from, to))?;
+    fs::remove_file(from)?;
+    Ok(())
+}
@@ -0,0 +1,251 @@
+use axum::{
+    http::{header, HeaderValue, Request, StatusCode, Uri},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use flate2::{write::GzEncoder, Compression};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    fs::{self, Metadata},
+    io::{self, Write},
+    path::{Component, Path, PathBuf},
+    sync::{Mutex, OnceLock},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+// everything under assets/ is the app's fallback (see lib.rs), which none of the route layers
+// cover, so fetching a stylesheet or a dog photo never looks up a session or makes a user.
+//
+// files get a hash of their contents in the url (`/js/top-doggo.js` is linked as
+// `/js/top-doggo.1a2b3c4d.js`), so a deploy that changes a file changes its url too and the
+// hashed url can be cached for good. the hash is taken out again before ServeDir looks for the
+// file. anything requested without one (fonts from the stylesheet, dog photos) is cached for a
+// while and then checked with its ETag.
+
+const ASSETS_DIR: &str = "assets";
+const HASH_LENGTH: usize = 8;
+
+const CACHE_FOREVER: &str = "public, max-age=31536000, immutable";
+const CACHE_A_WHILE: &str = "public, max-age=3600";
+/// A hash from an older deploy still gets the file, but only until it's checked again
+const CACHE_UNTIL_CHECKED: &str = "no-cache";
+
+/// Served gzipped or brotli'd when the browser takes it, see precompress
+const PRECOMPRESSED: [&str; 3] = ["output.css", "js/top-doggo.js", "vendor/htmx.min.js"];
+
+/// The url to link `path` (relative to assets/) with. Files that aren't there are linked as is.
+pub fn url(path: &str) -> String {
+    match current_hash(path) {
+        Some(hash) => format!("/{}", with_hash(path, &hash)),
+        None => format!("/{}", path),
+    }
+}
+
+/// Hashes are kept until the file changes, so `just tww` rebuilding the stylesheet gets a new url
+fn current_hash(path: &str) -> Option<String> {
+    static HASHES: OnceLock<Mutex<HashMap<String, (SystemTime, String)>>> = OnceLock::new();
+    let file = Path::new(ASSETS_DIR).join(path);
+    let modified = fs::metadata(&file)
+        .and_then(|metadata| metadata.modified())
+        .ok()?;
+
+    let mut hashes = HASHES.get_or_init(Default::default).lock().unwrap();
+    if let Some((hashed_at, hash)) = hashes.get(path) {
+        if *hashed_at == modified {
+            return Some(hash.clone());
+        }
+    }
+    let hash = hash_file(&file)?;
+    hashes.insert(path.to_string(), (modified, hash.clone()));
+    Some(hash)
+}
+
+fn hash_file(path: &Path) -> Option<String> {
+    let contents = fs::read(path).ok()?;
+    let hex = hex::encode(Sha256::digest(contents));
+    Some(hex[..HASH_LENGTH].to_string())
+}
+
+/// `js/top-doggo.js` -> `js/top-doggo.<hash>.js`, files without an extension are left alone
+fn with_hash(path: &str, hash: &str) -> String {
+    match path.rsplit_once('.') {
+        Some((stem, extension)) if !stem.ends_with('/') && !extension.contains('/') => {
+            format!("{}.{}.{}", stem, hash, extension)
+        }
+        _ => path.to_string(),
+    }
+}
+
+/// `js/top-doggo.<hash>.js` -> (`js/top-doggo.js`, `<hash>`), anything else is left alone
+fn without_hash(path: &str) -> Option<(String, &str)> {
+    let (rest, extension) = path.rsplit_once('.')?;
+    let (stem, hash) = rest.rsplit_once('.')?;
+    let is_hash = hash.len() == HASH_LENGTH && hash.bytes().all(|byte| byte.is_ascii_hexdigit());
+    (is_hash && !stem.ends_with('/') && !extension.contains('/'))
+        .then(|| (format!("{}.{}", stem, extension), hash))
+}
+
+/// The file under assets/ a request path is for, if it's one ServeDir would serve
+fn local_file(path: &str) -> Option<PathBuf> {
+    let relative = Path::new(path.trim_start_matches('/'));
+    let normal = relative
+        .components()
+        .all(|component| matches!(component, Component::Normal(_)));
+    normal.then(|| Path::new(ASSETS_DIR).join(relative))
+}
+
+/// Weak, since the same file goes out gzipped, brotli'd or neither
+fn etag(metadata: &Metadata) -> Option<String> {
+    let modified = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+    Some(format!(
+        "W/\"{:x}-{:x}\"",
+        metadata.len(),
+        modified.as_millis()
+    ))
+}
+
+/// Runs in front of ServeDir (see lib.rs): takes the hash out of the url, answers If-None-Match,
+/// and says how long the file can be cached for
+pub async fn static_files<B>(mut req: Request<B>, next: Next<B>) -> Response {
+    let requested = req.uri().path().to_string();
+    let (path, hash) = match without_hash(&requested) {
+        Some((path, hash)) => (path, Some(hash)),
+        None => (requested.clone(), None),
+    };
+    if hash.is_some() {
+        let path_and_query = match req.uri().query() {
+            Some(query) => format!("{}?{}", path, query),
+            None => path.clone(),
+        };
+        let mut parts = req.uri().clone().into_parts();
+        parts.path_and_query = path_and_query.parse().ok();
+        if let Ok(uri) = Uri::from_parts(parts) {
+            *req.uri_mut() = uri;
+        }
+    }
+
+    // anything that isn't a file is left to ServeDir, which knows how to say no
+    let metadata = local_file(&path)
+        .and_then(|file| fs::metadata(file).ok())
+        .filter(Metadata::is_file);
+    let Some(etag) = metadata.as_ref().and_then(etag) else {
+        return next.run(req).await;
+    };
+    let cache_control = match hash {
+        Some(hash) if current_hash(path.trim_start_matches('/')).as_deref() == Some(hash) => {
+            CACHE_FOREVER
+        }
+        Some(_) => CACHE_UNTIL_CHECKED,
+        None => CACHE_A_WHILE,
+    };
+
+    let not_modified = req
+        .headers()
+        .get(header::IF_NONE_MATCH)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.split(',').any(|tag| tag.trim() == etag));
+    let mut response = if not_modified {
+        StatusCode::NOT_MODIFIED.into_response()
+    } else {
+        next.run(req).await
+    };
+
+    if response.status().is_success() || response.status() == StatusCode::NOT_MODIFIED {
+        let headers = response.headers_mut();
+        headers.insert(header::ETAG, HeaderValue::from_str(&etag).unwrap());
+        headers.insert(
+            header::CACHE_CONTROL,
+            HeaderValue::from_static(cache_control),
+        );
+        headers.insert(header::VARY, HeaderValue::from_static("accept-encoding"));
+    }
+    response
+}
+
+/// Writes the .gz and .br copies ServeDir picks from, for whichever of the PRECOMPRESSED files
+/// changed since they were last written. Run at startup (see main.rs).
+pub fn precompress() -> io::Result<()> {
+    for path in PRECOMPRESSED {
+        let file = Path::new(ASSETS_DIR).join(path);
+        let Ok(contents) = fs::read(&file) else {
+            continue;
+        };
+        let modified = fs::metadata(&file)?.modified()?;
+
+        let gzip = |contents: &[u8]| -> io::Result<Vec<u8>> {
+            let mut encoder = GzEncoder::new(vec![], Compression::best());
+            encoder.write_all(contents)?;
+            encoder.finish()
+        };
+        let brotli = |contents: &[u8]| -> io::Result<Vec<u8>> {
+            let mut compressed = vec![];
+            let mut encoder = brotli::CompressorWriter::new(&mut compressed, 4096, 11, 22);
+            encoder.write_all(contents)?;
+            drop(encoder);
+            Ok(compressed)
+        };
+        for (extension, compress) in [("gz", &gzip as &dyn Fn(&[u8]) -> _), ("br", &brotli)] {
+            let compressed_file = PathBuf::from(format!("{}.{}", file.display(), extension));
+            let up_to_date = fs::metadata(&compressed_file)
+                .and_then(|metadata| metadata.modified())
+                .is_ok_and(|compressed_at| compressed_at >= modified);
+            if up_to_date {
+                continue;
+            }
+            // written next to it first, so nobody gets half a file
+            let partial = PathBuf::from(format!("{}.partial", compressed_file.display()));
+            fs::write(&partial, compress(&contents)?)?;
+            fs::rename(&partial, &compressed_file)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hashes_go_before_the_extension() {
+        assert_eq!(
+            with_hash("js/top-doggo.js", "1a2b3c4d"),
+            "js/top-doggo.1a2b3c4d.js"
+        );
+        assert_eq!(
+            with_hash("vendor/htmx.min.js", "1a2b3c4d"),
+            "vendor/htmx.min.1a2b3c4d.js"
+        );
+        assert_eq!(with_hash("LICENSE", "1a2b3c4d"), "LICENSE");
+    }
+
+    #[test]
+    fn hashes_come_back_out() {
+        assert_eq!(
+            without_hash("/vendor/htmx.min.1a2b3c4d.js"),
+            Some(("/vendor/htmx.min.js".to_string(), "1a2b3c4d"))
+        );
+        assert_eq!(without_hash("/vendor/htmx.min.js"), None);
+        assert_eq!(without_hash("/images/12.jpg"), None);
+        // only hex counts
+        assert_eq!(without_hash("/js/top-doggo.notahash.js"), None);
+    }
+
+    #[test]
+    fn only_files_under_assets_are_looked_at() {
+        assert_eq!(
+            local_file("/fonts/shantell-sans/a.woff2"),
+            Some(PathBuf::from("assets/fonts/shantell-sans/a.woff2"))
+        );
+        assert_eq!(local_file("/../Cargo.toml"), None);
+        assert_eq!(
+            local_file("/images/./5.jpg"),
+            Some(PathBuf::from("assets/images/5.jpg"))
+        );
+    }
+
+    #[test]
+    fn missing_files_are_linked_without_a_hash() {
+        assert_eq!(url("js/no-such-file.js"), "/js/no-such-file.js");
+    }
+}
@@ -1,27 +1,31 @@
-use crate::{AppContext, AppState};
+use crate::{db, error::AppError, session_cookie::SessionKeys, AppContext, AppState};
 use axum::{
-    extract::State,
-    http::{self, Request, StatusCode},
+    extract::{ConnectInfo, State},
+    http::{self, HeaderMap, Request},
     middleware::Next,
     response::Response,
 };
-use axum_client_ip::XForwardedFor;
+use axum_client_ip::RightmostXForwardedFor;
 use chrono::{Duration, Utc};
-use sqlx::{Pool, Sqlite};
-use uuid::Uuid;
+use std::net::{IpAddr, SocketAddr};
 
 // probably not worth renaming (it would sign everybody out)
 const AUTH_TOKEN_COOKIE_NAME: &str = "best_doggo_auth_token";
 
-pub async fn auth<B>(
-    State(state): State<AppState>,
-    // secure_client_ip: SecureClientIp,
-    XForwardedFor(client_ips): XForwardedFor,
-    mut req: Request<B>,
-    next: Next<B>,
-) -> Result<Response, StatusCode> {
-    let original_auth_token = req
-        .headers()
+/// The address the proxy in front of the app (traefik) got the request from, which it adds to the
+/// end of X-Forwarded-For. Everything before it came from the client, so it could say anything.
+/// Without a proxy it's the connection's address.
+pub fn client_ip(
+    forwarded_for: Option<RightmostXForwardedFor>,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+) -> Option<IpAddr> {
+    forwarded_for
+        .map(|RightmostXForwardedFor(ip)| ip)
+        .or(connect_info.map(|ConnectInfo(addr)| addr.ip()))
+}
+
+fn auth_cookie(headers: &HeaderMap) -> String {
+    headers
         .get(http::header::COOKIE)
         .and_then(|cookie_header| {
             cookie_header.to_str().ok().and_then(|cookie_str| {
@@ -35,91 +39,102 @@ pub async fn auth<B>(
                 })
             })
         })
-        .unwrap_or_default();
-    println!("-------------------------------------------------");
-    // println!(
-    //     "{}: authing request, original auth token is {}",
-    //     original_auth_token, original_auth_token
-    // );
+        .unwrap_or_default()
+}
+
+/// Whose signed session came with the request, without signing anybody up or swapping
+/// old sessions for new ones like `auth` does
+pub async fn session_user_id(
+    state: &AppState,
+    headers: &HeaderMap,
+) -> Result<Option<i64>, sqlx::Error> {
+    match state.session_keys.verify(&auth_cookie(headers)) {
+        Some(verified) => db::sessions::find_user(&state.pool, &verified.token).await,
+        None => Ok(None),
+    }
+}
+
+pub async fn auth<B>(
+    State(state): State<AppState>,
+    forwarded_for: Option<RightmostXForwardedFor>,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+    mut req: Request<B>,
+    next: Next<B>,
+) -> Result<Response, AppError> {
+    let original_auth_cookie = auth_cookie(req.headers());
+
+    let client_ip = client_ip(forwarded_for, connect_info);
 
     let mut new_auth_token: Option<String> = None;
 
-    let user_id = sqlx::query!(
-        "SELECT user_id FROM session WHERE token = $1",
-        original_auth_token
-    )
-    .fetch_optional(&state.pool)
-    .await;
-    let user_id = if let Ok(Some(record)) = user_id {
-        let used_email_token = sqlx::query!("SELECT user.id AS email_haver_id FROM email_token INNER JOIN user ON email_token.email = user.email WHERE email_token.sender_id = $1 AND email_token.used = true AND email_token.created_at > datetime('now', '-30 minutes')", record.user_id)
-            .fetch_optional(&state.pool).await.unwrap();
-
-        if let Some(used_email_token) = used_email_token {
-            if used_email_token.email_haver_id != record.user_id {
+    // cookies that aren't signed by us never get looked up (see session_cookie.rs)
+    let session_user_id = if let Some(verified) = state.session_keys.verify(&original_auth_cookie) {
+        let session_user_id = db::sessions::find_user(&state.pool, &verified.token).await?;
+        if verified.stale && session_user_id.is_some() {
+            new_auth_token = Some(verified.token);
+        }
+        session_user_id
+    } else if db::sessions::is_legacy(&original_auth_cookie) {
+        // the same session, just signed, so requests racing in with the old cookie all agree
+        let session_user_id = db::sessions::find_user(&state.pool, &original_auth_cookie).await?;
+        if session_user_id.is_some() {
+            new_auth_token = Some(original_auth_cookie.clone());
+        }
+        session_user_id
+    } else {
+        None
+    };
+
+    let user_id = if let Some(session_user_id) = session_user_id {
+        let email_haver_id =
+            db::tokens::logged_in_user_for_sender(&state.pool, session_user_id).await?;
+
+        if let Some(email_haver_id) = email_haver_id {
+            if email_haver_id != session_user_id {
                 // println!(
-                //     "{}: user {} found used email_token, setting new_auth_token to email haver {}",
-                //     original_auth_token, record.user_id, used_email_token.email_haver_id
+                //     "user {} found used email_token, setting new_auth_token to email haver {}",
+                //     session_user_id, email_haver_id
                 // );
-                new_auth_token =
-                    Some(create_new_auth_token(&state.pool, used_email_token.email_haver_id).await);
-                used_email_token.email_haver_id
+                new_auth_token = Some(db::sessions::create(&state.pool, email_haver_id).await?);
+                email_haver_id
             } else {
-                // println!("{}: user {} found used email_token, but we're already the email haver so not doing anything fancy", original_auth_token, record.user_id);
-                record.user_id
+                // println!("user {} found used email_token, but we're already the email haver so not doing anything fancy", session_user_id);
+                session_user_id
             }
         } else {
-            // println!("{}: user {}", original_auth_token, record.user_id);
-            record.user_id
+            // println!("user {}", session_user_id);
+            session_user_id
         }
     } else {
-        let new_user = sqlx::query!("INSERT INTO user DEFAULT VALUES RETURNING id")
-            .fetch_one(&state.pool)
-            .await
-            .unwrap();
-        let new_user_id = new_user.id;
+        let new_user_id = db::users::create(&state.pool, client_ip).await?;
 
-        new_auth_token = Some(create_new_auth_token(&state.pool, new_user_id).await);
+        new_auth_token = Some(db::sessions::create(&state.pool, new_user_id).await?);
 
-        // println!(
-        //     "{}: created new user {} with token {:?}",
-        //     original_auth_token, new_user_id, new_auth_token
-        // );
+        // println!("created new user {}", new_user_id);
 
         new_user_id
     };
 
-    let user_email: Option<String> = sqlx::query!("SELECT email FROM user WHERE id=$1", user_id)
-        .fetch_one(&state.pool)
-        .await
-        .unwrap()
-        .email;
-
-    // let client_ip = Some(secure_client_ip.0);
-    let client_ip = client_ips.first().cloned();
+    let user_email = db::users::email(&state.pool, user_id).await?;
 
     let app_context = AppContext {
         user_id,
         user_email,
         client_ip,
     };
-    println!("{:?}", app_context);
     req.extensions_mut().insert(app_context);
 
     let mut response = next.run(req).await;
 
     if let Some(token) = new_auth_token {
-        println!(
-            "{}: auth token already set by handler: {:?}",
-            original_auth_token,
-            response.headers().get(http::header::SET_COOKIE)
-        );
         // don't want to overwrite any set-cookie header set by the handler
         if response.headers().get(http::header::SET_COOKIE).is_none() {
-            println!("{}: no set-cookie header set by handler, so setting cookie to new_auth_token ({:?})", original_auth_token, token);
             // Set the updated cookie in the response
             response.headers_mut().insert(
                 http::header::SET_COOKIE,
-                create_new_auth_cookie(token).parse().unwrap(),
+                create_new_auth_cookie(&state.session_keys, &token)
+                    .parse()
+                    .unwrap(),
             );
         }
     }
@@ -127,24 +142,21 @@ pub async fn auth<B>(
     Ok(response)
 }
 
-pub fn create_new_auth_cookie(token: String) -> String {
+pub fn create_new_auth_cookie(session_keys: &SessionKeys, token: &str) -> String {
     let expiration = Utc::now() + Duration::days(365 * 10);
     let expiration = expiration.format("%a, %d %b %Y %H:%M:%S GMT");
     format!(
         "{}={}; Path=/; HttpOnly; Secure; SameSite=Strict; Expires={}",
-        AUTH_TOKEN_COOKIE_NAME, token, expiration
+        AUTH_TOKEN_COOKIE_NAME,
+        session_keys.sign(token),
+        expiration
     )
 }
 
-pub async fn create_new_auth_token(pool: &Pool<Sqlite>, user_id: i64) -> String {
-    let new_token = Uuid::new_v4().to_string();
-    println!("creating new auth token {} for user {}", new_token, user_id);
-    let _ = sqlx::query!(
-        "INSERT INTO session (token, user_id) VALUES ($1, $2)",
-        new_token,
-        user_id
+// for when the session's user is gone, the next request gets a fresh anonymous user
+pub fn clear_auth_cookie() -> String {
+    format!(
+        "{}=; Path=/; HttpOnly; Secure; SameSite=Strict; Expires=Thu, 01 Jan 1970 00:00:00 GMT",
+        AUTH_TOKEN_COOKIE_NAME
     )
-    .fetch_one(pool)
-    .await;
-    return new_token;
 }
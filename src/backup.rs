@@ -0,0 +1,354 @@
+use crate::{db::Backend, metrics};
+use anyhow::{bail, Context, Result};
+use chrono::{NaiveDate, NaiveDateTime, Utc};
+use sqlx::{Any, Pool, SqlitePool};
+use std::{
+    collections::HashSet,
+    env, fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+// snapshots are made with `VACUUM INTO`, which copies a consistent view of the database
+// without blocking the game, and are named like top-doggo-20240801-130500.db (UTC).
+// that makes them sqlite only, postgres has pg_dump for this
+
+const FILE_PREFIX: &str = "top-doggo-";
+const FILE_SUFFIX: &str = ".db";
+const TIMESTAMP_FORMAT: &str = "%Y%m%d-%H%M%S";
+
+#[derive(Debug)]
+pub struct Backup {
+    pub file_name: String,
+    pub path: PathBuf,
+    pub taken_at: NaiveDateTime,
+    pub size: u64,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Retention {
+    /// The newest backups are always kept
+    pub keep_last: usize,
+    /// As is the newest backup of each of the most recent this many days
+    pub keep_daily: usize,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Schedule {
+    pub interval: Duration,
+    pub retention: Retention,
+}
+
+impl Schedule {
+    /// Backups only run on a schedule when BACKUP_INTERVAL_MINUTES is set
+    pub fn from_env() -> Option<Self> {
+        let minutes: u64 = env::var("BACKUP_INTERVAL_MINUTES").ok()?.parse().ok()?;
+        if minutes == 0 {
+            return None;
+        }
+        let number = |name: &str, default: usize| {
+            env::var(name)
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(default)
+        };
+        Some(Self {
+            interval: Duration::from_secs(minutes * 60),
+            retention: Retention {
+                keep_last: number("BACKUP_KEEP_LAST", 24),
+                keep_daily: number("BACKUP_KEEP_DAILY", 14),
+            },
+        })
+    }
+}
+
+pub fn backups_dir_from_env() -> PathBuf {
+    env::var("BACKUP_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| PathBuf::from("./db/backups"))
+}
+
+pub async fn create_backup(pool: &Pool<Any>, dir: &Path) -> Result<Backup> {
+    if Backend::of(pool) != Backend::Sqlite {
+        bail!("backups only work with sqlite, use pg_dump for postgres");
+    }
+    fs::create_dir_all(dir).with_context(|| format!("couldn't create {:?}", dir))?;
+
+    let taken_at = Utc::now().naive_utc();
+    let file_name = format!(
+        "{}{}{}",
+        FILE_PREFIX,
+        taken_at.format(TIMESTAMP_FORMAT),
+        FILE_SUFFIX
+    );
+    let path = dir.join(&file_name);
+    // written under another name first so a half-finished backup is never listed
+    let partial_path = dir.join(format!("{}.partial", file_name));
+    let _ = fs::remove_file(&partial_path);
+
+    // as a uri with mode=rwc, otherwise an in-memory database "backs up" into memory
+    sqlx::query("VACUUM INTO $1")
+        .bind(format!("file:{}?mode=rwc", partial_path.to_string_lossy()))
+        .execute(pool)
+        .await
+        .context("VACUUM INTO failed")?;
+    fs::rename(&partial_path, &path)?;
+
+    Ok(Backup {
+        size: fs::metadata(&path)?.len(),
+        file_name,
+        path,
+        taken_at,
+    })
+}
+
+/// Newest first. A missing directory just means there aren't any backups yet.
+pub fn list_backups(dir: &Path) -> Result<Vec<Backup>> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(error) => return Err(error).with_context(|| format!("couldn't read {:?}", dir)),
+    };
+    let mut backups = vec![];
+    for entry in entries {
+        let entry = entry?;
+        let file_name = entry.file_name().to_string_lossy().to_string();
+        let Some(taken_at) = parse_file_name(&file_name) else {
+            continue;
+        };
+        backups.push(Backup {
+            size: entry.metadata()?.len(),
+            path: entry.path(),
+            file_name,
+            taken_at,
+        });
+    }
+    backups.sort_by_key(|backup| std::cmp::Reverse(backup.taken_at));
+    Ok(backups)
+}
+
+pub fn parse_file_name(file_name: &str) -> Option<NaiveDateTime> {
+    let timestamp = file_name
+        .strip_prefix(FILE_PREFIX)?
+        .strip_suffix(FILE_SUFFIX)?;
+    NaiveDateTime::parse_from_str(timestamp, TIMESTAMP_FORMAT).ok()
+}
+
+/// Which of `backups` (newest first) the retention rules let go of
+pub fn backups_to_delete(backups: &[Backup], retention: Retention) -> Vec<&Backup> {
+    let mut days: HashSet<NaiveDate> = HashSet::new();
+    backups
+        .iter()
+        .enumerate()
+        .filter(|(i, backup)| {
+            // the first backup seen for a day is that day's newest
+            let newest_of_its_day = !days.contains(&backup.taken_at.date());
+            let keep_for_day = newest_of_its_day && days.len() < retention.keep_daily;
+            if keep_for_day {
+                days.insert(backup.taken_at.date());
+            }
+            *i >= retention.keep_last && !keep_for_day
+        })
+        .map(|(_, backup)| backup)
+        .collect()
+}
+
+/// Deletes the backups the retention rules don't keep and returns their file names
+pub fn apply_retention(dir: &Path, retention: Retention) -> Result<Vec<String>> {
+    let backups = list_backups(dir)?;
+    let mut deleted = vec![];
+    for backup in backups_to_delete(&backups, retention) {
+        fs::remove_file(&backup.path)?;
+        deleted.push(backup.file_name.clone());
+    }
+    Ok(deleted)
+}
+
+/// Takes a backup every `schedule.interval` until the task is dropped
+pub async fn run_schedule(pool: Pool<Any>, dir: PathBuf, schedule: Schedule) {
+    let mut interval = tokio::time::interval_at(
+        tokio::time::Instant::now() + schedule.interval,
+        schedule.interval,
+    );
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        interval.tick().await;
+        match create_backup(&pool, &dir).await {
+            Ok(backup) => {
+                println!("backed up the database to {:?}", backup.path);
+                metrics::BACKUPS_TOTAL.with_label_values(&["ok"]).inc();
+                metrics::LAST_BACKUP_TIMESTAMP_SECONDS.set(backup.taken_at.and_utc().timestamp());
+            }
+            Err(error) => {
+                eprintln!("backup failed: {:#}", error);
+                metrics::BACKUPS_TOTAL.with_label_values(&["failed"]).inc();
+                continue;
+            }
+        }
+        match apply_retention(&dir, schedule.retention) {
+            Ok(deleted) => {
+                for file_name in deleted {
+                    println!("deleted old backup {}", file_name);
+                }
+            }
+            Err(error) => eprintln!("couldn't clean up old backups: {:#}", error),
+        }
+    }
+}
+
+/// The file behind a `sqlite:` DATABASE_URL
+pub fn database_path(database_url: &str) -> Result<PathBuf> {
+    let path = database_url
+        .strip_prefix("sqlite://")
+        .or_else(|| database_url.strip_prefix("sqlite:"))
+        .with_context(|| format!("{} isn't a sqlite url", database_url))?;
+    let path = path.split('?').next().unwrap_or_default();
+    if path.is_empty() || path == ":memory:" {
+        bail!("{} isn't a database file", database_url);
+    }
+    Ok(PathBuf::from(path))
+}
+
+/// Replaces the database with a backup. The server has to be stopped first.
+/// The current database is saved next to it (and returned) in case the backup was the wrong one.
+pub async fn restore(backup_path: &Path, database_path: &Path) -> Result<Option<PathBuf>> {
+    let backup = SqlitePool::connect(&format!("sqlite:{}?mode=ro", backup_path.to_string_lossy()))
+        .await
+        .with_context(|| format!("couldn't open {:?}", backup_path))?;
+    let integrity: String = sqlx::query_scalar("PRAGMA integrity_check")
+        .fetch_one(&backup)
+        .await?;
+    backup.close().await;
+    if integrity != "ok" {
+        bail!("{:?} is corrupt: {}", backup_path, integrity);
+    }
+
+    let mut saved_path = None;
+    if database_path.exists() {
+        let path = database_path.with_extension(format!(
+            "before-restore-{}.db",
+            Utc::now().format(TIMESTAMP_FORMAT)
+        ));
+        // VACUUM INTO picks up anything still sitting in the write-ahead log
+        let current =
+            SqlitePool::connect(&format!("sqlite:{}", database_path.to_string_lossy())).await?;
+        sqlx::query("VACUUM INTO $1")
+            .bind(path.to_string_lossy().to_string())
+            .execute(&current)
+            .await
+            .context("couldn't save the current database")?;
+        current.close().await;
+        saved_path = Some(path);
+    }
+
+    let temp_path = database_path.with_extension("restoring");
+    fs::copy(backup_path, &temp_path)?;
+    for suffix in ["-wal", "-shm"] {
+        let mut sidecar = database_path.as_os_str().to_owned();
+        sidecar.push(suffix);
+        let _ = fs::remove_file(sidecar);
+    }
+    fs::rename(&temp_path, database_path)?;
+    Ok(saved_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn backup_at(timestamp: &str) -> Backup {
+        let file_name = format!("{}{}{}", FILE_PREFIX, timestamp, FILE_SUFFIX);
+        Backup {
+            taken_at: parse_file_name(&file_name).unwrap(),
+            path: PathBuf::from(&file_name),
+            file_name,
+            size: 0,
+        }
+    }
+
+    fn kept(backups: &[Backup], retention: Retention) -> Vec<&str> {
+        let deleted = backups_to_delete(backups, retention);
+        backups
+            .iter()
+            .filter(|backup| !deleted.iter().any(|d| d.file_name == backup.file_name))
+            .map(|backup| &backup.file_name[FILE_PREFIX.len()..FILE_PREFIX.len() + 15])
+            .collect()
+    }
+
+    #[test]
+    fn file_names_round_trip() {
+        let backup = backup_at("20240801-130500");
+        assert_eq!(backup.file_name, "top-doggo-20240801-130500.db");
+        assert_eq!(
+            backup.taken_at.to_string(),
+            "2024-08-01 13:05:00".to_string()
+        );
+        assert!(parse_file_name("top-doggo-20240801-130500.db.partial").is_none());
+        assert!(parse_file_name("top-doggo.db").is_none());
+    }
+
+    #[test]
+    fn retention_keeps_the_newest_and_one_per_day() {
+        let backups = [
+            "20240803-120000",
+            "20240803-060000",
+            "20240803-000000",
+            "20240802-180000",
+            "20240802-120000",
+            "20240801-180000",
+            "20240731-180000",
+        ]
+        .map(backup_at);
+
+        assert_eq!(
+            kept(
+                &backups,
+                Retention {
+                    keep_last: 2,
+                    keep_daily: 3
+                }
+            ),
+            [
+                "20240803-120000",
+                "20240803-060000",
+                "20240802-180000",
+                "20240801-180000"
+            ]
+        );
+        assert_eq!(
+            kept(
+                &backups,
+                Retention {
+                    keep_last: 0,
+                    keep_daily: 0
+                }
+            ),
+            Vec::<&str>::new()
+        );
+        assert_eq!(
+            kept(
+                &backups,
+                Retention {
+                    keep_last: 10,
+                    keep_daily: 0
+                }
+            )
+            .len(),
+            7
+        );
+    }
+
+    #[test]
+    fn database_paths() {
+        assert_eq!(
+            database_path("sqlite:db/top-doggo.db").unwrap(),
+            PathBuf::from("db/top-doggo.db")
+        );
+        assert_eq!(
+            database_path("sqlite:///db/top-doggo.db?mode=rwc").unwrap(),
+            PathBuf::from("/db/top-doggo.db")
+        );
+        assert!(database_path("sqlite::memory:").is_err());
+        assert!(database_path("postgres://localhost/top_doggo").is_err());
+    }
+}
@@ -0,0 +1,339 @@
+use anyhow::Result;
+use clap::{Parser, Subcommand};
+use dotenv::dotenv;
+use sqlx::{Any, Pool};
+use std::{
+    fs::{self, File},
+    io::{self, BufReader, BufWriter},
+    path::PathBuf,
+};
+use top_doggo::{
+    admin::{self, export, import},
+    backup,
+    db::{self, log::Event},
+    routers::{doggo::elo::recompute_ratings, tournaments::bracket::Format},
+};
+
+/// Operate on a Top Doggo database (sqlite or postgres) without opening a sqlite3 or psql session
+#[derive(Parser)]
+#[command(name = "top-doggo-admin")]
+struct Cli {
+    #[arg(long, env = "DATABASE_URL")]
+    database_url: String,
+    /// Where approved dog photos are served from
+    #[arg(long, default_value = "./assets/images")]
+    images_dir: PathBuf,
+    /// Where uploads wait to be approved
+    #[arg(long, default_value = "./unapproved")]
+    unapproved_dir: PathBuf,
+    /// Where database backups are kept
+    #[arg(long, env = "BACKUP_DIR", default_value = "./db/backups")]
+    backups_dir: PathBuf,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Let an uploaded dog into the game
+    Approve { dog_id: i64 },
+    /// Delete an uploaded dog that hasn't been approved
+    Reject { dog_id: i64 },
+    /// Change (or with no name, clear) a dog's name
+    Rename { dog_id: i64, name: Option<String> },
+    /// Add every image in a folder or .zip/.tar/.tar.gz as an approved dog
+    Import {
+        path: PathBuf,
+        /// JSON or CSV of file names to dog names (a manifest.json/.csv inside the folder works too)
+        #[arg(long)]
+        manifest: Option<PathBuf>,
+    },
+    /// Dump users, dogs, matches, ratings, xp and logs as versioned JSON Lines
+    /// (dog photos aren't included, copy the images folder too)
+    ExportData {
+        /// Where to write the export, or stdout if left out
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+        /// Leave out emails and IP addresses
+        #[arg(long)]
+        anonymize: bool,
+    },
+    /// Load an export-data file into an empty database
+    ImportData { path: PathBuf },
+    /// Fold one user's votes, xp, sessions and email into another
+    MergeUsers {
+        from_user_id: i64,
+        into_user_id: i64,
+    },
+    /// Delete a user and their votes, and anonymize their log entries
+    DeleteUser { user_id: i64 },
+    /// Stop a user's votes from counting toward the overall ratings, including past ones
+    Quarantine { user_id: i64 },
+    /// Let a quarantined or flagged user's votes count in full again
+    Release { user_id: i64 },
+    /// Delete every vote a user has cast and replay the ratings without them
+    RollBackVotes { user_id: i64 },
+    /// Start a bracket tournament between the top rated dogs, which everybody can vote in
+    StartTournament {
+        name: String,
+        /// How many dogs to put in the bracket (4, 8, 16 or 32)
+        #[arg(long, default_value_t = 8)]
+        size: usize,
+        /// Give every dog a second chance in a losers bracket
+        #[arg(long)]
+        double_elimination: bool,
+        /// How long each round's voting stays open
+        #[arg(long, default_value_t = 24)]
+        window_hours: i64,
+    },
+    /// Replay every vote to rebuild the overall and personal ratings
+    RecomputeRatings,
+    /// Delete idle anonymous users, expired magic link tokens and idle rate limit buckets
+    Prune {
+        #[arg(long, default_value_t = 30)]
+        older_than_days: u32,
+    },
+    /// Print some numbers about the dog show
+    Stats,
+    /// Snapshot the database into the backups folder
+    Backup,
+    /// List the snapshots in the backups folder, newest first
+    Backups,
+    /// Replace the database with a snapshot (stop the server first)
+    Restore { backup: PathBuf },
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    dotenv().ok();
+    let cli = Cli::parse();
+
+    // the database is about to be swapped out, so don't open (or migrate) it here
+    if let Command::Restore { backup } = &cli.command {
+        let database_path = backup::database_path(&cli.database_url)?;
+        if let Some(saved_path) = backup::restore(backup, &database_path).await? {
+            println!("Saved the old database to {:?}", saved_path);
+        }
+        println!("Restored {:?} from {:?}", database_path, backup);
+        return Ok(());
+    }
+
+    let pool = db::connect(&cli.database_url).await?;
+    db::migrate(&pool).await?;
+
+    match cli.command {
+        Command::Approve { dog_id } => {
+            admin::approve_dog(&pool, dog_id, &cli.unapproved_dir, &cli.images_dir).await?;
+            log(&pool, Event::ApproveDog { dog_id }).await?;
+            println!("Approved dog {}", dog_id);
+        }
+        Command::Reject { dog_id } => {
+            admin::reject_dog(&pool, dog_id, &cli.unapproved_dir).await?;
+            log(&pool, Event::RejectDog { dog_id }).await?;
+            println!("Rejected dog {}", dog_id);
+        }
+        Command::Rename { dog_id, name } => {
+            admin::rename_dog(&pool, dog_id, name.as_deref()).await?;
+            println!("Renamed dog {} to {:?}", dog_id, name);
+            log(&pool, Event::RenameDog { dog_id, name }).await?;
+        }
+        Command::Import { path, manifest } => {
+            let mut files = import::read_path(&path)?;
+            let mut names = import::take_manifest(&mut files)?.unwrap_or_default();
+            if let Some(manifest) = manifest {
+                names = import::parse_manifest(&manifest.to_string_lossy(), &fs::read(&manifest)?)?;
+            }
+
+            let results = import::import_dogs(&pool, files, &names, &cli.images_dir).await;
+            let mut imported = 0;
+            for result in results.iter() {
+                match &result.outcome {
+                    Ok(dog) => {
+                        imported += 1;
+                        println!(
+                            "{} -> dog {} {}",
+                            result.file,
+                            dog.dog_id,
+                            dog.name.as_deref().unwrap_or("")
+                        );
+                    }
+                    Err(error) => println!("{} FAILED: {}", result.file, error),
+                }
+            }
+            println!("Imported {} of {} files", imported, results.len());
+            log(
+                &pool,
+                Event::ImportDogs {
+                    archive: path.display().to_string(),
+                    imported,
+                    files: results.len(),
+                },
+            )
+            .await?;
+        }
+        Command::ExportData { output, anonymize } => {
+            let counts = match &output {
+                Some(output) => {
+                    let mut file = BufWriter::new(File::create(output)?);
+                    export::export(&pool, &mut file, anonymize).await?
+                }
+                None => export::export(&pool, &mut io::stdout().lock(), anonymize).await?,
+            };
+            // stdout might be the export itself
+            eprintln!(
+                "Exported {} users, {} dogs, {} rankings, {} matches, {} tournaments, {} tournament votes, {} ratings and {} log entries",
+                counts.users,
+                counts.dogs,
+                counts.rankings,
+                counts.matches,
+                counts.tournaments,
+                counts.bracket_votes,
+                counts.ratings,
+                counts.logs
+            );
+        }
+        Command::ImportData { path } => {
+            let counts = export::import(&pool, BufReader::new(File::open(&path)?)).await?;
+            println!(
+                "Imported {} users, {} dogs, {} rankings, {} matches, {} tournaments, {} tournament votes, {} ratings and {} log entries",
+                counts.users,
+                counts.dogs,
+                counts.rankings,
+                counts.matches,
+                counts.tournaments,
+                counts.bracket_votes,
+                counts.ratings,
+                counts.logs
+            );
+        }
+        Command::MergeUsers {
+            from_user_id,
+            into_user_id,
+        } => {
+            admin::merge_users(&pool, from_user_id, into_user_id).await?;
+            log(
+                &pool,
+                Event::MergeUsers {
+                    from_user_id,
+                    into_user_id,
+                },
+            )
+            .await?;
+            println!("Merged user {} into user {}", from_user_id, into_user_id);
+        }
+        Command::DeleteUser { user_id } => {
+            admin::delete_user(&pool, user_id).await?;
+            log(&pool, Event::DeleteUser { user_id }).await?;
+            println!("Deleted user {}", user_id);
+        }
+        Command::Quarantine { user_id } => {
+            admin::quarantine_user(&pool, user_id).await?;
+            log(&pool, Event::QuarantineUser { user_id }).await?;
+            println!("Quarantined user {}", user_id);
+        }
+        Command::Release { user_id } => {
+            admin::release_user(&pool, user_id).await?;
+            log(&pool, Event::ReleaseUser { user_id }).await?;
+            println!("Released user {}", user_id);
+        }
+        Command::RollBackVotes { user_id } => {
+            let votes = admin::roll_back_votes(&pool, user_id).await?;
+            log(&pool, Event::RollBackVotes { user_id, votes }).await?;
+            println!("Rolled back {} votes by user {}", votes, user_id);
+        }
+        Command::StartTournament {
+            name,
+            size,
+            double_elimination,
+            window_hours,
+        } => {
+            let format = if double_elimination {
+                Format::Double
+            } else {
+                Format::Single
+            };
+            let tournament_id =
+                admin::start_tournament(&pool, &name, format, size, window_hours).await?;
+            log(
+                &pool,
+                Event::StartTournament {
+                    tournament_id,
+                    name: name.clone(),
+                },
+            )
+            .await?;
+            println!("Started tournament {} ({})", tournament_id, name);
+        }
+        Command::RecomputeRatings => {
+            let mut transaction = pool.begin().await?;
+            let replayed = recompute_ratings(&mut transaction, None).await?;
+            transaction.commit().await?;
+            println!("Replayed {} matches", replayed);
+        }
+        Command::Prune { older_than_days } => {
+            let report = admin::prune(&pool, older_than_days).await?;
+            log(&pool, Event::Prune { older_than_days }).await?;
+            println!(
+                "Pruned {} anonymous users, {} expired email tokens and {} idle rate limit buckets",
+                report.users, report.email_tokens, report.rate_limit_buckets
+            );
+        }
+        Command::Stats => {
+            let stats = admin::stats(&pool).await?;
+            println!(
+                "Dogs: {} approved, {} awaiting approval, {} named",
+                stats.approved_dogs, stats.unapproved_dogs, stats.named_dogs
+            );
+            println!(
+                "Users: {} total, {} with an email, {} xp earned",
+                stats.users, stats.users_with_email, stats.total_xp
+            );
+            println!(
+                "Matches: {} decided ({} ties), {} skipped, {} waiting on a vote",
+                stats.resolved_matches, stats.ties, stats.skips, stats.pending_matches
+            );
+            println!("Top dogs:");
+            for (i, (name, rating)) in stats.top_dogs.iter().enumerate() {
+                println!("  {}. {} ({})", i + 1, name, rating);
+            }
+            if !stats.most_skipped.is_empty() {
+                println!("Most skipped (worth checking their photos):");
+                for (name, skips, shown) in &stats.most_skipped {
+                    println!(
+                        "  {}: {} of {} ({}%)",
+                        name,
+                        skips,
+                        shown,
+                        skips * 100 / shown
+                    );
+                }
+            }
+        }
+        Command::Backup => {
+            let backup = backup::create_backup(&pool, &cli.backups_dir).await?;
+            println!("Backed up to {:?} ({} bytes)", backup.path, backup.size);
+            log(
+                &pool,
+                Event::Backup {
+                    file_name: backup.file_name,
+                },
+            )
+            .await?;
+        }
+        Command::Backups => {
+            for backup in backup::list_backups(&cli.backups_dir)? {
+                println!("{}  {} bytes", backup.file_name, backup.size);
+            }
+        }
+        Command::Restore { .. } => unreachable!("handled before connecting"),
+    }
+
+    pool.close().await;
+    Ok(())
+}
+
+// the command line has no user or IP address to put on its log entries
+async fn log(pool: &Pool<Any>, event: Event) -> Result<()> {
+    db::log::record(pool, &event, None, None).await?;
+    Ok(())
+}
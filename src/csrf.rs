@@ -0,0 +1,124 @@
+use crate::error::AppError;
+use axum::{
+    http::{self, HeaderMap, Method, Request},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use chrono::{Duration, Utc};
+use uuid::Uuid;
+
+// cross-site request forgery protection, double-submit style. every visitor gets a random token in
+// a cookie that our own pages can read, the layout copies it into an X-CSRF-Token header on every
+// htmx request, and anything other than a GET has to send that header back matching the cookie.
+// another site can't read our cookies, and it can't add headers to a cross-site request without
+// CORS (which we don't allow), so a forged form submission never gets past this.
+
+const CSRF_COOKIE_NAME: &str = "top_doggo_csrf";
+const CSRF_HEADER_NAME: &str = "x-csrf-token";
+
+pub async fn csrf<B>(req: Request<B>, next: Next<B>) -> Response {
+    let token = cookie_token(req.headers());
+
+    let safe = matches!(*req.method(), Method::GET | Method::HEAD | Method::OPTIONS);
+    let mut response = if safe
+        || token
+            .as_deref()
+            .is_some_and(|token| header_matches(&req, token))
+    {
+        next.run(req).await
+    } else {
+        AppError::Forbidden("This page went stale. Refresh it and try again.".to_string())
+            .into_response()
+    };
+
+    // a missing token is issued on the way out, even when the request was turned away, so that
+    // the next click works
+    if token.is_none() {
+        let cookie = create_csrf_cookie(&Uuid::new_v4().simple().to_string());
+        response
+            .headers_mut()
+            .append(http::header::SET_COOKIE, cookie.parse().unwrap());
+    }
+    response
+}
+
+fn cookie_token(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get_all(http::header::COOKIE)
+        .iter()
+        .filter_map(|cookie_header| cookie_header.to_str().ok())
+        .flat_map(|cookie_str| cookie_str.split(';'))
+        .find_map(|cookie| {
+            let (name, value) = cookie.trim().split_once('=')?;
+            (name == CSRF_COOKIE_NAME).then(|| value.to_string())
+        })
+        // anything we didn't issue gets replaced
+        .filter(|token| token.len() == 32 && token.bytes().all(|byte| byte.is_ascii_hexdigit()))
+}
+
+fn header_matches<B>(req: &Request<B>, token: &str) -> bool {
+    let Some(header) = req.headers().get(CSRF_HEADER_NAME) else {
+        return false;
+    };
+    let header = header.as_bytes();
+    // compared in constant time, so the token can't be guessed a byte at a time
+    header.len() == token.len()
+        && header
+            .iter()
+            .zip(token.as_bytes())
+            .fold(0, |difference, (a, b)| difference | (a ^ b))
+            == 0
+}
+
+// not HttpOnly, the layout's script reads it
+fn create_csrf_cookie(token: &str) -> String {
+    let expiration = Utc::now() + Duration::days(365 * 10);
+    let expiration = expiration.format("%a, %d %b %Y %H:%M:%S GMT");
+    format!(
+        "{}={}; Path=/; Secure; SameSite=Strict; Expires={}",
+        CSRF_COOKIE_NAME, token, expiration
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TOKEN: &str = "0123456789abcdef0123456789abcdef";
+
+    fn request(cookie: &str, header: Option<&str>) -> Request<()> {
+        let mut request = Request::post("/pick-winner/tie").header(http::header::COOKIE, cookie);
+        if let Some(header) = header {
+            request = request.header(CSRF_HEADER_NAME, header);
+        }
+        request.body(()).unwrap()
+    }
+
+    #[test]
+    fn tokens_come_from_the_cookie() {
+        let cookie = format!("best_doggo_auth_token=abc; top_doggo_csrf={}", TOKEN);
+        let req = request(&cookie, None);
+        assert_eq!(cookie_token(req.headers()).as_deref(), Some(TOKEN));
+
+        // ones we couldn't have issued are ignored
+        assert_eq!(
+            cookie_token(request("top_doggo_csrf=abc", None).headers()),
+            None
+        );
+        assert_eq!(
+            cookie_token(request("best_doggo_auth_token=abc", None).headers()),
+            None
+        );
+    }
+
+    #[test]
+    fn headers_have_to_match_exactly() {
+        assert!(header_matches(&request("", Some(TOKEN)), TOKEN));
+        assert!(!header_matches(&request("", None), TOKEN));
+        assert!(!header_matches(&request("", Some(&TOKEN[1..])), TOKEN));
+        assert!(!header_matches(
+            &request("", Some("1123456789abcdef0123456789abcdef")),
+            TOKEN
+        ));
+    }
+}
@@ -0,0 +1,124 @@
+use super::Executor;
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct Dog {
+    pub id: i64,
+    pub image_url: String,
+    pub name: Option<String>,
+}
+
+pub async fn get(executor: impl Executor<'_>, dog_id: i64) -> Result<Option<Dog>, sqlx::Error> {
+    sqlx::query_as::<_, Dog>("SELECT id, image_url, name FROM dog WHERE id = $1")
+        .bind(dog_id)
+        .fetch_optional(executor)
+        .await
+}
+
+/// Only approved dogs' photos are served resized (see routers/images)
+pub async fn approved_image_url(
+    executor: impl Executor<'_>,
+    dog_id: i64,
+) -> Result<Option<String>, sqlx::Error> {
+    sqlx::query_scalar("SELECT image_url FROM dog WHERE id = $1 AND approved = TRUE")
+        .bind(dog_id)
+        .fetch_optional(executor)
+        .await
+}
+
+/// Fails if another dog already has the name
+pub async fn set_name(
+    executor: impl Executor<'_>,
+    dog_id: i64,
+    name: &str,
+    namer_id: i64,
+) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE dog SET (name, namer_id) = ($1, $2) WHERE id = $3")
+        .bind(name)
+        .bind(namer_id)
+        .bind(dog_id)
+        .execute(executor)
+        .await?;
+    Ok(())
+}
+
+/// Approved dogs that still have someone left to be paired against for this user
+pub async fn unfinished_ids(
+    executor: impl Executor<'_>,
+    user_id: i64,
+) -> Result<Vec<i64>, sqlx::Error> {
+    sqlx::query_scalar(
+        "SELECT id FROM dog WHERE approved = TRUE AND id NOT IN (SELECT dog_id AS id FROM user_finished_with_dog WHERE user_id = $1)",
+    )
+    .bind(user_id)
+    .fetch_all(executor)
+    .await
+}
+
+/// Approved dogs this user hasn't seen up against `dog_id` yet
+pub async fn unmatched_opponent_ids(
+    executor: impl Executor<'_>,
+    dog_id: i64,
+    user_id: i64,
+) -> Result<Vec<i64>, sqlx::Error> {
+    sqlx::query_scalar(
+        "SELECT id FROM dog WHERE approved = TRUE AND id <> $1 AND id NOT IN (SELECT dog_a_id AS id FROM match WHERE dog_b_id = $1 AND user_id = $2 UNION SELECT dog_b_id AS id FROM match WHERE dog_a_id = $1 AND user_id = $2)",
+    )
+    .bind(dog_id)
+    .bind(user_id)
+    .fetch_all(executor)
+    .await
+}
+
+/// Records that this user has seen `dog_id` against every other dog
+pub async fn finish(
+    executor: impl Executor<'_>,
+    user_id: i64,
+    dog_id: i64,
+) -> Result<(), sqlx::Error> {
+    sqlx::query("INSERT INTO user_finished_with_dog (user_id, dog_id) VALUES ($1, $2)")
+        .bind(user_id)
+        .bind(dog_id)
+        .execute(executor)
+        .await?;
+    Ok(())
+}
+
+/// An uploaded dog waiting for approval. Its image_url is filled in once the photo is saved,
+/// since the file is named after the id.
+pub async fn create_unapproved(
+    executor: impl Executor<'_>,
+    namer_id: i64,
+) -> Result<i64, sqlx::Error> {
+    sqlx::query_scalar(
+        "INSERT INTO dog (image_url, approved, namer_id) VALUES ('temp', false, $1) RETURNING id",
+    )
+    .bind(namer_id)
+    .fetch_one(executor)
+    .await
+}
+
+pub async fn set_image_url(
+    executor: impl Executor<'_>,
+    dog_id: i64,
+    image_url: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE dog SET image_url = $1 WHERE id = $2")
+        .bind(image_url)
+        .bind(dog_id)
+        .execute(executor)
+        .await?;
+    Ok(())
+}
+
+/// The user's newest upload that's still waiting on a name, for when naming it failed the first time
+pub async fn latest_unnamed_upload(
+    executor: impl Executor<'_>,
+    namer_id: i64,
+) -> Result<Option<i64>, sqlx::Error> {
+    sqlx::query_scalar(
+        "SELECT id FROM dog WHERE approved = FALSE AND namer_id = $1 AND name IS NULL ORDER BY id DESC LIMIT 1",
+    )
+    .bind(namer_id)
+    .fetch_optional(executor)
+    .await
+}
@@ -0,0 +1,374 @@
+use super::Executor;
+use chrono::{Days, NaiveDate};
+use serde::{Deserialize, Serialize};
+use sqlx::{any::AnyArguments, Arguments};
+use std::{fmt, net::IpAddr};
+
+// the audit log. each row's `details` is its event as JSON, and `action` is the event's kebab-case
+// name pulled out into its own column so it can be filtered on. rows from before events were typed,
+// and rows whose details were scrubbed when a user was deleted, don't parse as an `Event`.
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "action", rename_all = "kebab-case")]
+pub enum Event {
+    NameDog {
+        dog_id: i64,
+    },
+    Upload {
+        dog_id: i64,
+    },
+    SendMagicLink {
+        email: String,
+    },
+    /// `device_user_id` is the anonymous user on the device the link was opened on
+    LogIn {
+        email: String,
+        device_user_id: i64,
+    },
+    SignUp {
+        email: String,
+    },
+    RequestAccountDeletion,
+    DeleteAccount,
+    /// A vote that looked like it came from a bot or someone stuffing the ballot
+    VoteFlagged {
+        match_id: i64,
+        reason: String,
+    },
+    ApproveDog {
+        dog_id: i64,
+    },
+    RejectDog {
+        dog_id: i64,
+    },
+    RenameDog {
+        dog_id: i64,
+        name: Option<String>,
+    },
+    #[serde(rename = "import")]
+    ImportDogs {
+        archive: String,
+        imported: usize,
+        files: usize,
+    },
+    Backup {
+        file_name: String,
+    },
+    MergeUsers {
+        from_user_id: i64,
+        into_user_id: i64,
+    },
+    DeleteUser {
+        user_id: i64,
+    },
+    Prune {
+        older_than_days: u32,
+    },
+    QuarantineUser {
+        user_id: i64,
+    },
+    ReleaseUser {
+        user_id: i64,
+    },
+    RollBackVotes {
+        user_id: i64,
+        votes: u64,
+    },
+    StartTournament {
+        tournament_id: i64,
+        name: String,
+    },
+}
+
+impl Event {
+    /// Every action, in the order the admin log viewer lists them
+    pub const ACTIONS: [&'static str; 20] = [
+        "name-dog",
+        "upload",
+        "send-magic-link",
+        "log-in",
+        "sign-up",
+        "request-account-deletion",
+        "delete-account",
+        "vote-flagged",
+        "approve-dog",
+        "reject-dog",
+        "rename-dog",
+        "import",
+        "backup",
+        "merge-users",
+        "delete-user",
+        "prune",
+        "quarantine-user",
+        "release-user",
+        "roll-back-votes",
+        "start-tournament",
+    ];
+
+    /// The name stored in the action column, which is the same as the JSON tag
+    pub fn action(&self) -> &'static str {
+        match self {
+            Self::NameDog { .. } => "name-dog",
+            Self::Upload { .. } => "upload",
+            Self::SendMagicLink { .. } => "send-magic-link",
+            Self::LogIn { .. } => "log-in",
+            Self::SignUp { .. } => "sign-up",
+            Self::RequestAccountDeletion => "request-account-deletion",
+            Self::DeleteAccount => "delete-account",
+            Self::VoteFlagged { .. } => "vote-flagged",
+            Self::ApproveDog { .. } => "approve-dog",
+            Self::RejectDog { .. } => "reject-dog",
+            Self::RenameDog { .. } => "rename-dog",
+            Self::ImportDogs { .. } => "import",
+            Self::Backup { .. } => "backup",
+            Self::MergeUsers { .. } => "merge-users",
+            Self::DeleteUser { .. } => "delete-user",
+            Self::Prune { .. } => "prune",
+            Self::QuarantineUser { .. } => "quarantine-user",
+            Self::ReleaseUser { .. } => "release-user",
+            Self::RollBackVotes { .. } => "roll-back-votes",
+            Self::StartTournament { .. } => "start-tournament",
+        }
+    }
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NameDog { dog_id } => write!(f, "named dog {}", dog_id),
+            Self::Upload { dog_id } => write!(f, "uploaded dog {}", dog_id),
+            Self::SendMagicLink { email } => write!(f, "sent a log-in link to {}", email),
+            Self::LogIn {
+                email,
+                device_user_id,
+            } => write!(
+                f,
+                "logged in as {} on user {}'s device",
+                email, device_user_id
+            ),
+            Self::SignUp { email } => write!(f, "signed up as {}", email),
+            Self::RequestAccountDeletion => write!(f, "asked to delete their account"),
+            Self::DeleteAccount => write!(f, "deleted an account"),
+            Self::VoteFlagged { match_id, reason } => {
+                write!(f, "vote on match {} flagged: {}", match_id, reason)
+            }
+            Self::ApproveDog { dog_id } => write!(f, "approved dog {}", dog_id),
+            Self::RejectDog { dog_id } => write!(f, "rejected dog {}", dog_id),
+            Self::RenameDog { dog_id, name } => match name {
+                Some(name) => write!(f, "renamed dog {} to {}", dog_id, name),
+                None => write!(f, "took dog {}'s name away", dog_id),
+            },
+            Self::ImportDogs {
+                archive,
+                imported,
+                files,
+            } => write!(
+                f,
+                "imported {} of {} files from {}",
+                imported, files, archive
+            ),
+            Self::Backup { file_name } => write!(f, "saved backup {}", file_name),
+            Self::MergeUsers {
+                from_user_id,
+                into_user_id,
+            } => write!(f, "merged user {} into user {}", from_user_id, into_user_id),
+            Self::DeleteUser { user_id } => write!(f, "deleted user {}", user_id),
+            Self::Prune { older_than_days } => {
+                write!(f, "pruned users older than {} days", older_than_days)
+            }
+            Self::QuarantineUser { user_id } => write!(f, "quarantined user {}", user_id),
+            Self::ReleaseUser { user_id } => write!(f, "released user {}", user_id),
+            Self::RollBackVotes { user_id, votes } => {
+                write!(f, "rolled back {} votes by user {}", votes, user_id)
+            }
+            Self::StartTournament {
+                tournament_id,
+                name,
+            } => write!(f, "started tournament {} ({})", tournament_id, name),
+        }
+    }
+}
+
+/// Adds an event to the log. `user_id` and `client_ip` are whoever caused it, if anybody
+/// (the admin command line has neither).
+pub async fn record(
+    executor: impl Executor<'_>,
+    event: &Event,
+    user_id: Option<i64>,
+    client_ip: Option<IpAddr>,
+) -> Result<(), sqlx::Error> {
+    let details =
+        serde_json::to_string(event).map_err(|error| sqlx::Error::Encode(error.into()))?;
+    sqlx::query("INSERT INTO log (action, user_id, client_ip, details) VALUES ($1, $2, $3, $4)")
+        .bind(event.action())
+        .bind(user_id)
+        .bind(client_ip.map(|ip| ip.to_string()))
+        .bind(details)
+        .execute(executor)
+        .await?;
+    Ok(())
+}
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct LogEntry {
+    pub id: i64,
+    pub created_at: Option<String>,
+    pub action: String,
+    pub user_id: Option<i64>,
+    pub client_ip: Option<String>,
+    pub details: Option<String>,
+}
+
+impl LogEntry {
+    /// None for entries from before events were typed and ones scrubbed of their details
+    pub fn event(&self) -> Option<Event> {
+        serde_json::from_str(self.details.as_deref()?).ok()
+    }
+}
+
+/// What to narrow the log down to. Dates are UTC and both ends are included.
+#[derive(Debug, Default, Clone)]
+pub struct LogFilter {
+    pub user_id: Option<i64>,
+    pub client_ip: Option<String>,
+    pub action: Option<String>,
+    pub from: Option<NaiveDate>,
+    pub to: Option<NaiveDate>,
+    /// Only entries older than this id, for paging back through the log
+    pub before_id: Option<i64>,
+}
+
+/// The newest `limit` entries that match `filter`, newest first
+pub async fn search(
+    executor: impl Executor<'_>,
+    filter: &LogFilter,
+    limit: u32,
+) -> Result<Vec<LogEntry>, sqlx::Error> {
+    // QueryBuilder writes ? placeholders under Any, which postgres doesn't take
+    let mut sql = String::from(
+        "SELECT id, CAST(created_at AS TEXT) AS created_at, action, user_id, client_ip, details FROM log WHERE 1 = 1",
+    );
+    let mut args = AnyArguments::default();
+    if let Some(user_id) = filter.user_id {
+        args.add(user_id).map_err(sqlx::Error::Encode)?;
+        sql += &format!(" AND user_id = ${}", args.len());
+    }
+    if let Some(client_ip) = &filter.client_ip {
+        args.add(client_ip.clone()).map_err(sqlx::Error::Encode)?;
+        sql += &format!(" AND client_ip = ${}", args.len());
+    }
+    if let Some(action) = &filter.action {
+        args.add(action.clone()).map_err(sqlx::Error::Encode)?;
+        sql += &format!(" AND action = ${}", args.len());
+    }
+    if let Some(from) = filter.from {
+        args.add(from.format("%Y-%m-%d").to_string())
+            .map_err(sqlx::Error::Encode)?;
+        sql += &format!(" AND created_at >= ${}", args.len());
+    }
+    if let Some(to) = filter.to.and_then(|to| to.checked_add_days(Days::new(1))) {
+        args.add(to.format("%Y-%m-%d").to_string())
+            .map_err(sqlx::Error::Encode)?;
+        sql += &format!(" AND created_at < ${}", args.len());
+    }
+    if let Some(before_id) = filter.before_id {
+        args.add(before_id).map_err(sqlx::Error::Encode)?;
+        sql += &format!(" AND id < ${}", args.len());
+    }
+    args.add(i64::from(limit)).map_err(sqlx::Error::Encode)?;
+    sql += &format!(" ORDER BY id DESC LIMIT ${}", args.len());
+
+    sqlx::query_as_with::<_, LogEntry, _>(&sql, args)
+        .fetch_all(executor)
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn every_event() -> Vec<Event> {
+        vec![
+            Event::NameDog { dog_id: 1 },
+            Event::Upload { dog_id: 1 },
+            Event::SendMagicLink {
+                email: "dogfan@example.com".to_string(),
+            },
+            Event::LogIn {
+                email: "dogfan@example.com".to_string(),
+                device_user_id: 2,
+            },
+            Event::SignUp {
+                email: "dogfan@example.com".to_string(),
+            },
+            Event::RequestAccountDeletion,
+            Event::DeleteAccount,
+            Event::VoteFlagged {
+                match_id: 3,
+                reason: "too fast".to_string(),
+            },
+            Event::ApproveDog { dog_id: 1 },
+            Event::RejectDog { dog_id: 1 },
+            Event::RenameDog {
+                dog_id: 1,
+                name: None,
+            },
+            Event::ImportDogs {
+                archive: "dogs.zip".to_string(),
+                imported: 2,
+                files: 3,
+            },
+            Event::Backup {
+                file_name: "top-doggo-20241101-000000.db".to_string(),
+            },
+            Event::MergeUsers {
+                from_user_id: 1,
+                into_user_id: 2,
+            },
+            Event::DeleteUser { user_id: 1 },
+            Event::Prune {
+                older_than_days: 30,
+            },
+            Event::QuarantineUser { user_id: 1 },
+            Event::ReleaseUser { user_id: 1 },
+            Event::RollBackVotes {
+                user_id: 1,
+                votes: 40,
+            },
+            Event::StartTournament {
+                tournament_id: 1,
+                name: "Spring Showdown".to_string(),
+            },
+        ]
+    }
+
+    #[test]
+    fn actions_match_the_json_tag() {
+        let events = every_event();
+        assert_eq!(events.len(), Event::ACTIONS.len());
+        for (event, action) in events.iter().zip(Event::ACTIONS) {
+            assert_eq!(event.action(), action);
+            let json: serde_json::Value = serde_json::to_value(event).unwrap();
+            assert_eq!(json["action"], action);
+            assert_eq!(serde_json::from_value::<Event>(json).unwrap(), *event);
+        }
+    }
+
+    #[test]
+    fn old_entries_have_no_event() {
+        let entry = |details: Option<&str>| LogEntry {
+            id: 1,
+            created_at: None,
+            action: "name-dog".to_string(),
+            user_id: None,
+            client_ip: None,
+            details: details.map(str::to_string),
+        };
+        assert_eq!(entry(Some("12")).event(), None);
+        assert_eq!(entry(None).event(), None);
+        assert_eq!(
+            entry(Some(r#"{"action":"name-dog","dog_id":12}"#)).event(),
+            Some(Event::NameDog { dog_id: 12 })
+        );
+    }
+}
@@ -0,0 +1,421 @@
+use super::{minutes_ago, parse_timestamp, ratings::RatingType, seconds_ago, Executor};
+use chrono::Duration;
+use sqlx::AnyConnection;
+
+// a match's status is '>' (dog a won), '<' (dog b won), '=' (tie), '-' (skipped), or '…' (still
+// being decided). skipped matches don't count as votes anywhere: they're left out of the ratings,
+// xp and fraud checks, and get offered again once the user has run out of fresh pairings.
+
+/// The status of a match the user passed on without voting
+pub const SKIPPED: &str = "-";
+
+// how much a match's vote counts toward the overall ratings, as a percentage. quarantined users'
+// votes don't count at all, and matches from before fraud checks count in full (see fraud.rs)
+const TRUST: &str = r#"CASE WHEN "user".quarantined THEN 0 ELSE COALESCE(match.trust, 100) END"#;
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct DogMatch {
+    pub id: i64,
+    pub dog_a_id: i64,
+    pub dog_b_id: i64,
+}
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct ResolvedMatch {
+    pub id: i64,
+    pub user_id: i64,
+    pub dog_a_id: i64,
+    pub dog_b_id: i64,
+    pub status: String,
+    pub trust: i64,
+}
+
+/// A user's latest pick, with everything it changed (see undo.rs)
+#[derive(Debug, sqlx::FromRow)]
+pub struct LastPick {
+    pub id: i64,
+    pub user_id: i64,
+    pub dog_a_id: i64,
+    pub dog_b_id: i64,
+    pub updated_at: Option<String>,
+    pub elo_change_overall_a: Option<i64>,
+    pub elo_change_overall_b: Option<i64>,
+    pub elo_change_personal_a: Option<i64>,
+    pub elo_change_personal_b: Option<i64>,
+    pub xp: Option<i64>,
+    pub ranking_id: Option<i64>,
+}
+
+/// The pairing this user is currently being shown
+pub async fn current(
+    executor: impl Executor<'_>,
+    user_id: i64,
+) -> Result<Option<DogMatch>, sqlx::Error> {
+    sqlx::query_as::<_, DogMatch>(
+        "SELECT id, dog_a_id, dog_b_id FROM match WHERE user_id = $1 AND status = '…' LIMIT 1",
+    )
+    .bind(user_id)
+    .fetch_optional(executor)
+    .await
+}
+
+pub async fn create(
+    executor: impl Executor<'_>,
+    user_id: i64,
+    dog_a_id: i64,
+    dog_b_id: i64,
+) -> Result<(), sqlx::Error> {
+    sqlx::query("INSERT INTO match (user_id, dog_a_id, dog_b_id) VALUES ($1, $2, $3)")
+        .bind(user_id)
+        .bind(dog_a_id)
+        .bind(dog_b_id)
+        .execute(executor)
+        .await?;
+    Ok(())
+}
+
+/// A match decided by where the user put the two dogs in a ranking, timed from when the ranking
+/// was put up. Returns its id.
+pub async fn create_ranked(
+    executor: impl Executor<'_>,
+    user_id: i64,
+    ranking_id: i64,
+    dog_a_id: i64,
+    dog_b_id: i64,
+    status: &str,
+) -> Result<i64, sqlx::Error> {
+    sqlx::query_scalar(
+        "INSERT INTO match (user_id, dog_a_id, dog_b_id, status, ranking_id, created_at) VALUES ($1, $2, $3, $4, $5, (SELECT created_at FROM ranking WHERE id = $5)) RETURNING id",
+    )
+    .bind(user_id)
+    .bind(dog_a_id)
+    .bind(dog_b_id)
+    .bind(status)
+    .bind(ranking_id)
+    .fetch_one(executor)
+    .await
+}
+
+/// Whether this user already has a match between the two dogs, either way round and in any state
+pub async fn exists(
+    executor: impl Executor<'_>,
+    user_id: i64,
+    dog_a_id: i64,
+    dog_b_id: i64,
+) -> Result<bool, sqlx::Error> {
+    let count: i64 = sqlx::query_scalar(
+        "SELECT COUNT(*) FROM match WHERE user_id = $1 AND ((dog_a_id = $2 AND dog_b_id = $3) OR (dog_a_id = $3 AND dog_b_id = $2))",
+    )
+    .bind(user_id)
+    .bind(dog_a_id)
+    .bind(dog_b_id)
+    .fetch_one(executor)
+    .await?;
+    Ok(count > 0)
+}
+
+/// False if the match had already been decided, by a request that got there first
+pub async fn resolve(
+    executor: impl Executor<'_>,
+    match_id: i64,
+    status: &str,
+) -> Result<bool, sqlx::Error> {
+    let resolved = sqlx::query("UPDATE match SET status = $1 WHERE id = $2 AND status = '…'")
+        .bind(status)
+        .bind(match_id)
+        .execute(executor)
+        .await?
+        .rows_affected();
+    Ok(resolved > 0)
+}
+
+/// Passes on a match without voting, counting the skip against both dogs.
+/// False if the match had already been decided.
+pub async fn skip(executor: impl Executor<'_>, match_id: i64) -> Result<bool, sqlx::Error> {
+    let skipped = sqlx::query(
+        "UPDATE match SET status = '-', skips = skips + 1 WHERE id = $1 AND status = '…'",
+    )
+    .bind(match_id)
+    .execute(executor)
+    .await?
+    .rows_affected();
+    Ok(skipped > 0)
+}
+
+/// Records how much xp the pick was worth
+pub async fn set_xp(
+    executor: impl Executor<'_>,
+    match_id: i64,
+    xp: u32,
+) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE match SET xp = $1 WHERE id = $2")
+        .bind(i64::from(xp))
+        .bind(match_id)
+        .execute(executor)
+        .await?;
+    Ok(())
+}
+
+/// The user's most recently decided match, if they decided it in the last `seconds` seconds.
+/// None if that was a ranking, whose matches can't be taken back one at a time.
+pub async fn last_pick(
+    executor: impl Executor<'_>,
+    user_id: i64,
+    seconds: i64,
+) -> Result<Option<LastPick>, sqlx::Error> {
+    sqlx::query_as::<_, LastPick>(
+        "SELECT id, user_id, dog_a_id, dog_b_id, CAST(updated_at AS TEXT) AS updated_at, elo_change_overall_a, elo_change_overall_b, elo_change_personal_a, elo_change_personal_b, xp, ranking_id FROM match WHERE user_id = $1 AND status IN ('>', '<', '=') AND updated_at >= $2 ORDER BY updated_at DESC, id DESC LIMIT 1",
+    )
+    .bind(user_id)
+    .bind(seconds_ago(seconds))
+    .fetch_optional(executor)
+    .await
+    .map(|pick| pick.filter(|pick| pick.ranking_id.is_none()))
+}
+
+/// Whether somebody else has decided a match with either dog since `pick` was. Timestamps only go
+/// down to the second, so one decided in the same second counts too. The user's own picks can be
+/// left out, since `pick` is their latest.
+pub async fn decided_since(
+    executor: impl Executor<'_>,
+    pick: &LastPick,
+) -> Result<bool, sqlx::Error> {
+    let count: i64 = sqlx::query_scalar(
+        "SELECT COUNT(*) FROM match WHERE user_id <> $1 AND status IN ('>', '<', '=') AND (dog_a_id IN ($2, $3) OR dog_b_id IN ($2, $3)) AND updated_at >= $4",
+    )
+    .bind(pick.user_id)
+    .bind(pick.dog_a_id)
+    .bind(pick.dog_b_id)
+    .bind(pick.updated_at.as_deref().unwrap_or_default())
+    .fetch_one(executor)
+    .await?;
+    Ok(count > 0)
+}
+
+/// The match between `dog_id` and someone else that this user skipped longest ago, if any
+pub async fn oldest_skipped(
+    executor: impl Executor<'_>,
+    dog_id: i64,
+    user_id: i64,
+) -> Result<Option<i64>, sqlx::Error> {
+    sqlx::query_scalar(
+        "SELECT id FROM match WHERE user_id = $1 AND status = '-' AND (dog_a_id = $2 OR dog_b_id = $2) ORDER BY updated_at, id LIMIT 1",
+    )
+    .bind(user_id)
+    .bind(dog_id)
+    .fetch_optional(executor)
+    .await
+}
+
+/// Shows a skipped match again, timed from now so deliberation xp starts over
+pub async fn reoffer(executor: impl Executor<'_>, match_id: i64) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE match SET status = '…', created_at = $1 WHERE id = $2")
+        .bind(seconds_ago(0))
+        .bind(match_id)
+        .execute(executor)
+        .await?;
+    Ok(())
+}
+
+/// Puts a decided match back in front of its user, forgetting what the pick changed.
+/// False if it wasn't decided (anymore), so the same pick can't be taken back twice.
+pub async fn reopen(executor: impl Executor<'_>, match_id: i64) -> Result<bool, sqlx::Error> {
+    let reopened = sqlx::query(
+        "UPDATE match SET status = '…', trust = NULL, xp = NULL, elo_change_overall_a = NULL, elo_change_overall_b = NULL, elo_change_personal_a = NULL, elo_change_personal_b = NULL WHERE id = $1 AND status IN ('>', '<', '=')",
+    )
+    .bind(match_id)
+    .execute(executor)
+    .await?
+    .rows_affected();
+    Ok(reopened > 0)
+}
+
+/// Takes back the pairing the user is currently being shown, if any: a new one is dropped, and
+/// one they'd skipped before goes back to being skipped
+pub async fn withdraw_current(conn: &mut AnyConnection, user_id: i64) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE match SET status = '-' WHERE user_id = $1 AND status = '…' AND skips > 0")
+        .bind(user_id)
+        .execute(&mut *conn)
+        .await?;
+    sqlx::query("DELETE FROM match WHERE user_id = $1 AND status = '…'")
+        .bind(user_id)
+        .execute(&mut *conn)
+        .await?;
+    Ok(())
+}
+
+/// Records how much this match's vote counts toward the overall ratings
+pub async fn set_trust(
+    executor: impl Executor<'_>,
+    match_id: i64,
+    trust: u8,
+) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE match SET trust = $1 WHERE id = $2")
+        .bind(i64::from(trust))
+        .bind(match_id)
+        .execute(executor)
+        .await?;
+    Ok(())
+}
+
+/// How much this match's vote counts toward the overall ratings, out of 100
+pub async fn trust(executor: impl Executor<'_>, match_id: i64) -> Result<u8, sqlx::Error> {
+    let trust: i64 = sqlx::query_scalar(&format!(
+        r#"SELECT {} FROM match JOIN "user" ON "user".id = match.user_id WHERE match.id = $1"#,
+        TRUST
+    ))
+    .bind(match_id)
+    .fetch_one(executor)
+    .await?;
+    Ok(trust.clamp(0, 100) as u8)
+}
+
+#[derive(Debug, Clone)]
+pub struct Pick {
+    pub status: String,
+    pub time_taken: Option<Duration>,
+}
+
+/// A user's last `limit` decided matches, newest first
+pub async fn recent_picks(
+    executor: impl Executor<'_>,
+    user_id: i64,
+    limit: u32,
+) -> Result<Vec<Pick>, sqlx::Error> {
+    let rows: Vec<(String, Option<String>, Option<String>)> = sqlx::query_as(
+        "SELECT status, CAST(created_at AS TEXT), CAST(updated_at AS TEXT) FROM match WHERE user_id = $1 AND status IN ('>', '<', '=') ORDER BY updated_at DESC, id DESC LIMIT $2",
+    )
+    .bind(user_id)
+    .bind(i64::from(limit))
+    .fetch_all(executor)
+    .await?;
+    Ok(rows
+        .into_iter()
+        .map(|(status, created_at, updated_at)| Pick {
+            status,
+            time_taken: between(created_at.as_deref(), updated_at.as_deref()),
+        })
+        .collect())
+}
+
+/// The statuses of a user's first `limit` decided matches strung together, like ">><="
+pub async fn first_outcomes(
+    executor: impl Executor<'_>,
+    user_id: i64,
+    limit: u32,
+) -> Result<String, sqlx::Error> {
+    let statuses: Vec<String> = sqlx::query_scalar(
+        "SELECT status FROM match WHERE user_id = $1 AND status IN ('>', '<', '=') ORDER BY updated_at, id LIMIT $2",
+    )
+    .bind(user_id)
+    .bind(i64::from(limit))
+    .fetch_all(executor)
+    .await?;
+    Ok(statuses.concat())
+}
+
+fn between(created_at: Option<&str>, updated_at: Option<&str>) -> Option<Duration> {
+    let created_at = created_at.and_then(parse_timestamp)?;
+    let updated_at = updated_at.and_then(parse_timestamp)?;
+    Some(updated_at - created_at)
+}
+
+/// How long the match was on screen before it was resolved
+pub async fn time_taken(
+    executor: impl Executor<'_>,
+    match_id: i64,
+) -> Result<Option<Duration>, sqlx::Error> {
+    let (created_at, updated_at): (Option<String>, Option<String>) = sqlx::query_as(
+        "SELECT CAST(created_at AS TEXT), CAST(updated_at AS TEXT) FROM match WHERE id = $1",
+    )
+    .bind(match_id)
+    .fetch_one(executor)
+    .await?;
+    Ok(between(created_at.as_deref(), updated_at.as_deref()))
+}
+
+pub async fn set_elo_changes(
+    executor: impl Executor<'_>,
+    match_id: i64,
+    rating_type: RatingType,
+    change_a: i32,
+    change_b: i32,
+) -> Result<(), sqlx::Error> {
+    let query = match rating_type {
+        RatingType::Overall => {
+            "UPDATE match SET elo_change_overall_a = $1, elo_change_overall_b = $2 WHERE id = $3"
+        }
+        RatingType::Personal => {
+            "UPDATE match SET elo_change_personal_a = $1, elo_change_personal_b = $2 WHERE id = $3"
+        }
+    };
+    sqlx::query(query)
+        .bind(change_a)
+        .bind(change_b)
+        .bind(match_id)
+        .execute(executor)
+        .await?;
+    Ok(())
+}
+
+/// How many decided matches a dog has been in, overall or for one user
+pub async fn count_resolved(
+    executor: impl Executor<'_>,
+    dog_id: i64,
+    rating_type: RatingType,
+    user_id: i64,
+) -> Result<u32, sqlx::Error> {
+    let count: i64 = match rating_type {
+        RatingType::Overall => sqlx::query_scalar(
+            "SELECT COUNT(*) FROM match WHERE (dog_a_id = $1 OR dog_b_id = $1) AND status IN ('>', '<', '=')",
+        )
+        .bind(dog_id),
+        RatingType::Personal => sqlx::query_scalar(
+            "SELECT COUNT(*) FROM match WHERE (dog_a_id = $1 OR dog_b_id = $1) AND user_id = $2 AND status IN ('>', '<', '=')",
+        )
+        .bind(dog_id)
+        .bind(user_id),
+    }
+    .fetch_one(executor)
+    .await?;
+    Ok(count as u32)
+}
+
+/// How many matches this user has decided, one at a time or in rankings
+pub async fn count_picks(executor: impl Executor<'_>, user_id: i64) -> Result<i64, sqlx::Error> {
+    sqlx::query_scalar(
+        "SELECT COUNT(*) FROM match WHERE user_id = $1 AND status IN ('>', '<', '=')",
+    )
+    .bind(user_id)
+    .fetch_one(executor)
+    .await
+}
+
+/// Every decided match (or just one user's) in the order they were decided
+pub async fn resolved(
+    executor: impl Executor<'_>,
+    user_id: Option<i64>,
+) -> Result<Vec<ResolvedMatch>, sqlx::Error> {
+    sqlx::query_as::<_, ResolvedMatch>(
+        &format!(
+            r#"SELECT match.id, match.user_id, dog_a_id, dog_b_id, status, {} AS trust FROM match JOIN "user" ON "user".id = match.user_id WHERE status IN ('>', '<', '=') AND ($1 IS NULL OR match.user_id = $1) ORDER BY match.updated_at, match.id"#,
+            TRUST
+        ),
+    )
+    .bind(user_id)
+    .fetch_all(executor)
+    .await
+}
+
+/// Users who have decided a match in the last `minutes` minutes
+pub async fn count_active_voters(
+    executor: impl Executor<'_>,
+    minutes: i64,
+) -> Result<i64, sqlx::Error> {
+    sqlx::query_scalar(
+        "SELECT COUNT(DISTINCT user_id) FROM match WHERE status IN ('>', '<', '=') AND updated_at > $1",
+    )
+    .bind(minutes_ago(minutes))
+    .fetch_one(executor)
+    .await
+}
@@ -0,0 +1,210 @@
+use chrono::{Duration, NaiveDateTime, Utc};
+use sqlx::{
+    any::{install_default_drivers, AnyPoolOptions, AnyTypeInfo, AnyValueRef},
+    error::BoxDynError,
+    migrate::{MigrateError, Migrator},
+    Any, Decode, Pool, Type,
+};
+
+// every query the app makes outside of the admin tools lives in the repositories below, one per
+// table (the log table's is `log`, email_token's is `tokens`).
+//
+// the app runs on sqlite or postgres, whichever DATABASE_URL points at, through sqlx's Any driver.
+// to keep the same queries working on both:
+// - the user table is always written "user" (it's a keyword in postgres)
+// - timestamps are 'YYYY-MM-DD HH:MM:SS' text in UTC, so "30 minutes ago" is worked out here and
+//   compared as a string instead of with datetime('now', ...)
+// - created_at/updated_at are selected as CAST(... AS TEXT) and booleans as CASE WHEN ... THEN 1
+//   ELSE 0 END, since the Any driver can't decode sqlite's DATETIME and BOOLEAN columns
+// - a NULL bind is typed as an integer on postgres, so optional text needs CAST($n AS TEXT)
+//   anywhere other than an INSERT or SET
+
+pub mod dogs;
+pub mod log;
+pub mod matches;
+pub mod rankings;
+pub mod rate_limits;
+pub mod ratings;
+pub mod sessions;
+pub mod tokens;
+pub mod tournaments;
+pub mod users;
+
+/// Anything a repository function can run its query on: the pool, or `&mut *transaction`
+pub trait Executor<'c>: sqlx::Executor<'c, Database = Any> {}
+impl<'c, T: sqlx::Executor<'c, Database = Any>> Executor<'c> for T {}
+
+pub static SQLITE_MIGRATOR: Migrator = sqlx::migrate!("./migrations");
+pub static POSTGRES_MIGRATOR: Migrator = sqlx::migrate!("./migrations/postgres");
+
+const TIMESTAMP_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    Sqlite,
+    Postgres,
+}
+
+impl Backend {
+    pub fn from_url(database_url: &str) -> Option<Self> {
+        if database_url.starts_with("sqlite:") {
+            Some(Self::Sqlite)
+        } else if database_url.starts_with("postgres:") || database_url.starts_with("postgresql:") {
+            Some(Self::Postgres)
+        } else {
+            None
+        }
+    }
+
+    pub fn of(pool: &Pool<Any>) -> Self {
+        Self::from_url(pool.connect_options().database_url.as_str())
+            .expect("pools are only opened for sqlite or postgres urls")
+    }
+
+    pub fn migrator(self) -> &'static Migrator {
+        match self {
+            Self::Sqlite => &SQLITE_MIGRATOR,
+            Self::Postgres => &POSTGRES_MIGRATOR,
+        }
+    }
+}
+
+pub async fn connect(database_url: &str) -> Result<Pool<Any>, sqlx::Error> {
+    connect_with(AnyPoolOptions::new(), database_url).await
+}
+
+pub async fn connect_with(
+    options: AnyPoolOptions,
+    database_url: &str,
+) -> Result<Pool<Any>, sqlx::Error> {
+    if Backend::from_url(database_url).is_none() {
+        return Err(sqlx::Error::Configuration(
+            format!("{} isn't a sqlite or postgres url", database_url).into(),
+        ));
+    }
+    install_default_drivers();
+    options.connect(database_url).await
+}
+
+/// Runs the migrations, then the ones that need Rust: hashing sessions from before tokens were
+/// hashed (see sessions.rs)
+pub async fn migrate(pool: &Pool<Any>) -> Result<(), MigrateError> {
+    Backend::of(pool).migrator().run(pool).await?;
+    sessions::hash_legacy(pool)
+        .await
+        .map_err(MigrateError::Execute)?;
+    Ok(())
+}
+
+/// A boolean column selected as `CASE WHEN column THEN 1 ELSE 0 END`.
+/// Decode it directly or use `#[sqlx(try_from = "db::Flag")]` on a `bool` field.
+pub struct Flag(pub bool);
+
+impl Type<Any> for Flag {
+    fn type_info() -> AnyTypeInfo {
+        <i64 as Type<Any>>::type_info()
+    }
+
+    fn compatible(ty: &AnyTypeInfo) -> bool {
+        <i64 as Type<Any>>::compatible(ty)
+    }
+}
+
+impl<'r> Decode<'r, Any> for Flag {
+    fn decode(value: AnyValueRef<'r>) -> Result<Self, BoxDynError> {
+        Ok(Self(<i64 as Decode<Any>>::decode(value)? != 0))
+    }
+}
+
+impl From<Flag> for bool {
+    fn from(flag: Flag) -> Self {
+        flag.0
+    }
+}
+
+/// The current time the way the database stores it
+pub fn now() -> String {
+    Utc::now().format(TIMESTAMP_FORMAT).to_string()
+}
+
+pub fn seconds_ago(seconds: i64) -> String {
+    (Utc::now() - Duration::seconds(seconds))
+        .format(TIMESTAMP_FORMAT)
+        .to_string()
+}
+
+pub fn minutes_ago(minutes: i64) -> String {
+    (Utc::now() - Duration::minutes(minutes))
+        .format(TIMESTAMP_FORMAT)
+        .to_string()
+}
+
+pub fn days_ago(days: i64) -> String {
+    (Utc::now() - Duration::days(days))
+        .format(TIMESTAMP_FORMAT)
+        .to_string()
+}
+
+/// `hours` after one of the database's timestamps, or None if it isn't one
+pub fn hours_after(timestamp: &str, hours: i64) -> Option<String> {
+    parse_timestamp(timestamp).map(|timestamp| {
+        (timestamp + Duration::hours(hours))
+            .format(TIMESTAMP_FORMAT)
+            .to_string()
+    })
+}
+
+/// A LIKE pattern (with ESCAPE '\') matching text that contains `needle`, since sqlite's
+/// instr() and postgres's strpos() don't have a common spelling
+pub fn containing(needle: &str) -> String {
+    let escaped = needle
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_");
+    format!("%{}%", escaped)
+}
+
+pub fn parse_timestamp(timestamp: &str) -> Option<NaiveDateTime> {
+    NaiveDateTime::parse_from_str(timestamp, TIMESTAMP_FORMAT).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backends_from_urls() {
+        assert_eq!(
+            Backend::from_url("sqlite:db/top-doggo.db"),
+            Some(Backend::Sqlite)
+        );
+        assert_eq!(
+            Backend::from_url("postgres://localhost/top_doggo"),
+            Some(Backend::Postgres)
+        );
+        assert_eq!(
+            Backend::from_url("postgresql://localhost/top_doggo"),
+            Some(Backend::Postgres)
+        );
+        assert_eq!(Backend::from_url("mysql://localhost/top_doggo"), None);
+    }
+
+    #[test]
+    fn timestamps_match_current_timestamp() {
+        let timestamp = minutes_ago(30);
+        assert_eq!(timestamp.len(), "2024-08-01 13:05:00".len());
+        assert!(timestamp < now());
+        assert!(parse_timestamp(&timestamp).is_some());
+        assert!(parse_timestamp(&days_ago(1)).unwrap() < parse_timestamp(&now()).unwrap());
+        assert_eq!(
+            hours_after("2024-08-01 13:05:00", 24).as_deref(),
+            Some("2024-08-02 13:05:00")
+        );
+    }
+
+    #[test]
+    fn containing_escapes_wildcards() {
+        assert_eq!(containing("dog@example.com"), "%dog@example.com%");
+        assert_eq!(containing("top_dog%"), "%top\\_dog\\%%");
+    }
+}
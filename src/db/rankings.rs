@@ -0,0 +1,113 @@
+use super::{parse_timestamp, Executor};
+use chrono::Duration;
+use sqlx::AnyConnection;
+
+// a ranking's status is '…' while it's on screen and '✓' once the user has put its dogs in order.
+// its places are kept as they were submitted, and the matches they turn into point back at it
+// through match.ranking_id (see routers/doggo/ranking.rs).
+
+/// The ranking this user is currently being shown
+pub async fn current(
+    executor: impl Executor<'_>,
+    user_id: i64,
+) -> Result<Option<i64>, sqlx::Error> {
+    sqlx::query_scalar("SELECT id FROM ranking WHERE user_id = $1 AND status = '…' LIMIT 1")
+        .bind(user_id)
+        .fetch_optional(executor)
+        .await
+}
+
+/// The dogs in a ranking, in the order they were put up on screen
+pub async fn dog_ids(
+    executor: impl Executor<'_>,
+    ranking_id: i64,
+) -> Result<Vec<i64>, sqlx::Error> {
+    sqlx::query_scalar(
+        "SELECT dog_id FROM ranking_place WHERE ranking_id = $1 ORDER BY position, dog_id",
+    )
+    .bind(ranking_id)
+    .fetch_all(executor)
+    .await
+}
+
+/// A new ranking of `dog_ids` for this user to put in order, returning its id. They're shown in
+/// the order given, so shuffle them first.
+pub async fn create(
+    conn: &mut AnyConnection,
+    user_id: i64,
+    dog_ids: &[i64],
+) -> Result<i64, sqlx::Error> {
+    let ranking_id: i64 =
+        sqlx::query_scalar("INSERT INTO ranking (user_id) VALUES ($1) RETURNING id")
+            .bind(user_id)
+            .fetch_one(&mut *conn)
+            .await?;
+    for (i, dog_id) in dog_ids.iter().enumerate() {
+        sqlx::query("INSERT INTO ranking_place (ranking_id, dog_id, position) VALUES ($1, $2, $3)")
+            .bind(ranking_id)
+            .bind(dog_id)
+            .bind(i as i64 + 1)
+            .execute(&mut *conn)
+            .await?;
+    }
+    Ok(ranking_id)
+}
+
+/// Records the order the user put the ranking's dogs in, favorite first. False if it had already
+/// been submitted, so a double click can't count twice.
+pub async fn submit(
+    conn: &mut AnyConnection,
+    ranking_id: i64,
+    order: &[i64],
+) -> Result<bool, sqlx::Error> {
+    let claimed = sqlx::query("UPDATE ranking SET status = '✓' WHERE id = $1 AND status = '…'")
+        .bind(ranking_id)
+        .execute(&mut *conn)
+        .await?
+        .rows_affected();
+    if claimed == 0 {
+        return Ok(false);
+    }
+    for (i, dog_id) in order.iter().enumerate() {
+        sqlx::query("UPDATE ranking_place SET place = $1 WHERE ranking_id = $2 AND dog_id = $3")
+            .bind(i as i64 + 1)
+            .bind(ranking_id)
+            .bind(dog_id)
+            .execute(&mut *conn)
+            .await?;
+    }
+    Ok(true)
+}
+
+/// How long the ranking was on screen before it was submitted
+pub async fn time_taken(
+    executor: impl Executor<'_>,
+    ranking_id: i64,
+) -> Result<Option<Duration>, sqlx::Error> {
+    let (created_at, updated_at): (Option<String>, Option<String>) = sqlx::query_as(
+        "SELECT CAST(created_at AS TEXT), CAST(updated_at AS TEXT) FROM ranking WHERE id = $1",
+    )
+    .bind(ranking_id)
+    .fetch_one(executor)
+    .await?;
+    let created_at = created_at.as_deref().and_then(parse_timestamp);
+    let updated_at = updated_at.as_deref().and_then(parse_timestamp);
+    Ok(created_at
+        .zip(updated_at)
+        .map(|(created_at, updated_at)| updated_at - created_at))
+}
+
+/// Drops the ranking the user is currently being shown, if any
+pub async fn withdraw_current(conn: &mut AnyConnection, user_id: i64) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "DELETE FROM ranking_place WHERE ranking_id IN (SELECT id FROM ranking WHERE user_id = $1 AND status = '…')",
+    )
+    .bind(user_id)
+    .execute(&mut *conn)
+    .await?;
+    sqlx::query("DELETE FROM ranking WHERE user_id = $1 AND status = '…'")
+        .bind(user_id)
+        .execute(&mut *conn)
+        .await?;
+    Ok(())
+}
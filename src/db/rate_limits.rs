@@ -0,0 +1,41 @@
+use super::Executor;
+
+/// A saved bucket's (tokens, updated_at in unix milliseconds)
+pub async fn get(
+    executor: impl Executor<'_>,
+    key: &str,
+) -> Result<Option<(f64, i64)>, sqlx::Error> {
+    sqlx::query_as("SELECT tokens, updated_at FROM rate_limit_bucket WHERE key = $1")
+        .bind(key)
+        .fetch_optional(executor)
+        .await
+}
+
+pub async fn save(
+    executor: impl Executor<'_>,
+    key: &str,
+    tokens: f64,
+    updated_at: i64,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT INTO rate_limit_bucket (key, tokens, updated_at) VALUES ($1, $2, $3)
+        ON CONFLICT (key) DO UPDATE SET tokens = excluded.tokens, updated_at = excluded.updated_at",
+    )
+    .bind(key)
+    .bind(tokens)
+    .bind(updated_at)
+    .execute(executor)
+    .await?;
+    Ok(())
+}
+
+/// Forgets buckets that haven't been touched since `before` (unix milliseconds)
+pub async fn prune(executor: impl Executor<'_>, before: i64) -> Result<u64, sqlx::Error> {
+    Ok(
+        sqlx::query("DELETE FROM rate_limit_bucket WHERE updated_at < $1")
+            .bind(before)
+            .execute(executor)
+            .await?
+            .rows_affected(),
+    )
+}
@@ -0,0 +1,171 @@
+use super::Executor;
+use serde::{Deserialize, Serialize};
+
+/// What a dog's rating starts at before its first match
+pub const STARTING_RATING: u16 = 1000;
+
+#[derive(Clone, Copy, Deserialize, Serialize, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum RatingType {
+    Overall,
+    Personal,
+}
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct LeaderboardRow {
+    pub dog_id: i64,
+    pub value: i64,
+    pub name: Option<String>,
+    pub image_url: String,
+    /// Tournaments the dog has won
+    pub titles: i64,
+}
+
+// get and set only look at `user_id` for personal ratings
+
+pub async fn get(
+    executor: impl Executor<'_>,
+    dog_id: i64,
+    rating_type: RatingType,
+    user_id: i64,
+) -> Result<Option<u16>, sqlx::Error> {
+    let value: Option<i64> = match rating_type {
+        RatingType::Overall => {
+            sqlx::query_scalar("SELECT value FROM rating WHERE dog_id = $1 AND type = 'overall'")
+                .bind(dog_id)
+        }
+        RatingType::Personal => sqlx::query_scalar(
+            "SELECT value FROM rating WHERE dog_id = $1 AND type = 'personal' AND user_id = $2",
+        )
+        .bind(dog_id)
+        .bind(user_id),
+    }
+    .fetch_optional(executor)
+    .await?;
+    Ok(value.map(|value| value as u16))
+}
+
+pub async fn set(
+    executor: impl Executor<'_>,
+    dog_id: i64,
+    rating_type: RatingType,
+    user_id: i64,
+    value: u16,
+) -> Result<(), sqlx::Error> {
+    match rating_type {
+        RatingType::Overall => {
+            sqlx::query("UPDATE rating SET value = $1 WHERE dog_id = $2 AND type = 'overall'")
+                .bind(i64::from(value))
+                .bind(dog_id)
+        }
+        RatingType::Personal => sqlx::query(
+            "UPDATE rating SET value = $1 WHERE dog_id = $2 AND type = 'personal' AND user_id = $3",
+        )
+        .bind(i64::from(value))
+        .bind(dog_id)
+        .bind(user_id),
+    }
+    .execute(executor)
+    .await?;
+    Ok(())
+}
+
+/// Moves a rating by `change` from wherever it is now, rather than setting it outright
+pub async fn shift(
+    executor: impl Executor<'_>,
+    dog_id: i64,
+    rating_type: RatingType,
+    user_id: i64,
+    change: i32,
+) -> Result<(), sqlx::Error> {
+    match rating_type {
+        RatingType::Overall => {
+            sqlx::query("UPDATE rating SET value = value + $1 WHERE dog_id = $2 AND type = 'overall'")
+                .bind(i64::from(change))
+                .bind(dog_id)
+        }
+        RatingType::Personal => sqlx::query(
+            "UPDATE rating SET value = value + $1 WHERE dog_id = $2 AND type = 'personal' AND user_id = $3",
+        )
+        .bind(i64::from(change))
+        .bind(dog_id)
+        .bind(user_id),
+    }
+    .execute(executor)
+    .await?;
+    Ok(())
+}
+
+pub async fn insert_overall(
+    executor: impl Executor<'_>,
+    dog_id: i64,
+    value: u16,
+) -> Result<(), sqlx::Error> {
+    sqlx::query("INSERT INTO rating (type, dog_id, value) VALUES ('overall', $1, $2)")
+        .bind(dog_id)
+        .bind(i64::from(value))
+        .execute(executor)
+        .await?;
+    Ok(())
+}
+
+pub async fn insert_personal(
+    executor: impl Executor<'_>,
+    user_id: i64,
+    dog_id: i64,
+    value: u16,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT INTO rating (type, user_id, dog_id, value) VALUES ('personal', $1, $2, $3)",
+    )
+    .bind(user_id)
+    .bind(dog_id)
+    .bind(i64::from(value))
+    .execute(executor)
+    .await?;
+    Ok(())
+}
+
+/// Deletes every rating, or with `user_id` just that user's personal ones
+pub async fn clear(executor: impl Executor<'_>, user_id: Option<i64>) -> Result<(), sqlx::Error> {
+    match user_id {
+        None => sqlx::query("DELETE FROM rating"),
+        Some(user_id) => {
+            sqlx::query("DELETE FROM rating WHERE type = 'personal' AND user_id = $1").bind(user_id)
+        }
+    }
+    .execute(executor)
+    .await?;
+    Ok(())
+}
+
+/// Highest rated first
+pub async fn leaderboard(
+    executor: impl Executor<'_>,
+    rating_type: RatingType,
+    user_id: i64,
+) -> Result<Vec<LeaderboardRow>, sqlx::Error> {
+    match rating_type {
+        RatingType::Overall => sqlx::query_as::<_, LeaderboardRow>(
+            "SELECT dog_id, value, name, image_url, (SELECT COUNT(*) FROM tournament WHERE champion_id = dog.id) AS titles FROM rating JOIN dog ON rating.dog_id = dog.id WHERE type = 'overall' ORDER BY value DESC",
+        ),
+        RatingType::Personal => sqlx::query_as::<_, LeaderboardRow>(
+            "SELECT dog_id, value, name, image_url, (SELECT COUNT(*) FROM tournament WHERE champion_id = dog.id) AS titles FROM rating JOIN dog ON rating.dog_id = dog.id WHERE type = 'personal' AND user_id = $1 ORDER BY value DESC",
+        )
+        .bind(user_id),
+    }
+    .fetch_all(executor)
+    .await
+}
+
+/// The `limit` highest rated approved dogs by overall rating, best first. Dogs that haven't been
+/// in a match yet count as having the starting rating.
+pub async fn top_dog_ids(executor: impl Executor<'_>, limit: i64) -> Result<Vec<i64>, sqlx::Error> {
+    sqlx::query_scalar(
+        "SELECT dog.id FROM dog LEFT JOIN rating ON rating.dog_id = dog.id AND rating.type = 'overall' WHERE dog.approved = TRUE ORDER BY COALESCE(rating.value, $1) DESC, dog.id LIMIT $2",
+    )
+    .bind(i64::from(STARTING_RATING))
+    .bind(limit)
+    .fetch_all(executor)
+    .await
+}
@@ -0,0 +1,59 @@
+use super::Executor;
+use sha2::{Digest, Sha256};
+use sqlx::{Any, Pool};
+use uuid::Uuid;
+
+// only a hash of each token is stored, the token itself lives in the (signed) auth cookie.
+// sessions from before that had the token as is, a dashed uuid, in an unsigned cookie. those rows
+// are hashed in place when the app starts (see hash_legacy) and the cookies are signed the next
+// time they come in.
+
+/// Starts a session for `user_id` and returns its token, which goes in the auth cookie
+pub async fn create(executor: impl Executor<'_>, user_id: i64) -> Result<String, sqlx::Error> {
+    let token = Uuid::new_v4().simple().to_string();
+    sqlx::query("INSERT INTO session (token, user_id) VALUES ($1, $2)")
+        .bind(hash(&token))
+        .bind(user_id)
+        .execute(executor)
+        .await?;
+    Ok(token)
+}
+
+pub async fn find_user(
+    executor: impl Executor<'_>,
+    token: &str,
+) -> Result<Option<i64>, sqlx::Error> {
+    sqlx::query_scalar("SELECT user_id FROM session WHERE token = $1")
+        .bind(hash(token))
+        .fetch_optional(executor)
+        .await
+}
+
+/// Whether `cookie` could be an unsigned token from before sessions were hashed
+pub fn is_legacy(cookie: &str) -> bool {
+    cookie.len() == 36 && Uuid::try_parse(cookie).is_ok()
+}
+
+/// Hashes the tokens of sessions from before tokens were hashed, so a copy of the database
+/// doesn't hold any that still work. Returns how many there were.
+pub async fn hash_legacy(pool: &Pool<Any>) -> Result<u64, sqlx::Error> {
+    let mut transaction = pool.begin().await?;
+    // hashes are 64 hex digits, legacy tokens are 36 characters
+    let tokens: Vec<String> =
+        sqlx::query_scalar("SELECT token FROM session WHERE LENGTH(token) = 36")
+            .fetch_all(&mut *transaction)
+            .await?;
+    for token in &tokens {
+        sqlx::query("UPDATE session SET token = $1 WHERE token = $2")
+            .bind(hash(token))
+            .bind(token)
+            .execute(&mut *transaction)
+            .await?;
+    }
+    transaction.commit().await?;
+    Ok(tokens.len() as u64)
+}
+
+fn hash(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
@@ -0,0 +1,118 @@
+use super::{minutes_ago, Executor};
+use uuid::Uuid;
+
+// email_token, the magic links we send out
+
+/// How long a magic link works for
+pub const LIFETIME_MINUTES: i64 = 30;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Purpose {
+    LogIn,
+    DeleteAccount,
+}
+
+impl Purpose {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::LogIn => "log-in",
+            Self::DeleteAccount => "delete-account",
+        }
+    }
+}
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct EmailToken {
+    pub email: String,
+    pub sender_id: Option<i64>,
+}
+
+/// A new magic link for `email`, returning its token
+pub async fn create(
+    executor: impl Executor<'_>,
+    email: &str,
+    sender_id: i64,
+    purpose: Purpose,
+) -> Result<String, sqlx::Error> {
+    let token = Uuid::new_v4().to_string();
+    sqlx::query(
+        "INSERT INTO email_token (token, email, sender_id, purpose) VALUES ($1, $2, $3, $4)",
+    )
+    .bind(&token)
+    .bind(email)
+    .bind(sender_id)
+    .bind(purpose.as_str())
+    .execute(executor)
+    .await?;
+    Ok(token)
+}
+
+/// A magic link that hasn't expired yet, used or not
+pub async fn find(
+    executor: impl Executor<'_>,
+    token: &str,
+    purpose: Purpose,
+) -> Result<Option<EmailToken>, sqlx::Error> {
+    sqlx::query_as::<_, EmailToken>(
+        "SELECT email, sender_id FROM email_token WHERE token = $1 AND purpose = $2 AND created_at > $3",
+    )
+    .bind(token)
+    .bind(purpose.as_str())
+    .bind(minutes_ago(LIFETIME_MINUTES))
+    .fetch_optional(executor)
+    .await
+}
+
+pub async fn mark_used(executor: impl Executor<'_>, token: &str) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE email_token SET used = true WHERE token = $1")
+        .bind(token)
+        .execute(executor)
+        .await?;
+    Ok(())
+}
+
+/// The address a log-in link went to from this user in the last minute, if any
+pub async fn recently_sent(
+    executor: impl Executor<'_>,
+    sender_id: i64,
+) -> Result<Option<String>, sqlx::Error> {
+    sqlx::query_scalar(
+        "SELECT email FROM email_token WHERE sender_id = $1 AND purpose = 'log-in' AND created_at > $2 LIMIT 1",
+    )
+    .bind(sender_id)
+    .bind(minutes_ago(1))
+    .fetch_optional(executor)
+    .await
+}
+
+/// When a log-in link sent from this user's device was opened somewhere else (usually a phone's
+/// mail app), the user that email belongs to, so the device can be logged in too
+pub async fn logged_in_user_for_sender(
+    executor: impl Executor<'_>,
+    sender_id: i64,
+) -> Result<Option<i64>, sqlx::Error> {
+    sqlx::query_scalar(
+        r#"SELECT "user".id FROM email_token INNER JOIN "user" ON email_token.email = "user".email
+        WHERE email_token.sender_id = $1 AND email_token.purpose = 'log-in' AND email_token.used = true AND email_token.created_at > $2"#,
+    )
+    .bind(sender_id)
+    .bind(minutes_ago(LIFETIME_MINUTES))
+    .fetch_optional(executor)
+    .await
+}
+
+/// The user an unused delete-account link was sent by, as long as the email is still theirs
+pub async fn account_to_delete(
+    executor: impl Executor<'_>,
+    token: &str,
+) -> Result<Option<i64>, sqlx::Error> {
+    let sender_id: Option<Option<i64>> = sqlx::query_scalar(
+        r#"SELECT sender_id FROM email_token INNER JOIN "user" ON email_token.sender_id = "user".id AND email_token.email = "user".email
+        WHERE token = $1 AND purpose = 'delete-account' AND used = false AND email_token.created_at > $2"#,
+    )
+    .bind(token)
+    .bind(minutes_ago(LIFETIME_MINUTES))
+    .fetch_optional(executor)
+    .await?;
+    Ok(sender_id.flatten())
+}
@@ -0,0 +1,282 @@
+use super::Executor;
+
+// a tournament's bracket is laid out by routers/tournaments/bracket.rs, and a bracket_match's
+// number is its place in that layout. matches are decided and their dogs moved along by
+// bracket::advance, which is run whenever somebody looks at or votes in the tournament.
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct Tournament {
+    pub id: i64,
+    pub name: String,
+    pub format: String,
+    pub window_hours: i64,
+    pub champion_id: Option<i64>,
+    pub created_at: Option<String>,
+}
+
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct BracketMatch {
+    pub id: i64,
+    pub number: i64,
+    pub dog_a_id: Option<i64>,
+    pub dog_b_id: Option<i64>,
+    pub winner_id: Option<i64>,
+    pub opens_at: Option<String>,
+    pub closes_at: Option<String>,
+}
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct Entrant {
+    pub dog_id: i64,
+    pub seed: i64,
+    pub name: Option<String>,
+}
+
+const TOURNAMENT_COLUMNS: &str =
+    "id, name, format, window_hours, champion_id, CAST(created_at AS TEXT) AS created_at";
+const MATCH_COLUMNS: &str = "id, number, dog_a_id, dog_b_id, winner_id, opens_at, closes_at";
+
+pub async fn get(
+    executor: impl Executor<'_>,
+    tournament_id: i64,
+) -> Result<Option<Tournament>, sqlx::Error> {
+    sqlx::query_as::<_, Tournament>(&format!(
+        "SELECT {} FROM tournament WHERE id = $1",
+        TOURNAMENT_COLUMNS
+    ))
+    .bind(tournament_id)
+    .fetch_optional(executor)
+    .await
+}
+
+/// Newest first
+pub async fn list(executor: impl Executor<'_>) -> Result<Vec<Tournament>, sqlx::Error> {
+    sqlx::query_as::<_, Tournament>(&format!(
+        "SELECT {} FROM tournament ORDER BY id DESC",
+        TOURNAMENT_COLUMNS
+    ))
+    .fetch_all(executor)
+    .await
+}
+
+/// A new tournament with nobody in it yet, returning its id
+pub async fn create(
+    executor: impl Executor<'_>,
+    name: &str,
+    format: &str,
+    window_hours: i64,
+) -> Result<i64, sqlx::Error> {
+    sqlx::query_scalar(
+        "INSERT INTO tournament (name, format, window_hours) VALUES ($1, $2, $3) RETURNING id",
+    )
+    .bind(name)
+    .bind(format)
+    .bind(window_hours)
+    .fetch_one(executor)
+    .await
+}
+
+pub async fn add_entrant(
+    executor: impl Executor<'_>,
+    tournament_id: i64,
+    dog_id: i64,
+    seed: i64,
+) -> Result<(), sqlx::Error> {
+    sqlx::query("INSERT INTO tournament_entry (tournament_id, dog_id, seed) VALUES ($1, $2, $3)")
+        .bind(tournament_id)
+        .bind(dog_id)
+        .bind(seed)
+        .execute(executor)
+        .await?;
+    Ok(())
+}
+
+/// Best seed first
+pub async fn entrants(
+    executor: impl Executor<'_>,
+    tournament_id: i64,
+) -> Result<Vec<Entrant>, sqlx::Error> {
+    sqlx::query_as::<_, Entrant>(
+        "SELECT dog_id, seed, name FROM tournament_entry JOIN dog ON tournament_entry.dog_id = dog.id WHERE tournament_id = $1 ORDER BY seed",
+    )
+    .bind(tournament_id)
+    .fetch_all(executor)
+    .await
+}
+
+pub async fn add_match(
+    executor: impl Executor<'_>,
+    tournament_id: i64,
+    number: i64,
+    dogs: Option<(i64, i64)>,
+    window: Option<(&str, &str)>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT INTO bracket_match (tournament_id, number, dog_a_id, dog_b_id, opens_at, closes_at) VALUES ($1, $2, $3, $4, $5, $6)",
+    )
+    .bind(tournament_id)
+    .bind(number)
+    .bind(dogs.map(|(dog_a_id, _)| dog_a_id))
+    .bind(dogs.map(|(_, dog_b_id)| dog_b_id))
+    .bind(window.map(|(opens_at, _)| opens_at))
+    .bind(window.map(|(_, closes_at)| closes_at))
+    .execute(executor)
+    .await?;
+    Ok(())
+}
+
+/// In the order they're laid out in the bracket
+pub async fn matches(
+    executor: impl Executor<'_>,
+    tournament_id: i64,
+) -> Result<Vec<BracketMatch>, sqlx::Error> {
+    sqlx::query_as::<_, BracketMatch>(&format!(
+        "SELECT {} FROM bracket_match WHERE tournament_id = $1 ORDER BY number",
+        MATCH_COLUMNS
+    ))
+    .bind(tournament_id)
+    .fetch_all(executor)
+    .await
+}
+
+pub async fn get_match(
+    executor: impl Executor<'_>,
+    tournament_id: i64,
+    match_id: i64,
+) -> Result<Option<BracketMatch>, sqlx::Error> {
+    sqlx::query_as::<_, BracketMatch>(&format!(
+        "SELECT {} FROM bracket_match WHERE tournament_id = $1 AND id = $2",
+        MATCH_COLUMNS
+    ))
+    .bind(tournament_id)
+    .bind(match_id)
+    .fetch_optional(executor)
+    .await
+}
+
+/// Puts a dog into one side of a match that's waiting on the matches before it
+pub async fn place(
+    executor: impl Executor<'_>,
+    match_id: i64,
+    side_a: bool,
+    dog_id: i64,
+) -> Result<(), sqlx::Error> {
+    let query = if side_a {
+        "UPDATE bracket_match SET dog_a_id = $1 WHERE id = $2"
+    } else {
+        "UPDATE bracket_match SET dog_b_id = $1 WHERE id = $2"
+    };
+    sqlx::query(query)
+        .bind(dog_id)
+        .bind(match_id)
+        .execute(executor)
+        .await?;
+    Ok(())
+}
+
+pub async fn open(
+    executor: impl Executor<'_>,
+    match_id: i64,
+    opens_at: &str,
+    closes_at: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE bracket_match SET (opens_at, closes_at) = ($1, $2) WHERE id = $3")
+        .bind(opens_at)
+        .bind(closes_at)
+        .bind(match_id)
+        .execute(executor)
+        .await?;
+    Ok(())
+}
+
+/// False if the match had already been decided, by a request that got there first
+pub async fn decide(
+    executor: impl Executor<'_>,
+    match_id: i64,
+    winner_id: i64,
+) -> Result<bool, sqlx::Error> {
+    let decided =
+        sqlx::query("UPDATE bracket_match SET winner_id = $1 WHERE id = $2 AND winner_id IS NULL")
+            .bind(winner_id)
+            .bind(match_id)
+            .execute(executor)
+            .await?
+            .rows_affected();
+    Ok(decided > 0)
+}
+
+pub async fn crown(
+    executor: impl Executor<'_>,
+    tournament_id: i64,
+    dog_id: i64,
+) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE tournament SET champion_id = $1 WHERE id = $2")
+        .bind(dog_id)
+        .bind(tournament_id)
+        .execute(executor)
+        .await?;
+    Ok(())
+}
+
+/// Records a user's vote in a match, replacing the one they cast before if they changed their mind
+pub async fn vote(
+    executor: impl Executor<'_>,
+    match_id: i64,
+    user_id: i64,
+    dog_id: i64,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT INTO bracket_vote (bracket_match_id, user_id, dog_id) VALUES ($1, $2, $3)
+        ON CONFLICT (bracket_match_id, user_id) DO UPDATE SET dog_id = excluded.dog_id",
+    )
+    .bind(match_id)
+    .bind(user_id)
+    .bind(dog_id)
+    .execute(executor)
+    .await?;
+    Ok(())
+}
+
+/// (match, dog) for every vote this user has cast in the tournament
+pub async fn votes_by(
+    executor: impl Executor<'_>,
+    tournament_id: i64,
+    user_id: i64,
+) -> Result<Vec<(i64, i64)>, sqlx::Error> {
+    sqlx::query_as(
+        "SELECT bracket_match_id, bracket_vote.dog_id FROM bracket_vote JOIN bracket_match ON bracket_vote.bracket_match_id = bracket_match.id WHERE tournament_id = $1 AND user_id = $2",
+    )
+    .bind(tournament_id)
+    .bind(user_id)
+    .fetch_all(executor)
+    .await
+}
+
+/// (match, dog, votes) across the tournament. quarantined users' votes don't count (see fraud.rs).
+pub async fn tallies(
+    executor: impl Executor<'_>,
+    tournament_id: i64,
+) -> Result<Vec<(i64, i64, i64)>, sqlx::Error> {
+    sqlx::query_as(
+        r#"SELECT bracket_match_id, bracket_vote.dog_id, COUNT(*) FROM bracket_vote
+        JOIN bracket_match ON bracket_vote.bracket_match_id = bracket_match.id
+        JOIN "user" ON bracket_vote.user_id = "user".id
+        WHERE tournament_id = $1 AND NOT "user".quarantined AND "user".trust >= 100
+        GROUP BY bracket_match_id, bracket_vote.dog_id"#,
+    )
+    .bind(tournament_id)
+    .fetch_all(executor)
+    .await
+}
+
+/// Deletes every vote a user has cast in any tournament. Matches they helped decide stay decided.
+pub async fn delete_votes_by(
+    executor: impl Executor<'_>,
+    user_id: i64,
+) -> Result<u64, sqlx::Error> {
+    Ok(sqlx::query("DELETE FROM bracket_vote WHERE user_id = $1")
+        .bind(user_id)
+        .execute(executor)
+        .await?
+        .rows_affected())
+}
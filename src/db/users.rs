@@ -0,0 +1,177 @@
+use super::{minutes_ago, Executor, Flag};
+use std::net::IpAddr;
+
+/// A new anonymous user, returning their id. `client_ip` is where they first showed up from.
+pub async fn create(
+    executor: impl Executor<'_>,
+    client_ip: Option<IpAddr>,
+) -> Result<i64, sqlx::Error> {
+    sqlx::query_scalar(r#"INSERT INTO "user" (client_ip) VALUES ($1) RETURNING id"#)
+        .bind(client_ip.map(|ip| ip.to_string()))
+        .fetch_one(executor)
+        .await
+}
+
+pub async fn email(
+    executor: impl Executor<'_>,
+    user_id: i64,
+) -> Result<Option<String>, sqlx::Error> {
+    sqlx::query_scalar(r#"SELECT email FROM "user" WHERE id = $1"#)
+        .bind(user_id)
+        .fetch_one(executor)
+        .await
+}
+
+pub async fn find_by_email(
+    executor: impl Executor<'_>,
+    email: &str,
+) -> Result<Option<i64>, sqlx::Error> {
+    sqlx::query_scalar(r#"SELECT id FROM "user" WHERE email = $1"#)
+        .bind(email)
+        .fetch_optional(executor)
+        .await
+}
+
+pub async fn total_xp(executor: impl Executor<'_>, user_id: i64) -> Result<u32, sqlx::Error> {
+    let total_xp: i64 = sqlx::query_scalar(r#"SELECT total_xp FROM "user" WHERE id = $1"#)
+        .bind(user_id)
+        .fetch_one(executor)
+        .await?;
+    Ok(total_xp as u32)
+}
+
+pub async fn add_xp(executor: impl Executor<'_>, user_id: i64, xp: u32) -> Result<(), sqlx::Error> {
+    sqlx::query(r#"UPDATE "user" SET total_xp = total_xp + $1 WHERE id = $2"#)
+        .bind(i64::from(xp))
+        .bind(user_id)
+        .execute(executor)
+        .await?;
+    Ok(())
+}
+
+/// Takes back xp, without going below zero
+pub async fn remove_xp(
+    executor: impl Executor<'_>,
+    user_id: i64,
+    xp: u32,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"UPDATE "user" SET total_xp = CASE WHEN total_xp > $1 THEN total_xp - $1 ELSE 0 END WHERE id = $2"#,
+    )
+    .bind(i64::from(xp))
+    .bind(user_id)
+    .execute(executor)
+    .await?;
+    Ok(())
+}
+
+/// Ties an email to an anonymous user, along with the xp they get for signing up
+pub async fn sign_up(
+    executor: impl Executor<'_>,
+    user_id: i64,
+    email: &str,
+    xp: u32,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(r#"UPDATE "user" SET email = $1, total_xp = total_xp + $2 WHERE id = $3"#)
+        .bind(email)
+        .bind(i64::from(xp))
+        .bind(user_id)
+        .execute(executor)
+        .await?;
+    Ok(())
+}
+
+/// What the fraud checks know about a user (see fraud.rs)
+#[derive(Debug, sqlx::FromRow)]
+pub struct Standing {
+    pub client_ip: Option<String>,
+    pub trust: i64,
+    #[sqlx(try_from = "Flag")]
+    pub quarantined: bool,
+    pub first_picks: Option<String>,
+}
+
+pub async fn standing(executor: impl Executor<'_>, user_id: i64) -> Result<Standing, sqlx::Error> {
+    sqlx::query_as::<_, Standing>(
+        r#"SELECT client_ip, trust, CASE WHEN quarantined THEN 1 ELSE 0 END AS quarantined, first_picks FROM "user" WHERE id = $1"#,
+    )
+    .bind(user_id)
+    .fetch_one(executor)
+    .await
+}
+
+pub async fn set_trust(
+    executor: impl Executor<'_>,
+    user_id: i64,
+    trust: u8,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(r#"UPDATE "user" SET trust = $1 WHERE id = $2"#)
+        .bind(i64::from(trust))
+        .bind(user_id)
+        .execute(executor)
+        .await?;
+    Ok(())
+}
+
+pub async fn set_first_picks(
+    executor: impl Executor<'_>,
+    user_id: i64,
+    first_picks: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(r#"UPDATE "user" SET first_picks = $1 WHERE id = $2"#)
+        .bind(first_picks)
+        .bind(user_id)
+        .execute(executor)
+        .await?;
+    Ok(())
+}
+
+/// Users created from `client_ip` in the last `minutes` minutes
+pub async fn count_new_from_ip(
+    executor: impl Executor<'_>,
+    client_ip: &str,
+    minutes: i64,
+) -> Result<i64, sqlx::Error> {
+    sqlx::query_scalar(r#"SELECT COUNT(*) FROM "user" WHERE client_ip = $1 AND created_at > $2"#)
+        .bind(client_ip)
+        .bind(minutes_ago(minutes))
+        .fetch_one(executor)
+        .await
+}
+
+/// Other users whose first picks came out exactly the same
+pub async fn count_with_first_picks(
+    executor: impl Executor<'_>,
+    first_picks: &str,
+    except_user_id: i64,
+) -> Result<i64, sqlx::Error> {
+    sqlx::query_scalar(r#"SELECT COUNT(*) FROM "user" WHERE first_picks = $1 AND id <> $2"#)
+        .bind(first_picks)
+        .bind(except_user_id)
+        .fetch_one(executor)
+        .await
+}
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct Suspect {
+    pub id: i64,
+    pub email: Option<String>,
+    pub client_ip: Option<String>,
+    pub trust: i64,
+    #[sqlx(try_from = "Flag")]
+    pub quarantined: bool,
+    pub votes: i64,
+    pub created_at: Option<String>,
+}
+
+/// Users who have been flagged or quarantined, least trusted first
+pub async fn suspects(executor: impl Executor<'_>) -> Result<Vec<Suspect>, sqlx::Error> {
+    sqlx::query_as::<_, Suspect>(
+        r#"SELECT id, email, client_ip, trust, CASE WHEN quarantined THEN 1 ELSE 0 END AS quarantined,
+            (SELECT COUNT(*) FROM match WHERE match.user_id = "user".id AND status IN ('>', '<', '=')) AS votes,
+            CAST(created_at AS TEXT) AS created_at
+        FROM "user" WHERE trust < 100 OR quarantined ORDER BY trust, id"#,
+    )
+    .fetch_all(executor)
+    .await
+}
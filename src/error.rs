@@ -0,0 +1,233 @@
+use crate::layout::layout;
+use axum::{
+    http::{
+        header::{self, HeaderName},
+        HeaderValue, Request, StatusCode,
+    },
+    middleware::Next,
+    response::{Html, IntoResponse, Response},
+};
+use maud::{html, Markup};
+use std::time::Duration;
+
+// handlers return Result<_, AppError> and use ? on anything that can fail. the cause is logged
+// here and the visitor gets a page that fits the site instead of a dropped connection.
+// htmx requests get a small toast instead of a whole page (see htmx_errors below).
+
+#[derive(Debug)]
+pub enum AppError {
+    NotFound,
+    /// Something about the request was wrong, and the message says what
+    BadRequest(String),
+    /// Turned away (see csrf.rs), and the message says what to do about it
+    Forbidden(String),
+    /// Over a rate limit (see rate_limit.rs), with how long until there's room again
+    TooManyRequests(Duration),
+    Database(sqlx::Error),
+    Internal(anyhow::Error),
+}
+
+impl From<sqlx::Error> for AppError {
+    fn from(error: sqlx::Error) -> Self {
+        match error {
+            sqlx::Error::RowNotFound => Self::NotFound,
+            error => Self::Database(error),
+        }
+    }
+}
+
+impl From<anyhow::Error> for AppError {
+    fn from(error: anyhow::Error) -> Self {
+        Self::Internal(error)
+    }
+}
+
+impl AppError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Self::NotFound => StatusCode::NOT_FOUND,
+            Self::BadRequest(_) => StatusCode::BAD_REQUEST,
+            Self::Forbidden(_) => StatusCode::FORBIDDEN,
+            Self::TooManyRequests(_) => StatusCode::TOO_MANY_REQUESTS,
+            Self::Database(sqlx::Error::PoolTimedOut | sqlx::Error::PoolClosed) => {
+                StatusCode::SERVICE_UNAVAILABLE
+            }
+            Self::Database(_) | Self::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// What the visitor is told, which never includes the cause
+    pub fn message(&self) -> String {
+        match self {
+            Self::NotFound => "We couldn't find what you were looking for.".to_string(),
+            Self::BadRequest(message) | Self::Forbidden(message) => message.clone(),
+            Self::TooManyRequests(retry_after) => match retry_seconds(*retry_after) {
+                1 => "Slow down there! Try again in a second.".to_string(),
+                seconds if seconds < 120 => {
+                    format!("Slow down there! Try again in {} seconds.", seconds)
+                }
+                seconds => format!(
+                    "Slow down there! Try again in {} minutes.",
+                    seconds.div_ceil(60)
+                ),
+            },
+            Self::Database(_) | Self::Internal(_) => {
+                if self.status() == StatusCode::SERVICE_UNAVAILABLE {
+                    "We're a little busy right now, try again in a moment.".to_string()
+                } else {
+                    "Something went wrong on our end. Try again?".to_string()
+                }
+            }
+        }
+    }
+}
+
+/// Whole seconds, rounded up so nobody comes back a moment too soon
+fn retry_seconds(retry_after: Duration) -> u64 {
+    retry_after.as_secs_f64().ceil().max(1.0) as u64
+}
+
+/// Attached to error responses so htmx_errors can swap the page for a toast
+#[derive(Clone)]
+struct ErrorMessage(String);
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        let status = self.status();
+        match &self {
+            Self::Database(error) => eprintln!("{}: database error: {}", status, error),
+            Self::Internal(error) => eprintln!("{}: {:#}", status, error),
+            Self::NotFound
+            | Self::BadRequest(_)
+            | Self::Forbidden(_)
+            | Self::TooManyRequests(_) => {}
+        }
+
+        let message = self.message();
+        let mut response =
+            (status, Html(error_page(status, &message).into_string())).into_response();
+        response.extensions_mut().insert(ErrorMessage(message));
+        if let Self::TooManyRequests(retry_after) = self {
+            response
+                .headers_mut()
+                .insert(header::RETRY_AFTER, retry_seconds(retry_after).into());
+        }
+        response
+    }
+}
+
+pub async fn not_found() -> AppError {
+    AppError::NotFound
+}
+
+fn error_page(status: StatusCode, message: &str) -> Markup {
+    let heading = match status {
+        StatusCode::NOT_FOUND => "Ruh-roh, nothing here",
+        StatusCode::BAD_REQUEST => "Hmm, that didn't work",
+        StatusCode::FORBIDDEN => "Hold your horses",
+        StatusCode::TOO_MANY_REQUESTS => "Easy there, pup",
+        _ => "Ruh-roh...",
+    };
+    layout(
+        html! {
+            div class="flex-1 flex flex-col gap-4 items-center justify-center text-center" {
+                h1 class="text-5xl" {(heading)}
+                h2 class="text-3xl" {(message)}
+                a class="text-3xl underline text-primary" href="/" {"Back to the dog show"}
+            }
+        },
+        Some(status.canonical_reason().unwrap_or("Error").to_string()),
+        None,
+        false,
+    )
+}
+
+/// Shown in the #error-toast slot of the layout, and goes away on its own
+fn error_toast(message: &str) -> Markup {
+    html! {
+        div role="alert" class="alert alert-error w-auto max-w-screen-sm text-lg pointer-events-auto"
+            data-fade-after="6" {
+            (message)
+        }
+    }
+}
+
+/// htmx won't swap in a whole page for a failed request, so those get the toast instead
+pub async fn htmx_errors<B>(req: Request<B>, next: Next<B>) -> Response {
+    let from_htmx = req.headers().contains_key("hx-request");
+    let response = next.run(req).await;
+    if !from_htmx {
+        return response;
+    }
+    let Some(ErrorMessage(message)) = response.extensions().get::<ErrorMessage>().cloned() else {
+        return response;
+    };
+
+    let mut toast = (response.status(), Html(error_toast(&message).into_string())).into_response();
+    let headers = toast.headers_mut();
+    if let Some(retry_after) = response.headers().get(header::RETRY_AFTER) {
+        headers.insert(header::RETRY_AFTER, retry_after.clone());
+    }
+    // cookies still need setting when the request failed (see csrf.rs)
+    for cookie in response.headers().get_all(header::SET_COOKIE) {
+        headers.append(header::SET_COOKIE, cookie.clone());
+    }
+    headers.insert(
+        HeaderName::from_static("hx-retarget"),
+        HeaderValue::from_static("#error-toast"),
+    );
+    headers.insert(
+        HeaderName::from_static("hx-reswap"),
+        HeaderValue::from_static("beforeend"),
+    );
+    toast
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn statuses() {
+        assert_eq!(AppError::NotFound.status(), StatusCode::NOT_FOUND);
+        assert_eq!(
+            AppError::from(sqlx::Error::RowNotFound).status(),
+            StatusCode::NOT_FOUND
+        );
+        assert_eq!(
+            AppError::from(sqlx::Error::PoolTimedOut).status(),
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+        assert_eq!(
+            AppError::TooManyRequests(Duration::from_secs(3)).status(),
+            StatusCode::TOO_MANY_REQUESTS
+        );
+        assert_eq!(
+            AppError::Forbidden("Refresh".to_string()).status(),
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            AppError::from(anyhow::anyhow!("disk full")).status(),
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+    }
+
+    #[test]
+    fn causes_stay_out_of_messages() {
+        let error = AppError::from(anyhow::anyhow!("couldn't open /secret/path"));
+        assert!(!error.message().contains("/secret/path"));
+        let error = AppError::BadRequest("Must be an image".to_string());
+        assert_eq!(error.message(), "Must be an image");
+    }
+
+    #[test]
+    fn waits_round_up() {
+        let message = |millis| AppError::TooManyRequests(Duration::from_millis(millis)).message();
+        assert_eq!(message(200), "Slow down there! Try again in a second.");
+        assert_eq!(message(4100), "Slow down there! Try again in 5 seconds.");
+        assert_eq!(
+            message(600_000),
+            "Slow down there! Try again in 10 minutes."
+        );
+    }
+}
@@ -0,0 +1,263 @@
+use crate::db::{self, log::Event, matches::Pick};
+use chrono::Duration;
+use sqlx::AnyConnection;
+use std::net::IpAddr;
+
+// ballot stuffing detection. anybody can script `POST /pick-winner/:id` with fresh cookies, and every
+// cookieless request gets a brand new user, so after each vote we look for signs of a script and
+// lower the voter's trust. trust is a percentage that scales how far their votes move the overall
+// ratings (personal ratings are theirs to wreck). it only ever goes down on its own, an admin can
+// quarantine a user (trust 0, including the votes they've already cast) or release them.
+
+/// How many of a user's first picks make up the sequence compared between users
+pub const FIRST_PICKS: u32 = 10;
+/// How many recent picks the checks look back over
+const RECENT_PICKS: u32 = 20;
+/// Consecutive picks made in under a second
+const FAST_STREAK: usize = 10;
+/// Share of recent picks that went to the dog on the left
+const LEFT_SIDE_SHARE: f64 = 0.95;
+/// New users from one IP address in the last hour. It's the address the proxy saw (see
+/// `auth::client_ip`), so forging X-Forwarded-For doesn't spread a script's users out.
+const USERS_PER_IP: i64 = 10;
+/// Other users with exactly the same first picks
+const COPIED_SEQUENCES: i64 = 3;
+
+#[derive(Debug, Default)]
+pub struct Signals {
+    /// Newest first
+    pub recent_picks: Vec<Pick>,
+    /// Including this user
+    pub new_users_from_ip: i64,
+    pub same_first_picks: i64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Flag {
+    FastPicks,
+    CrowdedIp,
+    LeftSide,
+    CopiedSequence,
+}
+
+impl Flag {
+    /// How much trust it costs, out of 100
+    fn penalty(self) -> u8 {
+        match self {
+            Self::FastPicks => 50,
+            Self::CrowdedIp => 30,
+            Self::LeftSide => 50,
+            Self::CopiedSequence => 50,
+        }
+    }
+
+    pub fn reason(self) -> &'static str {
+        match self {
+            Self::FastPicks => "a streak of sub-second picks",
+            Self::CrowdedIp => "lots of new users from the same IP address",
+            Self::LeftSide => "nearly always picks the dog on the left",
+            Self::CopiedSequence => "same first picks as several other users",
+        }
+    }
+}
+
+pub fn flags(signals: &Signals) -> Vec<Flag> {
+    let mut flags = vec![];
+
+    let fast = |pick: &Pick| {
+        pick.time_taken
+            .is_some_and(|time_taken| time_taken < Duration::seconds(1))
+    };
+    if signals.recent_picks.len() >= FAST_STREAK
+        && signals.recent_picks[..FAST_STREAK].iter().all(fast)
+    {
+        flags.push(Flag::FastPicks);
+    }
+
+    if signals.new_users_from_ip >= USERS_PER_IP {
+        flags.push(Flag::CrowdedIp);
+    }
+
+    let picks = signals.recent_picks.len();
+    let left = signals
+        .recent_picks
+        .iter()
+        .filter(|pick| pick.status == ">")
+        .count();
+    if picks >= RECENT_PICKS as usize && left as f64 >= picks as f64 * LEFT_SIDE_SHARE {
+        flags.push(Flag::LeftSide);
+    }
+
+    if signals.same_first_picks >= COPIED_SEQUENCES {
+        flags.push(Flag::CopiedSequence);
+    }
+
+    flags
+}
+
+pub fn trust(flags: &[Flag]) -> u8 {
+    let penalty: u8 = flags
+        .iter()
+        .map(|flag| flag.penalty())
+        .fold(0, u8::saturating_add);
+    100_u8.saturating_sub(penalty)
+}
+
+/// Looks the voter over after their pick on `match_id` is resolved, lowering their trust if they
+/// seem to be a script, and records how much the pick counts. Returns that trust.
+pub async fn check_vote(
+    conn: &mut AnyConnection,
+    user_id: i64,
+    match_id: i64,
+    client_ip: Option<IpAddr>,
+) -> Result<u8, sqlx::Error> {
+    let standing = db::users::standing(&mut *conn, user_id).await?;
+
+    let mut first_picks = standing.first_picks;
+    if first_picks.is_none() {
+        let outcomes = db::matches::first_outcomes(&mut *conn, user_id, FIRST_PICKS).await?;
+        if outcomes.chars().count() == FIRST_PICKS as usize {
+            db::users::set_first_picks(&mut *conn, user_id, &outcomes).await?;
+            first_picks = Some(outcomes);
+        }
+    }
+
+    let signals = Signals {
+        recent_picks: db::matches::recent_picks(&mut *conn, user_id, RECENT_PICKS).await?,
+        new_users_from_ip: match &standing.client_ip {
+            Some(ip) => db::users::count_new_from_ip(&mut *conn, ip, 60).await?,
+            None => 0,
+        },
+        same_first_picks: match &first_picks {
+            Some(first_picks) => {
+                db::users::count_with_first_picks(&mut *conn, first_picks, user_id).await?
+            }
+            None => 0,
+        },
+    };
+    let flags = flags(&signals);
+    let assessed = trust(&flags);
+
+    let previous = standing.trust.clamp(0, 100) as u8;
+    if assessed < previous {
+        db::users::set_trust(&mut *conn, user_id, assessed).await?;
+        let reason = flags
+            .iter()
+            .map(|flag| flag.reason())
+            .collect::<Vec<_>>()
+            .join(", ");
+        let _ = db::log::record(
+            &mut *conn,
+            &Event::VoteFlagged { match_id, reason },
+            Some(user_id),
+            client_ip,
+        )
+        .await;
+    }
+
+    let trust = if standing.quarantined {
+        0
+    } else {
+        assessed.min(previous)
+    };
+    db::matches::set_trust(&mut *conn, match_id, trust).await?;
+    Ok(trust)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn picks(statuses: &str, seconds: i64) -> Vec<Pick> {
+        statuses
+            .chars()
+            .map(|status| Pick {
+                status: status.to_string(),
+                time_taken: Some(Duration::seconds(seconds)),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn people_are_trusted() {
+        let signals = Signals {
+            recent_picks: picks("><=<>><<>=><>><<=><>", 3),
+            new_users_from_ip: 2,
+            same_first_picks: 1,
+        };
+        assert_eq!(flags(&signals), vec![]);
+        assert_eq!(trust(&flags(&signals)), 100);
+        assert_eq!(flags(&Signals::default()), vec![]);
+    }
+
+    #[test]
+    fn fast_streaks_are_flagged() {
+        let mut recent_picks = picks("><=<>><<>=", 0);
+        assert_eq!(
+            flags(&Signals {
+                recent_picks: recent_picks.clone(),
+                ..Default::default()
+            }),
+            vec![Flag::FastPicks]
+        );
+
+        // one slow pick breaks the streak, and so does not knowing how long it took
+        recent_picks[4].time_taken = Some(Duration::seconds(2));
+        assert_eq!(
+            flags(&Signals {
+                recent_picks: recent_picks.clone(),
+                ..Default::default()
+            }),
+            vec![]
+        );
+        recent_picks[4].time_taken = None;
+        assert_eq!(
+            flags(&Signals {
+                recent_picks,
+                ..Default::default()
+            }),
+            vec![]
+        );
+    }
+
+    #[test]
+    fn left_side_voters_are_flagged_once_theres_enough_picks() {
+        let signals = |statuses: &str| Signals {
+            recent_picks: picks(statuses, 4),
+            ..Default::default()
+        };
+        assert_eq!(flags(&signals(">>>>>>>>>>")), vec![]);
+        assert_eq!(
+            flags(&signals(">>>>>>>>>>>>>>>>>>>>")),
+            vec![Flag::LeftSide]
+        );
+        assert_eq!(
+            flags(&signals(">>>>>>>>>=>>>>>>>>>>")),
+            vec![Flag::LeftSide]
+        );
+        assert_eq!(flags(&signals(">>>>>>>>>=>>>>>>>><>")), vec![]);
+    }
+
+    #[test]
+    fn crowds_and_copies_are_flagged() {
+        let signals = Signals {
+            new_users_from_ip: USERS_PER_IP,
+            same_first_picks: COPIED_SEQUENCES,
+            ..Default::default()
+        };
+        assert_eq!(flags(&signals), vec![Flag::CrowdedIp, Flag::CopiedSequence]);
+        assert_eq!(trust(&flags(&signals)), 20);
+    }
+
+    #[test]
+    fn trust_bottoms_out() {
+        let every_flag = [
+            Flag::FastPicks,
+            Flag::CrowdedIp,
+            Flag::LeftSide,
+            Flag::CopiedSequence,
+        ];
+        assert_eq!(trust(&every_flag), 0);
+        assert_eq!(trust(&[Flag::FastPicks]), 50);
+    }
+}
@@ -1,5 +1,6 @@
+use crate::{assets, security_headers::ANALYTICS_ORIGIN};
 use axum::response::{Html, IntoResponse};
-use maud::{html, Markup, PreEscaped, DOCTYPE};
+use maud::{html, Markup, DOCTYPE};
 
 pub fn base(
     content: Markup,
@@ -15,10 +16,10 @@ pub fn layout(
     active_nav_link: Option<NavLink>,
     hide_navbar: bool,
 ) -> Markup {
-    let formatted_title = if title.is_none() {
-        "Top Doggo".to_string()
+    let formatted_title = if let Some(title) = title {
+        format!("{} - Top Doggo", title)
     } else {
-        format!("{} - Top Doggo", title.unwrap())
+        "Top Doggo".to_string()
     };
     let description = "Which doggo is best? You decide. Top Doggo is an elo-based dog show where you're the judge.";
     let image = "/images/5.jpg";
@@ -28,29 +29,15 @@ pub fn layout(
         html lang="en";
         head {
             // FOR PROD uncomment the Plausible analytics
-            script defer data-domain="topdoggo.app" src="https://plausible.parkerbedlan.com/js/script.js" {}
-            // https://www.srihash.org/
-            // https://htmx.org/docs/#installing
-            script src="https://unpkg.com/htmx.org@2.0.0" integrity="sha384-wS5l5IKJBvK6sPTKa2WZ1js3d947pvWXbPJ1OmWfEuxLgeHcEbjUUA5i9V5ZkpCw" crossorigin="anonymous" {}
-            script {(PreEscaped(r#"
-                        document.addEventListener("DOMContentLoaded", () => {
-                            htmx.config.useTemplateFragments = true;
-                            // https://htmx.org/events/
-                            // to see all the events as they happen
-                            // htmx.logAll()
-                            document.body.addEventListener("htmx:beforeSwap", (event) => {
-                                if (event.detail.xhr.status === 422) {
-                                    event.detail.shouldSwap = true;
-                                    // suppresses error logging in the console
-                                    event.detail.isError = false;
-                                }
-                            })
-                        })
-                "#))}
-            script src="https://unpkg.com/hyperscript.org@0.9.12" {}
+            script defer data-domain="topdoggo.app" src={(ANALYTICS_ORIGIN)"/js/script.js"} {}
+            // https://htmx.org/docs/#config, eval and script tags in responses are off so they don't need a CSP exception
+            meta name="htmx-config" content=r#"{"allowEval":false,"allowScriptTags":false,"useTemplateFragments":true}"#;
+            // htmx 2.0.0, downloaded and checked by vendor.sh
+            script src=(assets::url("vendor/htmx.min.js")) {}
+            script src=(assets::url("js/top-doggo.js")) {}
             // FOR PROD comment out the tailwind cdn
             // script src="https://cdn.tailwindcss.com" {}
-            link rel="stylesheet" href="/output.css";
+            link rel="stylesheet" href=(assets::url("output.css"));
             title {(formatted_title)}
             meta name="HandheldFriendly" content="true" ;
             meta name="viewport" content="width=device-width, initial-scale=1.0, maximum-scale=1.0, user-scalable=no" ;
@@ -80,6 +67,9 @@ pub fn layout(
                 class="animate-spin fixed top-1 left-1 z-50 rounded-full bg-base-100 htmx-indicator" {
                 (spinner_icon())
             }
+            div id="error-toast" aria-live="assertive" class="toast toast-top toast-center z-50 pointer-events-none" {}
+            // screen readers hear about xp and level ups from here, the bars themselves are only for looking at
+            div id="announcer" aria-live="polite" class="sr-only" {}
             {(content)}
             @if !hide_navbar {(navbar(active_nav_link))}
         }
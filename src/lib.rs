@@ -0,0 +1,126 @@
+use axum::extract::DefaultBodyLimit;
+use axum::{
+    handler::HandlerWithoutStateExt,
+    middleware::{self},
+    Router,
+};
+use sqlx::{Any, Pool};
+use std::{env, path::PathBuf, sync::Arc};
+use tower_http::{
+    normalize_path::{NormalizePath, NormalizePathLayer},
+    services::ServeDir,
+    trace::TraceLayer,
+};
+use tower_layer::Layer;
+
+pub mod admin;
+pub mod assets;
+mod auth;
+pub mod backup;
+mod csrf;
+pub mod db;
+pub mod error;
+pub mod fraud;
+mod layout;
+pub mod mailer;
+pub mod metrics;
+pub mod rate_limit;
+pub mod routers;
+mod security_headers;
+pub mod session_cookie;
+
+use mailer::Mailer;
+use rate_limit::RateLimiter;
+use session_cookie::SessionKeys;
+
+#[derive(Clone)]
+pub struct AppState {
+    // sqlite or postgres, see db.rs
+    pub pool: Pool<Any>,
+    pub mailer: Mailer,
+    // where approved dog photos are served from
+    pub images_dir: PathBuf,
+    // where uploads wait until they're approved
+    pub unapproved_dir: PathBuf,
+    // resized copies of the dog photos (see routers/images)
+    pub image_cache_dir: PathBuf,
+    // where database snapshots go (see backup.rs)
+    pub backups_dir: PathBuf,
+    pub rate_limiter: Arc<RateLimiter>,
+    // signs the auth cookie, see session_cookie.rs
+    pub session_keys: SessionKeys,
+}
+impl AppState {
+    pub fn new(pool: Pool<Any>) -> Self {
+        Self {
+            rate_limiter: Arc::new(RateLimiter::from_env(&pool)),
+            pool,
+            mailer: Mailer::from_env(),
+            images_dir: PathBuf::from("./assets/images"),
+            unapproved_dir: PathBuf::from("./unapproved"),
+            image_cache_dir: env::var("IMAGE_CACHE_DIR")
+                .map(PathBuf::from)
+                .unwrap_or_else(|_| PathBuf::from("./image_cache")),
+            backups_dir: backup::backups_dir_from_env(),
+            session_keys: SessionKeys::from_env(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AppContext {
+    user_id: i64,
+    user_email: Option<String>,
+    client_ip: Option<std::net::IpAddr>,
+}
+
+pub fn app(state: AppState) -> NormalizePath<Router> {
+    let app = Router::new()
+        .nest("/leaderboard", routers::leaderboard())
+        .nest("/", routers::doggo())
+        .nest("/upload", routers::upload())
+        .nest("/tournaments", routers::tournaments())
+        .nest("/", routers::me())
+        .nest("/admin", routers::admin())
+        .nest("/test", routers::test::test_router())
+        // static files, which skip the route layers below (see assets.rs)
+        .fallback_service(
+            middleware::from_fn(assets::static_files).layer(
+                ServeDir::new("assets")
+                    .precompressed_br()
+                    .precompressed_gzip()
+                    .not_found_service(error::not_found.into_service()),
+            ),
+        )
+        .route_layer(middleware::from_fn_with_state(state.clone(), auth::auth))
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            rate_limit::rate_limit,
+        ))
+        .route_layer(middleware::from_fn(csrf::csrf))
+        .route_layer(middleware::from_fn(metrics::track_metrics))
+        .merge(routers::metrics())
+        .merge(routers::health())
+        .merge(routers::images())
+        .layer(middleware::from_fn(error::htmx_errors))
+        .layer(middleware::from_fn(security_headers::security_headers))
+        .layer(TraceLayer::new_for_http())
+        .layer(DefaultBodyLimit::max(1024 * 1024 * 10)) // 10 MiB
+        .with_state(state);
+
+    // so that `/foo` and `/foo/` render the same page
+    NormalizePathLayer::trim_trailing_slash().layer(app)
+}
+
+pub struct FormField<T> {
+    value: T,
+    error: String,
+}
+impl FormField<String> {
+    fn empty() -> Self {
+        Self {
+            value: "".to_string(),
+            error: "".to_string(),
+        }
+    }
+}
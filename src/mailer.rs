@@ -0,0 +1,111 @@
+use lettre::{
+    message::{header::ContentType, Mailbox},
+    transport::smtp::{authentication::Credentials, client::TlsParameters},
+    Message, SmtpTransport, Transport,
+};
+use maud::Markup;
+use std::{
+    env,
+    sync::{Arc, Mutex},
+};
+
+#[derive(Clone)]
+pub enum Mailer {
+    // sends for real using the SMTP_* env variables
+    Smtp,
+    // MODE="development", just prints the email
+    Log,
+    // holds onto every email so that tests can follow magic links
+    Capture(Arc<Mutex<Vec<CapturedEmail>>>),
+}
+
+#[derive(Debug, Clone)]
+pub struct CapturedEmail {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+impl Mailer {
+    pub fn from_env() -> Self {
+        if env::var("MODE").unwrap() == "development" {
+            Mailer::Log
+        } else {
+            Mailer::Smtp
+        }
+    }
+
+    pub fn capture() -> Self {
+        Mailer::Capture(Arc::new(Mutex::new(vec![])))
+    }
+
+    pub fn captured(&self) -> Vec<CapturedEmail> {
+        match self {
+            Mailer::Capture(emails) => emails.lock().unwrap().clone(),
+            _ => vec![],
+        }
+    }
+
+    pub async fn send(
+        &self,
+        to_mailbox: Mailbox,
+        subject: &str,
+        content: Markup,
+    ) -> Result<(), ()> {
+        match self {
+            Mailer::Log => {
+                println!("Email that would be sent: {:?}{:?}", to_mailbox, content);
+                return Ok(());
+            }
+            Mailer::Capture(emails) => {
+                emails.lock().unwrap().push(CapturedEmail {
+                    to: to_mailbox.email.to_string(),
+                    subject: subject.to_string(),
+                    body: content.into_string(),
+                });
+                return Ok(());
+            }
+            Mailer::Smtp => {}
+        }
+
+        let email = Message::builder()
+            .from(
+                "Top Doggo <parkerbedlan@gmail.com>"
+                    .to_string()
+                    .parse()
+                    .unwrap(),
+            )
+            .to(to_mailbox)
+            .subject(subject)
+            .header(ContentType::TEXT_HTML)
+            .body(content.into_string())
+            .unwrap();
+
+        let creds = Credentials::new(
+            env::var("SMTP_USERNAME").expect("SMTP Username not specified "),
+            env::var("SMTP_PASSWORD").expect("SMTP Password not specified"),
+        );
+
+        // necessary to set to port 587 due to https://docs.hetzner.com/cloud/servers/faq/#why-can-i-not-send-any-mails-from-my-server
+        let tls_parameters =
+            TlsParameters::new(env::var("SMTP_HOST").expect("SMTP Host not specified")).unwrap();
+        let mailer = SmtpTransport::relay(&env::var("SMTP_HOST").expect("SMTP Host not specified"))
+            .unwrap()
+            .port(587)
+            .credentials(creds)
+            .tls(lettre::transport::smtp::client::Tls::Required(
+                tls_parameters,
+            ))
+            .build();
+
+        match mailer.send(&email) {
+            Ok(_) => println!("Email sent successfully!"),
+            Err(e) => {
+                println!("Email failed to send: {}", e);
+                return Err(());
+            }
+        }
+
+        Ok(())
+    }
+}
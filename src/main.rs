@@ -1,79 +1,92 @@
-use axum::extract::DefaultBodyLimit;
 use axum::ServiceExt;
-use axum::{
-    middleware::{self},
-    Router,
-};
 use dotenv::dotenv;
-use sqlx::{Pool, Sqlite, SqlitePool};
 use std::{env, error::Error, net::SocketAddr};
-use tower_http::{normalize_path::NormalizePathLayer, services::ServeDir, trace::TraceLayer};
-use tower_layer::Layer;
-
-mod auth;
-mod layout;
-mod routers;
-
-#[derive(Clone)]
-pub struct AppState {
-    pool: Pool<Sqlite>,
-}
-
-#[derive(Debug, Clone)]
-struct AppContext {
-    user_id: i64,
-    user_email: Option<String>,
-    client_ip: Option<std::net::IpAddr>,
-}
+use top_doggo::{
+    app, assets, backup,
+    db::{self, Backend},
+    metrics, AppState,
+};
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
     dotenv().ok();
 
-    let pool = SqlitePool::connect(&env::var("DATABASE_URL")?).await?;
+    // sqlite:... or postgres://...
+    let pool = db::connect(&env::var("DATABASE_URL")?).await?;
 
     // FOR PROD make sure this is not commented out
-    sqlx::migrate!("./migrations").run(&pool).await?;
-
-    let state = AppState { pool };
-
-    let app = Router::new()
-        .nest("/leaderboard", routers::leaderboard())
-        .nest("/", routers::doggo())
-        .nest("/upload", routers::upload())
-        .nest("/", routers::me())
-        .nest("/test", routers::test::test_router())
-        .fallback_service(ServeDir::new("assets"))
-        .route_layer(middleware::from_fn_with_state(state.clone(), auth::auth))
-        .layer(TraceLayer::new_for_http())
-        .layer(DefaultBodyLimit::max(1024 * 1024 * 10)) // 10 MiB
-        // only necessary if running the app without a proxy like traefik
-        // .layer(SecureClientIpSource::ConnectInfo.into_extension())
-        .with_state(state);
-
-    // so that `/foo` and `/foo/` render the same page
-    let app = NormalizePathLayer::trim_trailing_slash().layer(app);
+    db::migrate(&pool).await?;
+
+    metrics::init();
+
+    if let Err(error) = assets::precompress() {
+        eprintln!("couldn't precompress assets, serving them as is: {}", error);
+    }
+
+    let state = AppState::new(pool.clone());
+
+    let backups = backup::Schedule::from_env().and_then(|schedule| {
+        if Backend::of(&pool) != Backend::Sqlite {
+            eprintln!("scheduled backups only work with sqlite, ignoring BACKUP_INTERVAL_MINUTES");
+            return None;
+        }
+        println!(
+            "backing up to {:?} every {} minutes",
+            state.backups_dir,
+            schedule.interval.as_secs() / 60
+        );
+        Some(tokio::spawn(backup::run_schedule(
+            pool.clone(),
+            state.backups_dir.clone(),
+            schedule,
+        )))
+    });
+
+    let app = app(state);
 
     let addr = SocketAddr::from(([0, 0, 0, 0], 3000));
     println!("listening on {}", addr);
 
     axum::Server::bind(&addr)
         .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+        .with_graceful_shutdown(shutdown_signal())
         .await
         .unwrap();
 
+    // an interrupted backup only leaves a .partial file behind, which the next one replaces
+    if let Some(backups) = backups {
+        backups.abort();
+    }
+
+    // in-flight requests have finished by now, so this just waits for queries to wrap up and
+    // checkpoints the database before the process exits
+    println!("closing database pool");
+    pool.close().await;
+
     Ok(())
 }
 
-pub struct FormField<T> {
-    value: T,
-    error: String,
-}
-impl FormField<String> {
-    fn empty() -> Self {
-        Self {
-            value: "".to_string(),
-            error: "".to_string(),
-        }
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("failed to install Ctrl+C handler");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("failed to install SIGTERM handler")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
     }
+
+    println!("shutdown signal received, draining connections");
 }
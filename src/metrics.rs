@@ -0,0 +1,162 @@
+use axum::{
+    extract::MatchedPath,
+    http::Request,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use prometheus::{
+    register_histogram_vec, register_int_counter, register_int_counter_vec, register_int_gauge,
+    HistogramVec, IntCounter, IntCounterVec, IntGauge,
+};
+use std::{sync::LazyLock, time::Instant};
+
+// all of these live in the prometheus default registry, which is what /metrics gathers from
+
+pub static HTTP_REQUEST_DURATION_SECONDS: LazyLock<HistogramVec> = LazyLock::new(|| {
+    register_histogram_vec!(
+        "top_doggo_http_request_duration_seconds",
+        "HTTP request latency by route",
+        &["method", "route", "status"]
+    )
+    .unwrap()
+});
+
+pub static PICKS_TOTAL: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "top_doggo_picks_total",
+        "Matches resolved on the game board, by outcome",
+        &["outcome"]
+    )
+    .unwrap()
+});
+
+pub static PICKS_UNDONE_TOTAL: LazyLock<IntCounter> = LazyLock::new(|| {
+    register_int_counter!(
+        "top_doggo_picks_undone_total",
+        "Picks taken back with the undo button"
+    )
+    .unwrap()
+});
+
+pub static RANKINGS_TOTAL: LazyLock<IntCounter> = LazyLock::new(|| {
+    register_int_counter!(
+        "top_doggo_rankings_total",
+        "Rankings of several dogs at once submitted, each also counted as its matches"
+    )
+    .unwrap()
+});
+
+pub static NAMES_ASSIGNED_TOTAL: LazyLock<IntCounter> = LazyLock::new(|| {
+    register_int_counter!("top_doggo_names_assigned_total", "Dogs given a name").unwrap()
+});
+
+pub static UPLOADS_TOTAL: LazyLock<IntCounter> = LazyLock::new(|| {
+    register_int_counter!("top_doggo_uploads_total", "Dog photos uploaded").unwrap()
+});
+
+pub static MAGIC_LINKS_TOTAL: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "top_doggo_magic_links_total",
+        "Magic link emails, by whether they were sent or failed",
+        &["result"]
+    )
+    .unwrap()
+});
+
+pub static RATE_LIMITED_TOTAL: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "top_doggo_rate_limited_total",
+        "Requests turned away for going over a rate limit, by action",
+        &["action"]
+    )
+    .unwrap()
+});
+
+pub static BACKUPS_TOTAL: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "top_doggo_backups_total",
+        "Scheduled database backups, by whether they worked",
+        &["result"]
+    )
+    .unwrap()
+});
+
+pub static LAST_BACKUP_TIMESTAMP_SECONDS: LazyLock<IntGauge> = LazyLock::new(|| {
+    register_int_gauge!(
+        "top_doggo_last_backup_timestamp_seconds",
+        "When the last scheduled backup finished, as a unix timestamp"
+    )
+    .unwrap()
+});
+
+// the gauges below are refreshed every time /metrics is scraped
+
+pub static ACTIVE_SESSIONS: LazyLock<IntGauge> = LazyLock::new(|| {
+    register_int_gauge!(
+        "top_doggo_active_sessions",
+        "Users who have voted in the last 30 minutes"
+    )
+    .unwrap()
+});
+
+pub static DB_POOL_CONNECTIONS: LazyLock<IntGauge> = LazyLock::new(|| {
+    register_int_gauge!(
+        "top_doggo_db_pool_connections",
+        "Open connections in the database pool"
+    )
+    .unwrap()
+});
+
+pub static DB_POOL_IDLE_CONNECTIONS: LazyLock<IntGauge> = LazyLock::new(|| {
+    register_int_gauge!(
+        "top_doggo_db_pool_idle_connections",
+        "Idle connections in the database pool"
+    )
+    .unwrap()
+});
+
+/// Registers every metric up front so that /metrics lists them before they're first touched
+pub fn init() {
+    LazyLock::force(&HTTP_REQUEST_DURATION_SECONDS);
+    LazyLock::force(&PICKS_TOTAL);
+    LazyLock::force(&PICKS_UNDONE_TOTAL);
+    LazyLock::force(&RANKINGS_TOTAL);
+    LazyLock::force(&NAMES_ASSIGNED_TOTAL);
+    LazyLock::force(&UPLOADS_TOTAL);
+    LazyLock::force(&MAGIC_LINKS_TOTAL);
+    LazyLock::force(&RATE_LIMITED_TOTAL);
+    LazyLock::force(&BACKUPS_TOTAL);
+    LazyLock::force(&LAST_BACKUP_TIMESTAMP_SECONDS);
+    LazyLock::force(&ACTIVE_SESSIONS);
+    LazyLock::force(&DB_POOL_CONNECTIONS);
+    LazyLock::force(&DB_POOL_IDLE_CONNECTIONS);
+}
+
+/// Maps a `match.status` value to the label used by `PICKS_TOTAL`
+pub fn pick_outcome(status: &str) -> &'static str {
+    match status {
+        ">" => "dog_a",
+        "<" => "dog_b",
+        "=" => "tie",
+        "-" => "skip",
+        _ => "unknown",
+    }
+}
+
+pub async fn track_metrics<B>(req: Request<B>, next: Next<B>) -> impl IntoResponse {
+    let start = Instant::now();
+    // static files, 404s and scanners' probes share one label rather than a series per path
+    let route = match req.extensions().get::<MatchedPath>() {
+        Some(matched_path) => matched_path.as_str().to_string(),
+        None => "unmatched".to_string(),
+    };
+    let method = req.method().to_string();
+
+    let response: Response = next.run(req).await;
+
+    HTTP_REQUEST_DURATION_SECONDS
+        .with_label_values(&[&method, &route, response.status().as_str()])
+        .observe(start.elapsed().as_secs_f64());
+
+    response
+}
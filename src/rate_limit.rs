@@ -0,0 +1,507 @@
+use crate::{auth, db, error::AppError, metrics, AppState};
+use axum::{
+    extract::{ConnectInfo, State},
+    http::{Method, Request},
+    middleware::Next,
+    response::Response,
+};
+use axum_client_ip::RightmostXForwardedFor;
+use chrono::Utc;
+use sqlx::{Any, Pool};
+use std::{
+    collections::HashMap,
+    env,
+    net::{IpAddr, SocketAddr},
+    sync::Mutex,
+    time::Duration,
+};
+
+// token buckets, one per action for each user and each IP address. a bucket holds up to a budget's
+// worth of requests and refills smoothly over the budget's period, so bursts are fine but a steady
+// stream isn't. IP buckets are roomier since plenty of people share one (and requests without a
+// session only have their IP bucket, so a script that drops its cookies gains nothing).
+// requests are counted before `auth` runs, so one that's turned away never signs anybody up.
+// buckets live in memory, and with RATE_LIMIT_PERSIST=true they're also saved to the database so
+// a restart doesn't hand out fresh budgets.
+
+/// The most buckets kept in memory. Past it, ones that have sat idle long enough to be full are
+/// dropped, then the least recently used.
+const MAX_BUCKETS: usize = 10_000;
+/// Longer than any budget's period, so a bucket idle this long is certainly full again
+pub const IDLE_MILLIS: i64 = 1000 * 60 * 60 * 24;
+
+/// The things worth throttling
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Vote,
+    NameDog,
+    Upload,
+    /// Magic links and account deletion links
+    Email,
+}
+
+impl Action {
+    pub const ALL: [Self; 4] = [Self::Vote, Self::NameDog, Self::Upload, Self::Email];
+
+    /// Which action a request is, if it's one that's limited
+    pub fn of(method: &Method, path: &str) -> Option<Self> {
+        if method == Method::POST
+            && (path.starts_with("/pick-winner/")
+                || path == "/undo-pick"
+                || path == "/rank"
+                || path.starts_with("/tournaments/"))
+        {
+            Some(Self::Vote)
+        } else if method == Method::PATCH && path == "/name-dog" {
+            Some(Self::NameDog)
+        } else if method == Method::POST && path == "/upload" {
+            Some(Self::Upload)
+        } else if method == Method::POST
+            && (path == "/send-magic-link" || path == "/me/delete" || path == "/me/delete/confirm")
+        {
+            Some(Self::Email)
+        } else {
+            None
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Vote => "vote",
+            Self::NameDog => "name-dog",
+            Self::Upload => "upload",
+            Self::Email => "email",
+        }
+    }
+
+    fn env_name(self) -> &'static str {
+        match self {
+            Self::Vote => "RATE_LIMIT_VOTE",
+            Self::NameDog => "RATE_LIMIT_NAME_DOG",
+            Self::Upload => "RATE_LIMIT_UPLOAD",
+            Self::Email => "RATE_LIMIT_EMAIL",
+        }
+    }
+}
+
+/// `requests` every `per`. Zero requests means no limit.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Budget {
+    pub requests: u32,
+    pub per: Duration,
+}
+
+impl Budget {
+    pub const UNLIMITED: Self = Self::new(0, 1);
+
+    pub const fn new(requests: u32, seconds: u64) -> Self {
+        Self {
+            requests,
+            per: Duration::from_secs(seconds),
+        }
+    }
+
+    /// Written like "30/60", for 30 requests every 60 seconds
+    pub fn parse(value: &str) -> Option<Self> {
+        let (requests, seconds) = value.trim().split_once('/')?;
+        let seconds: u64 = seconds.trim().parse().ok()?;
+        if seconds == 0 {
+            return None;
+        }
+        Some(Self::new(requests.trim().parse().ok()?, seconds))
+    }
+
+    fn refill_per_milli(self) -> f64 {
+        f64::from(self.requests) / self.per.as_millis() as f64
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Limits {
+    pub user: Budget,
+    pub ip: Budget,
+}
+
+#[derive(Debug, Clone)]
+pub struct RateLimits {
+    pub vote: Limits,
+    pub name_dog: Limits,
+    pub upload: Limits,
+    pub email: Limits,
+}
+
+impl Default for RateLimits {
+    fn default() -> Self {
+        Self {
+            vote: Limits {
+                user: Budget::new(60, 60),
+                ip: Budget::new(600, 60),
+            },
+            name_dog: Limits {
+                user: Budget::new(10, 60),
+                ip: Budget::new(60, 60),
+            },
+            upload: Limits {
+                user: Budget::new(10, 60 * 60),
+                ip: Budget::new(30, 60 * 60),
+            },
+            email: Limits {
+                user: Budget::new(5, 60 * 60),
+                ip: Budget::new(20, 60 * 60),
+            },
+        }
+    }
+}
+
+impl RateLimits {
+    pub fn unlimited() -> Self {
+        let unlimited = Limits {
+            user: Budget::UNLIMITED,
+            ip: Budget::UNLIMITED,
+        };
+        Self {
+            vote: unlimited,
+            name_dog: unlimited,
+            upload: unlimited,
+            email: unlimited,
+        }
+    }
+
+    pub fn get(&self, action: Action) -> Limits {
+        match action {
+            Action::Vote => self.vote,
+            Action::NameDog => self.name_dog,
+            Action::Upload => self.upload,
+            Action::Email => self.email,
+        }
+    }
+
+    fn get_mut(&mut self, action: Action) -> &mut Limits {
+        match action {
+            Action::Vote => &mut self.vote,
+            Action::NameDog => &mut self.name_dog,
+            Action::Upload => &mut self.upload,
+            Action::Email => &mut self.email,
+        }
+    }
+
+    /// The defaults, with any of RATE_LIMIT_VOTE, RATE_LIMIT_VOTE_IP, RATE_LIMIT_NAME_DOG, ...
+    /// (per user and per IP address) swapped in
+    pub fn from_env() -> Self {
+        let mut limits = Self::default();
+        for action in Action::ALL {
+            let budget = |suffix: &str| {
+                let name = format!("{}{}", action.env_name(), suffix);
+                let value = env::var(&name).ok()?;
+                let budget = Budget::parse(&value);
+                if budget.is_none() {
+                    eprintln!("{} should look like 30/60, ignoring {:?}", name, value);
+                }
+                budget
+            };
+            if let Some(user) = budget("") {
+                limits.get_mut(action).user = user;
+            }
+            if let Some(ip) = budget("_IP") {
+                limits.get_mut(action).ip = ip;
+            }
+        }
+        limits
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Bucket {
+    tokens: f64,
+    /// Unix milliseconds
+    updated_at: i64,
+}
+
+impl Bucket {
+    fn full(budget: Budget, now: i64) -> Self {
+        Self {
+            tokens: f64::from(budget.requests),
+            updated_at: now,
+        }
+    }
+
+    /// Tops the bucket up for the time since it was last used
+    fn refill(&mut self, budget: Budget, now: i64) {
+        let elapsed = (now - self.updated_at).max(0) as f64;
+        self.tokens =
+            (self.tokens + elapsed * budget.refill_per_milli()).min(f64::from(budget.requests));
+        self.updated_at = now;
+    }
+
+    /// How long until there's a whole request's worth in the bucket, if there isn't now
+    fn wait(&self, budget: Budget) -> Option<Duration> {
+        if self.tokens >= 1.0 {
+            return None;
+        }
+        let millis = (1.0 - self.tokens) / budget.refill_per_milli();
+        Some(Duration::from_millis(millis.ceil() as u64))
+    }
+}
+
+/// Brings `buckets` back under `MAX_BUCKETS`, dropping the least recently used ones to make room
+/// if there aren't enough idle ones to drop
+fn evict(buckets: &mut HashMap<String, Bucket>, now: i64) {
+    if buckets.len() < MAX_BUCKETS {
+        return;
+    }
+    buckets.retain(|_, bucket| now - bucket.updated_at < IDLE_MILLIS);
+    if buckets.len() < MAX_BUCKETS {
+        return;
+    }
+    // a quarter at a time, so a steady stream of new keys doesn't sort the map on every request
+    let mut oldest: Vec<(i64, String)> = buckets
+        .iter()
+        .map(|(key, bucket)| (bucket.updated_at, key.clone()))
+        .collect();
+    oldest.sort_unstable();
+    let excess = buckets.len() - MAX_BUCKETS * 3 / 4;
+    for (_, key) in oldest.into_iter().take(excess) {
+        buckets.remove(&key);
+    }
+}
+
+/// Takes a request from every bucket in `keys`, or from none of them if any is empty, in which
+/// case it says how long until they all have room
+fn take(
+    buckets: &mut HashMap<String, Bucket>,
+    keys: &[(String, Budget)],
+    now: i64,
+) -> Result<(), Duration> {
+    let mut wait = None;
+    for (key, budget) in keys {
+        let bucket = buckets
+            .entry(key.clone())
+            .or_insert_with(|| Bucket::full(*budget, now));
+        bucket.refill(*budget, now);
+        wait = wait.max(bucket.wait(*budget));
+    }
+    if let Some(wait) = wait {
+        return Err(wait);
+    }
+    for (key, _) in keys {
+        if let Some(bucket) = buckets.get_mut(key) {
+            bucket.tokens -= 1.0;
+        }
+    }
+    Ok(())
+}
+
+pub struct RateLimiter {
+    limits: RateLimits,
+    buckets: Mutex<HashMap<String, Bucket>>,
+    /// Where buckets are saved, if they are
+    pool: Option<Pool<Any>>,
+}
+
+impl RateLimiter {
+    pub fn new(limits: RateLimits, pool: Option<Pool<Any>>) -> Self {
+        Self {
+            limits,
+            buckets: Mutex::new(HashMap::new()),
+            pool,
+        }
+    }
+
+    pub fn from_env(pool: &Pool<Any>) -> Self {
+        let persist = env::var("RATE_LIMIT_PERSIST").is_ok_and(|value| value == "true");
+        Self::new(RateLimits::from_env(), persist.then(|| pool.clone()))
+    }
+
+    /// Counts a request against the user's and the IP address's budgets for `action`,
+    /// or says how long to wait if either is used up
+    pub async fn check(
+        &self,
+        action: Action,
+        user_id: Option<i64>,
+        client_ip: Option<IpAddr>,
+    ) -> Result<(), Duration> {
+        let limits = self.limits.get(action);
+        let mut keys = vec![];
+        if let Some(user_id) = user_id {
+            keys.push((format!("{}:user:{}", action.name(), user_id), limits.user));
+        }
+        if let Some(client_ip) = client_ip {
+            keys.push((format!("{}:ip:{}", action.name(), client_ip), limits.ip));
+        }
+        keys.retain(|(_, budget)| budget.requests > 0);
+        if keys.is_empty() {
+            return Ok(());
+        }
+
+        if let Some(pool) = &self.pool {
+            self.load(pool, &keys).await;
+        }
+
+        let now = Utc::now().timestamp_millis();
+        let (result, touched) = {
+            let mut buckets = self.buckets.lock().unwrap();
+            evict(&mut buckets, now);
+            let result = take(&mut buckets, &keys, now);
+            let touched: Vec<(String, Bucket)> = keys
+                .iter()
+                .filter_map(|(key, _)| Some((key.clone(), *buckets.get(key)?)))
+                .collect();
+            (result, touched)
+        };
+
+        if let Some(pool) = &self.pool {
+            for (key, bucket) in touched {
+                if let Err(error) =
+                    db::rate_limits::save(pool, &key, bucket.tokens, bucket.updated_at).await
+                {
+                    eprintln!("couldn't save rate limit bucket {}: {}", key, error);
+                }
+            }
+        }
+        result
+    }
+
+    /// Brings saved buckets that aren't in memory yet into memory
+    async fn load(&self, pool: &Pool<Any>, keys: &[(String, Budget)]) {
+        for (key, _) in keys {
+            if self.buckets.lock().unwrap().contains_key(key) {
+                continue;
+            }
+            match db::rate_limits::get(pool, key).await {
+                Ok(Some((tokens, updated_at))) => {
+                    self.buckets
+                        .lock()
+                        .unwrap()
+                        .entry(key.clone())
+                        .or_insert(Bucket { tokens, updated_at });
+                }
+                Ok(None) => {}
+                Err(error) => eprintln!("couldn't load rate limit bucket {}: {}", key, error),
+            }
+        }
+    }
+}
+
+/// Runs in front of `auth` (see lib.rs)
+pub async fn rate_limit<B>(
+    State(state): State<AppState>,
+    forwarded_for: Option<RightmostXForwardedFor>,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Result<Response, AppError> {
+    let Some(action) = Action::of(req.method(), req.uri().path()) else {
+        return Ok(next.run(req).await);
+    };
+    let user_id = auth::session_user_id(&state, req.headers()).await?;
+    let client_ip = auth::client_ip(forwarded_for, connect_info);
+    if let Err(retry_after) = state.rate_limiter.check(action, user_id, client_ip).await {
+        metrics::RATE_LIMITED_TOTAL
+            .with_label_values(&[action.name()])
+            .inc();
+        return Err(AppError::TooManyRequests(retry_after));
+    }
+    Ok(next.run(req).await)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limited_routes() {
+        assert_eq!(
+            Action::of(&Method::POST, "/pick-winner/12"),
+            Some(Action::Vote)
+        );
+        assert_eq!(
+            Action::of(&Method::POST, "/pick-winner/tie"),
+            Some(Action::Vote)
+        );
+        assert_eq!(Action::of(&Method::POST, "/undo-pick"), Some(Action::Vote));
+        assert_eq!(Action::of(&Method::POST, "/rank"), Some(Action::Vote));
+        assert_eq!(Action::of(&Method::GET, "/rank"), None);
+        assert_eq!(
+            Action::of(&Method::POST, "/tournaments/1/matches/3/vote/12"),
+            Some(Action::Vote)
+        );
+        assert_eq!(Action::of(&Method::GET, "/tournaments/1"), None);
+        assert_eq!(
+            Action::of(&Method::PATCH, "/name-dog"),
+            Some(Action::NameDog)
+        );
+        assert_eq!(Action::of(&Method::POST, "/upload"), Some(Action::Upload));
+        assert_eq!(Action::of(&Method::POST, "/me/delete"), Some(Action::Email));
+        assert_eq!(
+            Action::of(&Method::POST, "/me/delete/confirm"),
+            Some(Action::Email)
+        );
+        assert_eq!(Action::of(&Method::GET, "/upload"), None);
+        assert_eq!(Action::of(&Method::GET, "/"), None);
+    }
+
+    #[test]
+    fn parsing_budgets() {
+        assert_eq!(Budget::parse("30/60"), Some(Budget::new(30, 60)));
+        assert_eq!(Budget::parse(" 0 / 1 "), Some(Budget::UNLIMITED));
+        assert_eq!(Budget::parse("30"), None);
+        assert_eq!(Budget::parse("30/0"), None);
+        assert_eq!(Budget::parse("lots/60"), None);
+    }
+
+    #[test]
+    fn buckets_empty_and_refill() {
+        let budget = Budget::new(2, 10);
+        let keys = [("vote:user:1".to_string(), budget)];
+        let mut buckets = HashMap::new();
+
+        assert_eq!(take(&mut buckets, &keys, 0), Ok(()));
+        assert_eq!(take(&mut buckets, &keys, 0), Ok(()));
+        // one request comes back every 5 seconds
+        assert_eq!(
+            take(&mut buckets, &keys, 1000),
+            Err(Duration::from_millis(4000))
+        );
+        assert_eq!(take(&mut buckets, &keys, 5000), Ok(()));
+        assert!(take(&mut buckets, &keys, 5000).is_err());
+        // but never holds more than the budget
+        assert_eq!(take(&mut buckets, &keys, 1_000_000), Ok(()));
+        assert_eq!(take(&mut buckets, &keys, 1_000_000), Ok(()));
+        assert!(take(&mut buckets, &keys, 1_000_000).is_err());
+    }
+
+    #[test]
+    fn a_full_bucket_saves_the_others() {
+        let keys = [
+            ("vote:user:1".to_string(), Budget::new(5, 60)),
+            ("vote:ip:127.0.0.1".to_string(), Budget::new(1, 60)),
+        ];
+        let mut buckets = HashMap::new();
+        assert_eq!(take(&mut buckets, &keys, 0), Ok(()));
+        assert!(take(&mut buckets, &keys, 0).is_err());
+        assert_eq!(buckets["vote:user:1"].tokens, 4.0);
+    }
+
+    #[test]
+    fn busy_buckets_outlast_a_flood_of_new_ones() {
+        let budget = Budget::new(1, 60);
+        let mut buckets = HashMap::new();
+        let regular = [("vote:ip:192.0.2.1".to_string(), budget)];
+        assert_eq!(take(&mut buckets, &regular, 0), Ok(()));
+        for i in 0..MAX_BUCKETS * 2 {
+            let now = i as i64 + 1;
+            evict(&mut buckets, now);
+            let _ = take(
+                &mut buckets,
+                &[(format!("vote:ip:spoofed-{}", i), budget)],
+                now,
+            );
+            if i % 1000 == 0 {
+                evict(&mut buckets, now);
+                let _ = take(&mut buckets, &regular, now);
+            }
+            assert!(buckets.len() <= MAX_BUCKETS);
+        }
+        assert!(buckets.contains_key("vote:ip:192.0.2.1"));
+    }
+}
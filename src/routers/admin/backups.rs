@@ -0,0 +1,122 @@
+use crate::{
+    backup::{self, Backup},
+    db::{self, log::Event},
+    error::AppError,
+    layout::base,
+    AppContext, AppState,
+};
+use axum::{
+    extract::{Path, State},
+    http::{header, HeaderMap},
+    response::{IntoResponse, Response},
+    Extension,
+};
+use maud::{html, Markup};
+
+pub async fn backups_page(State(state): State<AppState>) -> impl IntoResponse {
+    base(
+        backups_list(&state, None),
+        Some("Backups".to_string()),
+        None,
+    )
+}
+
+pub async fn create_backup(
+    State(state): State<AppState>,
+    Extension(context): Extension<AppContext>,
+) -> impl IntoResponse {
+    let result = backup::create_backup(&state.pool, &state.backups_dir).await;
+
+    let message = match &result {
+        Ok(backup) => {
+            let _ = db::log::record(
+                &state.pool,
+                &Event::Backup {
+                    file_name: backup.file_name.clone(),
+                },
+                Some(context.user_id),
+                context.client_ip,
+            )
+            .await;
+            Ok(format!("Saved {}", backup.file_name))
+        }
+        Err(error) => Err(format!("Backup failed: {:#}", error)),
+    };
+
+    base(
+        backups_list(&state, Some(message)),
+        Some("Backups".to_string()),
+        None,
+    )
+}
+
+pub async fn download_backup(
+    State(state): State<AppState>,
+    Path(file_name): Path<String>,
+) -> Response {
+    // only names that look like backups, so this can't be pointed anywhere else
+    if backup::parse_file_name(&file_name).is_none() {
+        return AppError::NotFound.into_response();
+    }
+    let Ok(data) = tokio::fs::read(state.backups_dir.join(&file_name)).await else {
+        return AppError::NotFound.into_response();
+    };
+
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        header::CONTENT_TYPE,
+        "application/vnd.sqlite3".parse().unwrap(),
+    );
+    headers.insert(
+        header::CONTENT_DISPOSITION,
+        format!("attachment; filename=\"{}\"", file_name)
+            .parse()
+            .unwrap(),
+    );
+    (headers, data).into_response()
+}
+
+fn backups_list(state: &AppState, message: Option<Result<String, String>>) -> Markup {
+    let backups = backup::list_backups(&state.backups_dir);
+    html! {
+        div class="flex-1 flex flex-col items-center gap-6 max-w-screen-md mx-auto p-4" {
+            h1 class="text-5xl text-center" {"Backups"}
+            p class="text-center" {
+                "Snapshots in " code {(state.backups_dir.to_string_lossy())}
+                ". Restore one with " code {"top-doggo-admin restore <file>"} " while the server is stopped."
+            }
+            form method="post" action="/admin/backups" {
+                button type="submit" class="btn btn-primary text-xl" {"Back up now"}
+            }
+            @match message {
+                Some(Ok(message)) => p class="text-lg text-success" {(message)},
+                Some(Err(message)) => p class="text-lg text-error" {(message)},
+                None => {},
+            }
+            @match backups {
+                Ok(backups) if backups.is_empty() => p class="text-lg" {"No backups yet"},
+                Ok(backups) => (backups_table(&backups)),
+                Err(error) => p class="text-lg text-error" {(format!("{:#}", error))},
+            }
+        }
+    }
+}
+
+fn backups_table(backups: &[Backup]) -> Markup {
+    html! {
+        table class="table table-sm table-zebra" {
+            thead { tr { th {"Taken (UTC)"} th {"Size"} th {} } }
+            tbody {
+                @for backup in backups {
+                    tr {
+                        td {(backup.taken_at.format("%Y-%m-%d %H:%M:%S"))}
+                        td {(format!("{:.1} MB", backup.size as f64 / 1_000_000.0))}
+                        td {
+                            a class="underline text-primary" href={"/admin/backups/"(backup.file_name)} download hx-boost="false" {"Download"}
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,205 @@
+use crate::{
+    db::{
+        self,
+        log::{Event, LogEntry, LogFilter},
+    },
+    error::AppError,
+    layout::base,
+    AppState,
+};
+use axum::{
+    extract::{Query, State},
+    response::IntoResponse,
+};
+use maud::{html, Markup};
+use serde::Deserialize;
+
+const PAGE_SIZE: u32 = 100;
+
+// straight from the filter form, where every field is a string and empty means "any"
+#[derive(Deserialize, Default)]
+pub struct LogParams {
+    user_id: Option<String>,
+    client_ip: Option<String>,
+    action: Option<String>,
+    from: Option<String>,
+    to: Option<String>,
+    before_id: Option<String>,
+}
+
+impl LogParams {
+    fn filter(&self) -> Result<LogFilter, AppError> {
+        Ok(LogFilter {
+            user_id: parse(&self.user_id, "user id")?,
+            client_ip: non_empty(&self.client_ip).map(str::to_string),
+            action: non_empty(&self.action).map(str::to_string),
+            from: parse(&self.from, "from date")?,
+            to: parse(&self.to, "to date")?,
+            before_id: parse(&self.before_id, "page")?,
+        })
+    }
+}
+
+fn non_empty(value: &Option<String>) -> Option<&str> {
+    value
+        .as_deref()
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+}
+
+fn parse<T: std::str::FromStr>(value: &Option<String>, what: &str) -> Result<Option<T>, AppError> {
+    non_empty(value)
+        .map(|value| {
+            value
+                .parse()
+                .map_err(|_| AppError::BadRequest(format!("That {} doesn't look right", what)))
+        })
+        .transpose()
+}
+
+pub async fn log_page(
+    State(state): State<AppState>,
+    Query(params): Query<LogParams>,
+) -> Result<impl IntoResponse, AppError> {
+    let filter = params.filter()?;
+    let entries = db::log::search(&state.pool, &filter, PAGE_SIZE).await?;
+
+    Ok(base(
+        html! {
+            div class="flex-1 flex flex-col items-center gap-6 max-w-screen-xl mx-auto p-4 w-full" {
+                h1 class="text-5xl text-center" {"Log"}
+                (filter_form(&params))
+                @if entries.is_empty() {
+                    p class="text-lg" {"Nothing matches"}
+                } @else {
+                    (log_table(&entries))
+                }
+                @if entries.len() == PAGE_SIZE as usize {
+                    @if let Some(last) = entries.last() {
+                        (older_link(&params, last.id))
+                    }
+                }
+            }
+        },
+        Some("Log".to_string()),
+        None,
+    ))
+}
+
+fn filter_form(params: &LogParams) -> Markup {
+    let value = |field: &Option<String>| field.clone().unwrap_or_default();
+    let action = non_empty(&params.action);
+    html! {
+        form method="get" action="/admin/log" class="flex flex-wrap gap-2 items-end justify-center" {
+            div class="flex flex-col" {
+                label for="user_id" {"User id"}
+                input type="text" inputmode="numeric" id="user_id" name="user_id" value=(value(&params.user_id)) class="input input-bordered w-28" ;
+            }
+            div class="flex flex-col" {
+                label for="client_ip" {"IP address"}
+                input type="text" id="client_ip" name="client_ip" value=(value(&params.client_ip)) class="input input-bordered w-40" ;
+            }
+            div class="flex flex-col" {
+                label for="action" {"Action"}
+                select id="action" name="action" class="select select-bordered" {
+                    option value="" {"Any"}
+                    @for name in Event::ACTIONS {
+                        option value=(name) selected[action == Some(name)] {(name)}
+                    }
+                }
+            }
+            div class="flex flex-col" {
+                label for="from" {"From"}
+                input type="date" id="from" name="from" value=(value(&params.from)) class="input input-bordered" ;
+            }
+            div class="flex flex-col" {
+                label for="to" {"To"}
+                input type="date" id="to" name="to" value=(value(&params.to)) class="input input-bordered" ;
+            }
+            button type="submit" class="btn btn-primary" {"Filter"}
+            a href="/admin/log" class="btn" {"Clear"}
+        }
+    }
+}
+
+fn log_table(entries: &[LogEntry]) -> Markup {
+    html! {
+        div class="overflow-x-auto w-full" {
+            table class="table table-sm table-zebra" {
+                thead { tr { th {"Time (UTC)"} th {"Action"} th {"User"} th {"IP address"} th {"Details"} } }
+                tbody {
+                    @for entry in entries {
+                        tr {
+                            td class="whitespace-nowrap" {(entry.created_at.as_deref().unwrap_or(""))}
+                            td {(entry.action)}
+                            td {
+                                @if let Some(user_id) = entry.user_id {
+                                    a class="underline text-primary" href={"/admin/log?user_id="(user_id)} {(user_id)}
+                                }
+                            }
+                            td {(entry.client_ip.as_deref().unwrap_or(""))}
+                            // older entries are shown as they were written
+                            td class="break-all" {
+                                @match entry.event() {
+                                    Some(event) => (event.to_string()),
+                                    None => (entry.details.as_deref().unwrap_or("")),
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+// the same filters, one page further back
+fn older_link(params: &LogParams, last_id: i64) -> Markup {
+    let fields = [
+        ("user_id", &params.user_id),
+        ("client_ip", &params.client_ip),
+        ("action", &params.action),
+        ("from", &params.from),
+        ("to", &params.to),
+    ];
+    html! {
+        form method="get" action="/admin/log" {
+            @for (name, value) in fields {
+                @if let Some(value) = non_empty(value) {
+                    input type="hidden" name=(name) value=(value) ;
+                }
+            }
+            input type="hidden" name="before_id" value=(last_id) ;
+            button type="submit" class="btn" {"Older"}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    #[test]
+    fn empty_fields_mean_any() {
+        let params = LogParams {
+            user_id: Some("".to_string()),
+            client_ip: Some(" ".to_string()),
+            action: Some("upload".to_string()),
+            from: Some("2024-11-01".to_string()),
+            ..Default::default()
+        };
+        let filter = params.filter().unwrap();
+        assert_eq!(filter.user_id, None);
+        assert_eq!(filter.client_ip, None);
+        assert_eq!(filter.action.as_deref(), Some("upload"));
+        assert_eq!(filter.from, NaiveDate::from_ymd_opt(2024, 11, 1));
+        assert_eq!(filter.to, None);
+
+        let params = LogParams {
+            from: Some("last tuesday".to_string()),
+            ..Default::default()
+        };
+        assert!(params.filter().is_err());
+    }
+}
@@ -0,0 +1,204 @@
+use crate::{
+    admin::import::{self, ImportResult},
+    db::{self, log::Event},
+    error::AppError,
+    layout::base,
+    AppContext, AppState,
+};
+use axum::{
+    extract::{DefaultBodyLimit, Multipart, State},
+    http::Request,
+    middleware::{self, Next},
+    response::{Html, IntoResponse, Response},
+    routing::{get, post},
+    Extension, Router,
+};
+use maud::{html, Markup};
+use std::env;
+
+pub mod backups;
+pub mod log;
+pub mod suspects;
+pub mod tournaments;
+
+pub fn admin_router() -> Router<AppState> {
+    Router::<AppState>::new()
+        .route(
+            "/",
+            get(|| async move {
+                base(
+                    html! {
+                        div class="flex-1 flex flex-col gap-4 items-center justify-center" {
+                            h1 class="text-5xl" {"Admin"}
+                            a class="text-3xl underline text-primary" href="/admin/import" {"Import dogs"}
+                            a class="text-3xl underline text-primary" href="/admin/backups" {"Backups"}
+                            a class="text-3xl underline text-primary" href="/admin/log" {"Log"}
+                            a class="text-3xl underline text-primary" href="/admin/suspects" {"Suspects"}
+                            a class="text-3xl underline text-primary" href="/admin/tournaments" {"Tournaments"}
+                        }
+                    },
+                    Some("Admin".to_string()),
+                    None,
+                )
+            }),
+        )
+        .route(
+            "/import",
+            get(|| async move {
+                base(import_form(None), Some("Import".to_string()), None)
+            })
+            .post(import_dogs)
+            .layer(DefaultBodyLimit::max(import::MAX_ARCHIVE_BYTES)),
+        )
+        .route(
+            "/backups",
+            get(backups::backups_page).post(backups::create_backup),
+        )
+        .route("/backups/:file_name", get(backups::download_backup))
+        .route("/log", get(log::log_page))
+        .route("/suspects", get(suspects::suspects_page))
+        .route("/suspects/:user_id/quarantine", post(suspects::quarantine))
+        .route("/suspects/:user_id/release", post(suspects::release))
+        .route("/suspects/:user_id/roll-back", post(suspects::roll_back_votes))
+        .route(
+            "/tournaments",
+            get(tournaments::tournaments_page).post(tournaments::start_tournament),
+        )
+        .route_layer(middleware::from_fn(require_admin))
+}
+
+pub fn is_admin(context: &AppContext) -> bool {
+    match (&context.user_email, env::var("ADMIN_EMAIL")) {
+        (Some(email), Ok(admin_email)) => *email == admin_email,
+        _ => false,
+    }
+}
+
+// pretend the admin pages don't exist for everybody else
+async fn require_admin<B>(
+    Extension(context): Extension<AppContext>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    if is_admin(&context) {
+        next.run(req).await
+    } else {
+        AppError::NotFound.into_response()
+    }
+}
+
+async fn import_dogs(
+    State(state): State<AppState>,
+    Extension(context): Extension<AppContext>,
+    mut multipart: Multipart,
+) -> Html<String> {
+    let err = |error: &str| Html(import_form(Some(Err(error.to_string()))).into_string());
+
+    let mut archive: Option<(String, Vec<u8>)> = None;
+    let mut manifest: Option<(String, Vec<u8>)> = None;
+    while let Ok(Some(field)) = multipart.next_field().await {
+        let name = field.name().unwrap_or_default().to_string();
+        let file_name = field.file_name().unwrap_or_default().to_string();
+        let Ok(data) = field.bytes().await else {
+            return err("Couldn't read the upload");
+        };
+        if data.is_empty() {
+            continue;
+        }
+        if name == "archive" {
+            archive = Some((file_name, data.to_vec()));
+        } else if name == "manifest" {
+            manifest = Some((file_name, data.to_vec()));
+        }
+    }
+    let Some((archive_name, archive_data)) = archive else {
+        return err("Pick a .zip, .tar, or .tar.gz to import");
+    };
+
+    let mut files = match import::read_archive(&archive_name, &archive_data) {
+        Ok(files) => files,
+        Err(error) => return err(&format!("{:#}", error)),
+    };
+    let names = match manifest {
+        Some((manifest_name, manifest_data)) => {
+            import::parse_manifest(&manifest_name, &manifest_data).map(Some)
+        }
+        None => import::take_manifest(&mut files),
+    };
+    let names = match names {
+        Ok(names) => names.unwrap_or_default(),
+        Err(error) => return err(&format!("{:#}", error)),
+    };
+
+    let results = import::import_dogs(&state.pool, files, &names, &state.images_dir).await;
+
+    let event = Event::ImportDogs {
+        archive: archive_name,
+        imported: results
+            .iter()
+            .filter(|result| result.outcome.is_ok())
+            .count(),
+        files: results.len(),
+    };
+    let _ = db::log::record(
+        &state.pool,
+        &event,
+        Some(context.user_id),
+        context.client_ip,
+    )
+    .await;
+
+    Html(import_form(Some(Ok(results))).into_string())
+}
+
+fn import_form(results: Option<Result<Vec<ImportResult>, String>>) -> Markup {
+    html! {
+        form
+            id="import-form"
+            hx-post="/admin/import"
+            hx-encoding="multipart/form-data"
+            hx-swap="outerHTML"
+            class="flex-1 flex flex-col items-center gap-6 max-w-screen-md mx-auto p-4"
+        {
+            h1 class="text-5xl text-center" {"Import dogs"}
+            p class="text-center" {"Every image in the archive becomes an approved dog. Names come from a manifest.json or manifest.csv (file,name) inside the archive, or the one picked below."}
+            div class="flex flex-col gap-1 w-full" {
+                label for="archive" class="text-lg" {"Archive (.zip, .tar, .tar.gz)"}
+                input type="file" id="archive" name="archive" accept=".zip,.tar,.gz,.tgz" class="file-input file-input-bordered w-full" ;
+            }
+            div class="flex flex-col gap-1 w-full" {
+                label for="manifest" class="text-lg" {"Manifest (optional)"}
+                input type="file" id="manifest" name="manifest" accept=".json,.csv" class="file-input file-input-bordered w-full" ;
+            }
+            button type="submit" class="btn btn-primary w-full text-xl" {"Import"}
+            @match results {
+                None => {},
+                Some(Err(error)) => {
+                    p class="text-lg text-error" {(error)}
+                },
+                Some(Ok(results)) => {
+                    p class="text-2xl" {
+                        "Imported " (results.iter().filter(|result| result.outcome.is_ok()).count()) " of " (results.len()) " files"
+                    }
+                    table class="table table-sm table-zebra" {
+                        thead { tr { th {"File"} th {"Result"} } }
+                        tbody {
+                            @for result in results.iter() {
+                                tr {
+                                    td class="break-all" {(result.file)}
+                                    @match &result.outcome {
+                                        Ok(dog) => td class="text-success" {
+                                            "dog " (dog.dog_id)
+                                            @if let Some(name) = &dog.name {" (" (name) ")"}
+                                        },
+                                        Err(error) => td class="text-error" {(error)},
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
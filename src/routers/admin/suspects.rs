@@ -0,0 +1,159 @@
+use crate::{
+    admin,
+    db::{self, log::Event, users::Suspect},
+    error::AppError,
+    layout::base,
+    AppContext, AppState,
+};
+use axum::{
+    extract::{Path, State},
+    response::IntoResponse,
+    Extension,
+};
+use maud::{html, Markup};
+
+// users the fraud checks have flagged (see fraud.rs), and what to do about them
+
+pub async fn suspects_page(State(state): State<AppState>) -> Result<impl IntoResponse, AppError> {
+    suspects_list(&state, None).await
+}
+
+pub async fn quarantine(
+    State(state): State<AppState>,
+    Extension(context): Extension<AppContext>,
+    Path(user_id): Path<i64>,
+) -> Result<impl IntoResponse, AppError> {
+    let result = admin::quarantine_user(&state.pool, user_id).await;
+    let message = match result {
+        Ok(()) => {
+            record(&state, &context, Event::QuarantineUser { user_id }).await;
+            Ok(format!("Quarantined user {}", user_id))
+        }
+        Err(error) => Err(format!("Couldn't quarantine user {}: {:#}", user_id, error)),
+    };
+    suspects_list(&state, Some(message)).await
+}
+
+pub async fn release(
+    State(state): State<AppState>,
+    Extension(context): Extension<AppContext>,
+    Path(user_id): Path<i64>,
+) -> Result<impl IntoResponse, AppError> {
+    let result = admin::release_user(&state.pool, user_id).await;
+    let message = match result {
+        Ok(()) => {
+            record(&state, &context, Event::ReleaseUser { user_id }).await;
+            Ok(format!("Released user {}", user_id))
+        }
+        Err(error) => Err(format!("Couldn't release user {}: {:#}", user_id, error)),
+    };
+    suspects_list(&state, Some(message)).await
+}
+
+pub async fn roll_back_votes(
+    State(state): State<AppState>,
+    Extension(context): Extension<AppContext>,
+    Path(user_id): Path<i64>,
+) -> Result<impl IntoResponse, AppError> {
+    let result = admin::roll_back_votes(&state.pool, user_id).await;
+    let message = match result {
+        Ok(votes) => {
+            record(&state, &context, Event::RollBackVotes { user_id, votes }).await;
+            Ok(format!("Rolled back {} votes by user {}", votes, user_id))
+        }
+        Err(error) => Err(format!(
+            "Couldn't roll back user {}'s votes: {:#}",
+            user_id, error
+        )),
+    };
+    suspects_list(&state, Some(message)).await
+}
+
+async fn record(state: &AppState, context: &AppContext, event: Event) {
+    let _ = db::log::record(
+        &state.pool,
+        &event,
+        Some(context.user_id),
+        context.client_ip,
+    )
+    .await;
+}
+
+async fn suspects_list(
+    state: &AppState,
+    message: Option<Result<String, String>>,
+) -> Result<impl IntoResponse, AppError> {
+    let suspects = db::users::suspects(&state.pool).await?;
+    Ok(base(
+        html! {
+            div class="flex-1 flex flex-col items-center gap-6 max-w-screen-xl mx-auto p-4 w-full" {
+                h1 class="text-5xl text-center" {"Suspects"}
+                p class="text-center max-w-screen-md" {
+                    "Users whose votes look scripted. Trust is how much their votes move the overall ratings. "
+                    "Quarantining stops all of their votes from counting, rolling back deletes them."
+                }
+                @match message {
+                    Some(Ok(message)) => p class="text-lg text-success" {(message)},
+                    Some(Err(message)) => p class="text-lg text-error" {(message)},
+                    None => {},
+                }
+                @if suspects.is_empty() {
+                    p class="text-lg" {"Nobody looks suspicious"}
+                } @else {
+                    (suspects_table(&suspects))
+                }
+            }
+        },
+        Some("Suspects".to_string()),
+        None,
+    ))
+}
+
+fn suspects_table(suspects: &[Suspect]) -> Markup {
+    html! {
+        div class="overflow-x-auto w-full" {
+            table class="table table-sm table-zebra" {
+                thead { tr { th {"User"} th {"Email"} th {"IP address"} th {"Joined (UTC)"} th {"Votes"} th {"Trust"} th {} } }
+                tbody {
+                    @for suspect in suspects {
+                        tr {
+                            td {
+                                a class="underline text-primary" href={"/admin/log?user_id="(suspect.id)} {(suspect.id)}
+                            }
+                            td {(suspect.email.as_deref().unwrap_or(""))}
+                            td {(suspect.client_ip.as_deref().unwrap_or(""))}
+                            td class="whitespace-nowrap" {(suspect.created_at.as_deref().unwrap_or(""))}
+                            td {(suspect.votes)}
+                            td {
+                                @if suspect.quarantined {
+                                    span class="badge badge-error" {"quarantined"}
+                                } @else {
+                                    (suspect.trust) "%"
+                                }
+                            }
+                            td class="flex gap-2" {
+                                @if suspect.quarantined {
+                                    form method="post" action={"/admin/suspects/"(suspect.id)"/release"} {
+                                        button type="submit" class="btn btn-sm" {"Release"}
+                                    }
+                                } @else {
+                                    form method="post" action={"/admin/suspects/"(suspect.id)"/quarantine"} {
+                                        button type="submit" class="btn btn-sm btn-warning" {"Quarantine"}
+                                    }
+                                    form method="post" action={"/admin/suspects/"(suspect.id)"/release"} {
+                                        button type="submit" class="btn btn-sm" {"Trust"}
+                                    }
+                                }
+                                @if suspect.votes > 0 {
+                                    form method="post" action={"/admin/suspects/"(suspect.id)"/roll-back"} {
+                                        button type="submit" class="btn btn-sm btn-error" {"Roll back votes"}
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,123 @@
+use crate::{
+    admin,
+    db::{self, log::Event},
+    error::AppError,
+    layout::base,
+    routers::tournaments::bracket::{Format, SIZES},
+    AppContext, AppState,
+};
+use axum::{extract::State, response::IntoResponse, Extension, Form};
+use maud::html;
+use serde::Deserialize;
+
+// starting tournaments from the browser, the same as `top-doggo-admin start-tournament`
+
+#[derive(Deserialize)]
+pub struct StartTournamentParams {
+    name: String,
+    format: String,
+    size: usize,
+    window_hours: i64,
+}
+
+pub async fn tournaments_page(
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    tournaments_form(&state, None).await
+}
+
+pub async fn start_tournament(
+    State(state): State<AppState>,
+    Extension(context): Extension<AppContext>,
+    Form(params): Form<StartTournamentParams>,
+) -> Result<impl IntoResponse, AppError> {
+    let format = Format::parse(&params.format).unwrap_or(Format::Single);
+    let result = admin::start_tournament(
+        &state.pool,
+        &params.name,
+        format,
+        params.size,
+        params.window_hours,
+    )
+    .await;
+    let message = match result {
+        Ok(tournament_id) => {
+            let event = Event::StartTournament {
+                tournament_id,
+                name: params.name.trim().to_string(),
+            };
+            let _ = db::log::record(
+                &state.pool,
+                &event,
+                Some(context.user_id),
+                context.client_ip,
+            )
+            .await;
+            Ok(format!("Started tournament {}", tournament_id))
+        }
+        Err(error) => Err(format!("Couldn't start the tournament: {:#}", error)),
+    };
+    tournaments_form(&state, Some(message)).await
+}
+
+async fn tournaments_form(
+    state: &AppState,
+    message: Option<Result<String, String>>,
+) -> Result<impl IntoResponse, AppError> {
+    let tournaments = db::tournaments::list(&state.pool).await?;
+    Ok(base(
+        html! {
+            div class="flex-1 flex flex-col items-center gap-6 max-w-screen-md mx-auto p-4 w-full" {
+                h1 class="text-5xl text-center" {"Tournaments"}
+                p class="text-center" {
+                    "The highest rated approved dogs are seeded into the bracket by their overall rating. "
+                    "Each round is open to votes for the window below, and the dog with more votes goes through."
+                }
+                @match message {
+                    Some(Ok(message)) => p class="text-lg text-success" {(message)},
+                    Some(Err(message)) => p class="text-lg text-error" {(message)},
+                    None => {},
+                }
+                form method="post" action="/admin/tournaments" class="flex flex-col gap-4 w-full max-w-md" {
+                    label class="form-control" {
+                        span class="label-text" {"Name"}
+                        input type="text" name="name" required class="input input-bordered";
+                    }
+                    label class="form-control" {
+                        span class="label-text" {"Format"}
+                        select name="format" class="select select-bordered" {
+                            @for format in [Format::Single, Format::Double] {
+                                option value=(format.as_str()) {(format.name())}
+                            }
+                        }
+                    }
+                    label class="form-control" {
+                        span class="label-text" {"Dogs"}
+                        select name="size" class="select select-bordered" {
+                            @for size in SIZES {
+                                option value=(size) selected[size == 8] {(size)}
+                            }
+                        }
+                    }
+                    label class="form-control" {
+                        span class="label-text" {"Hours to vote on each round"}
+                        input type="number" name="window_hours" min="1" value="24" required class="input input-bordered";
+                    }
+                    button type="submit" class="btn btn-primary" {"Start tournament"}
+                }
+                @if !tournaments.is_empty() {
+                    ul class="flex flex-col gap-2 w-full" {
+                        @for tournament in &tournaments {
+                            li {
+                                a class="underline text-primary" href={"/tournaments/"(tournament.id)} {(tournament.name)}
+                                @if tournament.champion_id.is_some() {" (finished)"}
+                            }
+                        }
+                    }
+                }
+            }
+        },
+        Some("Tournaments".to_string()),
+        None,
+    ))
+}
@@ -1,58 +1,66 @@
-use std::cmp;
-
-use serde::{Deserialize, Serialize};
-use sqlx::{Pool, Sqlite};
-
-#[derive(Clone, Copy, Deserialize, Serialize, Debug, PartialEq)]
-#[serde(rename_all = "snake_case")]
-pub enum RatingType {
-    Overall,
-    Personal,
-}
+pub use crate::db::ratings::RatingType;
+use crate::db::{
+    self,
+    ratings::{self, STARTING_RATING},
+};
+use sqlx::AnyConnection;
+use std::{cmp, collections::HashMap};
 
 /*
  * https://en.wikipedia.org/wiki/Elo_rating_system#Theory
  */
 pub async fn update_ratings(
-    pool: &Pool<Sqlite>,
+    conn: &mut AnyConnection,
+    match_id: i64,
     user_id: i64,
     dog_a_id: i64,
     dog_b_id: i64,
     rating_type: RatingType,
     status: &str,
-) {
+) -> Result<(), sqlx::Error> {
     // key for pseudocode comments: k stands for max rating change, r stands for current rating, e stands for
     // expected score, s stands for actual score, new_r stands for new rating
 
+    // set s_a and s_b as functions of status (s_b is just 1 - s_a)
+    let Some(actual_score_a) = get_actual_score(status) else {
+        eprintln!("Can't update ratings for a match with status {:?}", status);
+        return Ok(());
+    };
+    let actual_score_b: f32 = 1.0 - actual_score_a;
+
     // give each dog an initial rating if they don't have one yet
     // store ratings (new or old) in r_a and r_b
-    let current_rating_a: u16 = get_current_rating(pool, dog_a_id, rating_type, user_id).await;
-    let current_rating_b: u16 = get_current_rating(pool, dog_b_id, rating_type, user_id).await;
+    let current_rating_a: u16 =
+        get_current_rating(&mut *conn, dog_a_id, rating_type, user_id).await?;
+    let current_rating_b: u16 =
+        get_current_rating(&mut *conn, dog_b_id, rating_type, user_id).await?;
 
     // get k_a and k_b (based on how many total matches they have)
     // -1 because the current match doesn't count
-    let max_rating_change_a =
-        get_max_rating_change(get_num_matches(pool, dog_a_id, rating_type, user_id).await - 1);
-    let max_rating_change_b =
-        get_max_rating_change(get_num_matches(pool, dog_b_id, rating_type, user_id).await - 1);
+    let max_rating_change_a = get_max_rating_change(
+        db::matches::count_resolved(&mut *conn, dog_a_id, rating_type, user_id)
+            .await?
+            .saturating_sub(1),
+    );
+    let max_rating_change_b = get_max_rating_change(
+        db::matches::count_resolved(&mut *conn, dog_b_id, rating_type, user_id)
+            .await?
+            .saturating_sub(1),
+    );
+
+    // votes from users who look like scripts count for less overall (see fraud.rs)
+    let trust = match rating_type {
+        RatingType::Overall => db::matches::trust(&mut *conn, match_id).await?,
+        RatingType::Personal => 100,
+    };
+    let max_rating_change_a = weighted(max_rating_change_a, trust);
+    let max_rating_change_b = weighted(max_rating_change_b, trust);
 
     // calculate e_a and e_b as functions of r_a and r_b
     let expected_score_a: f64 = get_my_expected_score(current_rating_a, current_rating_b);
     // let expected_score_b: f64 = get_my_expected_score(current_rating_b, current_rating_a);
     let expected_score_b: f64 = 1.0 - expected_score_a;
 
-    // set s_a and s_b as functions of status (s_b is just 1 - s_a)
-    let actual_score_a: f32 = if status == ">" {
-        1.0
-    } else if status == "<" {
-        0.0
-    } else if status == "=" {
-        0.5
-    } else {
-        -10000.0
-    };
-    let actual_score_b: f32 = 1.0 - actual_score_a;
-
     // calculate new_r_a as a function of r_a, k_a, s_a, and e_a
     // same for b
     let new_rating_a = get_my_new_rating(
@@ -68,81 +76,141 @@ pub async fn update_ratings(
         expected_score_b,
     );
 
-    match rating_type {
-        RatingType::Overall => {
-            let rating_change_a = i32::from(new_rating_a) - i32::from(current_rating_a);
-            let rating_change_b = i32::from(new_rating_b) - i32::from(current_rating_b);
-            let _ = sqlx::query!("UPDATE match SET elo_change_overall_a = $1, elo_change_overall_b = $2 WHERE user_id = $3 AND dog_a_id = $4 AND dog_b_id = $5",
-                                 rating_change_a,
-                                 rating_change_b,
-                                 user_id,
-                                 dog_a_id,
-                                 dog_b_id
-                                ).fetch_one(pool).await;
-        }
-        RatingType::Personal => {
-            let rating_change_a = i32::from(new_rating_a) - i32::from(current_rating_a);
-            let rating_change_b = i32::from(new_rating_b) - i32::from(current_rating_b);
-            let _ = sqlx::query!("UPDATE match SET elo_change_personal_a = $1, elo_change_personal_b = $2 WHERE user_id = $3 AND dog_a_id = $4 AND dog_b_id = $5",
-                                 rating_change_a,
-                                 rating_change_b,
-                                 user_id,
-                                 dog_a_id,
-                                 dog_b_id
-                                ).fetch_one(pool).await;
+    let rating_change_a = i32::from(new_rating_a) - i32::from(current_rating_a);
+    let rating_change_b = i32::from(new_rating_b) - i32::from(current_rating_b);
+    db::matches::set_elo_changes(
+        &mut *conn,
+        match_id,
+        rating_type,
+        rating_change_a,
+        rating_change_b,
+    )
+    .await?;
+
+    // set the new ratings in the database
+    ratings::set(&mut *conn, dog_a_id, rating_type, user_id, new_rating_a).await?;
+    ratings::set(&mut *conn, dog_b_id, rating_type, user_id, new_rating_b).await?;
+    Ok(())
+}
+
+/// Throws away the stored ratings and replays every resolved match in the order they were decided,
+/// rewriting the `elo_change_*` columns along the way. With `user_id` it only redoes that user's
+/// personal ratings. Returns how many matches were replayed.
+pub async fn recompute_ratings(
+    conn: &mut AnyConnection,
+    user_id: Option<i64>,
+) -> Result<usize, sqlx::Error> {
+    let matches = db::matches::resolved(&mut *conn, user_id).await?;
+
+    // (rating, number of matches played so far), keyed by dog (and user, for personal ratings)
+    let mut overall: HashMap<i64, (u16, u32)> = HashMap::new();
+    let mut personal: HashMap<(i64, i64), (u16, u32)> = HashMap::new();
+
+    for dog_match in matches.iter() {
+        let Some(actual_score_a) = get_actual_score(&dog_match.status) else {
+            continue;
+        };
+
+        if user_id.is_none() {
+            let a = *overall
+                .get(&dog_match.dog_a_id)
+                .unwrap_or(&(STARTING_RATING, 0));
+            let b = *overall
+                .get(&dog_match.dog_b_id)
+                .unwrap_or(&(STARTING_RATING, 0));
+            let trust = dog_match.trust.clamp(0, 100) as u8;
+            let (new_a, new_b) = replay_match(a, b, actual_score_a, trust);
+            overall.insert(dog_match.dog_a_id, new_a);
+            overall.insert(dog_match.dog_b_id, new_b);
+
+            db::matches::set_elo_changes(
+                &mut *conn,
+                dog_match.id,
+                RatingType::Overall,
+                i32::from(new_a.0) - i32::from(a.0),
+                i32::from(new_b.0) - i32::from(b.0),
+            )
+            .await?;
         }
+
+        let key_a = (dog_match.user_id, dog_match.dog_a_id);
+        let key_b = (dog_match.user_id, dog_match.dog_b_id);
+        let a = *personal.get(&key_a).unwrap_or(&(STARTING_RATING, 0));
+        let b = *personal.get(&key_b).unwrap_or(&(STARTING_RATING, 0));
+        let (new_a, new_b) = replay_match(a, b, actual_score_a, 100);
+        personal.insert(key_a, new_a);
+        personal.insert(key_b, new_b);
+
+        db::matches::set_elo_changes(
+            &mut *conn,
+            dog_match.id,
+            RatingType::Personal,
+            i32::from(new_a.0) - i32::from(a.0),
+            i32::from(new_b.0) - i32::from(b.0),
+        )
+        .await?;
     }
 
-    // set the new ratings in the database
-    set_rating(pool, dog_a_id, new_rating_a, rating_type, user_id).await;
-    set_rating(pool, dog_b_id, new_rating_b, rating_type, user_id).await;
+    ratings::clear(&mut *conn, user_id).await?;
+    for (dog_id, (value, _)) in overall {
+        ratings::insert_overall(&mut *conn, dog_id, value).await?;
+    }
+    for ((user_id, dog_id), (value, _)) in personal {
+        ratings::insert_personal(&mut *conn, user_id, dog_id, value).await?;
+    }
+
+    Ok(matches.len())
 }
 
+/// Same math as update_ratings, on (rating, matches played) pairs
+fn replay_match(
+    a: (u16, u32),
+    b: (u16, u32),
+    actual_score_a: f32,
+    trust: u8,
+) -> ((u16, u32), (u16, u32)) {
+    let expected_score_a = get_my_expected_score(a.0, b.0);
+    let new_rating_a = get_my_new_rating(
+        a.0,
+        weighted(get_max_rating_change(a.1), trust),
+        actual_score_a,
+        expected_score_a,
+    );
+    let new_rating_b = get_my_new_rating(
+        b.0,
+        weighted(get_max_rating_change(b.1), trust),
+        1.0 - actual_score_a,
+        1.0 - expected_score_a,
+    );
+    ((new_rating_a, a.1 + 1), (new_rating_b, b.1 + 1))
+}
+
+/// Gives the dog a starting rating first if it doesn't have one yet
 async fn get_current_rating(
-    pool: &Pool<Sqlite>,
+    conn: &mut AnyConnection,
     dog_id: i64,
     rating_type: RatingType,
     user_id: i64,
-) -> u16 {
-    struct RatingValueResult {
-        value: i64,
+) -> Result<u16, sqlx::Error> {
+    if let Some(rating) = ratings::get(&mut *conn, dog_id, rating_type, user_id).await? {
+        return Ok(rating);
     }
+    match rating_type {
+        RatingType::Overall => ratings::insert_overall(&mut *conn, dog_id, STARTING_RATING).await?,
+        RatingType::Personal => {
+            ratings::insert_personal(&mut *conn, user_id, dog_id, STARTING_RATING).await?
+        }
+    }
+    Ok(STARTING_RATING)
+}
 
-    let mut current_rating = match rating_type {
-        RatingType::Overall => sqlx::query_as!(
-            RatingValueResult,
-            "SELECT value FROM rating WHERE dog_id=$1 AND type='overall'",
-            dog_id
-        )
-        .fetch_optional(pool)
-        .await
-        .unwrap_or(None),
-        RatingType::Personal => sqlx::query_as!(
-            RatingValueResult,
-            "SELECT value FROM rating WHERE dog_id=$1 AND type='personal' AND user_id=$2",
-            dog_id,
-            user_id
-        )
-        .fetch_optional(pool)
-        .await
-        .unwrap_or(None),
-    };
-    if current_rating.is_none() {
-        current_rating = Some(match rating_type {
-            RatingType::Overall => sqlx::query_as!(
-                RatingValueResult,
-                "INSERT INTO rating (dog_id) VALUES ($1) RETURNING value",
-                dog_id
-            ).fetch_one(pool).await.unwrap(),
-            RatingType::Personal => sqlx::query_as!(
-                RatingValueResult,
-                "INSERT INTO rating (dog_id, type, user_id) VALUES ($1, 'personal', $2) RETURNING value",
-                dog_id, user_id
-            ).fetch_one(pool).await.unwrap()
-        });
+fn get_actual_score(status: &str) -> Option<f32> {
+    match status {
+        ">" => Some(1.0),
+        "<" => Some(0.0),
+        "=" => Some(0.5),
+        _ => None,
     }
-    let current_rating: u16 = current_rating.unwrap().value.try_into().unwrap();
-    current_rating
 }
 
 fn get_max_rating_change(num_matches: u32) -> u8 {
@@ -154,35 +222,9 @@ fn get_max_rating_change(num_matches: u32) -> u8 {
         32
     }
 }
-async fn get_num_matches(
-    pool: &Pool<Sqlite>,
-    dog_id: i64,
-    rating_type: RatingType,
-    user_id: i64,
-) -> u32 {
-    match rating_type {
-        RatingType::Overall => sqlx::query!(
-            "SELECT COUNT(*) as count FROM match WHERE dog_a_id=$1 OR dog_b_id=$1",
-            dog_id
-        )
-        .fetch_one(pool)
-        .await
-        .unwrap()
-        .count
-        .try_into()
-        .unwrap(),
-        RatingType::Personal => sqlx::query!(
-            "SELECT COUNT(*) as count FROM match WHERE dog_a_id=$1 OR dog_b_id=$1 AND user_id=$2",
-            dog_id,
-            user_id
-        )
-        .fetch_one(pool)
-        .await
-        .unwrap()
-        .count
-        .try_into()
-        .unwrap(),
-    }
+/// Scales a max rating change by how much the vote is trusted, out of 100
+fn weighted(max_rating_change: u8, trust: u8) -> u8 {
+    (u16::from(max_rating_change) * u16::from(trust.min(100)) / 100) as u8
 }
 
 fn get_my_expected_score(my_current_rating: u16, their_current_rating: u16) -> f64 {
@@ -204,25 +246,114 @@ fn get_my_new_rating(
     )
 }
 
-async fn set_rating(
-    pool: &Pool<Sqlite>,
-    dog_id: i64,
-    new_rating: u16,
-    rating_type: RatingType,
-    user_id: i64,
-) {
-    match rating_type {
-        RatingType::Overall => {
-            let _ = sqlx::query!(
-                "UPDATE rating SET value = $1 WHERE dog_id = $2 AND type = 'overall'",
-                new_rating,
-                dog_id
-            )
-            .fetch_one(pool)
-            .await;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn rating() -> impl Strategy<Value = u16> {
+        100..4000_u16
+    }
+
+    fn max_rating_change() -> impl Strategy<Value = u8> {
+        prop_oneof![Just(32_u8), Just(64), Just(128)]
+    }
+
+    fn actual_score() -> impl Strategy<Value = f32> {
+        prop_oneof![Just(0.0_f32), Just(0.5), Just(1.0)]
+    }
+
+    #[test]
+    fn actual_scores() {
+        assert_eq!(get_actual_score(">"), Some(1.0));
+        assert_eq!(get_actual_score("<"), Some(0.0));
+        assert_eq!(get_actual_score("="), Some(0.5));
+        assert_eq!(get_actual_score("…"), None);
+    }
+
+    #[test]
+    fn max_rating_change_settles_down() {
+        assert_eq!(get_max_rating_change(0), 128);
+        assert_eq!(get_max_rating_change(5), 64);
+        assert_eq!(get_max_rating_change(10), 32);
+        assert_eq!(get_max_rating_change(u32::MAX), 32);
+    }
+
+    #[test]
+    fn untrusted_votes_count_for_less() {
+        assert_eq!(weighted(128, 100), 128);
+        assert_eq!(weighted(64, 50), 32);
+        assert_eq!(weighted(32, 0), 0);
+        assert_eq!(weighted(32, 255), 32);
+    }
+
+    proptest! {
+        #[test]
+        fn expected_scores_are_probabilities(a in rating(), b in rating()) {
+            let expected_a = get_my_expected_score(a, b);
+            let expected_b = get_my_expected_score(b, a);
+            prop_assert!((0.0..=1.0).contains(&expected_a));
+            prop_assert!((expected_a + expected_b - 1.0).abs() < 1e-9);
         }
-        RatingType::Personal => {
-            let _ = sqlx::query!("UPDATE rating SET value = $1 WHERE dog_id = $2 AND type = 'personal' AND user_id = $3", new_rating, dog_id, user_id).fetch_one(pool).await;
+
+        #[test]
+        fn higher_rated_dogs_are_expected_to_win(a in rating(), b in rating()) {
+            let expected_a = get_my_expected_score(a, b);
+            if a > b {
+                prop_assert!(expected_a > 0.5);
+            } else if a < b {
+                prop_assert!(expected_a < 0.5);
+            } else {
+                prop_assert!((expected_a - 0.5).abs() < 1e-9);
+            }
         }
-    };
+
+        #[test]
+        fn ratings_are_conserved_between_equally_experienced_dogs(
+            a in 300..4000_u16,
+            b in 300..4000_u16,
+            k in max_rating_change(),
+            actual_score_a in actual_score(),
+        ) {
+            // far enough above the floor that it can't kick in
+            let expected_a = get_my_expected_score(a, b);
+            let new_a = get_my_new_rating(a, k, actual_score_a, expected_a);
+            let new_b = get_my_new_rating(b, k, 1.0 - actual_score_a, 1.0 - expected_a);
+            let change_a = i32::from(new_a) - i32::from(a);
+            let change_b = i32::from(new_b) - i32::from(b);
+            prop_assert!((change_a + change_b).abs() <= 1, "{} + {}", change_a, change_b);
+        }
+
+        #[test]
+        fn winners_never_lose_and_losers_never_gain(
+            a in rating(),
+            b in rating(),
+            k in max_rating_change(),
+        ) {
+            let expected_a = get_my_expected_score(a, b);
+            prop_assert!(get_my_new_rating(a, k, 1.0, expected_a) >= a);
+            prop_assert!(get_my_new_rating(a, k, 0.0, expected_a) <= a);
+        }
+
+        #[test]
+        fn changes_are_bounded_by_k(
+            a in rating(),
+            b in rating(),
+            k in max_rating_change(),
+            actual_score_a in actual_score(),
+        ) {
+            let new_a = get_my_new_rating(a, k, actual_score_a, get_my_expected_score(a, b));
+            prop_assert!((i32::from(new_a) - i32::from(a)).abs() <= i32::from(k));
+        }
+
+        #[test]
+        fn ratings_never_drop_below_the_floor(
+            a in 0..u16::MAX,
+            expected in 0.0..=1.0_f64,
+            k in max_rating_change(),
+            actual_score_a in actual_score(),
+        ) {
+            prop_assert!(get_my_new_rating(a, k, actual_score_a, expected) >= 100);
+        }
+    }
 }
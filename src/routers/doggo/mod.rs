@@ -1,218 +1,286 @@
 pub use self::elo::RatingType;
 use crate::{
+    db::{self, dogs::Dog, matches::DogMatch},
+    error::AppError,
+    fraud,
     layout::{base, NavLink},
-    routers::doggo::xp::{get_xp, get_xp_increase_from_pick, xp_section},
+    metrics,
+    routers::{
+        doggo::{
+            undo::{undo_last_pick, Undo, UndoFormParams, UNDO_BUTTON_SECONDS},
+            xp::{get_xp_increase_from_pick, xp_announcement, xp_section},
+        },
+        images::dog_image,
+    },
     AppContext, AppState, FormField,
 };
 use axum::{
     extract::{Path, State},
     response::Html,
     routing::{get, patch, post},
-    Extension, Router,
+    Extension, Form, Router,
 };
-use maud::{html, Markup, Render};
+use chrono::Duration;
+use maud::{html, Markup};
 use rand::seq::SliceRandom;
-use sqlx::{Pool, Sqlite};
-use std::cmp;
+use sqlx::{Any, Pool};
 
-mod elo;
+pub mod elo;
 pub mod name_dog;
+pub mod ranking;
+pub mod undo;
 pub mod xp;
 
-#[derive(Debug)]
-struct Dog {
-    id: i64,
-    image_url: String,
-    name: Option<String>,
+// the board can be played from the keyboard: top-doggo.js presses whichever button lists the key
+// in its aria-keyshortcuts, and puts focus back where it was once the next pairing is swapped in
+
+#[derive(Clone, Copy)]
+enum Side {
+    Left,
+    Right,
 }
-impl Render for Dog {
-    fn render(&self) -> Markup {
-        html! {
-            div class="max-w-96 w-5/12 flex flex-col items-center gap-3" {
-                button hx-post={"/pick-winner/"(self.id)} hx-target="#game-board" hx-swap="outerHTML transition:true" class="w-full aspect-square overflow-auto bg-base-200 hover:bg-base-300 active:scale-95 transition-all duration-75 rounded-md p-2" {
-                    img class="object-center object-cover aspect-square w-full" src=(self.image_url) ;
-                }
-                @if let Some(name) = &self.name {
-                    div class="text-3xl break-words max-w-full" {(name)}
-                } @else {
-                    (name_dog::name_dog_form(self.id, FormField::empty()))
-                }
-            }
 
+impl Side {
+    fn id(self) -> &'static str {
+        match self {
+            Self::Left => "pick-left",
+            Self::Right => "pick-right",
+        }
+    }
+
+    fn keyshortcuts(self) -> &'static str {
+        match self {
+            Self::Left => "ArrowLeft A",
+            Self::Right => "ArrowRight D",
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Left => "left",
+            Self::Right => "right",
         }
     }
 }
 
-async fn get_dog(dog_id: i64, pool: &Pool<Sqlite>) -> Option<Dog> {
-    let result = sqlx::query_as!(
-        Dog,
-        "SELECT id, image_url, name FROM dog WHERE id = $1",
-        dog_id
-    )
-    .fetch_one(pool)
-    .await;
-    match result {
-        Ok(dog) => Some(dog),
-        Err(_) => None,
+fn dog_choice(dog: &Dog, side: Side) -> Markup {
+    let label = match &dog.name {
+        Some(name) => format!("Pick {}", name),
+        None => format!("Pick the dog on the {}", side.name()),
+    };
+    html! {
+        div class="max-w-96 w-5/12 flex flex-col items-center gap-3" {
+            button id=(side.id()) hx-post={"/pick-winner/"(dog.id)} hx-target="#game-board" hx-swap="outerHTML transition:true"
+                aria-label=(label) aria-keyshortcuts=(side.keyshortcuts())
+                class="w-full aspect-square overflow-auto bg-base-200 hover:bg-base-300 active:scale-95 transition-all duration-75 rounded-md p-2 focus-visible:outline focus-visible:outline-4 focus-visible:outline-primary" {
+                (dog_image(dog.id, dog.name.as_deref().unwrap_or("A dog with no name"), "object-center object-cover aspect-square w-full", "(min-width: 56rem) 24rem, 42vw", false))
+            }
+            @if let Some(name) = &dog.name {
+                div class="text-3xl break-words max-w-full" {(name)}
+            } @else {
+                (name_dog::name_dog_form(dog.id, FormField::empty()))
+            }
+        }
     }
 }
 
-struct DogMatch {
-    id: i64,
+async fn get_dogs(
+    pool: &Pool<Any>,
     dog_a_id: i64,
     dog_b_id: i64,
-}
-async fn get_current_dog_match(user_id: i64, pool: &Pool<Sqlite>) -> Option<DogMatch> {
-    sqlx::query_as!(
-        DogMatch,
-        "SELECT id, dog_a_id, dog_b_id FROM match WHERE user_id=$1 AND status='…' LIMIT 1",
-        user_id
-    )
-    .fetch_optional(pool)
-    .await
-    .unwrap_or(None)
+) -> Result<(Dog, Dog), sqlx::Error> {
+    let dog_a = db::dogs::get(pool, dog_a_id)
+        .await?
+        .ok_or(sqlx::Error::RowNotFound)?;
+    let dog_b = db::dogs::get(pool, dog_b_id)
+        .await?
+        .ok_or(sqlx::Error::RowNotFound)?;
+    Ok((dog_a, dog_b))
 }
 
-async fn get_dog_match(user_id: i64, pool: &Pool<Sqlite>) -> Option<(Dog, Dog)> {
-    let current_dog_match = get_current_dog_match(user_id, pool).await;
-    if let Some(dog_match) = current_dog_match {
-        let dog_a = get_dog(dog_match.dog_a_id, pool).await.unwrap();
-        let dog_b = get_dog(dog_match.dog_b_id, pool).await.unwrap();
-        return Some((dog_a, dog_b));
+/// The user's current pairing, or a new one if they've decided the last one. Pairings they skipped
+/// only come back once none of their unfinished dogs has a fresh opponent left.
+/// None once they've seen every dog against every other dog.
+async fn get_dog_match(user_id: i64, pool: &Pool<Any>) -> Result<Option<(Dog, Dog)>, sqlx::Error> {
+    if let Some(dog_match) = db::matches::current(pool, user_id).await? {
+        return get_dogs(pool, dog_match.dog_a_id, dog_match.dog_b_id)
+            .await
+            .map(Some);
     }
 
-    let valid_dog_ids = sqlx::query!(
-        "SELECT id FROM dog WHERE approved = TRUE AND id NOT IN (SELECT dog_id AS id FROM user_finished_with_dog WHERE user_id=$1)", 
-        user_id)
-        .fetch_all(pool).await.unwrap();
+    let mut valid_dog_ids = db::dogs::unfinished_ids(pool, user_id).await?;
+    valid_dog_ids.shuffle(&mut rand::thread_rng());
 
-    if valid_dog_ids.is_empty() {
-        return None;
+    let mut skipped_match_id = None;
+    for dog_a_id in valid_dog_ids {
+        let potential_dog_b_ids = db::dogs::unmatched_opponent_ids(pool, dog_a_id, user_id).await?;
+        let dog_b_id = potential_dog_b_ids.choose(&mut rand::thread_rng()).copied();
+        if let Some(dog_b_id) = dog_b_id {
+            db::matches::create(pool, user_id, dog_a_id, dog_b_id).await?;
+            return get_dogs(pool, dog_a_id, dog_b_id).await.map(Some);
+        }
+        match db::matches::oldest_skipped(pool, dog_a_id, user_id).await? {
+            Some(match_id) => skipped_match_id = skipped_match_id.or(Some(match_id)),
+            None => db::dogs::finish(pool, user_id, dog_a_id).await?,
+        }
     }
 
-    let dog_a_id = valid_dog_ids.choose(&mut rand::thread_rng()).unwrap().id;
-
-    let potential_dog_b_ids = sqlx::query!(
-        "SELECT id FROM dog WHERE approved = TRUE AND id <> $1 AND id NOT IN (SELECT dog_a_id AS id FROM match WHERE dog_b_id=$1 AND user_id=$2 UNION SELECT dog_b_id AS id FROM match WHERE dog_a_id=$1 AND user_id=$2)",
-        dog_a_id, user_id)
-        .fetch_all(pool).await.unwrap();
-
-    if potential_dog_b_ids.is_empty() {
-        let _ = sqlx::query!(
-            "INSERT INTO user_finished_with_dog (user_id, dog_id) VALUES ($1, $2)",
-            user_id,
-            dog_a_id
-        )
-        .fetch_one(pool)
-        .await;
+    let Some(skipped_match_id) = skipped_match_id else {
+        return Ok(None);
+    };
+    db::matches::reoffer(pool, skipped_match_id).await?;
+    let dog_match = db::matches::current(pool, user_id)
+        .await?
+        .ok_or(sqlx::Error::RowNotFound)?;
+    get_dogs(pool, dog_match.dog_a_id, dog_match.dog_b_id)
+        .await
+        .map(Some)
+}
 
-        return Box::pin(get_dog_match(user_id, pool)).await;
+/// The match status a click on `winner` (a dog id, "tie" or "skip") means, if it's part of the pairing
+fn pick_status(winner: &str, dog_match: &DogMatch) -> Option<&'static str> {
+    if winner == dog_match.dog_a_id.to_string() {
+        Some(">")
+    } else if winner == dog_match.dog_b_id.to_string() {
+        Some("<")
+    } else if winner == "tie" {
+        Some("=")
+    } else if winner == "skip" {
+        Some(db::matches::SKIPPED)
+    } else {
+        None
     }
+}
 
-    let dog_b_id = potential_dog_b_ids
-        .choose(&mut rand::thread_rng())
-        .unwrap()
-        .id;
-
-    let _ = sqlx::query!(
-        "INSERT INTO match (user_id, dog_a_id, dog_b_id) VALUES ($1, $2, $3)",
-        user_id,
-        dog_a_id,
-        dog_b_id
-    )
-    .fetch_one(pool)
-    .await;
-
-    let dog_a = get_dog(dog_a_id, pool).await.unwrap();
-    let dog_b = get_dog(dog_b_id, pool).await.unwrap();
-    Some((dog_a, dog_b))
+/// Pick xp grows with thinking time up to 5 seconds. 5 if we can't tell how long it took.
+fn seconds_deliberated(time_taken: Option<Duration>) -> u32 {
+    time_taken.map_or(5, |time_taken| time_taken.num_seconds().clamp(0, 5) as u32)
 }
 
-async fn game_board(user_id: i64, pool: &Pool<Sqlite>, xp_increase: Option<u32>) -> Markup {
-    let dogs = get_dog_match(user_id, pool).await;
+/// `pick` is the id of the pick that led here, if one did, and what it was worth. It can be undone for a few seconds.
+async fn game_board(
+    user_id: i64,
+    pool: &Pool<Any>,
+    pick: Option<(i64, u32)>,
+) -> Result<Markup, AppError> {
+    let xp_increase = pick.map(|(_, xp_increase)| xp_increase);
+    let dogs = get_dog_match(user_id, pool).await?;
     let Some((dog_a, dog_b)) = dogs else {
-        return html! {
-            div class="flex flex-col items-center justify-center gap-6 flex-1" {
-                h1 class="text-5xl" {"You've won! Check out " a href="/leaderboard" class="underline text-blue-700" {"the leaderboard!"}}
+        return Ok(html! {
+            div id="game-board" class="flex flex-col items-center justify-center gap-6 flex-1" {
+                h1 id="game-board-heading" tabindex="-1" class="text-5xl focus:outline-none" {"You've won! Check out " a href="/leaderboard" class="underline text-blue-700" {"the leaderboard!"}}
                 p {"(Then please go outside and touch grass and pet a real dog or something)"}
             }
-        };
+        });
     };
 
-    let xp = get_xp(pool, user_id).await;
+    let xp = db::users::total_xp(pool, user_id).await?;
 
-    html! {
+    Ok(html! {
         div id="game-board" class="flex flex-col items-center justify-center gap-6 flex-1" {
             (xp_section(xp, xp_increase, false))
-            h1 class="text-5xl text-center" {"Pick your favorite"}
+            @if let Some(xp_increase) = xp_increase {
+                (xp_announcement(xp, xp_increase))
+            }
+            h1 id="game-board-heading" tabindex="-1" aria-describedby="game-board-help" class="text-5xl text-center focus:outline-none" {"Pick your favorite"}
+            p id="game-board-help" class="sr-only" {"Press the left arrow or A to pick the dog on the left, the right arrow or D to pick the dog on the right, or space for a tie."}
             div class="flex justify-center gap-6 w-full vt-slide-up" {
-                (dog_a)
-                (dog_b)
+                (dog_choice(&dog_a, Side::Left))
+                (dog_choice(&dog_b, Side::Right))
             }
             div class="flex justify-center -mt-2" {
-                button hx-post="/pick-winner/tie" hx-target="#game-board" hx-swap="outerHTML transition:true" class="flex flex-col justify-center items-center gap-1 bg-base-200 hover:bg-base-300 active:scale-90 transition-all duration-75 rounded-md w-28 h-28 p-8 vt-stay-on-top" {
-                    div class="text-6xl" {"="}
+                button id="pick-tie" hx-post="/pick-winner/tie" hx-target="#game-board" hx-swap="outerHTML transition:true" aria-label="Tie" aria-keyshortcuts="Space" class="flex flex-col justify-center items-center gap-1 bg-base-200 hover:bg-base-300 active:scale-90 transition-all duration-75 rounded-md w-28 h-28 p-8 vt-stay-on-top focus-visible:outline focus-visible:outline-4 focus-visible:outline-primary" {
+                    div class="text-6xl" aria-hidden="true" {"="}
                     div class="text-lg" {"Tie"}
                 }
             }
+            div class="h-8 -mt-2 flex justify-center gap-2" {
+                button id="pick-skip" hx-post="/pick-winner/skip" hx-target="#game-board" hx-swap="outerHTML transition:true" aria-label="Skip this pairing" class="btn btn-ghost btn-sm text-lg" {"Skip ⏭"}
+                @if let Some((pick_id, _)) = pick {
+                    button id="undo-pick" hx-post="/undo-pick" hx-vals=(format!(r#"{{"pick_id": {}}}"#, pick_id)) hx-target="#game-board" hx-swap="outerHTML transition:true" aria-label="Undo your last pick" class="btn btn-ghost btn-sm text-lg"
+                        data-fade-after=(UNDO_BUTTON_SECONDS)
+                        {"↶ Undo"}
+                }
+            }
+            a href="/rank" class="underline text-primary text-lg" {"Rank a few dogs at once"}
         }
-    }
+    })
 }
 
 pub fn doggo_router() -> Router<AppState> {
     Router::<AppState>::new()
         .route("/", get(
             |State(state): State<AppState>, Extension(context): Extension<AppContext>| async move {
-                base(
+                Ok::<_, AppError>(base(
                     html! {
-                        (game_board(context.user_id, &state.pool, None).await)
+                        (game_board(context.user_id, &state.pool, None).await?)
                     },
                     None,
                     Some(NavLink::Root)
-                )
+                ))
             },
         ))
         .route("/name-dog", patch(name_dog::name_dog_router))
+        .route("/rank", get(ranking::ranking_page).post(ranking::submit_ranking))
         .route("/pick-winner/:winner", post(
             |State(state): State<AppState>, Extension(context): Extension<AppContext>, Path(winner): Path<String>| async move {
                 let pool = &state.pool;
                 let user_id = context.user_id;
 
-                let new_game_board = |xp_increase: Option<u32>| async move {
-                    Html(game_board(user_id, pool, xp_increase).await.into_string())
+                let new_game_board = |pick: Option<(i64, u32)>| async move {
+                    Ok::<_, AppError>(Html(game_board(user_id, pool, pick).await?.into_string()))
                 };
 
-                let current_dog_match = get_current_dog_match(user_id, pool).await;
-                if current_dog_match.is_none() {
+                let Some(current_dog_match) = db::matches::current(pool, user_id).await? else {
                     return new_game_board(None).await;
-                }
-                let current_dog_match = current_dog_match.unwrap();
+                };
+                let Some(status) = pick_status(&winner, &current_dog_match) else {
+                    return new_game_board(None).await;
+                };
 
-                let status: Result<&str, ()> = if winner == current_dog_match.dog_a_id.to_string() {Ok(">")}
-                    else if winner == current_dog_match.dog_b_id.to_string() {Ok("<")}
-                    else if winner == "tie" {Ok("=")}
-                    else {Err(())};
-                if status.is_err() {
+                // passing on a pairing doesn't count as a vote
+                if status == db::matches::SKIPPED {
+                    if db::matches::skip(pool, current_dog_match.id).await? {
+                        metrics::PICKS_TOTAL.with_label_values(&[metrics::pick_outcome(status)]).inc();
+                    }
                     return new_game_board(None).await;
                 }
-                let status = status.unwrap();
 
-                let _ = sqlx::query!("UPDATE match SET status = $1 WHERE id = $2", status, current_dog_match.id).fetch_one(pool).await;
+                // all or nothing, so undo always has the whole pick to take back. a double submit
+                // finds the match already decided and leaves it be
+                let mut transaction = pool.begin().await?;
+                if !db::matches::resolve(&mut *transaction, current_dog_match.id, status).await? {
+                    drop(transaction);
+                    return new_game_board(None).await;
+                }
 
-                let DogMatch {dog_a_id, dog_b_id, ..} = current_dog_match;
+                let DogMatch {id, dog_a_id, dog_b_id} = current_dog_match;
 
-                elo::update_ratings(pool, user_id, dog_a_id, dog_b_id, RatingType::Overall, status).await;
-                elo::update_ratings(pool, user_id, dog_a_id, dog_b_id, RatingType::Personal, status).await;
+                fraud::check_vote(&mut transaction, user_id, id, context.client_ip).await?;
+                elo::update_ratings(&mut transaction, id, user_id, dog_a_id, dog_b_id, RatingType::Overall, status).await?;
+                elo::update_ratings(&mut transaction, id, user_id, dog_a_id, dog_b_id, RatingType::Personal, status).await?;
 
-                struct SecondsRecord {seconds: Option<i64>}
-                let seconds_deliberated = sqlx::query_as!(SecondsRecord, "select CAST ((JulianDay(updated_at) - JulianDay(created_at)) * 24 * 60 * 60 AS INTEGER) as seconds FROM match WHERE id = $1 AND created_at IS NOT NULL AND updated_at IS NOT NULL", current_dog_match.id).fetch_one(pool).await.unwrap_or(SecondsRecord { seconds: Some(5) }).seconds.unwrap();
-                let seconds_deliberated = cmp::min(5, seconds_deliberated) as u32;
+                let time_taken = db::matches::time_taken(&mut *transaction, id).await?;
+                let xp_increase: u32 = get_xp_increase_from_pick(seconds_deliberated(time_taken));
 
-                let xp_increase: u32 = get_xp_increase_from_pick(seconds_deliberated);
+                db::users::add_xp(&mut *transaction, user_id, xp_increase).await?;
+                db::matches::set_xp(&mut *transaction, id, xp_increase).await?;
+                transaction.commit().await?;
 
-                let _ = sqlx::query!("UPDATE user SET total_xp = total_xp + $1 WHERE id = $2", xp_increase, user_id).fetch_one(pool).await;
+                metrics::PICKS_TOTAL.with_label_values(&[metrics::pick_outcome(status)]).inc();
 
-                new_game_board(Some(xp_increase)).await
+                new_game_board(Some((id, xp_increase))).await
+            }
+        ))
+        .route("/undo-pick", post(
+            |State(state): State<AppState>, Extension(context): Extension<AppContext>, form: Option<Form<UndoFormParams>>| async move {
+                let pick_id = form.and_then(|Form(form)| form.pick_id);
+                match undo_last_pick(&state.pool, context.user_id, pick_id).await? {
+                    Undo::Undone => metrics::PICKS_UNDONE_TOTAL.inc(),
+                    Undo::NothingToUndo => {}
+                    Undo::TooLate => return Err(AppError::BadRequest("Too late to undo that one, other votes have built on it since.".to_string())),
+                }
+                Ok(Html(game_board(context.user_id, &state.pool, None).await?.into_string()))
             }
         ))
         .route("/dedication", get(|| async move {
@@ -225,3 +293,33 @@ pub fn doggo_router() -> Router<AppState> {
             }, Some("Dedication".to_string()), None)
         }))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn picks_map_to_statuses() {
+        let dog_match = DogMatch {
+            id: 1,
+            dog_a_id: 7,
+            dog_b_id: 12,
+        };
+        assert_eq!(pick_status("7", &dog_match), Some(">"));
+        assert_eq!(pick_status("12", &dog_match), Some("<"));
+        assert_eq!(pick_status("tie", &dog_match), Some("="));
+        assert_eq!(pick_status("skip", &dog_match), Some("-"));
+        assert_eq!(pick_status("3", &dog_match), None);
+        assert_eq!(pick_status("", &dog_match), None);
+    }
+
+    #[test]
+    fn deliberation_is_capped() {
+        assert_eq!(seconds_deliberated(Some(Duration::seconds(1))), 1);
+        assert_eq!(seconds_deliberated(Some(Duration::seconds(3))), 3);
+        assert_eq!(seconds_deliberated(Some(Duration::minutes(10))), 5);
+        assert_eq!(seconds_deliberated(None), 5);
+        // a clock that went backwards shouldn't be worth a fortune
+        assert_eq!(seconds_deliberated(Some(Duration::seconds(-30))), 0);
+    }
+}
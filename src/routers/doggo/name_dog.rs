@@ -1,5 +1,8 @@
 use crate::{
-    routers::doggo::xp::{get_xp, xp_section, XP_INCREASE_FOR_NAME_DOG},
+    db::{self, log::Event},
+    error::AppError,
+    metrics,
+    routers::doggo::xp::{xp_announcement, xp_section, XP_INCREASE_FOR_NAME_DOG},
     AppContext, AppState, FormField,
 };
 use axum::{
@@ -9,13 +12,13 @@ use axum::{
 };
 use maud::{html, Markup};
 use serde::Deserialize;
-use sqlx::{Pool, Sqlite};
+use sqlx::{Any, Pool};
 
 pub async fn name_dog_router(
     State(state): State<AppState>,
     Extension(context): Extension<AppContext>,
     Form(form): Form<NameDogFormParams>,
-) -> impl IntoResponse {
+) -> Result<impl IntoResponse, AppError> {
     let new_name = form.new_name.trim();
 
     let err = |form_error: &str| {
@@ -32,78 +35,76 @@ pub async fn name_dog_router(
     };
 
     if new_name.is_empty() {
-        return err("^ Type this dog's new name right up here :)");
+        return Ok(err("^ Type this dog's new name right up here :)"));
     }
 
-    let result = name_dog(&state.pool, context.user_id, form.dog_id, new_name).await;
-    if let Err(error) = result {
-        return err(&error);
-    }
+    let new_name = match name_dog(&state.pool, context.user_id, form.dog_id, new_name).await? {
+        Ok(new_name) => new_name,
+        Err(error) => return Ok(err(&error)),
+    };
 
-    let client_ip: Option<String> = context.client_ip.map(|ip| ip.to_string());
-    let _ = sqlx::query!(
-        "INSERT INTO log (action, user_id, client_ip, notes) VALUES ('name-dog', $1, $2, $3)",
-        context.user_id,
-        client_ip,
-        form.dog_id
+    let _ = db::log::record(
+        &state.pool,
+        &Event::NameDog {
+            dog_id: form.dog_id,
+        },
+        Some(context.user_id),
+        context.client_ip,
     )
-    .fetch_one(&state.pool)
     .await;
 
-    Html(
+    let total_xp = db::users::total_xp(&state.pool, context.user_id).await?;
+    Ok(Html(
         html! {
-            div class="text-3xl" {(result.unwrap())}
-            (xp_section(get_xp(&state.pool, context.user_id).await, Some(XP_INCREASE_FOR_NAME_DOG), true))
+            div class="text-3xl" {(new_name)}
+            (xp_section(total_xp, Some(XP_INCREASE_FOR_NAME_DOG), true))
+            (xp_announcement(total_xp, XP_INCREASE_FOR_NAME_DOG))
 
         }
         .into_string(),
-    )
+    ))
 }
 
 pub async fn name_dog(
-    pool: &Pool<Sqlite>,
+    pool: &Pool<Any>,
     user_id: i64,
     dog_id: i64,
     new_name: &str,
-) -> Result<String, String> {
-    if new_name == "Jeff" {
-        return Err("NO, don't name him Jeff >:(".to_string());
+) -> Result<Result<String, String>, AppError> {
+    // the inner error is for the visitor, shown under the name field
+    if let Err(error) = validate_dog_name(new_name) {
+        return Ok(Err(error));
     }
-    if new_name.len() > 100 {
-        return Err("Maybe something a little shorter?".to_string());
+    let dog = db::dogs::get(pool, dog_id).await?;
+    let Some(dog) = dog else {
+        return Ok(Err("404: Dog not found".to_string()));
+    };
+    if let Some(old_name) = dog.name {
+        return Ok(Err(format!("{} already has a name, silly.", old_name)));
     }
-    let dog = sqlx::query!("SELECT name FROM dog WHERE id = $1", dog_id)
-        .fetch_optional(pool)
+
+    if db::dogs::set_name(pool, dog_id, new_name, user_id)
         .await
-        .unwrap();
-    if dog.is_none() {
-        return Err("404: Dog not found".to_string());
-    }
-    let old_name = dog.unwrap().name;
-    if old_name.is_some() {
-        return Err(format!("{} already has a name, silly.", old_name.unwrap()));
+        .is_err()
+    {
+        return Ok(Err("C'mon, something more original!".to_string()));
     }
 
-    let result = sqlx::query!(
-        "UPDATE dog SET (name, namer_id) = ($1, $2) WHERE id = $3 RETURNING name",
-        new_name,
-        user_id,
-        dog_id
-    )
-    .fetch_one(pool)
-    .await;
-    if result.is_err() {
-        return Err("C'mon, something more original!".to_string());
-    }
+    metrics::NAMES_ASSIGNED_TOTAL.inc();
 
-    let _ = sqlx::query!(
-        "UPDATE user SET total_xp = total_xp + $1 WHERE id = $2",
-        XP_INCREASE_FOR_NAME_DOG,
-        user_id
-    )
-    .fetch_one(pool)
-    .await;
-    Ok(result.unwrap().name.unwrap())
+    db::users::add_xp(pool, user_id, XP_INCREASE_FOR_NAME_DOG).await?;
+    Ok(Ok(new_name.to_string()))
+}
+
+/// The rules every name has to follow, apart from being unique (which the database checks)
+pub fn validate_dog_name(new_name: &str) -> Result<(), String> {
+    if new_name == "Jeff" {
+        return Err("NO, don't name him Jeff >:(".to_string());
+    }
+    if new_name.len() > 100 {
+        return Err("Maybe something a little shorter?".to_string());
+    }
+    Ok(())
 }
 
 #[derive(Deserialize, Debug)]
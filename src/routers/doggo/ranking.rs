@@ -0,0 +1,368 @@
+use super::seconds_deliberated;
+use crate::{
+    db::{self, dogs::Dog},
+    error::AppError,
+    fraud,
+    layout::{base, NavLink},
+    metrics,
+    routers::{
+        doggo::{
+            elo::{self, RatingType},
+            xp::{get_xp_increase_from_pick, xp_announcement, xp_section},
+        },
+        images::dog_image,
+    },
+    AppContext, AppState,
+};
+use axum::{
+    extract::{Query, State},
+    response::{Html, IntoResponse},
+    Extension, Form,
+};
+use maud::{html, Markup};
+use rand::{seq::SliceRandom, Rng};
+use serde::Deserialize;
+use sqlx::{Any, Pool};
+
+// ranking mode puts a handful of dogs up at once and the user puts them in order, favorite first.
+// the order is broken down into one match per pair of dogs (the first beats every dog after it,
+// the second beats every dog after that, ...), so the ratings, fraud checks and admin tools treat
+// them like any other votes. the dogs in a ranking are ones the user hasn't seen against each
+// other yet, so none of those pairs are ever voted on twice.
+
+pub const MIN_RANKING_SIZE: usize = 3;
+pub const MAX_RANKING_SIZE: usize = 5;
+const DEFAULT_RANKING_SIZE: usize = 4;
+
+#[derive(Deserialize)]
+pub struct RankingParams {
+    size: Option<usize>,
+}
+
+#[derive(Deserialize)]
+pub struct RankingFormParams {
+    ranking_id: i64,
+    /// The dogs' ids, favorite first, separated by commas
+    order: String,
+}
+
+pub async fn ranking_page(
+    State(state): State<AppState>,
+    Extension(context): Extension<AppContext>,
+    Query(params): Query<RankingParams>,
+) -> Result<impl IntoResponse, AppError> {
+    // a different size means a fresh ranking, even if there's one on screen already
+    if let Some(size) = params.size {
+        let size = size.clamp(MIN_RANKING_SIZE, MAX_RANKING_SIZE);
+        if let Some(ranking_id) = db::rankings::current(&state.pool, context.user_id).await? {
+            if db::rankings::dog_ids(&state.pool, ranking_id).await?.len() != size {
+                let mut conn = state.pool.acquire().await?;
+                db::rankings::withdraw_current(&mut conn, context.user_id).await?;
+            }
+        }
+    }
+    Ok(base(
+        ranking_board(context.user_id, &state.pool, params.size, None).await?,
+        Some("Rank".to_string()),
+        Some(NavLink::Root),
+    ))
+}
+
+pub async fn submit_ranking(
+    State(state): State<AppState>,
+    Extension(context): Extension<AppContext>,
+    Form(form): Form<RankingFormParams>,
+) -> Result<Html<String>, AppError> {
+    let pool = &state.pool;
+    let user_id = context.user_id;
+
+    let new_ranking_board = |xp_increase: Option<u32>| async move {
+        Ok::<_, AppError>(Html(
+            ranking_board(user_id, pool, None, xp_increase)
+                .await?
+                .into_string(),
+        ))
+    };
+
+    if db::rankings::current(pool, user_id).await? != Some(form.ranking_id) {
+        return new_ranking_board(None).await;
+    }
+    let dog_ids = db::rankings::dog_ids(pool, form.ranking_id).await?;
+    let Some(order) = parse_order(&form.order, &dog_ids) else {
+        return new_ranking_board(None).await;
+    };
+
+    // the claim, the matches it turns into and the xp for them land together or not at all
+    let mut transaction = pool.begin().await?;
+    if !db::rankings::submit(&mut transaction, form.ranking_id, &order).await? {
+        drop(transaction);
+        return new_ranking_board(None).await;
+    }
+
+    let pairs = ranked_pairs(&order, &mut rand::thread_rng());
+    for (dog_a_id, dog_b_id, status) in pairs {
+        // the pairing might have been put up one at a time since the ranking was
+        if db::matches::exists(&mut *transaction, user_id, dog_a_id, dog_b_id).await? {
+            continue;
+        }
+        let match_id = db::matches::create_ranked(
+            &mut *transaction,
+            user_id,
+            form.ranking_id,
+            dog_a_id,
+            dog_b_id,
+            status,
+        )
+        .await?;
+
+        fraud::check_vote(&mut transaction, user_id, match_id, context.client_ip).await?;
+        elo::update_ratings(
+            &mut transaction,
+            match_id,
+            user_id,
+            dog_a_id,
+            dog_b_id,
+            RatingType::Overall,
+            status,
+        )
+        .await?;
+        elo::update_ratings(
+            &mut transaction,
+            match_id,
+            user_id,
+            dog_a_id,
+            dog_b_id,
+            RatingType::Personal,
+            status,
+        )
+        .await?;
+    }
+
+    // every dog after the first is worth a pick, with the time split between them
+    let picks = order.len() as i32 - 1;
+    let time_taken = db::rankings::time_taken(&mut *transaction, form.ranking_id)
+        .await?
+        .map(|time_taken| time_taken / picks);
+    let xp_increase = get_xp_increase_from_pick(seconds_deliberated(time_taken)) * picks as u32;
+    db::users::add_xp(&mut *transaction, user_id, xp_increase).await?;
+    transaction.commit().await?;
+
+    metrics::RANKINGS_TOTAL.inc();
+
+    new_ranking_board(Some(xp_increase)).await
+}
+
+/// The submitted order, if it has every dog in the ranking exactly once
+fn parse_order(order: &str, dog_ids: &[i64]) -> Option<Vec<i64>> {
+    let order = order
+        .split(',')
+        .map(|dog_id| dog_id.trim().parse().ok())
+        .collect::<Option<Vec<i64>>>()?;
+    let mut sorted = order.clone();
+    sorted.sort_unstable();
+    let mut expected = dog_ids.to_vec();
+    expected.sort_unstable();
+    (sorted == expected).then_some(order)
+}
+
+/// One (dog a, dog b, status) match for every pair in `order`, the better placed dog winning.
+/// Which dog goes on the left is a coin flip, like it would be on the game board, so the fraud
+/// checks don't take a ranking for somebody always picking the same side.
+fn ranked_pairs(order: &[i64], rng: &mut impl Rng) -> Vec<(i64, i64, &'static str)> {
+    let mut pairs = vec![];
+    for (i, &winner) in order.iter().enumerate() {
+        for &loser in &order[i + 1..] {
+            if rng.gen_bool(0.5) {
+                pairs.push((winner, loser, ">"));
+            } else {
+                pairs.push((loser, winner, "<"));
+            }
+        }
+    }
+    pairs
+}
+
+/// Up to `size` dogs this user hasn't seen against each other yet, or none if there aren't at
+/// least MIN_RANKING_SIZE of them
+async fn pick_dogs(pool: &Pool<Any>, user_id: i64, size: usize) -> Result<Vec<i64>, sqlx::Error> {
+    let mut start_ids = db::dogs::unfinished_ids(pool, user_id).await?;
+    start_ids.shuffle(&mut rand::thread_rng());
+
+    for start_id in start_ids {
+        let mut dog_ids = vec![start_id];
+        let mut candidate_ids = db::dogs::unmatched_opponent_ids(pool, start_id, user_id).await?;
+        while dog_ids.len() < size {
+            let next_id = candidate_ids.choose(&mut rand::thread_rng()).copied();
+            let Some(next_id) = next_id else {
+                break;
+            };
+            let opponent_ids = db::dogs::unmatched_opponent_ids(pool, next_id, user_id).await?;
+            candidate_ids.retain(|id| *id != next_id && opponent_ids.contains(id));
+            dog_ids.push(next_id);
+        }
+        if dog_ids.len() >= MIN_RANKING_SIZE {
+            return Ok(dog_ids);
+        }
+    }
+    Ok(vec![])
+}
+
+/// The user's current ranking, or a new one of `size` dogs if they've submitted the last one
+async fn get_ranking(
+    user_id: i64,
+    pool: &Pool<Any>,
+    size: Option<usize>,
+) -> Result<Option<(i64, Vec<Dog>)>, sqlx::Error> {
+    let ranking_id = match db::rankings::current(pool, user_id).await? {
+        Some(ranking_id) => ranking_id,
+        None => {
+            let size = size
+                .unwrap_or(DEFAULT_RANKING_SIZE)
+                .clamp(MIN_RANKING_SIZE, MAX_RANKING_SIZE);
+            let mut dog_ids = pick_dogs(pool, user_id, size).await?;
+            if dog_ids.is_empty() {
+                return Ok(None);
+            }
+            // pick_dogs always starts with the dog it built the ranking around, and the first
+            // dog on screen tends to end up on top
+            dog_ids.shuffle(&mut rand::thread_rng());
+            let mut transaction = pool.begin().await?;
+            let ranking_id = db::rankings::create(&mut transaction, user_id, &dog_ids).await?;
+            transaction.commit().await?;
+            ranking_id
+        }
+    };
+
+    let mut dogs = vec![];
+    for dog_id in db::rankings::dog_ids(pool, ranking_id).await? {
+        dogs.push(
+            db::dogs::get(pool, dog_id)
+                .await?
+                .ok_or(sqlx::Error::RowNotFound)?,
+        );
+    }
+    Ok(Some((ranking_id, dogs)))
+}
+
+/// `xp_increase` is what the ranking that led here was worth, if one did
+async fn ranking_board(
+    user_id: i64,
+    pool: &Pool<Any>,
+    size: Option<usize>,
+    xp_increase: Option<u32>,
+) -> Result<Markup, AppError> {
+    let xp = db::users::total_xp(pool, user_id).await?;
+    let Some((ranking_id, dogs)) = get_ranking(user_id, pool, size).await? else {
+        return Ok(html! {
+            div id="ranking-board" class="flex flex-col items-center justify-center gap-6 flex-1 text-center" {
+                @if let Some(xp_increase) = xp_increase {
+                    (xp_section(xp, Some(xp_increase), false))
+                    (xp_announcement(xp, xp_increase))
+                }
+                h1 id="ranking-board-heading" tabindex="-1" class="text-5xl focus:outline-none" {"You've ranked every dog you can!"}
+                p class="text-lg" {
+                    "There aren't three dogs left that you haven't seen against each other. "
+                    a href="/" class="underline text-primary" {"Back to one pair at a time"}
+                }
+            }
+        });
+    };
+
+    let order = dogs
+        .iter()
+        .map(|dog| dog.id.to_string())
+        .collect::<Vec<_>>()
+        .join(",");
+
+    Ok(html! {
+        div id="ranking-board" class="flex flex-col items-center justify-center gap-6 flex-1" {
+            (xp_section(xp, xp_increase, false))
+            @if let Some(xp_increase) = xp_increase {
+                (xp_announcement(xp, xp_increase))
+            }
+            h1 id="ranking-board-heading" tabindex="-1" aria-describedby="ranking-help" class="text-5xl text-center focus:outline-none" {"Rank your favorites"}
+            p id="ranking-help" class="text-lg text-center px-2" {"Tap the dogs from favorite to least favorite, or drag them into order."}
+            // top-doggo.js keeps the order field in step with the list
+            form hx-post="/rank" hx-target="#ranking-board" hx-swap="outerHTML" class="flex flex-col items-center gap-4 w-full" {
+                input type="hidden" name="ranking_id" value=(ranking_id);
+                input type="hidden" name="order" id="ranking-order" value=(order);
+                ol id="ranking" class="flex flex-wrap justify-center gap-4 w-full px-2" {
+                    @for (i, dog) in dogs.iter().enumerate() {
+                        li draggable="true" data-dog-id=(dog.id) class="w-5/12 sm:w-1/6 min-w-32 flex flex-col items-center gap-2 cursor-grab" {
+                            button type="button" aria-label={"Put "(dog.name.as_deref().unwrap_or("this dog"))" next"}
+                                class="ranking-dog relative w-full aspect-square bg-base-200 hover:bg-base-300 active:scale-95 transition-all duration-75 rounded-md p-2 focus-visible:outline focus-visible:outline-4 focus-visible:outline-primary" {
+                                span class="ranking-place badge badge-lg badge-primary absolute top-1 left-1 z-10" aria-hidden="true" {(i + 1)}
+                                (dog_image(dog.id, dog.name.as_deref().unwrap_or("A dog with no name"), "object-center object-cover aspect-square w-full rounded", "(min-width: 40rem) 16vw, 42vw", false))
+                            }
+                            div class="text-xl break-words max-w-full" {(dog.name.as_deref().unwrap_or("No name yet"))}
+                        }
+                    }
+                }
+                div class="flex gap-2" {
+                    button type="button" id="ranking-reset" class="btn btn-ghost" {"Start over"}
+                    button type="submit" class="btn btn-primary" {"Lock it in"}
+                }
+            }
+            nav class="flex flex-wrap justify-center items-center gap-2 text-lg" aria-label="Dogs per round" {
+                "Dogs per round:"
+                @for size in MIN_RANKING_SIZE..=MAX_RANKING_SIZE {
+                    @if size == dogs.len() {
+                        span class="btn btn-sm btn-active" aria-current="true" {(size)}
+                    } @else {
+                        a href={"/rank?size="(size)} class="btn btn-sm btn-ghost" {(size)}
+                    }
+                }
+            }
+            a href="/" class="underline text-primary text-lg" {"Back to one pair at a time"}
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    #[test]
+    fn orders_need_every_dog_once() {
+        assert_eq!(parse_order("3,1,2", &[1, 2, 3]), Some(vec![3, 1, 2]));
+        assert_eq!(parse_order(" 2, 3 ,1", &[1, 2, 3]), Some(vec![2, 3, 1]));
+        assert_eq!(parse_order("3,1", &[1, 2, 3]), None);
+        assert_eq!(parse_order("3,1,1", &[1, 2, 3]), None);
+        assert_eq!(parse_order("3,1,4", &[1, 2, 3]), None);
+        assert_eq!(parse_order("3,1,2,2", &[1, 2, 3]), None);
+        assert_eq!(parse_order("", &[1, 2, 3]), None);
+        assert_eq!(parse_order("one,two,three", &[1, 2, 3]), None);
+    }
+
+    #[test]
+    fn every_dog_beats_the_ones_ranked_below_it() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let pairs = ranked_pairs(&[30, 10, 20, 40], &mut rng);
+        assert_eq!(pairs.len(), 6);
+
+        let winners: Vec<(i64, i64)> = pairs
+            .iter()
+            .map(|&(dog_a_id, dog_b_id, status)| match status {
+                ">" => (dog_a_id, dog_b_id),
+                "<" => (dog_b_id, dog_a_id),
+                _ => panic!("{} isn't a win", status),
+            })
+            .collect();
+        assert_eq!(
+            winners,
+            vec![(30, 10), (30, 20), (30, 40), (10, 20), (10, 40), (20, 40)]
+        );
+    }
+
+    #[test]
+    fn winners_land_on_both_sides() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let statuses: Vec<&str> = (0..10)
+            .flat_map(|_| ranked_pairs(&[1, 2, 3, 4, 5], &mut rng))
+            .map(|(_, _, status)| status)
+            .collect();
+        assert!(statuses.contains(&">"));
+        assert!(statuses.contains(&"<"));
+    }
+}
@@ -0,0 +1,103 @@
+use crate::db::{self, ratings::RatingType};
+use serde::Deserialize;
+use sqlx::{Any, Pool};
+
+// a pick can be taken back for a little while after it's made. the ratings it moved get moved back
+// by the changes stored on the match instead of being replayed, which only comes out right if
+// neither dog has been in another decided match since. if one has, the pick stays: taking it back
+// then means recomputing everybody's ratings (see elo::recompute_ratings), which is an admin job.
+
+/// How long the undo button stays up after a pick, in seconds
+pub const UNDO_BUTTON_SECONDS: u32 = 5;
+/// How long a pick can still be undone for, with some slack on top of the button for slow
+/// connections
+const UNDO_WINDOW_SECONDS: i64 = 30;
+
+#[derive(Deserialize)]
+pub struct UndoFormParams {
+    /// The pick the undo button was put up for, so a double tap can't take back the one before it
+    pub pick_id: Option<i64>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Undo {
+    Undone,
+    /// No pick recent enough
+    NothingToUndo,
+    /// Other votes have built on the pick's ratings since
+    TooLate,
+}
+
+/// Puts the user's last pick back in front of them, as if they'd never made it: its ratings
+/// changes reversed, its xp taken back, and the pairing they were shown after it dropped.
+/// With `pick_id`, only if that's still their last pick.
+pub async fn undo_last_pick(
+    pool: &Pool<Any>,
+    user_id: i64,
+    pick_id: Option<i64>,
+) -> Result<Undo, sqlx::Error> {
+    let mut transaction = pool.begin().await?;
+
+    let Some(pick) =
+        db::matches::last_pick(&mut *transaction, user_id, UNDO_WINDOW_SECONDS).await?
+    else {
+        return Ok(Undo::NothingToUndo);
+    };
+    if pick_id.is_some_and(|pick_id| pick_id != pick.id) {
+        return Ok(Undo::NothingToUndo);
+    }
+    if db::matches::decided_since(&mut *transaction, &pick).await? {
+        return Ok(Undo::TooLate);
+    }
+
+    // claimed before anything else moves, so two undos racing in only take it back once
+    db::matches::withdraw_current(&mut transaction, user_id).await?;
+    if !db::matches::reopen(&mut *transaction, pick.id).await? {
+        return Ok(Undo::NothingToUndo);
+    }
+
+    for (dog_id, rating_type, change) in [
+        (
+            pick.dog_a_id,
+            RatingType::Overall,
+            pick.elo_change_overall_a,
+        ),
+        (
+            pick.dog_b_id,
+            RatingType::Overall,
+            pick.elo_change_overall_b,
+        ),
+        (
+            pick.dog_a_id,
+            RatingType::Personal,
+            pick.elo_change_personal_a,
+        ),
+        (
+            pick.dog_b_id,
+            RatingType::Personal,
+            pick.elo_change_personal_b,
+        ),
+    ] {
+        if let Some(change) = change {
+            db::ratings::shift(
+                &mut *transaction,
+                dog_id,
+                rating_type,
+                user_id,
+                -(change as i32),
+            )
+            .await?;
+        }
+    }
+    if let Some(xp) = pick.xp {
+        db::users::remove_xp(
+            &mut *transaction,
+            user_id,
+            xp.clamp(0, i64::from(u32::MAX)) as u32,
+        )
+        .await?;
+    }
+
+    transaction.commit().await?;
+    Ok(Undo::Undone)
+}
@@ -1,24 +1,35 @@
 use maud::{html, Markup, PreEscaped};
 use rand::Rng;
-use sqlx::{Pool, Sqlite};
 
-pub fn get_xp_threshold(level: u32) -> u32 {
-    1000 * (level) * (level + 1) / 2
+// level n costs n * 1000 xp to get through, so reaching level n takes 1000 * (1 + 2 + ... + n) xp total
+
+/// Total xp needed to reach `level`
+pub fn get_xp_threshold(level: u32) -> u64 {
+    1000 * (u64::from(level) * (u64::from(level) + 1) / 2)
 }
 
 pub fn get_level(xp: u32) -> u32 {
-    (f64::sqrt((1 + 8 * (xp / 1000)) as f64) as u32 - 1) / 2
+    // inverse of get_xp_threshold, nudged afterwards in case the float sqrt is off by one
+    let mut level = ((f64::sqrt(1.0 + 8.0 * f64::from(xp) / 1000.0) - 1.0) / 2.0) as u32;
+    while get_xp_threshold(level) > u64::from(xp) {
+        level -= 1;
+    }
+    while get_xp_threshold(level + 1) <= u64::from(xp) {
+        level += 1;
+    }
+    level
 }
 
+/// How far into the current level `xp` is
 pub fn get_xp_remainder(xp: u32) -> u32 {
     let level = get_level(xp);
-    let last_threshold = get_xp_threshold(level);
-    xp - last_threshold
+    (u64::from(xp) - get_xp_threshold(level)) as u32
 }
 
+/// How much xp the current level takes to get through in total
 pub fn get_next_xp_target(xp: u32) -> u32 {
     let level = get_level(xp);
-    (level + 1) * 1000
+    (get_xp_threshold(level + 1) - get_xp_threshold(level)) as u32
 }
 
 pub fn get_xp_increase_from_pick(seconds_deliberated: u32) -> u32 {
@@ -37,26 +48,121 @@ pub fn xp_section(xp: u32, xp_increase: Option<u32>, oob: bool) -> Markup {
                 h3 class="text-2xl text-center" {"Level "(get_level(xp))}
                 div class="w-full flex items-center justify-center gap-3" {
                     div class="w-1/6 text-right" {(get_xp_remainder(xp))(PreEscaped("&nbsp;"))"xp"}
-                    div id="xp-bar-wrapper" class="w-1/2 h-5 rounded-full bg-base-200 overflow-hidden" {
-                        div id="xp-bar" class="w-full h-full rounded-full bg-purple-400 transition-all duration-1000"
+                    div id="xp-bar-wrapper" class="w-1/2 h-5 rounded-full bg-base-200 overflow-hidden"
+                        role="progressbar" aria-label={"Progress to level "(get_level(xp) + 1)}
+                        aria-valuemin="0" aria-valuemax=(get_next_xp_target(xp)) aria-valuenow=(get_xp_remainder(xp)) {
+                        div id="xp-bar" class="w-full h-full rounded-full bg-purple-400 transition-all duration-1000 motion-reduce:transition-none"
                         style={"transform: translateX(-"((1.0 - (get_xp_remainder(xp) as f64 / get_next_xp_target(xp) as f64))*100.0)"%);"}
                         {}
                     }
                     div class="w-1/6" {(get_next_xp_target(xp))(PreEscaped("&nbsp;"))"xp"}
                 }
                 @if let Some(inc) = xp_increase {
-                    div class="absolute -bottom-6 -left-50 -right-50 mx-auto animate-scale-up-down" {"+"(inc)" xp"}
+                    // screen readers hear about it from xp_announcement instead
+                    div aria-hidden="true" class="absolute -bottom-6 -left-50 -right-50 mx-auto animate-scale-up-down motion-reduce:animate-none" {"+"(inc)" xp"}
                 }
             }
     }
 }
 
-pub async fn get_xp(pool: &Pool<Sqlite>, user_id: i64) -> u32 {
-    sqlx::query!("SELECT total_xp FROM user WHERE id = $1", user_id)
-        .fetch_one(pool)
-        .await
-        .unwrap()
-        .total_xp as u32
+/// What screen readers say about an xp gain, swapped into the layout's #announcer, which is a live
+/// region that stays put (one that's swapped in along with the news doesn't get read out)
+pub fn xp_announcement(xp: u32, xp_increase: u32) -> Markup {
+    html! {
+        div id="announcer" hx-swap-oob="innerHTML" {(announcement_text(xp, xp_increase))}
+    }
+}
+
+fn announcement_text(xp: u32, xp_increase: u32) -> String {
+    let level = get_level(xp);
+    if get_level(xp.saturating_sub(xp_increase)) < level {
+        format!("+{} xp. Level up! You're now level {}.", xp_increase, level)
+    } else {
+        format!(
+            "+{} xp. {} of {} xp to level {}.",
+            xp_increase,
+            get_xp_remainder(xp),
+            get_next_xp_target(xp),
+            level + 1
+        )
+    }
 }
 
 pub const XP_INCREASE_FOR_NAME_DOG: u32 = 200;
+pub const XP_INCREASE_FOR_UPLOAD: u32 = 1000;
+pub const XP_INCREASE_FOR_SIGN_UP: u32 = 2000;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    #[test]
+    fn announcements_mention_level_ups() {
+        assert_eq!(
+            announcement_text(1500, 40),
+            "+40 xp. 500 of 2000 xp to level 2."
+        );
+        assert_eq!(
+            announcement_text(1020, 40),
+            "+40 xp. Level up! You're now level 1."
+        );
+    }
+
+    #[test]
+    fn first_few_levels() {
+        assert_eq!(get_level(0), 0);
+        assert_eq!(get_level(999), 0);
+        assert_eq!(get_level(1000), 1);
+        assert_eq!(get_level(2999), 1);
+        assert_eq!(get_level(3000), 2);
+        assert_eq!(get_level(6000), 3);
+        assert_eq!(get_next_xp_target(0), 1000);
+        assert_eq!(get_next_xp_target(1500), 2000);
+        assert_eq!(get_xp_remainder(1500), 500);
+    }
+
+    proptest! {
+        #[test]
+        fn levels_never_go_down(xp in any::<u32>(), more in any::<u32>()) {
+            let more_xp = xp.saturating_add(more);
+            prop_assert!(get_level(more_xp) >= get_level(xp));
+        }
+
+        #[test]
+        fn xp_falls_between_level_thresholds(xp in any::<u32>()) {
+            let level = get_level(xp);
+            prop_assert!(get_xp_threshold(level) <= u64::from(xp));
+            prop_assert!(u64::from(xp) < get_xp_threshold(level + 1));
+        }
+
+        #[test]
+        fn remainder_and_target_agree_with_thresholds(xp in any::<u32>()) {
+            let level = get_level(xp);
+            let remainder = get_xp_remainder(xp);
+            let target = get_next_xp_target(xp);
+            prop_assert_eq!(get_xp_threshold(level) + u64::from(remainder), u64::from(xp));
+            prop_assert!(remainder < target);
+            prop_assert_eq!(get_xp_threshold(level) + u64::from(target), get_xp_threshold(level + 1));
+        }
+
+        #[test]
+        fn reaching_a_threshold_levels_you_up(level in 0..2900_u32) {
+            let threshold = get_xp_threshold(level) as u32;
+            prop_assert_eq!(get_level(threshold), level);
+            if threshold > 0 {
+                prop_assert_eq!(get_level(threshold - 1), level - 1);
+            }
+        }
+
+        #[test]
+        fn pick_xp_stays_in_range(seconds in 0..=5_u32) {
+            let xp = get_xp_increase_from_pick(seconds);
+            if seconds < 2 {
+                prop_assert_eq!(xp, 1);
+            } else {
+                prop_assert!((seconds * 6..=seconds * 14).contains(&xp));
+            }
+        }
+    }
+}
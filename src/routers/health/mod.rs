@@ -0,0 +1,71 @@
+use crate::{db::Backend, AppState};
+use axum::{extract::State, http::StatusCode, routing::get, Router};
+use std::{collections::HashSet, fs, path::Path};
+use uuid::Uuid;
+
+// like /metrics, these are merged in after the auth middleware so that probes don't create users
+pub fn health_router() -> Router<AppState> {
+    Router::<AppState>::new()
+        .route("/healthz", get(|| async move { (StatusCode::OK, "ok") }))
+        .route(
+            "/readyz",
+            get(|State(state): State<AppState>| async move {
+                let mut problems: Vec<String> = vec![];
+
+                if sqlx::query("SELECT 1 AS one")
+                    .fetch_one(&state.pool)
+                    .await
+                    .is_err()
+                {
+                    problems.push("database unreachable".to_string());
+                } else if let Some(problem) = check_migrations(&state).await {
+                    problems.push(problem);
+                }
+
+                // where uploads and approved images get written, so they'd better be writable
+                for directory in [&state.images_dir, &state.unapproved_dir] {
+                    if !is_writable(directory) {
+                        problems.push(format!("{} is not writable", directory.display()));
+                    }
+                }
+
+                if problems.is_empty() {
+                    (StatusCode::OK, "ready".to_string())
+                } else {
+                    eprintln!("Not ready: {:?}", problems);
+                    (StatusCode::SERVICE_UNAVAILABLE, problems.join("\n"))
+                }
+            }),
+        )
+}
+
+async fn check_migrations(state: &AppState) -> Option<String> {
+    // sqlx owns this table, on either database
+    let applied: Result<Vec<i64>, _> =
+        sqlx::query_scalar("SELECT version FROM _sqlx_migrations WHERE success = TRUE")
+            .fetch_all(&state.pool)
+            .await;
+    let Ok(applied) = applied else {
+        return Some("migrations table missing".to_string());
+    };
+    let applied: HashSet<i64> = applied.into_iter().collect();
+
+    let pending = Backend::of(&state.pool)
+        .migrator()
+        .iter()
+        .filter(|migration| !applied.contains(&migration.version))
+        .count();
+    if pending > 0 {
+        Some(format!("{} migrations not applied", pending))
+    } else {
+        None
+    }
+}
+
+fn is_writable(directory: &Path) -> bool {
+    let probe = directory.join(format!(".readyz-{}", Uuid::new_v4()));
+    if fs::write(&probe, b"").is_err() {
+        return false;
+    }
+    fs::remove_file(&probe).is_ok()
+}
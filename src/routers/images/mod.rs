@@ -0,0 +1,227 @@
+use crate::{db, error::AppError, AppState};
+use axum::{
+    extract::{Path, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Redirect, Response},
+    routing::get,
+    Router,
+};
+use image::{
+    codecs::{avif::AvifEncoder, jpeg::JpegEncoder},
+    imageops::FilterType,
+    ImageEncoder,
+};
+use maud::{html, Markup};
+use std::{
+    fs,
+    path::{Path as FilePath, PathBuf},
+};
+use uuid::Uuid;
+
+// dog photos are uploaded at whatever size the phone took them, so pages link smaller square
+// copies instead: /img/:id/:size, in the best format the browser says it takes. each copy is made
+// the first time it's asked for and kept in image_cache_dir, and made again if the photo changes.
+// like /healthz, this is merged in after the auth middleware, so photos don't look up sessions.
+
+/// The widths copies come in, anything else is a 404
+pub const SIZES: [u32; 4] = [160, 320, 640, 960];
+const CACHE_CONTROL: &str = "public, max-age=604800";
+
+pub fn images_router() -> Router<AppState> {
+    Router::<AppState>::new().route("/img/:dog_id/:size", get(resized_image))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Avif,
+    Webp,
+    Jpeg,
+}
+
+impl Format {
+    /// The smallest one the Accept header allows
+    fn negotiate(headers: &HeaderMap) -> Self {
+        let accept = headers
+            .get(header::ACCEPT)
+            .and_then(|accept| accept.to_str().ok())
+            .unwrap_or_default();
+        if accept.contains("image/avif") {
+            Self::Avif
+        } else if accept.contains("image/webp") {
+            Self::Webp
+        } else {
+            Self::Jpeg
+        }
+    }
+
+    fn extension(self) -> &'static str {
+        match self {
+            Self::Avif => "avif",
+            Self::Webp => "webp",
+            Self::Jpeg => "jpg",
+        }
+    }
+
+    fn content_type(self) -> &'static str {
+        match self {
+            Self::Avif => "image/avif",
+            Self::Webp => "image/webp",
+            Self::Jpeg => "image/jpeg",
+        }
+    }
+}
+
+async fn resized_image(
+    State(state): State<AppState>,
+    Path((dog_id, size)): Path<(i64, u32)>,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
+    if !SIZES.contains(&size) {
+        return Err(AppError::NotFound);
+    }
+    let image_url = db::dogs::approved_image_url(&state.pool, dog_id)
+        .await?
+        .ok_or(AppError::NotFound)?;
+    let original = FilePath::new(&image_url)
+        .file_name()
+        .map(|file_name| state.images_dir.join(file_name))
+        .ok_or(AppError::NotFound)?;
+
+    let format = Format::negotiate(&headers);
+    let cached = state
+        .image_cache_dir
+        .join(format!("{}-{}.{}", dog_id, size, format.extension()));
+
+    let bytes = match fresh_copy(&original, &cached) {
+        Some(bytes) => bytes,
+        None => {
+            let (original, cached) = (original.clone(), cached.clone());
+            let resized =
+                tokio::task::spawn_blocking(move || resize(&original, &cached, size, format))
+                    .await
+                    .map_err(anyhow::Error::from)?;
+            match resized {
+                Ok(bytes) => bytes,
+                Err(error) => {
+                    // the original still works, it's just bigger
+                    eprintln!("couldn't resize dog {}'s photo: {:#}", dog_id, error);
+                    return Ok(Redirect::temporary(&image_url).into_response());
+                }
+            }
+        }
+    };
+
+    Ok((
+        StatusCode::OK,
+        [
+            (header::CONTENT_TYPE, format.content_type()),
+            (header::CACHE_CONTROL, CACHE_CONTROL),
+            (header::VARY, "accept"),
+        ],
+        bytes,
+    )
+        .into_response())
+}
+
+/// The cached copy, unless it's missing or older than the original
+fn fresh_copy(original: &FilePath, cached: &FilePath) -> Option<Vec<u8>> {
+    let original_modified = fs::metadata(original).ok()?.modified().ok()?;
+    let cached_modified = fs::metadata(cached).ok()?.modified().ok()?;
+    if cached_modified < original_modified {
+        return None;
+    }
+    fs::read(cached).ok()
+}
+
+/// Crops the original to a square `size` wide (or as wide as it is, they're never blown up),
+/// encodes it and saves it to `cached`
+fn resize(
+    original: &FilePath,
+    cached: &FilePath,
+    size: u32,
+    format: Format,
+) -> anyhow::Result<Vec<u8>> {
+    let image = image::ImageReader::open(original)?
+        .with_guessed_format()?
+        .decode()?;
+    let side = size.min(image.width()).min(image.height());
+    let image = image
+        .resize_to_fill(side, side, FilterType::CatmullRom)
+        .into_rgb8();
+
+    let mut bytes = vec![];
+    match format {
+        Format::Avif => {
+            AvifEncoder::new_with_speed_quality(&mut bytes, 8, 60).write_image(
+                image.as_raw(),
+                side,
+                side,
+                image::ExtendedColorType::Rgb8,
+            )?;
+        }
+        Format::Webp => {
+            bytes = webp::Encoder::from_rgb(image.as_raw(), side, side)
+                .encode(75.0)
+                .to_vec();
+        }
+        Format::Jpeg => {
+            JpegEncoder::new_with_quality(&mut bytes, 80).encode_image(&image)?;
+        }
+    }
+
+    // written next to it first, so nobody gets half a file (even if two requests make it at once)
+    if let Some(directory) = cached.parent() {
+        fs::create_dir_all(directory)?;
+    }
+    let partial = PathBuf::from(format!(
+        "{}.{}.partial",
+        cached.display(),
+        Uuid::new_v4().simple()
+    ));
+    fs::write(&partial, &bytes)?;
+    fs::rename(&partial, cached)?;
+    Ok(bytes)
+}
+
+/// A dog's photo, with `sizes` saying how wide it's shown so the browser can pick a copy.
+/// Photos further down the page wait until they're scrolled to when `lazy`.
+pub fn dog_image(dog_id: i64, alt: &str, class: &str, sizes: &str, lazy: bool) -> Markup {
+    let srcset = SIZES
+        .iter()
+        .map(|size| format!("/img/{}/{} {}w", dog_id, size, size))
+        .collect::<Vec<_>>()
+        .join(", ");
+    html! {
+        img class=(class)
+            src={"/img/"(dog_id)"/640"}
+            srcset=(srcset)
+            sizes=(sizes)
+            width="640"
+            height="640"
+            alt=(alt)
+            loading=(if lazy {"lazy"} else {"eager"})
+            decoding="async" ;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    #[test]
+    fn the_smallest_format_the_browser_takes() {
+        let accepting = |accept: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(header::ACCEPT, HeaderValue::from_str(accept).unwrap());
+            Format::negotiate(&headers)
+        };
+        assert_eq!(
+            accepting("image/avif,image/webp,image/apng,image/*,*/*;q=0.8"),
+            Format::Avif
+        );
+        assert_eq!(accepting("image/webp,*/*"), Format::Webp);
+        assert_eq!(accepting("*/*"), Format::Jpeg);
+        assert_eq!(Format::negotiate(&HeaderMap::new()), Format::Jpeg);
+    }
+}
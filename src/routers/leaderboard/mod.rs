@@ -1,6 +1,8 @@
 use crate::{
+    db,
+    error::AppError,
     layout::{base, NavLink},
-    routers::doggo::RatingType,
+    routers::{doggo::RatingType, images::dog_image, tournaments::champion_badge},
     AppContext, AppState,
 };
 use axum::{
@@ -39,30 +41,17 @@ pub fn leaderboard_router() -> Router<AppState> {
                 |State(state): State<AppState>,
                 Extension(context): Extension<AppContext>,
                 Path(rating_type): Path<RatingType>| async move {
-                    struct LeaderboardRow {
-                        value: i64,
-                        name: Option<String>,
-                        image_url: String
-                    }
-                    let ratings = match rating_type {
-                        RatingType::Overall => sqlx::query_as!(
-                            LeaderboardRow,
-                            "select value, name, image_url from rating join dog on rating.dog_id = dog.id where type='overall' order by value desc;")
-                            .fetch_all(&state.pool).await.unwrap(),
-                        RatingType::Personal => sqlx::query_as!(
-                            LeaderboardRow,
-                            "select value, name, image_url from rating join dog on rating.dog_id = dog.id where type='personal' and user_id = $1 order by value desc;",
-                            context.user_id)
-                            .fetch_all(&state.pool).await.unwrap(),
-                            
-                    };
+                    let ratings = db::ratings::leaderboard(&state.pool, rating_type, context.user_id).await?;
 
-                    base(
+                    Ok::<_, AppError>(base(
                         html! {
                             div class="flex justify-center gap-4 md:gap-16 mt-4" {
                                 (tab(RatingType::Overall, rating_type == RatingType::Overall))
                                 (tab(RatingType::Personal, rating_type == RatingType::Personal))
                             }
+                            div class="flex justify-center mt-2" {
+                                a class="text-xl underline text-primary" href="/tournaments" {"🏆 Tournaments"}
+                            }
                             div class="overflow-x-auto" {
                                 table class="table table-sm table-zebra [&_*]:text-2xl overflow-x-auto" {
                                     thead {
@@ -81,8 +70,8 @@ pub fn leaderboard_router() -> Router<AppState> {
                                             @let name_display = rating.name.clone().unwrap_or("A dog with no name".to_string());
                                             tr {
                                                 th {(i+1)}
-                                                td class="min-w-32" {img class="object-center object-cover aspect-square w-32" src=(rating.image_url) ;}
-                                                td class="break-words max-w-36" {(name_display)}
+                                                td class="min-w-32" {(dog_image(rating.dog_id, &name_display, "object-center object-cover aspect-square w-32", "8rem", true))}
+                                                td class="break-words max-w-36" {(name_display)" "(champion_badge(rating.titles))}
                                                 td {(rating.value)}
                                             }
                                         }
@@ -92,7 +81,7 @@ pub fn leaderboard_router() -> Router<AppState> {
                         },
                         Some("Leaderboard".to_string()),
                         Some(NavLink::Leaderboard)
-                    )
+                    ))
                 },
             ),
         )
@@ -0,0 +1,329 @@
+use crate::{
+    admin::{
+        self,
+        export::{
+            BracketVoteRow, LogRow, MatchRow, RankingPlaceRow, RankingRow, RatingRow,
+            UserFinishedWithDogRow, UserRow, BRACKET_VOTE_COLUMNS, LOG_COLUMNS, MATCH_COLUMNS,
+            RANKING_COLUMNS, RANKING_PLACE_COLUMNS, RATING_COLUMNS, USER_COLUMNS,
+            USER_FINISHED_WITH_DOG_COLUMNS,
+        },
+    },
+    auth::clear_auth_cookie,
+    db::{self, log::Event, tokens::Purpose},
+    error::AppError,
+    layout::{base, NavLink},
+    AppContext, AppState,
+};
+use axum::{
+    extract::{Query, State},
+    http::{header, HeaderMap},
+    response::{IntoResponse, Redirect, Response},
+    Extension, Form,
+};
+use lettre::message::Mailbox;
+use maud::{html, Markup};
+use serde::{Deserialize, Serialize};
+use sqlx::{Any, Pool};
+use std::env;
+
+// everything we store about one person, for "download my data"
+#[derive(Serialize)]
+pub struct PersonalData {
+    exported_at: String,
+    user: UserRow,
+    sessions: Vec<SessionRow>,
+    matches: Vec<MatchRow>,
+    rankings: Vec<RankingRow>,
+    ranking_places: Vec<RankingPlaceRow>,
+    tournament_votes: Vec<BracketVoteRow>,
+    personal_ratings: Vec<RatingRow>,
+    finished_with_dogs: Vec<UserFinishedWithDogRow>,
+    named_dogs: Vec<NamedDogRow>,
+    magic_links: Vec<MagicLinkRow>,
+    log: Vec<LogRow>,
+}
+
+// the tokens themselves are left out, they're as good as a password
+#[derive(Serialize, sqlx::FromRow)]
+struct SessionRow {
+    created_at: Option<String>,
+    updated_at: Option<String>,
+}
+
+#[derive(Serialize, sqlx::FromRow)]
+struct MagicLinkRow {
+    email: String,
+    purpose: String,
+    #[sqlx(try_from = "db::Flag")]
+    used: bool,
+    created_at: Option<String>,
+}
+
+#[derive(Serialize, sqlx::FromRow)]
+struct NamedDogRow {
+    id: i64,
+    name: Option<String>,
+}
+
+pub async fn get_personal_data(
+    pool: &Pool<Any>,
+    user_id: i64,
+) -> Result<PersonalData, sqlx::Error> {
+    let user = sqlx::query_as::<_, UserRow>(&format!(
+        r#"SELECT {} FROM "user" WHERE id = $1"#,
+        USER_COLUMNS
+    ))
+    .bind(user_id)
+    .fetch_one(pool)
+    .await?;
+    let sessions = sqlx::query_as::<_, SessionRow>(
+        "SELECT CAST(created_at AS TEXT) AS created_at, CAST(updated_at AS TEXT) AS updated_at FROM session WHERE user_id = $1 ORDER BY created_at",
+    )
+    .bind(user_id)
+    .fetch_all(pool)
+    .await?;
+    let matches = sqlx::query_as::<_, MatchRow>(&format!(
+        "SELECT {} FROM match WHERE user_id = $1 ORDER BY id",
+        MATCH_COLUMNS
+    ))
+    .bind(user_id)
+    .fetch_all(pool)
+    .await?;
+    let rankings = sqlx::query_as::<_, RankingRow>(&format!(
+        "SELECT {} FROM ranking WHERE user_id = $1 ORDER BY id",
+        RANKING_COLUMNS
+    ))
+    .bind(user_id)
+    .fetch_all(pool)
+    .await?;
+    let ranking_places = sqlx::query_as::<_, RankingPlaceRow>(&format!(
+        "SELECT {} FROM ranking_place WHERE ranking_id IN (SELECT id FROM ranking WHERE user_id = $1) ORDER BY ranking_id, dog_id",
+        RANKING_PLACE_COLUMNS
+    ))
+    .bind(user_id)
+    .fetch_all(pool)
+    .await?;
+    let tournament_votes = sqlx::query_as::<_, BracketVoteRow>(&format!(
+        "SELECT {} FROM bracket_vote WHERE user_id = $1 ORDER BY bracket_match_id",
+        BRACKET_VOTE_COLUMNS
+    ))
+    .bind(user_id)
+    .fetch_all(pool)
+    .await?;
+    let personal_ratings = sqlx::query_as::<_, RatingRow>(&format!(
+        "SELECT {} FROM rating WHERE type = 'personal' AND user_id = $1 ORDER BY dog_id",
+        RATING_COLUMNS
+    ))
+    .bind(user_id)
+    .fetch_all(pool)
+    .await?;
+    let finished_with_dogs = sqlx::query_as::<_, UserFinishedWithDogRow>(&format!(
+        "SELECT {} FROM user_finished_with_dog WHERE user_id = $1 ORDER BY dog_id",
+        USER_FINISHED_WITH_DOG_COLUMNS
+    ))
+    .bind(user_id)
+    .fetch_all(pool)
+    .await?;
+    let named_dogs = sqlx::query_as::<_, NamedDogRow>(
+        "SELECT id, name FROM dog WHERE namer_id = $1 ORDER BY id",
+    )
+    .bind(user_id)
+    .fetch_all(pool)
+    .await?;
+    let magic_links = sqlx::query_as::<_, MagicLinkRow>(
+        "SELECT email, purpose, CASE WHEN used THEN 1 ELSE 0 END AS used, CAST(created_at AS TEXT) AS created_at FROM email_token WHERE sender_id = $1 OR email = CAST($2 AS TEXT) ORDER BY created_at",
+    )
+    .bind(user_id)
+    .bind(&user.email)
+    .fetch_all(pool)
+    .await?;
+    // sign-ups and log-ins from another device are logged under that device's user
+    let log = sqlx::query_as::<_, LogRow>(&format!(
+        r"SELECT {} FROM log WHERE user_id = $1 OR details LIKE CAST($2 AS TEXT) ESCAPE '\' ORDER BY id",
+        LOG_COLUMNS
+    ))
+    .bind(user_id)
+    .bind(user.email.as_deref().map(db::containing))
+    .fetch_all(pool)
+    .await?;
+
+    Ok(PersonalData {
+        exported_at: chrono::Utc::now().to_rfc3339(),
+        user,
+        sessions,
+        matches,
+        rankings,
+        ranking_places,
+        tournament_votes,
+        personal_ratings,
+        finished_with_dogs,
+        named_dogs,
+        magic_links,
+        log,
+    })
+}
+
+pub async fn download_my_data(
+    State(state): State<AppState>,
+    Extension(context): Extension<AppContext>,
+) -> Result<Response, AppError> {
+    let data = get_personal_data(&state.pool, context.user_id).await?;
+
+    let mut headers = HeaderMap::new();
+    headers.insert(header::CONTENT_TYPE, "application/json".parse().unwrap());
+    headers.insert(
+        header::CONTENT_DISPOSITION,
+        "attachment; filename=\"top-doggo-my-data.json\""
+            .parse()
+            .unwrap(),
+    );
+    let json = serde_json::to_string_pretty(&data).map_err(anyhow::Error::from)?;
+    Ok((headers, json).into_response())
+}
+
+// users with an email confirm through a magic link, anonymous users just confirm on the page
+pub async fn request_account_deletion(
+    State(state): State<AppState>,
+    Extension(context): Extension<AppContext>,
+) -> Response {
+    let Some(email) = context.user_email else {
+        return base(
+            delete_account_form(None),
+            Some("Delete Account".to_string()),
+            Some(NavLink::Me),
+        )
+        .into_response();
+    };
+
+    let email_sent = send_delete_account_email(&state, context.user_id, &email).await;
+
+    let _ = db::log::record(
+        &state.pool,
+        &Event::RequestAccountDeletion,
+        Some(context.user_id),
+        context.client_ip,
+    )
+    .await;
+
+    base(
+        html! {
+            div class="flex-1 flex flex-col gap-4 items-center justify-center text-center" {
+                @if email_sent.is_ok() {
+                    h1 class="text-4xl" {"Email sent to "(email)"."}
+                    h2 class="text-2xl" {"Follow the link in it to finish deleting your account."}
+                } @else {
+                    h1 class="text-4xl" {"Couldn't send an email to "(email)" :("}
+                    a class="text-2xl underline text-primary" href="/me" {"Back to your page"}
+                }
+            }
+        },
+        Some("Delete Account".to_string()),
+        Some(NavLink::Me),
+    )
+    .into_response()
+}
+
+async fn send_delete_account_email(state: &AppState, user_id: i64, email: &str) -> Result<(), ()> {
+    let to_mailbox: Mailbox = format!("Top Doggo Judge <{}>", email)
+        .parse()
+        .map_err(|_| ())?;
+
+    let token = db::tokens::create(&state.pool, email, user_id, Purpose::DeleteAccount)
+        .await
+        .map_err(|_| ())?;
+
+    state.mailer.send(to_mailbox, "Top Doggo - Delete your account",
+        html!{
+            h1 {"Deleting your Top Doggo account"}
+            h3 {"Follow this link to permanently delete your account, votes and xp. If you didn't ask for this, you can ignore this email."}
+            a target="_blank" href={ (env::var("BASE_URL").unwrap()) "/me/delete?token=" (token)} style="font-size: 1.5rem;" {"Delete my account"}
+        }
+    ).await
+}
+
+#[derive(Deserialize)]
+pub struct DeleteAccountParams {
+    token: Option<String>,
+}
+
+// the link only shows a button, so link previews and mail scanners can't delete anybody
+pub async fn delete_account_page(Query(params): Query<DeleteAccountParams>) -> Response {
+    base(
+        delete_account_form(params.token),
+        Some("Delete Account".to_string()),
+        Some(NavLink::Me),
+    )
+    .into_response()
+}
+
+pub async fn confirm_account_deletion(
+    State(state): State<AppState>,
+    Extension(context): Extension<AppContext>,
+    Form(params): Form<DeleteAccountParams>,
+) -> Result<Response, AppError> {
+    let user_id = match params.token.filter(|token| !token.is_empty()) {
+        Some(token) => {
+            let sender_id = db::tokens::account_to_delete(&state.pool, &token).await?;
+            let Some(sender_id) = sender_id else {
+                return Ok(Redirect::to("/sorry?reason=expired_or_does_not_exist").into_response());
+            };
+            sender_id
+        }
+        // an account with an email has to prove it owns the inbox
+        None if context.user_email.is_none() => context.user_id,
+        None => return Ok(Redirect::to("/me").into_response()),
+    };
+
+    admin::delete_account(&state.pool, user_id).await?;
+
+    let _ = db::log::record(&state.pool, &Event::DeleteAccount, None, None).await;
+
+    let mut headers = HeaderMap::new();
+    if user_id == context.user_id {
+        headers.insert(header::SET_COOKIE, clear_auth_cookie().parse().unwrap());
+    }
+    Ok((
+        headers,
+        base(
+            html! {
+                div class="flex-1 flex flex-col gap-4 items-center justify-center text-center" {
+                    h1 class="text-4xl" {"Your account has been deleted."}
+                    h2 class="text-2xl" {"Thanks for judging!"}
+                    a class="text-2xl underline text-primary" href="/" {"Back to the dog show"}
+                }
+            },
+            Some("Delete Account".to_string()),
+            None,
+        ),
+    )
+        .into_response())
+}
+
+fn delete_account_form(token: Option<String>) -> Markup {
+    html! {
+        form
+            method="post"
+            action="/me/delete/confirm"
+            class="flex-1 flex flex-col gap-4 items-center justify-center text-center"
+        {
+            h1 class="text-4xl" {"Delete your account?"}
+            h2 class="text-2xl max-w-screen-sm" {"Your votes, personal leaderboard and xp will be gone for good. Dogs you named keep their names."}
+            @if let Some(token) = token {
+                input type="hidden" name="token" value=(token) ;
+            }
+            button type="submit" class="btn btn-error text-xl" {"Delete my account"}
+            a class="text-xl underline text-primary" href="/me" {"Never mind"}
+        }
+    }
+}
+
+pub fn account_section() -> Markup {
+    html! {
+        div class="flex gap-4 flex-wrap justify-center" {
+            a href="/me/data" download hx-boost="false" class="btn btn-outline" {"Download my data"}
+            form method="post" action="/me/delete" {
+                button type="submit" class="btn btn-outline btn-error" {"Delete my account"}
+            }
+        }
+    }
+}
@@ -1,36 +1,42 @@
-use super::doggo::xp::xp_section;
+use super::doggo::xp::{xp_section, XP_INCREASE_FOR_SIGN_UP};
 use crate::{
-    auth::{create_new_auth_cookie, create_new_auth_token}, layout::{base, layout, NavLink}, routers::doggo::xp::get_xp, AppContext, AppState, FormField
+    auth::create_new_auth_cookie,
+    db::{self, log::Event, tokens::Purpose},
+    error::AppError,
+    layout::{base, layout, NavLink},
+    metrics, AppContext, AppState, FormField,
 };
 use axum::{
-    extract::{Query, State}, http::{header, HeaderMap, StatusCode}, response::Html, routing::{get, post}, Extension, Form, Router
-};
-use lettre::{
-    address::AddressError,
-    message::{header::ContentType, Mailbox},
-    transport::smtp::{authentication::Credentials, client::TlsParameters},
-    Message, SmtpTransport, Transport,
+    extract::{Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::Html,
+    routing::{get, post},
+    Extension, Form, Router,
 };
+use lettre::{address::AddressError, message::Mailbox};
 use maud::{html, Markup, PreEscaped};
 use serde::Deserialize;
-use sqlx::{Pool, Sqlite};
 use std::env;
-use uuid::Uuid;
+
+pub mod account;
 
 pub fn me_router() -> Router<AppState> {
     Router::<AppState>::new()
         .route(
             "/me",
             get(|State(state): State<AppState>, Extension(context): Extension<AppContext>, Query(params): Query<MeParams>| async move {
-                base(
-                    me_page_content(state, context, params).await,
+                Ok::<_, AppError>(base(
+                    me_page_content(state, context, params).await?,
                     Some("Me".to_string()),
                     Some(NavLink::Me),
-                )
+                ))
             }),
         )
+        .route("/me/data", get(account::download_my_data))
+        .route("/me/delete", get(account::delete_account_page).post(account::request_account_deletion))
+        .route("/me/delete/confirm", post(account::confirm_account_deletion))
         .route("/me-refresh", get(|State(state): State<AppState>, Extension(context): Extension<AppContext>, Query(params): Query<MeParams>| async move {
-            Html(me_page_content(state, context, params).await.into_string())
+            Ok::<_, AppError>(Html(me_page_content(state, context, params).await?.into_string()))
         }))
         .route(
             "/send-magic-link",
@@ -49,18 +55,17 @@ pub fn me_router() -> Router<AppState> {
                     )
                 };
 
-                // TODO: rate limit the email
-
-                let email_sent = send_magic_link_email(&state.pool, context.user_id, &form.email_address).await;
+                let email_sent = send_magic_link_email(&state, context.user_id, &form.email_address).await;
                 if email_sent.is_err() {
+                    metrics::MAGIC_LINKS_TOTAL.with_label_values(&["failed"]).inc();
                     return err("Invalid Email");
                 }
+                metrics::MAGIC_LINKS_TOTAL.with_label_values(&["sent"]).inc();
 
-                let client_ip: Option<String> = context.client_ip.map(|ip| ip.to_string());
-                let _ = sqlx::query!("INSERT INTO log (action, user_id, client_ip, notes) VALUES ('send-magic-link', $1, $2, $3)", context.user_id, client_ip, form.email_address)
-                    .fetch_one(&state.pool).await;
+                let event = Event::SendMagicLink { email: form.email_address.clone() };
+                let _ = db::log::record(&state.pool, &event, Some(context.user_id), context.client_ip).await;
 
-                return Html(email_sent_message(form.email_address.to_string()).into_string())
+                Html(email_sent_message(form.email_address.to_string()).into_string())
             })
         )
         .route("/login", get(|
@@ -68,14 +73,9 @@ pub fn me_router() -> Router<AppState> {
             Extension(context): Extension<AppContext>,
             Query(params): Query<LoginParams>
         | async move {
-            println!("/login");
-            println!("using email_token: {}", params.token);
-
-            let token_record = sqlx::query!("SELECT email, sender_id FROM email_token WHERE token=$1 AND created_at > datetime('now', '-30 minutes')", params.token)
-                .fetch_optional(&state.pool)
-                .await.unwrap();
-            if token_record.is_none() {
-                return (
+            let token_record = db::tokens::find(&state.pool, &params.token, Purpose::LogIn).await?;
+            let Some(db::tokens::EmailToken { email: token_email, sender_id }) = token_record else {
+                return Ok((
                     StatusCode::TEMPORARY_REDIRECT,
                     {
                         let mut headers = HeaderMap::new();
@@ -83,14 +83,15 @@ pub fn me_router() -> Router<AppState> {
                         headers
                     },
                     Html("".to_string())
-                )
-            }
-            let token_email = (token_record.as_ref().unwrap().email).clone();
-            let sender_id = (token_record.as_ref().unwrap().sender_id.expect("All email_token records should have a sender_id")).clone();
+                ))
+            };
+            let Some(sender_id) = sender_id else {
+                return Err(AppError::Internal(anyhow::anyhow!("email_token {} has no sender_id", params.token)));
+            };
 
             if let Some(current_email) = context.user_email {
                 if current_email != token_email {
-                    return (
+                    return Ok((
                         StatusCode::TEMPORARY_REDIRECT,
                         {
                             let mut headers = HeaderMap::new();
@@ -98,10 +99,9 @@ pub fn me_router() -> Router<AppState> {
                             headers
                         },
                         Html("".to_string())
-                    )
+                    ))
                 } else {
-                    println!("Already logged in as {}, redirecting to /me", token_email);
-                    return (
+                    return Ok((
                         StatusCode::TEMPORARY_REDIRECT,
                         {
                             let mut headers = HeaderMap::new();
@@ -109,54 +109,46 @@ pub fn me_router() -> Router<AppState> {
                             headers
                         },
                         Html("".to_string())
-                    )
+                    ))
                 }
             }
 
-            let _ = sqlx::query!("UPDATE email_token SET used = true WHERE token = $1", params.token).fetch_one(&state.pool).await;
-            
-            let existing_user = sqlx::query!("SELECT id FROM user WHERE email = $1", token_email)
-                .fetch_optional(&state.pool).await.unwrap();
+            db::tokens::mark_used(&state.pool, &params.token).await?;
 
-            if let Some(existing_user) = existing_user {
-                // log in
-                println!("logging in receiver user {} as existing user {}, who has email {}", context.user_id, existing_user.id, token_email);
+            let existing_user_id = db::users::find_by_email(&state.pool, &token_email).await?;
 
-                let client_ip: Option<String> = context.client_ip.map(|ip| ip.to_string());
-                let notes = format!("{} {}", token_email, context.user_id);
-                let _ = sqlx::query!("INSERT INTO log (action, user_id, client_ip, notes) VALUES ('log-in', $1, $2, $3)", existing_user.id, client_ip, notes)
-                    .fetch_one(&state.pool).await;
+            if let Some(existing_user_id) = existing_user_id {
+                // log in
+                let event = Event::LogIn { email: token_email.clone(), device_user_id: context.user_id };
+                let _ = db::log::record(&state.pool, &event, Some(existing_user_id), context.client_ip).await;
 
-                return (
+                let token = db::sessions::create(&state.pool, existing_user_id).await?;
+                Ok((
                     StatusCode::OK,
                     {
                         let mut headers = HeaderMap::new();
-                        headers.insert(header::SET_COOKIE, create_new_auth_cookie(create_new_auth_token(&state.pool, existing_user.id).await).parse().unwrap());
+                        headers.insert(header::SET_COOKIE, create_new_auth_cookie(&state.session_keys, &token).parse().unwrap());
                         headers
                     },
                     Html(logged_in_page().into_string())
-                )
+                ))
             } else {
                // sign up (tie email to sender)
-                println!("receiver user {} signing up sender user {} with email {}", context.user_id, sender_id, token_email);
-
-                let _ = sqlx::query!("UPDATE user SET email = $1, total_xp = total_xp + 2000 WHERE id = $2", token_email, sender_id).fetch_one(&state.pool).await;
+                db::users::sign_up(&state.pool, sender_id, &token_email, XP_INCREASE_FOR_SIGN_UP).await?;
 
-                let client_ip: Option<String> = context.client_ip.map(|ip| ip.to_string());
-                let _ = sqlx::query!("INSERT INTO log (action, user_id, client_ip, notes) VALUES ('sign-up', $1, $2, $3)", context.user_id, client_ip, token_email)
-                    .fetch_one(&state.pool).await;
+                let event = Event::SignUp { email: token_email.clone() };
+                let _ = db::log::record(&state.pool, &event, Some(context.user_id), context.client_ip).await;
 
-                return (
+                let mut headers = HeaderMap::new();
+                if context.user_id != sender_id {
+                    let token = db::sessions::create(&state.pool, sender_id).await?;
+                    headers.insert(header::SET_COOKIE, create_new_auth_cookie(&state.session_keys, &token).parse().unwrap());
+                }
+                Ok((
                     StatusCode::OK,
-                    {
-                        let mut headers = HeaderMap::new();
-                        if context.user_id != sender_id {
-                            headers.insert(header::SET_COOKIE, create_new_auth_cookie(create_new_auth_token(&state.pool, sender_id).await).parse().unwrap());
-                        }
-                        headers
-                    },
+                    headers,
                     Html(logged_in_page().into_string())
-                )
+                ))
             }
         }))
         .route("/sorry", get(|Extension(context): Extension<AppContext>, Query(params): Query<SorryParams>| async move {
@@ -179,51 +171,11 @@ pub fn me_router() -> Router<AppState> {
         }))
 }
 
-pub async fn send_email(to_mailbox: Mailbox, subject: &str, content: Markup) -> Result<(), ()> {
-    let mode = env::var("MODE").unwrap();
-    if mode == "development" {
-        println!("Email that would be sent: {:?}{:?}", to_mailbox, content);
-        return Ok(())
-    }
-
-    let email = Message::builder()
-        .from(
-            "Top Doggo <parkerbedlan@gmail.com>"
-                .to_string()
-                .parse()
-                .unwrap(),
-        )
-        .to(to_mailbox)
-        .subject(subject)
-        .header(ContentType::TEXT_HTML)
-        .body(content.into_string())
-        .unwrap();
-
-    let creds = Credentials::new(
-        env::var("SMTP_USERNAME").expect("SMTP Username not specified "),
-        env::var("SMTP_PASSWORD").expect("SMTP Password not specified"),
-    );
-
-    // necessary to set to port 587 due to https://docs.hetzner.com/cloud/servers/faq/#why-can-i-not-send-any-mails-from-my-server
-    let tls_parameters = TlsParameters::new(env::var("SMTP_HOST").expect("SMTP Host not specified")).unwrap();
-    let mailer = SmtpTransport::relay(&env::var("SMTP_HOST").expect("SMTP Host not specified"))
-        .unwrap()
-        .port(587)
-        .credentials(creds)
-        .tls(lettre::transport::smtp::client::Tls::Required(tls_parameters))
-        .build();
-
-    match mailer.send(&email) {
-        Ok(_) => println!("Email sent successfully!"),
-        Err(e) => {
-            println!("Email failed to send: {}", e);
-            return Err(());
-        }
-    }
-
-    Ok(())
-}
-async fn send_magic_link_email(pool: &Pool<Sqlite>, sender_id: i64, to_email_address: &str) -> Result<(), ()> {
+async fn send_magic_link_email(
+    state: &AppState,
+    sender_id: i64,
+    to_email_address: &str,
+) -> Result<(), ()> {
     let to_mailbox: Result<Mailbox, AddressError> =
         format!("Top Doggo Judge <{}>", to_email_address).parse();
     if to_mailbox.is_err() {
@@ -231,17 +183,11 @@ async fn send_magic_link_email(pool: &Pool<Sqlite>, sender_id: i64, to_email_add
     }
     let to_mailbox = to_mailbox.unwrap();
 
-    let magic_token = Uuid::new_v4().to_string();
-    let _ = sqlx::query!(
-        "INSERT INTO email_token (token, email, sender_id) VALUES ($1, $2, $3)",
-        magic_token,
-        to_email_address,
-        sender_id
-    )
-    .fetch_one(pool)
-    .await;
+    let magic_token = db::tokens::create(&state.pool, to_email_address, sender_id, Purpose::LogIn)
+        .await
+        .map_err(|_| ())?;
 
-    send_email(to_mailbox, "Top Doggo - Your Magic Link",
+    state.mailer.send(to_mailbox, "Top Doggo - Your Magic Link",
         html!{
             h1 {"Magic Link for Top Doggo"}
             h3 {"Follow this link to log in to the platform:"}
@@ -290,48 +236,55 @@ struct LoginParams {
 #[serde(rename_all = "snake_case")]
 enum SorryReason {
     ExpiredOrDoesNotExist,
-    AlreadyLoggedIn
+    AlreadyLoggedIn,
 }
 
 #[derive(Deserialize)]
 struct SorryParams {
-    reason: SorryReason
+    reason: SorryReason,
 }
 
-
 #[derive(Deserialize)]
 struct MeParams {
-    new_user: Option<bool>
+    new_user: Option<bool>,
 }
-async fn me_page_content(state: AppState, context: AppContext, params: MeParams) -> Markup {
-    let recently_sent_magic_link = sqlx::query!("SELECT email FROM email_token WHERE sender_id=$1 AND created_at > datetime('now', '-1 minutes')", context.user_id)
-        .fetch_one(&state.pool).await;
-
-    html! {
+async fn me_page_content(
+    state: AppState,
+    context: AppContext,
+    params: MeParams,
+) -> Result<Markup, AppError> {
+    let recently_sent_magic_link = db::tokens::recently_sent(&state.pool, context.user_id)
+        .await
+        .unwrap_or_default();
+
+    let total_xp = db::users::total_xp(&state.pool, context.user_id).await?;
+
+    Ok(html! {
         div
             hx-get="/me-refresh"
             hx-target="this"
             hx-swap="outerHTML"
             hx-trigger="me-refresh"
-            _="on visibilitychange from document if document.visibilityState is 'visible' send 'me-refresh' end"
-            class="flex-1 flex flex-col items-center justify-center gap-20 text-center" 
+            data-on-visible="me-refresh"
+            class="flex-1 flex flex-col items-center justify-center gap-20 text-center"
         {
             @if let Some(email) = context.user_email {
                 h1 class="text-2xl"
                 {"You're currently logged in with the email "(email)" :)"}
-            } @else if recently_sent_magic_link.is_ok() {
-                (email_sent_message(recently_sent_magic_link.unwrap().email))
+            } @else if let Some(recently_sent_magic_link) = recently_sent_magic_link {
+                (email_sent_message(recently_sent_magic_link))
             } @else {
                 (send_magic_link_form(FormField::empty()))
             }
             (xp_section(
-                get_xp(&state.pool, context.user_id).await, 
-                if params.new_user.unwrap_or(false) {Some(2000)} else {None},
+                total_xp,
+                if params.new_user.unwrap_or(false) {Some(XP_INCREASE_FOR_SIGN_UP)} else {None},
                 false)
             )
             a href="/leaderboard/top/personal" class="underline text-primary text-lg" {"Your personal leaderboard"}
+            (account::account_section())
         }
-    }
+    })
 }
 
 fn email_sent_message(email: String) -> Markup {
@@ -344,10 +297,15 @@ fn email_sent_message(email: String) -> Markup {
 }
 
 fn logged_in_page() -> Markup {
-    layout(html!{
-        div class="flex-1 flex flex-col gap-4 items-center justify-center text-center" {
-            h1 class="text-4xl" {"Successfully verified your email!"}
-            h2 class="text-2xl" {"(You can close this tab)"}
-        }
-    }, None, None, true)
+    layout(
+        html! {
+            div class="flex-1 flex flex-col gap-4 items-center justify-center text-center" {
+                h1 class="text-4xl" {"Successfully verified your email!"}
+                h2 class="text-2xl" {"(You can close this tab)"}
+            }
+        },
+        None,
+        None,
+        true,
+    )
 }
@@ -0,0 +1,53 @@
+use crate::{db, metrics, AppState};
+use axum::{
+    extract::State,
+    http::{header, HeaderMap, StatusCode},
+    response::IntoResponse,
+    routing::get,
+    Router,
+};
+use prometheus::{Encoder, TextEncoder};
+use std::env;
+
+// not behind the auth middleware (scrapers shouldn't be minting users), so instead it's protected
+// with a bearer token, and turned off entirely if METRICS_TOKEN isn't set
+pub fn metrics_router() -> Router<AppState> {
+    Router::<AppState>::new().route(
+        "/metrics",
+        get(
+            |State(state): State<AppState>, headers: HeaderMap| async move {
+                let Ok(metrics_token) = env::var("METRICS_TOKEN") else {
+                    return StatusCode::NOT_FOUND.into_response();
+                };
+                let authorized = headers
+                    .get(header::AUTHORIZATION)
+                    .and_then(|value| value.to_str().ok())
+                    .map(|value| value == format!("Bearer {}", metrics_token))
+                    .unwrap_or(false);
+                if !authorized {
+                    return StatusCode::UNAUTHORIZED.into_response();
+                }
+
+                let active_sessions = db::matches::count_active_voters(&state.pool, 30)
+                    .await
+                    .unwrap_or(0);
+                metrics::ACTIVE_SESSIONS.set(active_sessions);
+                metrics::DB_POOL_CONNECTIONS.set(state.pool.size().into());
+                metrics::DB_POOL_IDLE_CONNECTIONS.set(state.pool.num_idle() as i64);
+
+                let mut buffer = vec![];
+                let encoder = TextEncoder::new();
+                if let Err(error) = encoder.encode(&prometheus::gather(), &mut buffer) {
+                    eprintln!("Error encoding metrics: {:?}", error);
+                    return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+                }
+
+                (
+                    [(header::CONTENT_TYPE, encoder.format_type().to_string())],
+                    buffer,
+                )
+                    .into_response()
+            },
+        ),
+    )
+}
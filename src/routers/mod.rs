@@ -4,10 +4,25 @@ pub use leaderboard::leaderboard_router as leaderboard;
 pub mod doggo;
 pub use doggo::doggo_router as doggo;
 
+pub mod tournaments;
+pub use tournaments::tournaments_router as tournaments;
+
 pub mod upload;
 pub use upload::upload_router as upload;
 
 pub mod me;
 pub use me::me_router as me;
 
+pub mod admin;
+pub use admin::admin_router as admin;
+
+pub mod metrics;
+pub use metrics::metrics_router as metrics;
+
+pub mod health;
+pub use health::health_router as health;
+
+pub mod images;
+pub use images::images_router as images;
+
 pub mod test;
@@ -0,0 +1,352 @@
+use crate::db::{self, tournaments::BracketMatch};
+use sqlx::AnyConnection;
+use std::collections::HashMap;
+
+// how a bracket is laid out and how it plays out. the layout is worked out again from the format
+// and the number of entrants whenever it's needed, so the database only keeps each match's number.
+//
+// double elimination: the first round's losers play each other in losers round 1, then every even
+// losers round has the survivors take on the dogs who just lost in the winners bracket, and every
+// odd one after that pairs the survivors up. the winners bracket's champion meets the losers
+// bracket's in a single grand final.
+
+/// How many dogs a tournament can have, which has to be a power of two so nobody gets a bye
+pub const SIZES: [usize; 4] = [4, 8, 16, 32];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Single,
+    Double,
+}
+
+impl Format {
+    pub fn parse(format: &str) -> Option<Self> {
+        match format {
+            "single" => Some(Self::Single),
+            "double" => Some(Self::Double),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Single => "single",
+            Self::Double => "double",
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Single => "Single elimination",
+            Self::Double => "Double elimination",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Bracket {
+    Winners,
+    Losers,
+    Final,
+}
+
+/// Where a dog goes after a match: the number of the next match, and whether it plays on side A
+pub type Next = Option<(usize, bool)>;
+
+#[derive(Debug, PartialEq)]
+pub struct Slot {
+    pub bracket: Bracket,
+    /// Starting from 1 in each bracket
+    pub round: usize,
+    pub winner_to: Next,
+    pub loser_to: Next,
+}
+
+/// Seeds in the order they line up in the first round, so 1 plays `size`, 2 plays `size` - 1 and
+/// the top two seeds can only meet in the final
+pub fn seeding(size: usize) -> Vec<usize> {
+    let mut seeds = vec![1];
+    while seeds.len() < size {
+        let next_size = seeds.len() * 2;
+        seeds = seeds
+            .iter()
+            .flat_map(|&seed| [seed, next_size + 1 - seed])
+            .collect();
+    }
+    seeds
+}
+
+/// Every match in a bracket of `size` dogs, numbered by their place in the returned list.
+/// A match only ever feeds matches after it.
+pub fn layout(format: Format, size: usize) -> Vec<Slot> {
+    let winners_rounds = size.trailing_zeros() as usize;
+    let losers_rounds = 2 * (winners_rounds - 1);
+
+    let mut rounds = vec![];
+    for round in 1..=winners_rounds {
+        rounds.push((Bracket::Winners, round, size >> round));
+    }
+    if format == Format::Double {
+        for round in 1..=losers_rounds {
+            rounds.push((Bracket::Losers, round, size >> (round.div_ceil(2) + 1)));
+        }
+        rounds.push((Bracket::Final, 1, 1));
+    }
+    let first_of = |bracket: Bracket, round: usize| -> usize {
+        rounds
+            .iter()
+            .take_while(|(b, r, _)| (*b, *r) != (bracket, round))
+            .map(|(_, _, count)| count)
+            .sum()
+    };
+    let at = |bracket: Bracket, round: usize, i: usize, side_a: bool| -> Next {
+        Some((first_of(bracket, round) + i, side_a))
+    };
+
+    let mut slots = vec![];
+    for &(bracket, round, count) in &rounds {
+        for i in 0..count {
+            let (winner_to, loser_to) = match (bracket, format) {
+                (Bracket::Winners, Format::Single) => (
+                    (round < winners_rounds)
+                        .then(|| at(Bracket::Winners, round + 1, i / 2, i % 2 == 0))
+                        .flatten(),
+                    None,
+                ),
+                (Bracket::Winners, Format::Double) => (
+                    if round < winners_rounds {
+                        at(Bracket::Winners, round + 1, i / 2, i % 2 == 0)
+                    } else {
+                        at(Bracket::Final, 1, 0, true)
+                    },
+                    if round == 1 {
+                        at(Bracket::Losers, 1, i / 2, i % 2 == 0)
+                    } else {
+                        // in reverse, so dogs who met in the winners bracket don't meet again
+                        // straight away
+                        at(Bracket::Losers, 2 * (round - 1), count - 1 - i, false)
+                    },
+                ),
+                (Bracket::Losers, _) => (
+                    if round % 2 == 1 {
+                        at(Bracket::Losers, round + 1, i, true)
+                    } else if round == losers_rounds {
+                        at(Bracket::Final, 1, 0, false)
+                    } else {
+                        at(Bracket::Losers, round + 1, i / 2, i % 2 == 0)
+                    },
+                    None,
+                ),
+                (Bracket::Final, _) => (None, None),
+            };
+            slots.push(Slot {
+                bracket,
+                round,
+                winner_to,
+                loser_to,
+            });
+        }
+    }
+    slots
+}
+
+/// (winner, loser). More votes wins, and a tie goes to the better seed.
+pub fn winner(dog_a: (i64, i64, i64), dog_b: (i64, i64, i64)) -> (i64, i64) {
+    let (a_id, a_votes, a_seed) = dog_a;
+    let (b_id, b_votes, b_seed) = dog_b;
+    if (a_votes, -a_seed) >= (b_votes, -b_seed) {
+        (a_id, b_id)
+    } else {
+        (b_id, a_id)
+    }
+}
+
+/// Seeds the top rated dogs into a new bracket and opens the first round's voting
+pub async fn start(
+    conn: &mut AnyConnection,
+    name: &str,
+    format: Format,
+    window_hours: i64,
+    dog_ids: &[i64],
+) -> Result<i64, sqlx::Error> {
+    let tournament_id =
+        db::tournaments::create(&mut *conn, name, format.as_str(), window_hours).await?;
+    for (i, dog_id) in dog_ids.iter().enumerate() {
+        db::tournaments::add_entrant(&mut *conn, tournament_id, *dog_id, i as i64 + 1).await?;
+    }
+
+    let opens_at = db::now();
+    let closes_at = db::hours_after(&opens_at, window_hours).unwrap_or_default();
+    let seeds = seeding(dog_ids.len());
+    for (number, slot) in layout(format, dog_ids.len()).iter().enumerate() {
+        let first_round = slot.bracket == Bracket::Winners && slot.round == 1;
+        let dogs = first_round.then(|| {
+            let dog = |i: usize| dog_ids[seeds[i] - 1];
+            (dog(2 * number), dog(2 * number + 1))
+        });
+        let window = first_round.then_some((opens_at.as_str(), closes_at.as_str()));
+        db::tournaments::add_match(&mut *conn, tournament_id, number as i64, dogs, window).await?;
+    }
+    Ok(tournament_id)
+}
+
+/// Decides every match whose voting window has closed and moves its dogs along. A match opens as
+/// soon as both of its dogs are known, with its window starting when the match before it closed,
+/// so a round plays out on schedule even if nobody looked at the bracket in the meantime.
+/// Run it in a transaction.
+pub async fn advance(conn: &mut AnyConnection, tournament_id: i64) -> Result<(), sqlx::Error> {
+    let Some(tournament) = db::tournaments::get(&mut *conn, tournament_id).await? else {
+        return Ok(());
+    };
+    let Some(format) = Format::parse(&tournament.format) else {
+        return Ok(());
+    };
+    if tournament.champion_id.is_some() {
+        return Ok(());
+    }
+    let seeds: HashMap<i64, i64> = db::tournaments::entrants(&mut *conn, tournament_id)
+        .await?
+        .into_iter()
+        .map(|entrant| (entrant.dog_id, entrant.seed))
+        .collect();
+    let votes: HashMap<(i64, i64), i64> = db::tournaments::tallies(&mut *conn, tournament_id)
+        .await?
+        .into_iter()
+        .map(|(match_id, dog_id, votes)| ((match_id, dog_id), votes))
+        .collect();
+    let layout = layout(format, seeds.len());
+    let mut matches = db::tournaments::matches(&mut *conn, tournament_id).await?;
+    if matches.len() != layout.len() {
+        return Ok(());
+    }
+
+    let now = db::now();
+    loop {
+        let due = matches
+            .iter()
+            .filter(|m| m.winner_id.is_none())
+            .filter_map(|m| Some((m.closes_at.clone()?, m)))
+            .filter(|(closes_at, _)| *closes_at <= now)
+            .min_by_key(|(closes_at, m)| (closes_at.clone(), m.number));
+        let Some((closes_at, due)) = due else {
+            break;
+        };
+        let BracketMatch {
+            id,
+            number,
+            dog_a_id: Some(dog_a_id),
+            dog_b_id: Some(dog_b_id),
+            ..
+        } = *due
+        else {
+            break;
+        };
+        let entry = |dog_id: i64| {
+            let votes = votes.get(&(id, dog_id)).copied().unwrap_or(0);
+            (
+                dog_id,
+                votes,
+                seeds.get(&dog_id).copied().unwrap_or(i64::MAX),
+            )
+        };
+        let (winner_id, loser_id) = winner(entry(dog_a_id), entry(dog_b_id));
+        if !db::tournaments::decide(&mut *conn, id, winner_id).await? {
+            return Ok(());
+        }
+        matches[number as usize].winner_id = Some(winner_id);
+
+        let slot = &layout[number as usize];
+        let Some(winner_to) = slot.winner_to else {
+            db::tournaments::crown(&mut *conn, tournament_id, winner_id).await?;
+            break;
+        };
+        let moves = [
+            Some((winner_to, winner_id)),
+            slot.loser_to.map(|to| (to, loser_id)),
+        ];
+        for ((next, side_a), dog_id) in moves.into_iter().flatten() {
+            let next_match = &mut matches[next];
+            db::tournaments::place(&mut *conn, next_match.id, side_a, dog_id).await?;
+            if side_a {
+                next_match.dog_a_id = Some(dog_id);
+            } else {
+                next_match.dog_b_id = Some(dog_id);
+            }
+            if next_match.dog_a_id.is_some() && next_match.dog_b_id.is_some() {
+                let next_closes_at =
+                    db::hours_after(&closes_at, tournament.window_hours).unwrap_or_default();
+                db::tournaments::open(&mut *conn, next_match.id, &closes_at, &next_closes_at)
+                    .await?;
+                next_match.opens_at = Some(closes_at.clone());
+                next_match.closes_at = Some(next_closes_at);
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn top_seeds_are_kept_apart() {
+        assert_eq!(seeding(4), vec![1, 4, 2, 3]);
+        assert_eq!(seeding(8), vec![1, 8, 4, 5, 2, 7, 3, 6]);
+    }
+
+    #[test]
+    fn brackets_have_the_right_number_of_matches() {
+        for size in SIZES {
+            assert_eq!(layout(Format::Single, size).len(), size - 1);
+            // everybody but the champion loses twice, less the grand final's loser's second loss
+            assert_eq!(layout(Format::Double, size).len(), 2 * size - 2);
+        }
+    }
+
+    #[test]
+    fn every_match_after_the_first_round_is_fed_two_dogs() {
+        for format in [Format::Single, Format::Double] {
+            for size in SIZES {
+                let slots = layout(format, size);
+                let mut fed = vec![(0, 0); slots.len()];
+                for (number, slot) in slots.iter().enumerate() {
+                    for (next, side_a) in [slot.winner_to, slot.loser_to].into_iter().flatten() {
+                        assert!(next > number);
+                        if side_a {
+                            fed[next].0 += 1;
+                        } else {
+                            fed[next].1 += 1;
+                        }
+                    }
+                }
+                for (slot, fed) in slots.iter().zip(fed) {
+                    let first_round = slot.bracket == Bracket::Winners && slot.round == 1;
+                    assert_eq!(fed, if first_round { (0, 0) } else { (1, 1) });
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn only_the_last_match_has_nowhere_to_go() {
+        for format in [Format::Single, Format::Double] {
+            let slots = layout(format, 8);
+            let finals: Vec<_> = slots
+                .iter()
+                .enumerate()
+                .filter(|(_, slot)| slot.winner_to.is_none())
+                .map(|(number, _)| number)
+                .collect();
+            assert_eq!(finals, vec![slots.len() - 1]);
+        }
+    }
+
+    #[test]
+    fn ties_go_to_the_better_seed() {
+        assert_eq!(winner((10, 3, 4), (20, 5, 1)), (20, 10));
+        assert_eq!(winner((10, 5, 4), (20, 3, 1)), (10, 20));
+        assert_eq!(winner((10, 2, 4), (20, 2, 1)), (20, 10));
+        assert_eq!(winner((10, 0, 1), (20, 0, 8)), (10, 20));
+    }
+}
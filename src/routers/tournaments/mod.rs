@@ -0,0 +1,354 @@
+use crate::{
+    db::{
+        self,
+        tournaments::{BracketMatch, Entrant, Tournament},
+    },
+    error::AppError,
+    fraud,
+    layout::{base, NavLink},
+    routers::images::dog_image,
+    AppContext, AppState,
+};
+use axum::{
+    extract::{Path, State},
+    response::{Html, IntoResponse},
+    routing::{get, post},
+    Extension, Router,
+};
+use bracket::{layout, Bracket, Format, Slot};
+use maud::{html, Markup};
+use sqlx::{Any, Pool};
+use std::collections::{BTreeMap, HashMap};
+
+pub mod bracket;
+
+// tournaments are started by an admin (see admin::start_tournament) and played out in public.
+// every open match in the bracket can be voted on by anybody who has played a while, once each,
+// and the votes are only shown once the match is decided so nobody just piles on to the dog that's
+// ahead. votes from users the fraud checks have flagged (see fraud.rs) don't count.
+
+/// Picks a user has to have made before they can vote, so a script can't vote with fresh cookies
+/// and the fraud checks have seen enough of them to go on
+const PICKS_TO_VOTE: i64 = fraud::FIRST_PICKS as i64;
+
+pub fn tournaments_router() -> Router<AppState> {
+    Router::<AppState>::new()
+        .route("/", get(tournaments_page))
+        .route("/:tournament_id", get(tournament_page))
+        .route("/:tournament_id/matches/:match_id/vote/:dog_id", post(vote))
+}
+
+/// A trophy for every tournament a dog has won
+pub fn champion_badge(titles: i64) -> Markup {
+    let label = if titles == 1 {
+        "Won a tournament".to_string()
+    } else {
+        format!("Won {} tournaments", titles)
+    };
+    html! {
+        @if titles > 0 {
+            span class="whitespace-nowrap" title=(label) aria-label=(label) role="img" {
+                "🏆" @if titles > 1 {"×"(titles)}
+            }
+        }
+    }
+}
+
+async fn tournaments_page(State(state): State<AppState>) -> Result<impl IntoResponse, AppError> {
+    let tournaments = db::tournaments::list(&state.pool).await?;
+    let mut champions = HashMap::new();
+    for champion_id in tournaments.iter().filter_map(|t| t.champion_id) {
+        if let Some(dog) = db::dogs::get(&state.pool, champion_id).await? {
+            champions.insert(champion_id, dog.name);
+        }
+    }
+    Ok(base(
+        html! {
+            div class="flex-1 flex flex-col items-center gap-6 max-w-screen-md mx-auto p-4 w-full" {
+                h1 class="text-5xl text-center" {"Tournaments"}
+                @if tournaments.is_empty() {
+                    p class="text-2xl text-center" {"No tournaments yet, check back soon!"}
+                }
+                ul class="flex flex-col gap-4 w-full" {
+                    @for tournament in &tournaments {
+                        li {
+                            a class="flex flex-col gap-1 rounded-md bg-base-200 hover:bg-base-300 p-4" href={"/tournaments/"(tournament.id)} {
+                                span class="text-3xl break-words" {(tournament.name)}
+                                span class="text-lg" {
+                                    @if let Some(format) = Format::parse(&tournament.format) {(format.name())}
+                                    " · "
+                                    @match tournament.champion_id {
+                                        Some(champion_id) => {
+                                            "Won by "
+                                            (champions.get(&champion_id).cloned().flatten().unwrap_or("a dog with no name".to_string()))
+                                            " 🏆"
+                                        },
+                                        None => "Voting now",
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        },
+        Some("Tournaments".to_string()),
+        Some(NavLink::Leaderboard),
+    ))
+}
+
+async fn tournament_page(
+    State(state): State<AppState>,
+    Extension(context): Extension<AppContext>,
+    Path(tournament_id): Path<i64>,
+) -> Result<impl IntoResponse, AppError> {
+    let view = BracketView::load(&state.pool, tournament_id, context.user_id).await?;
+    Ok(base(
+        html! {
+            div class="flex-1 flex flex-col gap-6 p-4 w-full" {
+                div class="flex flex-col items-center gap-1" {
+                    h1 class="text-5xl text-center break-words" {(view.tournament.name)}
+                    p class="text-xl" {(view.format.name())}
+                    a class="underline text-primary" href="/tournaments" {"All tournaments"}
+                }
+                (bracket(&view))
+            }
+        },
+        Some(view.tournament.name.clone()),
+        Some(NavLink::Leaderboard),
+    ))
+}
+
+async fn vote(
+    State(state): State<AppState>,
+    Extension(context): Extension<AppContext>,
+    Path((tournament_id, match_id, dog_id)): Path<(i64, i64, i64)>,
+) -> Result<impl IntoResponse, AppError> {
+    advance(&state.pool, tournament_id).await?;
+    let dog_match = db::tournaments::get_match(&state.pool, tournament_id, match_id)
+        .await?
+        .ok_or(AppError::NotFound)?;
+    if dog_match.dog_a_id != Some(dog_id) && dog_match.dog_b_id != Some(dog_id) {
+        return Err(AppError::NotFound);
+    }
+    if !is_open(&dog_match, &db::now()) {
+        return Err(AppError::BadRequest(
+            "Voting on this match has closed.".to_string(),
+        ));
+    }
+    let picks_to_go = picks_to_go(&state.pool, context.user_id).await?;
+    if picks_to_go > 0 {
+        return Err(AppError::Forbidden(picks_to_go_message(picks_to_go)));
+    }
+    db::tournaments::vote(&state.pool, match_id, context.user_id, dog_id).await?;
+
+    let view = BracketView::load(&state.pool, tournament_id, context.user_id).await?;
+    Ok(Html(bracket(&view).into_string()))
+}
+
+/// How many more picks this user has to make before they can vote
+async fn picks_to_go(pool: &Pool<Any>, user_id: i64) -> Result<i64, sqlx::Error> {
+    Ok((PICKS_TO_VOTE - db::matches::count_picks(pool, user_id).await?).max(0))
+}
+
+fn picks_to_go_message(picks_to_go: i64) -> String {
+    let winners = if picks_to_go == 1 {
+        "winner"
+    } else {
+        "winners"
+    };
+    format!(
+        "Pick {} more {} on the game board and you can vote in tournaments.",
+        picks_to_go, winners
+    )
+}
+
+async fn advance(pool: &Pool<Any>, tournament_id: i64) -> Result<(), sqlx::Error> {
+    let mut transaction = pool.begin().await?;
+    bracket::advance(&mut transaction, tournament_id).await?;
+    transaction.commit().await
+}
+
+fn is_open(dog_match: &BracketMatch, now: &str) -> bool {
+    match (&dog_match.opens_at, &dog_match.closes_at) {
+        (Some(opens_at), Some(closes_at)) => {
+            dog_match.winner_id.is_none() && opens_at.as_str() <= now && now < closes_at.as_str()
+        }
+        _ => false,
+    }
+}
+
+struct BracketView {
+    tournament: Tournament,
+    format: Format,
+    slots: Vec<Slot>,
+    matches: Vec<BracketMatch>,
+    entrants: HashMap<i64, Entrant>,
+    /// (match, dog) to how many votes the dog got
+    tallies: HashMap<(i64, i64), i64>,
+    /// match to the dog this user voted for
+    votes: HashMap<i64, i64>,
+    /// How many more picks this user has to make before they can vote
+    picks_to_go: i64,
+    now: String,
+}
+
+impl BracketView {
+    async fn load(pool: &Pool<Any>, tournament_id: i64, user_id: i64) -> Result<Self, AppError> {
+        advance(pool, tournament_id).await?;
+        let tournament = db::tournaments::get(pool, tournament_id)
+            .await?
+            .ok_or(AppError::NotFound)?;
+        let format = Format::parse(&tournament.format).ok_or(AppError::NotFound)?;
+        let entrants: HashMap<i64, Entrant> = db::tournaments::entrants(pool, tournament_id)
+            .await?
+            .into_iter()
+            .map(|entrant| (entrant.dog_id, entrant))
+            .collect();
+        let tallies = db::tournaments::tallies(pool, tournament_id)
+            .await?
+            .into_iter()
+            .map(|(match_id, dog_id, votes)| ((match_id, dog_id), votes))
+            .collect();
+        let votes = db::tournaments::votes_by(pool, tournament_id, user_id)
+            .await?
+            .into_iter()
+            .collect();
+        let picks_to_go = picks_to_go(pool, user_id).await?;
+        Ok(Self {
+            slots: layout(format, entrants.len()),
+            matches: db::tournaments::matches(pool, tournament_id).await?,
+            tournament,
+            format,
+            entrants,
+            tallies,
+            votes,
+            picks_to_go,
+            now: db::now(),
+        })
+    }
+
+    fn name(&self, dog_id: i64) -> String {
+        self.entrants
+            .get(&dog_id)
+            .and_then(|entrant| entrant.name.clone())
+            .unwrap_or("A dog with no name".to_string())
+    }
+
+    fn round_name(&self, bracket: Bracket, round: usize) -> String {
+        // losers rounds run past the number of winners rounds, so only count down in the winners
+        let rounds_left = match bracket {
+            Bracket::Winners => self.entrants.len().trailing_zeros() as usize - round,
+            Bracket::Losers => return format!("Round {}", round),
+            Bracket::Final => return "Grand final".to_string(),
+        };
+        match (self.format, rounds_left) {
+            (Format::Single, 0) => "Final".to_string(),
+            (Format::Single, 1) => "Semifinals".to_string(),
+            (Format::Single, 2) => "Quarterfinals".to_string(),
+            (Format::Double, 0) => "Winners final".to_string(),
+            _ => format!("Round {}", round),
+        }
+    }
+}
+
+fn bracket(view: &BracketView) -> Markup {
+    let mut rounds: BTreeMap<(Bracket, usize), Vec<&BracketMatch>> = BTreeMap::new();
+    for (slot, dog_match) in view.slots.iter().zip(&view.matches) {
+        rounds
+            .entry((slot.bracket, slot.round))
+            .or_default()
+            .push(dog_match);
+    }
+    let sections = [
+        (Bracket::Winners, "Winners bracket"),
+        (Bracket::Losers, "Losers bracket"),
+        (Bracket::Final, "Grand final"),
+    ];
+    html! {
+        div id="bracket" class="flex flex-col gap-8 w-full" {
+            @if let Some(champion_id) = view.tournament.champion_id {
+                @let name = view.name(champion_id);
+                div class="flex flex-col items-center gap-2" {
+                    (dog_image(champion_id, &name, "object-center object-cover aspect-square w-48 rounded-md", "12rem", false))
+                    p class="text-3xl text-center break-words" {"🏆 "(name)" is the champion!"}
+                }
+            }
+            @if view.picks_to_go > 0 && view.matches.iter().any(|dog_match| is_open(dog_match, &view.now)) {
+                p class="text-lg text-center" {
+                    (picks_to_go_message(view.picks_to_go))" "
+                    a href="/" class="underline text-primary" {"Start picking"}
+                }
+            }
+            @for (section, label) in sections {
+                @let section_rounds: Vec<_> = rounds.iter().filter(|((bracket, _), _)| *bracket == section).collect();
+                @if !section_rounds.is_empty() {
+                    section class="flex flex-col gap-2" {
+                        @if view.format == Format::Double && section != Bracket::Final {
+                            h2 class="text-3xl" {(label)}
+                        }
+                        div class="flex gap-4 overflow-x-auto pb-2" {
+                            @for ((bracket, round), matches) in section_rounds {
+                                div class="flex flex-col justify-around gap-4 min-w-72" {
+                                    h3 class="text-xl text-center" {(view.round_name(*bracket, *round))}
+                                    @for dog_match in matches {
+                                        (match_card(view, dog_match))
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn match_card(view: &BracketView, dog_match: &BracketMatch) -> Markup {
+    let open = is_open(dog_match, &view.now);
+    html! {
+        div class="rounded-md bg-base-200 p-2 flex flex-col gap-2" {
+            @for dog_id in [dog_match.dog_a_id, dog_match.dog_b_id] {
+                @match dog_id {
+                    Some(dog_id) => (contender(view, dog_match, dog_id, open)),
+                    None => div class="h-16 flex items-center px-2 opacity-50" {"To be decided"},
+                }
+            }
+            @if let (true, Some(closes_at)) = (open, &dog_match.closes_at) {
+                p class="text-sm text-center" {"Voting closes " (closes_at) " UTC"}
+            }
+        }
+    }
+}
+
+fn contender(view: &BracketView, dog_match: &BracketMatch, dog_id: i64, open: bool) -> Markup {
+    let name = view.name(dog_id);
+    let seed = view.entrants.get(&dog_id).map(|entrant| entrant.seed);
+    let voted = view.votes.get(&dog_match.id) == Some(&dog_id);
+    let lost = dog_match
+        .winner_id
+        .is_some_and(|winner_id| winner_id != dog_id);
+    html! {
+        div class={"flex items-center gap-2" @if lost {" opacity-50"}} {
+            (dog_image(dog_id, &name, "object-center object-cover aspect-square w-16 rounded", "4rem", true))
+            @if let Some(seed) = seed {
+                span class="text-sm opacity-70" title="Seed" {(seed)}
+            }
+            span class="flex-1 break-words min-w-0" {(name)}
+            @if dog_match.winner_id.is_some() {
+                span class="text-lg" {
+                    (view.tallies.get(&(dog_match.id, dog_id)).copied().unwrap_or(0))
+                    @if dog_match.winner_id == Some(dog_id) {" ✓"}
+                }
+            }
+            @if open && view.picks_to_go == 0 {
+                button class={"btn btn-sm" @if voted {" btn-primary"}}
+                    hx-post={"/tournaments/"(view.tournament.id)"/matches/"(dog_match.id)"/vote/"(dog_id)}
+                    hx-target="#bracket" hx-swap="outerHTML"
+                    aria-pressed=(voted) aria-label={"Vote for "(name)} {
+                    @if voted {"Voted"} @else {"Vote"}
+                }
+            }
+        }
+    }
+}
@@ -1,6 +1,9 @@
 use crate::{
+    db::{self, log::Event},
+    error::AppError,
     layout::{base, NavLink},
-    routers::doggo::name_dog::name_dog,
+    metrics,
+    routers::doggo::{name_dog::name_dog, xp::XP_INCREASE_FOR_UPLOAD},
     AppContext, AppState, FormField,
 };
 use axum::{
@@ -10,10 +13,8 @@ use axum::{
     routing::get,
     Extension, Router,
 };
-use maud::{html, Markup, PreEscaped};
-use std::{env, fs, path::Path};
-
-use super::me::send_email;
+use maud::{html, Markup};
+use std::{env, fs};
 
 pub fn upload_router() -> Router<AppState> {
     Router::<AppState>::new().route(
@@ -32,28 +33,27 @@ pub fn upload_router() -> Router<AppState> {
                 let mut dog_name: Option<String> = None;
                 let mut dog_photo: Option<Bytes> = None;
 
-                let critical_err = || Html("Error processing form".to_string());
+                let critical_err = || AppError::BadRequest("We couldn't read that upload, try again?".to_string());
 
                 // extract out dog_name and dog_photo
                 while let Some(field) = match multipart.next_field().await {
                     Ok(field) => field,
                     Err(error) => {
                         eprintln!("Error getting next field: {:?}", error);
-                        return critical_err();
+                        return Err(critical_err());
                     }
                 } {
                     let name = match field.name() {
                         Some(name) => name.to_string(),
                         None => {
                             eprintln!("Field without a name");
-                            return critical_err();
+                            return Err(critical_err());
                         }
                     };
 
                     if let Some(file_type) = field.content_type() {
                         if !file_type.starts_with("image/") {
-                            println!("oop {:?}", file_type);
-                            return Html(
+                            return Ok(Html(
                                 upload_dog_form(
                                     FormField {
                                         value: dog_name.unwrap_or("".to_string()),
@@ -62,9 +62,7 @@ pub fn upload_router() -> Router<AppState> {
                                     FileUploadStatus::Err("Must be an image".to_string()),
                                 )
                                 .into_string(),
-                            );
-                        } else {
-                            println!("yayaya {:?}", file_type);
+                            ));
                         }
                     }
 
@@ -72,13 +70,15 @@ pub fn upload_router() -> Router<AppState> {
                         Ok(data) => data,
                         Err(error) => {
                             eprintln!("Error reading bytes: {:?}", error);
-                            return critical_err();
+                            return Err(critical_err());
                         }
                     };
 
                     if name == "new_dog_name" {
-                        dog_name =
-                            Some(String::from_utf8(data.to_vec()).unwrap().trim().to_string());
+                        let Ok(new_dog_name) = String::from_utf8(data.to_vec()) else {
+                            return Err(critical_err());
+                        };
+                        dog_name = Some(new_dog_name.trim().to_string());
                     } else if name == "new_dog_photo" {
                         dog_photo = Some(data);
                     }
@@ -86,10 +86,10 @@ pub fn upload_router() -> Router<AppState> {
                 // should always at least be an empty string
                 if dog_name.is_none() {
                     eprintln!("No dog_name value");
-                    return critical_err();
+                    return Err(critical_err());
                 }
                 if dog_photo.is_none() {
-                    return Html(
+                    return Ok(Html(
                         upload_dog_form(
                             FormField {
                                 value: dog_name.unwrap_or("".to_string()),
@@ -98,7 +98,7 @@ pub fn upload_router() -> Router<AppState> {
                             FileUploadStatus::Err("Required".to_string()),
                         )
                         .into_string(),
-                    );
+                    ));
                 }
                 let dog_name = dog_name.unwrap();
                 let dog_photo = dog_photo.unwrap();
@@ -106,52 +106,57 @@ pub fn upload_router() -> Router<AppState> {
                 let uploaded =
                     String::from_utf8(dog_photo.to_vec()).unwrap_or("".to_string()) == "uploaded";
 
-                let mut transaction = state.pool.begin().await.unwrap();
+                if !uploaded {
+                    if let Err(error) = validate_dog_photo(&dog_photo) {
+                        return Ok(Html(
+                            upload_dog_form(
+                                FormField {
+                                    value: dog_name,
+                                    error: "".to_string(),
+                                },
+                                FileUploadStatus::Err(error),
+                            )
+                            .into_string(),
+                        ));
+                    }
+                }
+
+                let mut transaction = state.pool.begin().await?;
 
                 let dog_id = if uploaded {
-                    let result = sqlx::query!("SELECT id FROM dog WHERE approved = FALSE AND namer_id = $1 AND name IS NULL ORDER BY id DESC LIMIT 1", context.user_id)
-                        .fetch_one(&mut *transaction).await;
-                    if result.is_err() {
-                        eprintln!("Couldn't find uploaded dog");
-                        return critical_err();
+                    match db::dogs::latest_unnamed_upload(&mut *transaction, context.user_id).await? {
+                        Some(dog_id) => dog_id,
+                        None => {
+                            eprintln!("Couldn't find uploaded dog");
+                            return Err(critical_err());
+                        }
                     }
-                    result.unwrap().id
                 } else {
-                    sqlx::query!( "INSERT INTO dog (image_url, approved, namer_id) VALUES ('temp', false, $1) RETURNING id", context.user_id)
-                        .fetch_one(&mut *transaction) .await .unwrap() .id
+                    db::dogs::create_unapproved(&mut *transaction, context.user_id).await?
                 };
 
                 if !uploaded {
                     let file_name = format!("{}.jpg", dog_id);
-                    let file_path = Path::new("./unapproved").join(&file_name);
+                    let file_path = state.unapproved_dir.join(&file_name);
                     if let Err(error) = fs::write(&file_path, &dog_photo) {
-                        eprintln!("Error saving file: {:?}", error);
-                        // can be implicit?
-                        // let _ = transaction.rollback().await;
-                        return critical_err();
+                        return Err(anyhow::Error::from(error).context(format!("saving {:?}", file_path)).into());
                     }
-                    println!("Saved file '{}' to {:?}", file_name, file_path);
 
                     let image_url = format!("/images/{}", file_name);
-                    let _ = sqlx::query!(
-                        "UPDATE dog SET image_url = $1 WHERE id = $2",
-                        image_url,
-                        dog_id
-                    )
-                    .fetch_one(&mut *transaction)
-                    .await;
+                    db::dogs::set_image_url(&mut *transaction, dog_id, &image_url).await?;
 
-                    let client_ip: Option<String> = context.client_ip.map(|ip| ip.to_string());
-                    let _ = sqlx::query!("INSERT INTO log (action, user_id, client_ip, notes) VALUES ('upload', $1, $2, $3)", context.user_id, client_ip, dog_id)
-                        .fetch_one(&mut *transaction).await;
+                    let _ = db::log::record(&mut *transaction, &Event::Upload { dog_id }, Some(context.user_id), context.client_ip).await;
                 }
 
-                let _ = transaction.commit().await;
+                transaction.commit().await?;
+                if !uploaded {
+                    metrics::UPLOADS_TOTAL.inc();
+                }
 
                 if !dog_name.is_empty() {
-                    let result = name_dog(&state.pool, context.user_id, dog_id, &dog_name).await;
+                    let result = name_dog(&state.pool, context.user_id, dog_id, &dog_name).await?;
                     if let Err(error) = result {
-                        return Html(
+                        return Ok(Html(
                             upload_dog_form(
                                 FormField {
                                     value: dog_name,
@@ -160,32 +165,55 @@ pub fn upload_router() -> Router<AppState> {
                                 FileUploadStatus::Uploaded,
                             )
                             .into_string(),
-                        );
+                        ));
                     }
                 }
 
-                let _ = sqlx::query!(
-                    "UPDATE user SET total_xp = total_xp + 1000 WHERE id = $1",
-                    context.user_id
-                )
-                .fetch_one(&state.pool)
-                .await;
-                
-                let _ = send_email(format!("Top Doggo Admin <{}>", env::var("ADMIN_EMAIL").expect("ADMIN_EMAIL should be set")).parse().unwrap(), "A dog has been uploaded", html!{"Well ain't that nifty!"}).await;
+                db::users::add_xp(&state.pool, context.user_id, XP_INCREASE_FOR_UPLOAD).await?;
+
+                match env::var("ADMIN_EMAIL").map(|admin_email| format!("Top Doggo Admin <{}>", admin_email).parse()) {
+                    Ok(Ok(admin_mailbox)) => {
+                        let _ = state.mailer.send(admin_mailbox, "A dog has been uploaded", html!{"Well ain't that nifty!"}).await;
+                    }
+                    _ => eprintln!("ADMIN_EMAIL isn't set to an email address, not sending the upload notification"),
+                }
 
-                Html(html!{
+                Ok(Html(html!{
                     div class="flex-1 flex flex-col gap-4 items-center justify-center text-center" {
                         h1 class="text-4xl" {"Thanks for adding your dog!"}
                         p class="text-2xl" {"Our team will approve em, and then they'll join the squad :)"}
                         p class="text-base" {"( Also you just got 1000xp :D )"}
                     }
-                }.into_string())
+                }.into_string()))
 
             },
         ),
     )
 }
 
+// matches the DefaultBodyLimit in lib.rs
+pub const MAX_DOG_PHOTO_BYTES: usize = 1024 * 1024 * 10;
+
+/// Sniffs the file itself instead of trusting the content type the browser sent
+pub fn validate_dog_photo(data: &[u8]) -> Result<(), String> {
+    if data.len() > MAX_DOG_PHOTO_BYTES {
+        return Err("That file is too big!".to_string());
+    }
+    let is_jpeg = data.starts_with(&[0xFF, 0xD8, 0xFF]);
+    let is_png = data.starts_with(b"\x89PNG\r\n\x1a\n");
+    let is_gif = data.starts_with(b"GIF87a") || data.starts_with(b"GIF89a");
+    let is_webp = data.len() >= 12 && &data[0..4] == b"RIFF" && &data[8..12] == b"WEBP";
+    // iphones
+    let is_heic_or_avif = data.len() >= 12
+        && &data[4..8] == b"ftyp"
+        && matches!(&data[8..12], b"heic" | b"heix" | b"mif1" | b"avif");
+    if is_jpeg || is_png || is_gif || is_webp || is_heic_or_avif {
+        Ok(())
+    } else {
+        Err("Must be an image".to_string())
+    }
+}
+
 pub enum FileUploadStatus {
     Uploaded,
     NotUploaded,
@@ -243,16 +271,5 @@ pub fn upload_dog_form(
                 {"Add 🐕"}
             // progress id="progress" value="0" max="100" class="w-full" {}
         }
-        script {(PreEscaped(r#"
-            // htmx.on('#upload-form', 'htmx:xhr:progress', function(evt) {
-            //     htmx.find('#progress').setAttribute('value', evt.detail.loaded/evt.detail.total * 100)
-            // });
-            document.body.addEventListener('htmx:error', e => {
-                if (e.detail.errorInfo.pathInfo.requestPath === '/upload') {
-                    document.getElementById("new_dog_photo").classList.add("file-input-error");
-                    document.getElementById("new_dog_photo_error").innerHTML = "That file is too big!";
-                }
-            });
-        "#))}
     }
 }
@@ -0,0 +1,136 @@
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::env;
+
+// the auth cookie is `<token>.<signature>`, the signature being an HMAC of the token with a key
+// only the server knows. a cookie that doesn't verify is thrown out before the session table is
+// ever asked about it. the table only keeps a hash of each token (see db::sessions), so a copy
+// of the database can't be used to sign in as anybody either.
+//
+// SESSION_KEYS is a comma separated list. the first key signs new cookies, and the rest still
+// verify, so a key can be rotated by putting the new one in front and dropping the old one once
+// everybody has been around since (cookies signed with an old key get re-signed on their next
+// request).
+
+const MIN_KEY_LENGTH: usize = 32;
+
+#[derive(Clone)]
+pub struct SessionKeys {
+    keys: Vec<Vec<u8>>,
+}
+
+/// A token whose signature checked out
+#[derive(Debug, PartialEq, Eq)]
+pub struct Verified {
+    pub token: String,
+    /// Signed with a key other than the first, so it should be signed again
+    pub stale: bool,
+}
+
+impl SessionKeys {
+    pub fn new(keys: &[&str]) -> Result<Self, String> {
+        if keys.is_empty() {
+            return Err("at least one session key is needed".to_string());
+        }
+        if let Some(key) = keys.iter().find(|key| key.len() < MIN_KEY_LENGTH) {
+            return Err(format!(
+                "session keys need to be at least {} characters, one is {}",
+                MIN_KEY_LENGTH,
+                key.len()
+            ));
+        }
+        Ok(Self {
+            keys: keys.iter().map(|key| key.as_bytes().to_vec()).collect(),
+        })
+    }
+
+    pub fn from_env() -> Self {
+        let keys = env::var("SESSION_KEYS").expect("SESSION_KEYS not specified");
+        let keys: Vec<&str> = keys
+            .split(',')
+            .map(str::trim)
+            .filter(|key| !key.is_empty())
+            .collect();
+        Self::new(&keys).unwrap_or_else(|error| panic!("bad SESSION_KEYS: {}", error))
+    }
+
+    /// The cookie value for `token`
+    pub fn sign(&self, token: &str) -> String {
+        let signature = mac(&self.keys[0], token).finalize().into_bytes();
+        format!("{}.{}", token, hex::encode(signature))
+    }
+
+    pub fn verify(&self, cookie: &str) -> Option<Verified> {
+        let (token, signature) = cookie.split_once('.')?;
+        let signature = hex::decode(signature).ok()?;
+        let key_index = self
+            .keys
+            .iter()
+            .position(|key| mac(key, token).verify_slice(&signature).is_ok())?;
+        Some(Verified {
+            token: token.to_string(),
+            stale: key_index > 0,
+        })
+    }
+}
+
+fn mac(key: &[u8], token: &str) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC takes keys of any length");
+    mac.update(token.as_bytes());
+    mac
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OLD_KEY: &str = "an old key that's long enough to use";
+    const NEW_KEY: &str = "a new key that's also long enough to use";
+
+    #[test]
+    fn signed_tokens_verify() {
+        let keys = SessionKeys::new(&[NEW_KEY]).unwrap();
+        let cookie = keys.sign("abc123");
+        assert!(cookie.starts_with("abc123."));
+        assert_eq!(
+            keys.verify(&cookie),
+            Some(Verified {
+                token: "abc123".to_string(),
+                stale: false
+            })
+        );
+    }
+
+    #[test]
+    fn tampered_cookies_dont() {
+        let keys = SessionKeys::new(&[NEW_KEY]).unwrap();
+        let cookie = keys.sign("abc123");
+        let (_, signature) = cookie.split_once('.').unwrap();
+
+        assert_eq!(keys.verify(&format!("abc124.{}", signature)), None);
+        assert_eq!(keys.verify(&cookie[..cookie.len() - 2]), None);
+        assert_eq!(keys.verify("abc123"), None);
+        assert_eq!(keys.verify("abc123.not-hex"), None);
+        // or anything signed with somebody else's key
+        let elsewhere = SessionKeys::new(&[OLD_KEY]).unwrap();
+        assert_eq!(keys.verify(&elsewhere.sign("abc123")), None);
+    }
+
+    #[test]
+    fn old_keys_still_verify_until_theyre_dropped() {
+        let before = SessionKeys::new(&[OLD_KEY]).unwrap();
+        let during = SessionKeys::new(&[NEW_KEY, OLD_KEY]).unwrap();
+        let after = SessionKeys::new(&[NEW_KEY]).unwrap();
+        let cookie = before.sign("abc123");
+
+        assert!(during.verify(&cookie).unwrap().stale);
+        assert!(!during.verify(&during.sign("abc123")).unwrap().stale);
+        assert_eq!(after.verify(&cookie), None);
+    }
+
+    #[test]
+    fn short_keys_are_refused() {
+        assert!(SessionKeys::new(&[]).is_err());
+        assert!(SessionKeys::new(&[NEW_KEY, "hunter2"]).is_err());
+    }
+}
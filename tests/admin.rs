@@ -0,0 +1,681 @@
+mod common;
+
+use axum::http::Method;
+use common::{total_xp, TestApp};
+use std::io::Write;
+use top_doggo::{
+    admin::{self, export, import},
+    backup,
+    db::{self, log::Event},
+    routers::{doggo::elo::recompute_ratings, upload::MAX_DOG_PHOTO_BYTES},
+};
+
+async fn ratings(app: &TestApp) -> Vec<(String, Option<i64>, i64, i64)> {
+    sqlx::query_as("SELECT type, user_id, dog_id, value FROM rating ORDER BY type, user_id, dog_id")
+        .fetch_all(app.pool())
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn recomputing_ratings_matches_live_voting() {
+    let app = TestApp::new().await;
+    app.add_dogs(5).await;
+    app.client().vote_until_done().await;
+    app.client().vote_until_done().await;
+    let live = ratings(&app).await;
+
+    let mut conn = app.pool().acquire().await.unwrap();
+    let replayed = recompute_ratings(&mut conn, None).await.unwrap();
+    drop(conn);
+
+    assert_eq!(replayed, 20);
+    assert_eq!(ratings(&app).await, live);
+}
+
+#[tokio::test]
+async fn approving_and_rejecting_uploads() {
+    let app = TestApp::new().await;
+    let mut client = app.client();
+    client.get("/upload").await;
+    for file_name in ["a.jpg", "b.jpg"] {
+        client
+            .multipart(
+                "/upload",
+                &[("new_dog_name", "")],
+                &[("new_dog_photo", file_name, "image/jpeg", JPEG)],
+            )
+            .await;
+    }
+    let dog_ids: Vec<i64> = sqlx::query_scalar("SELECT id FROM dog ORDER BY id")
+        .fetch_all(app.pool())
+        .await
+        .unwrap();
+    let (unapproved_dir, images_dir) = (&app.state.unapproved_dir, &app.state.images_dir);
+
+    admin::approve_dog(app.pool(), dog_ids[0], unapproved_dir, images_dir)
+        .await
+        .unwrap();
+    assert!(images_dir.join(format!("{}.jpg", dog_ids[0])).exists());
+    assert!(!unapproved_dir.join(format!("{}.jpg", dog_ids[0])).exists());
+    assert!(
+        admin::approve_dog(app.pool(), dog_ids[0], unapproved_dir, images_dir)
+            .await
+            .is_err()
+    );
+
+    admin::reject_dog(app.pool(), dog_ids[1], unapproved_dir)
+        .await
+        .unwrap();
+    assert!(!unapproved_dir.join(format!("{}.jpg", dog_ids[1])).exists());
+
+    let stats = admin::stats(app.pool()).await.unwrap();
+    assert_eq!(stats.approved_dogs, 1);
+    assert_eq!(stats.unapproved_dogs, 0);
+}
+
+#[tokio::test]
+async fn renaming_dogs() {
+    let app = TestApp::new().await;
+    let dog_ids = app.add_dogs(2).await;
+
+    admin::rename_dog(app.pool(), dog_ids[0], Some("Noodle"))
+        .await
+        .unwrap();
+    assert!(admin::rename_dog(app.pool(), dog_ids[1], Some("Noodle"))
+        .await
+        .is_err());
+    admin::rename_dog(app.pool(), dog_ids[0], None)
+        .await
+        .unwrap();
+    admin::rename_dog(app.pool(), dog_ids[1], Some("Noodle"))
+        .await
+        .unwrap();
+}
+
+const JPEG: &[u8] = b"\xFF\xD8\xFF\xE0 a good dog";
+const PNG: &[u8] = b"\x89PNG\r\n\x1a\n another good dog";
+
+#[tokio::test]
+async fn importing_a_folder_of_dogs() {
+    let app = TestApp::new().await;
+    let folder = tempfile::tempdir().unwrap();
+    std::fs::write(folder.path().join("rex.jpg"), JPEG).unwrap();
+    std::fs::write(folder.path().join("fido.PNG"), PNG).unwrap();
+    std::fs::write(folder.path().join("notes.txt"), b"not a dog").unwrap();
+    std::fs::write(folder.path().join(".DS_Store"), b"junk").unwrap();
+    std::fs::write(
+        folder.path().join("manifest.csv"),
+        "file,name\nrex.jpg,Rex\nnotes.txt,Notes\n",
+    )
+    .unwrap();
+
+    let mut files = import::read_dir(folder.path()).unwrap();
+    let manifest = import::take_manifest(&mut files).unwrap().unwrap();
+    let results = import::import_dogs(app.pool(), files, &manifest, &app.state.images_dir).await;
+
+    assert_eq!(results.len(), 3);
+    let imported: Vec<_> = results
+        .iter()
+        .filter_map(|result| result.outcome.as_ref().ok())
+        .collect();
+    assert_eq!(imported.len(), 2);
+    assert!(imported
+        .iter()
+        .any(|dog| dog.name.as_deref() == Some("Rex")));
+    for dog in imported {
+        assert!(app
+            .state
+            .images_dir
+            .join(format!("{}.jpg", dog.dog_id))
+            .exists());
+    }
+    let notes = results.iter().find(|result| result.file == "notes.txt");
+    assert!(notes.unwrap().outcome.is_err());
+    assert_eq!(admin::stats(app.pool()).await.unwrap().approved_dogs, 2);
+}
+
+#[test]
+fn parsing_manifests() {
+    let json_map = import::parse_manifest("manifest.json", br#"{"a.jpg": "Ace"}"#).unwrap();
+    assert_eq!(json_map.get("a.jpg").map(String::as_str), Some("Ace"));
+
+    let json_list = import::parse_manifest(
+        "manifest.json",
+        br#"[{"file": "a.jpg", "name": "Ace"}, {"file": "b.jpg", "name": "Bo"}]"#,
+    )
+    .unwrap();
+    assert_eq!(json_list.len(), 2);
+
+    let csv = import::parse_manifest("names.csv", b"a.jpg, Ace\nb.jpg,Bo\nc.jpg\n").unwrap();
+    assert_eq!(csv.get("a.jpg").map(String::as_str), Some("Ace"));
+    assert_eq!(csv.len(), 2);
+
+    assert!(import::parse_manifest("manifest.json", b"{nope").is_err());
+}
+
+fn zip_of(files: &[(&str, &[u8])]) -> Vec<u8> {
+    let mut writer = zip::ZipWriter::new(std::io::Cursor::new(vec![]));
+    for (name, data) in files {
+        writer
+            .start_file(*name, zip::write::SimpleFileOptions::default())
+            .unwrap();
+        writer.write_all(data).unwrap();
+    }
+    writer.finish().unwrap().into_inner()
+}
+
+#[test]
+fn archives_that_unpack_into_huge_files_are_turned_away() {
+    // compresses down to almost nothing
+    let bomb = vec![0; MAX_DOG_PHOTO_BYTES + 1];
+    let archive = zip_of(&[("biscuit.jpg", JPEG), ("bomb.jpg", &bomb)]);
+    assert!(archive.len() < 1024 * 1024);
+    let error = import::read_archive("dogs.zip", &archive).err().unwrap();
+    assert!(error.to_string().contains("bomb.jpg is bigger than"));
+
+    let archive = zip_of(&[("biscuit.jpg", JPEG)]);
+    assert_eq!(import::read_archive("dogs.zip", &archive).unwrap().len(), 1);
+}
+
+async fn make_admin(app: &TestApp, user_id: i64) {
+    sqlx::query(r#"UPDATE "user" SET email = 'admin@example.com' WHERE id = $1"#)
+        .bind(user_id)
+        .execute(app.pool())
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn importing_an_archive_as_an_admin() {
+    let app = TestApp::new().await;
+    let archive = zip_of(&[
+        ("dogs/biscuit.jpg", JPEG),
+        ("dogs/waffle.png", PNG),
+        ("__MACOSX/dogs/._biscuit.jpg", b"junk"),
+        ("manifest.json", br#"{"biscuit.jpg": "Biscuit"}"#),
+    ]);
+
+    let mut client = app.client();
+    let user_id = client.user_id().await;
+    let response = client
+        .multipart(
+            "/admin/import",
+            &[],
+            &[("archive", "dogs.zip", "application/zip", &archive)],
+        )
+        .await;
+    assert_eq!(response.status, 404);
+    assert_eq!(admin::stats(app.pool()).await.unwrap().approved_dogs, 0);
+
+    make_admin(&app, user_id).await;
+    assert_eq!(client.get("/admin").await.status, 200);
+    let response = client
+        .multipart(
+            "/admin/import",
+            &[],
+            &[("archive", "dogs.zip", "application/zip", &archive)],
+        )
+        .await;
+    assert_eq!(response.status, 200);
+    assert!(response.body.contains("Imported 2 of 2 files"));
+
+    let stats = admin::stats(app.pool()).await.unwrap();
+    assert_eq!(stats.approved_dogs, 2);
+    assert_eq!(stats.named_dogs, 1);
+}
+
+#[tokio::test]
+async fn viewing_the_log_as_an_admin() {
+    let app = TestApp::new().await;
+    let dog_ids = app.add_dogs(2).await;
+    let mut namer = app.client();
+    let namer_id = namer.user_id().await;
+    namer
+        .form(
+            Method::PATCH,
+            "/name-dog",
+            &[("dog_id", &dog_ids[0].to_string()), ("new_name", "Biscuit")],
+        )
+        .await;
+    db::log::record(
+        app.pool(),
+        &Event::ApproveDog { dog_id: dog_ids[1] },
+        None,
+        None,
+    )
+    .await
+    .unwrap();
+
+    let mut client = app.client();
+    let user_id = client.user_id().await;
+    assert_eq!(client.get("/admin/log").await.status, 404);
+
+    make_admin(&app, user_id).await;
+    let response = client.get("/admin/log").await;
+    assert_eq!(response.status, 200);
+    assert!(response.body.contains(&format!("named dog {}", dog_ids[0])));
+    assert!(response
+        .body
+        .contains(&format!("approved dog {}", dog_ids[1])));
+
+    let response = client.get("/admin/log?action=approve-dog&user_id=").await;
+    assert!(!response.body.contains("named dog"));
+    assert!(response.body.contains("approved dog"));
+
+    let response = client
+        .get(&format!("/admin/log?user_id={}", namer_id))
+        .await;
+    assert!(response.body.contains("named dog"));
+    assert!(!response.body.contains("approved dog"));
+
+    let response = client.get("/admin/log?from=yesterday").await;
+    assert_eq!(response.status, 400);
+}
+
+#[tokio::test]
+async fn merging_users() {
+    let app = TestApp::new().await;
+    let dog_ids = app.add_dogs(3).await;
+    let mut phone = app.client();
+    let mut laptop = app.client();
+    phone.vote_until_done().await;
+    laptop.vote_until_done().await;
+    laptop
+        .form(
+            Method::PATCH,
+            "/name-dog",
+            &[("dog_id", &dog_ids[0].to_string()), ("new_name", "Bean")],
+        )
+        .await;
+    let phone_user_id = phone.user_id().await;
+    let laptop_user_id = laptop.user_id().await;
+    let expected_xp =
+        total_xp(app.pool(), phone_user_id).await + total_xp(app.pool(), laptop_user_id).await;
+
+    admin::merge_users(app.pool(), laptop_user_id, phone_user_id)
+        .await
+        .unwrap();
+
+    assert_eq!(laptop.user_id().await, phone_user_id);
+    assert_eq!(total_xp(app.pool(), phone_user_id).await, expected_xp);
+    let namer_id: i64 = sqlx::query_scalar("SELECT namer_id FROM dog WHERE id = $1")
+        .bind(dog_ids[0])
+        .fetch_one(app.pool())
+        .await
+        .unwrap();
+    assert_eq!(namer_id, phone_user_id);
+    // both judged every pairing, so only the phone's votes survive
+    let match_count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM match")
+        .fetch_one(app.pool())
+        .await
+        .unwrap();
+    assert_eq!(match_count, 3);
+}
+
+#[tokio::test]
+async fn merging_users_takes_dropped_votes_out_of_the_overall_ratings() {
+    let app = TestApp::new().await;
+    app.add_dogs(4).await;
+    let mut phone = app.client();
+    phone.vote_until_done().await;
+    let phone_only = ratings(&app).await;
+    let mut laptop = app.client();
+    laptop.vote_until_done().await;
+    assert_ne!(ratings(&app).await, phone_only);
+
+    // every one of the laptop's votes was on a pairing the phone judged too
+    let laptop_user_id = laptop.user_id().await;
+    admin::merge_users(app.pool(), laptop_user_id, phone.user_id().await)
+        .await
+        .unwrap();
+    assert_eq!(ratings(&app).await, phone_only);
+}
+
+#[tokio::test]
+async fn merging_users_keeps_a_vote_over_a_pairing_that_was_only_shown() {
+    let app = TestApp::new().await;
+    app.add_dogs(2).await;
+    let mut laptop = app.client();
+    laptop.vote_until_done().await;
+    // the phone has the same pairing up on screen, waiting on a vote
+    let mut phone = app.client();
+    phone.get("/").await;
+    let phone_user_id = phone.user_id().await;
+
+    admin::merge_users(app.pool(), laptop.user_id().await, phone_user_id)
+        .await
+        .unwrap();
+    let statuses: Vec<String> = sqlx::query_scalar("SELECT status FROM match WHERE user_id = $1")
+        .bind(phone_user_id)
+        .fetch_all(app.pool())
+        .await
+        .unwrap();
+    assert_eq!(statuses.len(), 1);
+    assert_ne!(statuses[0], "…");
+}
+
+#[tokio::test]
+async fn merging_a_flagged_user_carries_the_flags_over() {
+    let app = TestApp::new().await;
+    let mut clean = app.client();
+    let clean_id = clean.user_id().await;
+    let mut flagged = app.client();
+    let flagged_id = flagged.user_id().await;
+    db::users::set_trust(app.pool(), flagged_id, 50)
+        .await
+        .unwrap();
+    admin::merge_users(app.pool(), flagged_id, clean_id)
+        .await
+        .unwrap();
+    assert_eq!(
+        db::users::standing(app.pool(), clean_id)
+            .await
+            .unwrap()
+            .trust,
+        50
+    );
+
+    let mut quarantined = app.client();
+    let quarantined_id = quarantined.user_id().await;
+    admin::quarantine_user(app.pool(), quarantined_id)
+        .await
+        .unwrap();
+    admin::merge_users(app.pool(), quarantined_id, clean_id)
+        .await
+        .unwrap();
+    assert!(
+        db::users::standing(app.pool(), clean_id)
+            .await
+            .unwrap()
+            .quarantined
+    );
+}
+
+#[tokio::test]
+async fn quarantining_and_rolling_back_a_ballot_stuffer() {
+    let app = TestApp::new().await;
+    app.add_dogs(4).await;
+    app.client().vote_until_done().await;
+    let honest = ratings(&app).await;
+    let mut stuffer = app.client();
+    stuffer.vote_until_done().await;
+    let stuffer_id = stuffer.user_id().await;
+    let stuffed = ratings(&app).await;
+    let overall = |ratings: &[(String, Option<i64>, i64, i64)]| {
+        ratings
+            .iter()
+            .filter(|(rating_type, ..)| rating_type == "overall")
+            .cloned()
+            .collect::<Vec<_>>()
+    };
+    assert_ne!(overall(&stuffed), overall(&honest));
+
+    // the stuffer voted after everybody else, so without them it's as if they never had
+    admin::quarantine_user(app.pool(), stuffer_id)
+        .await
+        .unwrap();
+    assert_eq!(overall(&ratings(&app).await), overall(&honest));
+
+    admin::release_user(app.pool(), stuffer_id).await.unwrap();
+    assert_eq!(ratings(&app).await, stuffed);
+
+    let votes = admin::roll_back_votes(app.pool(), stuffer_id)
+        .await
+        .unwrap();
+    assert_eq!(votes, 6);
+    assert_eq!(ratings(&app).await, honest);
+
+    assert!(admin::quarantine_user(app.pool(), 9999).await.is_err());
+}
+
+#[tokio::test]
+async fn handling_suspects_as_an_admin() {
+    let app = TestApp::new().await;
+    app.add_dogs(3).await;
+    let mut suspect = app.client();
+    suspect.vote_until_done().await;
+    let suspect_id = suspect.user_id().await;
+    sqlx::query(r#"UPDATE "user" SET trust = 50 WHERE id = $1"#)
+        .bind(suspect_id)
+        .execute(app.pool())
+        .await
+        .unwrap();
+
+    let mut client = app.client();
+    let user_id = client.user_id().await;
+    assert_eq!(client.get("/admin/suspects").await.status, 404);
+
+    make_admin(&app, user_id).await;
+    let response = client.get("/admin/suspects").await;
+    assert_eq!(response.status, 200);
+    assert!(response.body.contains("50%"));
+
+    let response = client
+        .post(&format!("/admin/suspects/{}/quarantine", suspect_id))
+        .await;
+    assert!(response
+        .body
+        .contains(&format!("Quarantined user {}", suspect_id)));
+    assert!(response.body.contains("quarantined"));
+
+    let response = client
+        .post(&format!("/admin/suspects/{}/roll-back", suspect_id))
+        .await;
+    assert!(response
+        .body
+        .contains(&format!("Rolled back 3 votes by user {}", suspect_id)));
+
+    let response = client
+        .post(&format!("/admin/suspects/{}/release", suspect_id))
+        .await;
+    assert!(response.body.contains("Nobody looks suspicious"));
+
+    let actions: Vec<String> = sqlx::query_scalar("SELECT action FROM log ORDER BY id")
+        .fetch_all(app.pool())
+        .await
+        .unwrap();
+    assert_eq!(
+        actions,
+        ["quarantine-user", "roll-back-votes", "release-user"]
+    );
+}
+
+#[tokio::test]
+async fn pruning_leaves_active_users_alone() {
+    let app = TestApp::new().await;
+    app.add_dogs(2).await;
+    let mut voter = app.client();
+    voter.vote_until_done().await;
+    let voter_id = voter.user_id().await;
+    let mut lurker = app.client();
+    lurker.get("/").await;
+    sqlx::query(r#"UPDATE "user" SET created_at = $1"#)
+        .bind(db::days_ago(60))
+        .execute(app.pool())
+        .await
+        .unwrap();
+
+    let report = admin::prune(app.pool(), 30).await.unwrap();
+
+    assert_eq!(report.users, 1);
+    let user_ids: Vec<i64> = sqlx::query_scalar(r#"SELECT id FROM "user""#)
+        .fetch_all(app.pool())
+        .await
+        .unwrap();
+    assert_eq!(user_ids, vec![voter_id]);
+}
+
+#[tokio::test]
+async fn exporting_and_importing_everything() {
+    let app = TestApp::new().await;
+    let dog_ids = app.add_dogs(3).await;
+    let mut client = app.client();
+    client.vote_until_done().await;
+    client
+        .form(
+            Method::PATCH,
+            "/name-dog",
+            &[("dog_id", &dog_ids[0].to_string()), ("new_name", "Bean")],
+        )
+        .await;
+    let user_id = client.user_id().await;
+    sqlx::query(r#"UPDATE "user" SET email = 'bean@example.com' WHERE id = $1"#)
+        .bind(user_id)
+        .execute(app.pool())
+        .await
+        .unwrap();
+
+    let mut exported = vec![];
+    let counts = export::export(app.pool(), &mut exported, false)
+        .await
+        .unwrap();
+    assert_eq!(counts.dogs, 3);
+    assert_eq!(counts.matches, 3);
+
+    let staging = TestApp::new().await;
+    assert_eq!(
+        export::import(staging.pool(), exported.as_slice())
+            .await
+            .unwrap(),
+        counts
+    );
+    let mut reexported = vec![];
+    export::export(staging.pool(), &mut reexported, false)
+        .await
+        .unwrap();
+    // everything but the header's timestamp should round trip
+    let body = |export: &[u8]| {
+        String::from_utf8(export.to_vec())
+            .unwrap()
+            .lines()
+            .skip(1)
+            .collect::<Vec<_>>()
+            .join("\n")
+    };
+    assert_eq!(body(&exported), body(&reexported));
+    assert!(export::import(staging.pool(), exported.as_slice())
+        .await
+        .is_err());
+
+    let mut anonymized = vec![];
+    export::export(app.pool(), &mut anonymized, true)
+        .await
+        .unwrap();
+    let anonymized = String::from_utf8(anonymized).unwrap();
+    assert!(!anonymized.contains("bean@example.com"));
+    assert!(!anonymized.contains("127.0.0.1"));
+}
+
+#[tokio::test]
+async fn importing_a_future_export_version() {
+    let app = TestApp::new().await;
+    let export = format!(
+        "{{\"format\":\"{}\",\"version\":{},\"exported_at\":\"\",\"anonymized\":false}}\n",
+        export::FORMAT,
+        export::VERSION + 1
+    );
+    assert!(export::import(app.pool(), export.as_bytes()).await.is_err());
+}
+
+#[tokio::test]
+async fn backing_up_from_the_admin_page() {
+    let app = TestApp::new().await;
+    // backups are sqlite only
+    if db::Backend::of(app.pool()) != db::Backend::Sqlite {
+        return;
+    }
+    app.add_dogs(2).await;
+    let mut client = app.client();
+    let user_id = client.user_id().await;
+    assert_eq!(client.post("/admin/backups").await.status, 404);
+
+    make_admin(&app, user_id).await;
+    assert!(client
+        .get("/admin/backups")
+        .await
+        .body
+        .contains("No backups yet"));
+    let response = client.post("/admin/backups").await;
+    assert!(response.body.contains("Saved top-doggo-"));
+
+    let backups = backup::list_backups(&app.state.backups_dir).unwrap();
+    assert_eq!(backups.len(), 1);
+    let response = client
+        .get(&format!("/admin/backups/{}", backups[0].file_name))
+        .await;
+    assert_eq!(response.status, 200);
+    assert!(response.body.starts_with("SQLite format 3"));
+    assert_eq!(
+        client
+            .get("/admin/backups/..%2F..%2Fetc%2Fpasswd")
+            .await
+            .status,
+        404
+    );
+}
+
+#[tokio::test]
+async fn restoring_a_backup() {
+    let dir = tempfile::tempdir().unwrap();
+    let database_path = dir.path().join("top-doggo.db");
+    let database_url = format!("sqlite:{}?mode=rwc", database_path.to_string_lossy());
+    let backups_dir = dir.path().join("backups");
+
+    let pool = db::connect(&database_url).await.unwrap();
+    db::migrate(&pool).await.unwrap();
+    sqlx::query("INSERT INTO dog (image_url) VALUES ('/images/1.jpg')")
+        .execute(&pool)
+        .await
+        .unwrap();
+    let snapshot = backup::create_backup(&pool, &backups_dir).await.unwrap();
+    sqlx::query("INSERT INTO dog (image_url) VALUES ('/images/2.jpg')")
+        .execute(&pool)
+        .await
+        .unwrap();
+    pool.close().await;
+
+    let saved_path = backup::restore(
+        &snapshot.path,
+        &backup::database_path(&database_url).unwrap(),
+    )
+    .await
+    .unwrap()
+    .unwrap();
+
+    let count_dogs = |url: String| async move {
+        let pool = db::connect(&url).await.unwrap();
+        let dogs: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM dog")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        pool.close().await;
+        dogs
+    };
+    assert_eq!(count_dogs(database_url.clone()).await, 1);
+    // the database it replaced is kept, just in case
+    assert_eq!(
+        count_dogs(format!("sqlite:{}", saved_path.to_string_lossy())).await,
+        2
+    );
+}
+
+#[tokio::test]
+async fn stats_single_out_dogs_that_get_skipped() {
+    let app = TestApp::new().await;
+    app.add_dogs(2).await;
+    let mut client = app.client();
+    client.get("/").await;
+    client.post("/pick-winner/skip").await;
+    client.post("/pick-winner/tie").await;
+
+    let stats = admin::stats(app.pool()).await.unwrap();
+    assert_eq!(stats.resolved_matches, 1);
+    assert_eq!(stats.ties, 1);
+    // the only pairing was skipped, shown again and then decided, and the skip still counts
+    assert_eq!(stats.skips, 1);
+    assert_eq!(stats.most_skipped.len(), 2);
+    for (_, skips, shown) in &stats.most_skipped {
+        assert_eq!((*skips, *shown), (1, 2));
+    }
+}
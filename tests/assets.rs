@@ -0,0 +1,84 @@
+mod common;
+
+use axum::http::{header, StatusCode};
+use common::TestApp;
+
+/// The hashed url the page links `prefix` with, e.g. `/output.` -> `/output.1a2b3c4d.css`
+fn linked_url<'a>(page: &'a str, prefix: &str) -> &'a str {
+    let start = page.find(prefix).unwrap();
+    let end = start + page[start..].find('"').unwrap();
+    &page[start..end]
+}
+
+#[tokio::test]
+async fn static_files_skip_auth() {
+    let app = TestApp::new().await;
+    let mut client = app.client();
+
+    for uri in ["/output.css", "/favicon.ico", "/js/top-doggo.js"] {
+        assert_eq!(client.get(uri).await.status, StatusCode::OK);
+    }
+    assert_eq!(
+        client.get("/no-such-file.css").await.status,
+        StatusCode::NOT_FOUND
+    );
+
+    assert!(client.cookie.is_none());
+    assert!(client.csrf_token.is_none());
+    let user_count: i64 = sqlx::query_scalar(r#"SELECT COUNT(*) FROM "user""#)
+        .fetch_one(app.pool())
+        .await
+        .unwrap();
+    assert_eq!(user_count, 0);
+}
+
+#[tokio::test]
+async fn hashed_urls_are_cached_for_good() {
+    let app = TestApp::new().await;
+    let mut client = app.client();
+    let page = client.get("/").await.body;
+    let stylesheet = linked_url(&page, "/output.").to_string();
+    assert_ne!(stylesheet, "/output.css");
+
+    let response = client.get(&stylesheet).await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(
+        response.headers[header::CACHE_CONTROL],
+        "public, max-age=31536000, immutable"
+    );
+    let etag = response.headers[header::ETAG].to_str().unwrap().to_string();
+
+    // and anything else gets checked now and then
+    let response = client.get("/output.css").await;
+    assert_eq!(
+        response.headers[header::CACHE_CONTROL],
+        "public, max-age=3600"
+    );
+    assert_eq!(response.headers[header::ETAG], etag.as_str());
+    let response = client.get("/output.00000000.css").await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.headers[header::CACHE_CONTROL], "no-cache");
+
+    client.headers = vec![("If-None-Match", etag.clone())];
+    let response = client.get("/output.css").await;
+    assert_eq!(response.status, StatusCode::NOT_MODIFIED);
+    assert!(response.body.is_empty());
+    assert_eq!(response.headers[header::ETAG], etag.as_str());
+}
+
+#[tokio::test]
+async fn the_stylesheet_comes_compressed() {
+    top_doggo::assets::precompress().unwrap();
+    let app = TestApp::new().await;
+    let mut client = app.client();
+    let original = client.get("/output.css").await.body;
+
+    for encoding in ["br", "gzip"] {
+        client.headers = vec![("Accept-Encoding", encoding.to_string())];
+        let response = client.get("/output.css").await;
+        assert_eq!(response.status, StatusCode::OK);
+        assert_eq!(response.headers[header::CONTENT_ENCODING], encoding);
+        assert_eq!(response.headers[header::VARY], "accept-encoding");
+        assert!(response.body.len() < original.len() / 2);
+    }
+}
@@ -0,0 +1,342 @@
+#![allow(dead_code)]
+
+use axum::{
+    body::Body,
+    http::{header, Method, Request, StatusCode},
+    Router,
+};
+use sqlx::{any::AnyPoolOptions, Any, Pool};
+use std::sync::Arc;
+use tempfile::TempDir;
+use top_doggo::{
+    app, db,
+    mailer::Mailer,
+    rate_limit::{RateLimiter, RateLimits},
+    session_cookie::SessionKeys,
+    AppState,
+};
+use tower::ServiceExt;
+use tower_http::normalize_path::NormalizePath;
+
+/// An empty database for one test: sqlite in memory, or a fresh database on the postgres server
+/// at TEST_DATABASE_URL when that's set
+async fn test_pool() -> Pool<Any> {
+    let Ok(server_url) = std::env::var("TEST_DATABASE_URL") else {
+        // every connection to :memory: gets its own database, so there can only be one
+        let options = AnyPoolOptions::new()
+            .max_connections(1)
+            .idle_timeout(None)
+            .max_lifetime(None);
+        return db::connect_with(options, "sqlite::memory:").await.unwrap();
+    };
+
+    let name = format!("top_doggo_test_{}", uuid::Uuid::new_v4().simple());
+    let server = db::connect_with(AnyPoolOptions::new().max_connections(1), &server_url)
+        .await
+        .unwrap();
+    sqlx::query(&format!("CREATE DATABASE {}", name))
+        .execute(&server)
+        .await
+        .unwrap();
+    server.close().await;
+
+    let (base_url, _) = server_url.rsplit_once('/').unwrap();
+    db::connect_with(
+        AnyPoolOptions::new().max_connections(5),
+        &format!("{}/{}", base_url, name),
+    )
+    .await
+    .unwrap()
+}
+
+pub const TEST_SESSION_KEY: &str = "a session key that's only for the tests";
+
+pub struct TestApp {
+    pub state: AppState,
+    app: NormalizePath<Router>,
+    // kept around so the image directories live as long as the test
+    _dirs: TempDir,
+}
+
+impl TestApp {
+    pub async fn new() -> Self {
+        Self::with_rate_limits(RateLimits::default()).await
+    }
+
+    pub async fn with_rate_limits(rate_limits: RateLimits) -> Self {
+        std::env::set_var("BASE_URL", "http://localhost:3000");
+        std::env::set_var("ADMIN_EMAIL", "admin@example.com");
+
+        let pool = test_pool().await;
+        db::migrate(&pool).await.unwrap();
+
+        let dirs = tempfile::tempdir().unwrap();
+        let images_dir = dirs.path().join("images");
+        let unapproved_dir = dirs.path().join("unapproved");
+        std::fs::create_dir(&images_dir).unwrap();
+        std::fs::create_dir(&unapproved_dir).unwrap();
+
+        let state = AppState {
+            pool,
+            mailer: Mailer::capture(),
+            images_dir,
+            unapproved_dir,
+            image_cache_dir: dirs.path().join("image_cache"),
+            backups_dir: dirs.path().join("backups"),
+            rate_limiter: Arc::new(RateLimiter::new(rate_limits, None)),
+            session_keys: SessionKeys::new(&[TEST_SESSION_KEY]).unwrap(),
+        };
+
+        TestApp {
+            app: app(state.clone()),
+            state,
+            _dirs: dirs,
+        }
+    }
+
+    /// The same app and database, signing cookies with other keys
+    pub fn with_session_keys(mut self, session_keys: SessionKeys) -> Self {
+        self.state.session_keys = session_keys;
+        self.app = app(self.state.clone());
+        self
+    }
+
+    pub fn pool(&self) -> &Pool<Any> {
+        &self.state.pool
+    }
+
+    /// A fresh browser with no cookies
+    pub fn client(&self) -> Client {
+        Client {
+            app: self.app.clone(),
+            pool: self.state.pool.clone(),
+            cookie: None,
+            csrf_token: None,
+            send_csrf_token: true,
+            htmx: false,
+            client_ip: None,
+            headers: vec![],
+        }
+    }
+
+    pub async fn add_dogs(&self, count: usize) -> Vec<i64> {
+        let mut ids = vec![];
+        for _ in 0..count {
+            let id: i64 = sqlx::query_scalar(
+                "INSERT INTO dog (image_url) VALUES ('/images/' || (SELECT COUNT(*) FROM dog) || '.jpg') RETURNING id",
+            )
+            .fetch_one(self.pool())
+            .await
+            .unwrap();
+            ids.push(id);
+        }
+        ids
+    }
+}
+
+pub struct Client {
+    app: NormalizePath<Router>,
+    pool: Pool<Any>,
+    pub cookie: Option<String>,
+    /// From the csrf cookie, kept apart from the session cookie
+    pub csrf_token: Option<String>,
+    /// Copy the csrf token into an X-CSRF-Token header, like the layout's script does
+    pub send_csrf_token: bool,
+    /// Send requests the way htmx does, with an HX-Request header
+    pub htmx: bool,
+    /// Sent as X-Forwarded-For, like the proxy in front of the app does
+    pub client_ip: Option<String>,
+    /// Sent with every request, on top of the ones above
+    pub headers: Vec<(&'static str, String)>,
+}
+
+pub struct TestResponse {
+    pub status: StatusCode,
+    pub headers: axum::http::HeaderMap,
+    pub body: String,
+    /// The body as sent, for anything that isn't text
+    pub bytes: Vec<u8>,
+}
+
+impl Client {
+    pub async fn request(
+        &mut self,
+        method: Method,
+        uri: &str,
+        content_type: Option<&str>,
+        body: Vec<u8>,
+    ) -> TestResponse {
+        let mut request = Request::builder().method(method).uri(uri);
+        let csrf_cookie = self
+            .csrf_token
+            .as_ref()
+            .map(|token| format!("top_doggo_csrf={}", token));
+        let cookies: Vec<&str> = [&self.cookie, &csrf_cookie]
+            .into_iter()
+            .flatten()
+            .map(String::as_str)
+            .collect();
+        if !cookies.is_empty() {
+            request = request.header(header::COOKIE, cookies.join("; "));
+        }
+        if let (Some(token), true) = (&self.csrf_token, self.send_csrf_token) {
+            request = request.header("X-CSRF-Token", token);
+        }
+        if let Some(content_type) = content_type {
+            request = request.header(header::CONTENT_TYPE, content_type);
+        }
+        if self.htmx {
+            request = request.header("HX-Request", "true");
+        }
+        if let Some(client_ip) = &self.client_ip {
+            request = request.header("X-Forwarded-For", client_ip);
+        }
+        for (name, value) in &self.headers {
+            request = request.header(*name, value);
+        }
+        let request = request.body(Body::from(body)).unwrap();
+
+        let response = self.app.clone().oneshot(request).await.unwrap();
+
+        for set_cookie in response.headers().get_all(header::SET_COOKIE) {
+            let cookie = set_cookie.to_str().unwrap().split(';').next().unwrap();
+            match cookie.strip_prefix("top_doggo_csrf=") {
+                Some(token) => self.csrf_token = Some(token.to_string()),
+                None => self.cookie = Some(cookie.to_string()),
+            }
+        }
+
+        let status = response.status();
+        let headers = response.headers().clone();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        TestResponse {
+            status,
+            headers,
+            body: String::from_utf8_lossy(&body).to_string(),
+            bytes: body.to_vec(),
+        }
+    }
+
+    pub async fn get(&mut self, uri: &str) -> TestResponse {
+        self.request(Method::GET, uri, None, vec![]).await
+    }
+
+    pub async fn post(&mut self, uri: &str) -> TestResponse {
+        self.request(Method::POST, uri, None, vec![]).await
+    }
+
+    pub async fn form(
+        &mut self,
+        method: Method,
+        uri: &str,
+        fields: &[(&str, &str)],
+    ) -> TestResponse {
+        let body = fields
+            .iter()
+            .map(|(key, value)| format!("{}={}", key, urlencode(value)))
+            .collect::<Vec<_>>()
+            .join("&");
+        self.request(
+            method,
+            uri,
+            Some("application/x-www-form-urlencoded"),
+            body.into_bytes(),
+        )
+        .await
+    }
+
+    /// `files` are (field name, file name, content type, bytes)
+    pub async fn multipart(
+        &mut self,
+        uri: &str,
+        fields: &[(&str, &str)],
+        files: &[(&str, &str, &str, &[u8])],
+    ) -> TestResponse {
+        let boundary = "----top-doggo-test-boundary";
+        let mut body: Vec<u8> = vec![];
+        for (name, value) in fields {
+            body.extend(
+                format!(
+                    "--{}\r\nContent-Disposition: form-data; name=\"{}\"\r\n\r\n{}\r\n",
+                    boundary, name, value
+                )
+                .into_bytes(),
+            );
+        }
+        for (name, file_name, content_type, bytes) in files {
+            body.extend(format!("--{}\r\nContent-Disposition: form-data; name=\"{}\"; filename=\"{}\"\r\nContent-Type: {}\r\n\r\n", boundary, name, file_name, content_type).into_bytes());
+            body.extend_from_slice(bytes);
+            body.extend(b"\r\n");
+        }
+        body.extend(format!("--{}--\r\n", boundary).into_bytes());
+        let content_type = format!("multipart/form-data; boundary={}", boundary);
+        self.request(Method::POST, uri, Some(&content_type), body)
+            .await
+    }
+
+    /// Votes (with a tie every third pick) until there are no pairings left
+    pub async fn vote_until_done(&mut self) {
+        self.get("/").await;
+        for i in 0.. {
+            let response = if i % 3 == 0 {
+                self.post("/pick-winner/tie").await
+            } else {
+                let dog_id = self.current_dog_a().await;
+                self.post(&format!("/pick-winner/{}", dog_id)).await
+            };
+            if response.body.contains("You've won!") {
+                break;
+            }
+        }
+    }
+
+    /// The left-hand dog of the pairing this client is currently being shown
+    pub async fn current_dog_a(&mut self) -> i64 {
+        let user_id = self.user_id().await;
+        sqlx::query_scalar("SELECT dog_a_id FROM match WHERE user_id = $1 AND status = '…'")
+            .bind(user_id)
+            .fetch_one(&self.pool)
+            .await
+            .unwrap()
+    }
+
+    /// The user behind this client's cookie (makes a request first if there isn't one yet)
+    pub async fn user_id(&mut self) -> i64 {
+        if self.cookie.is_none() {
+            self.get("/dedication").await;
+        }
+        // the cookie is `<token>.<signature>`
+        let token = self
+            .cookie
+            .as_ref()
+            .unwrap()
+            .split(['=', '.'])
+            .nth(1)
+            .unwrap()
+            .to_string();
+        db::sessions::find_user(&self.pool, &token)
+            .await
+            .unwrap()
+            .unwrap()
+    }
+}
+
+fn urlencode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+pub async fn total_xp(pool: &Pool<Any>, user_id: i64) -> i64 {
+    sqlx::query_scalar(r#"SELECT total_xp FROM "user" WHERE id = $1"#)
+        .bind(user_id)
+        .fetch_one(pool)
+        .await
+        .unwrap()
+}
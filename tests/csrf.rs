@@ -0,0 +1,73 @@
+mod common;
+
+use axum::http::{header, StatusCode};
+use common::TestApp;
+
+async fn resolved_matches(app: &TestApp) -> i64 {
+    sqlx::query_scalar("SELECT COUNT(*) FROM match WHERE status <> '…'")
+        .fetch_one(app.pool())
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn pages_hand_out_a_token_for_htmx_to_send_back() {
+    let app = TestApp::new().await;
+    let mut client = app.client();
+
+    let response = client.get("/").await;
+    let csrf_cookie = response
+        .headers
+        .get_all(header::SET_COOKIE)
+        .iter()
+        .map(|cookie| cookie.to_str().unwrap())
+        .find(|cookie| cookie.starts_with("top_doggo_csrf="))
+        .unwrap();
+    // the layout's script has to be able to read it
+    assert!(!csrf_cookie.contains("HttpOnly"));
+    assert!(csrf_cookie.contains("SameSite=Strict"));
+    assert!(response.body.contains("/js/top-doggo."));
+    let script = std::fs::read_to_string("assets/js/top-doggo.js").unwrap();
+    assert!(script.contains("X-CSRF-Token"));
+
+    // and it sticks around
+    let token = client.csrf_token.clone();
+    let response = client.get("/dedication").await;
+    assert!(!response.headers.contains_key(header::SET_COOKIE));
+    assert_eq!(client.csrf_token, token);
+}
+
+#[tokio::test]
+async fn forged_votes_are_turned_away() {
+    let app = TestApp::new().await;
+    app.add_dogs(3).await;
+    let mut client = app.client();
+    client.htmx = true;
+    client.get("/").await;
+
+    client.send_csrf_token = false;
+    let response = client.post("/pick-winner/tie").await;
+    assert_eq!(response.status, StatusCode::FORBIDDEN);
+    assert_eq!(response.headers["hx-retarget"], "#error-toast");
+    assert!(response.body.contains("Refresh it and try again"));
+    assert_eq!(resolved_matches(&app).await, 0);
+
+    client.send_csrf_token = true;
+    assert_eq!(client.post("/pick-winner/tie").await.status, StatusCode::OK);
+    assert_eq!(resolved_matches(&app).await, 1);
+}
+
+#[tokio::test]
+async fn turned_away_visitors_can_try_again() {
+    let app = TestApp::new().await;
+    app.add_dogs(3).await;
+    let mut client = app.client();
+    client.htmx = true;
+
+    // say the page was loaded before there were tokens
+    let response = client.post("/pick-winner/tie").await;
+    assert_eq!(response.status, StatusCode::FORBIDDEN);
+    assert!(client.csrf_token.is_some());
+
+    assert_eq!(client.post("/pick-winner/tie").await.status, StatusCode::OK);
+}
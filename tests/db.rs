@@ -0,0 +1,209 @@
+mod common;
+
+use chrono::NaiveDate;
+use common::TestApp;
+use top_doggo::db::{
+    self,
+    log::{Event, LogFilter},
+    ratings::RatingType,
+    tokens::{self, Purpose},
+};
+
+#[tokio::test]
+async fn magic_links_expire() {
+    let app = TestApp::new().await;
+    let user_id = db::users::create(app.pool(), None).await.unwrap();
+    let token = tokens::create(app.pool(), "dogfan@example.com", user_id, Purpose::LogIn)
+        .await
+        .unwrap();
+
+    let found = tokens::find(app.pool(), &token, Purpose::LogIn)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(found.email, "dogfan@example.com");
+    assert_eq!(found.sender_id, Some(user_id));
+    assert!(tokens::find(app.pool(), &token, Purpose::DeleteAccount)
+        .await
+        .unwrap()
+        .is_none());
+    assert_eq!(
+        tokens::recently_sent(app.pool(), user_id).await.unwrap(),
+        Some("dogfan@example.com".to_string())
+    );
+
+    sqlx::query("UPDATE email_token SET created_at = $1")
+        .bind(db::minutes_ago(tokens::LIFETIME_MINUTES + 1))
+        .execute(app.pool())
+        .await
+        .unwrap();
+    assert!(tokens::find(app.pool(), &token, Purpose::LogIn)
+        .await
+        .unwrap()
+        .is_none());
+    assert_eq!(
+        tokens::recently_sent(app.pool(), user_id).await.unwrap(),
+        None
+    );
+}
+
+#[tokio::test]
+async fn using_a_magic_link_elsewhere_logs_in_the_sender() {
+    let app = TestApp::new().await;
+    let laptop_user_id = db::users::create(app.pool(), None).await.unwrap();
+    let existing_user_id = db::users::create(app.pool(), None).await.unwrap();
+    db::users::sign_up(app.pool(), existing_user_id, "dogfan@example.com", 0)
+        .await
+        .unwrap();
+
+    let token = tokens::create(
+        app.pool(),
+        "dogfan@example.com",
+        laptop_user_id,
+        Purpose::LogIn,
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        tokens::logged_in_user_for_sender(app.pool(), laptop_user_id)
+            .await
+            .unwrap(),
+        None
+    );
+
+    tokens::mark_used(app.pool(), &token).await.unwrap();
+    assert_eq!(
+        tokens::logged_in_user_for_sender(app.pool(), laptop_user_id)
+            .await
+            .unwrap(),
+        Some(existing_user_id)
+    );
+}
+
+#[tokio::test]
+async fn leaderboards_are_highest_first() {
+    let app = TestApp::new().await;
+    let user_id = db::users::create(app.pool(), None).await.unwrap();
+    let dog_ids = app.add_dogs(3).await;
+    for (dog_id, value) in dog_ids.iter().zip([1000, 1200, 900]) {
+        db::ratings::insert_overall(app.pool(), *dog_id, value)
+            .await
+            .unwrap();
+    }
+    db::ratings::insert_personal(app.pool(), user_id, dog_ids[2], 1100)
+        .await
+        .unwrap();
+
+    let overall = db::ratings::leaderboard(app.pool(), RatingType::Overall, user_id)
+        .await
+        .unwrap();
+    let values: Vec<i64> = overall.iter().map(|row| row.value).collect();
+    assert_eq!(values, [1200, 1000, 900]);
+
+    let personal = db::ratings::leaderboard(app.pool(), RatingType::Personal, user_id)
+        .await
+        .unwrap();
+    assert_eq!(personal.len(), 1);
+    assert_eq!(personal[0].value, 1100);
+
+    db::ratings::set(app.pool(), dog_ids[2], RatingType::Overall, user_id, 1300)
+        .await
+        .unwrap();
+    assert_eq!(
+        db::ratings::get(app.pool(), dog_ids[2], RatingType::Overall, user_id)
+            .await
+            .unwrap(),
+        Some(1300)
+    );
+    assert_eq!(
+        db::ratings::get(app.pool(), dog_ids[2], RatingType::Personal, user_id)
+            .await
+            .unwrap(),
+        Some(1100)
+    );
+}
+
+#[tokio::test]
+async fn searching_the_log() {
+    let app = TestApp::new().await;
+    let user_id = db::users::create(app.pool(), None).await.unwrap();
+    let ip = "203.0.113.7".parse().unwrap();
+    db::log::record(
+        app.pool(),
+        &Event::Upload { dog_id: 1 },
+        Some(user_id),
+        Some(ip),
+    )
+    .await
+    .unwrap();
+    db::log::record(
+        app.pool(),
+        &Event::NameDog { dog_id: 1 },
+        Some(user_id),
+        None,
+    )
+    .await
+    .unwrap();
+    db::log::record(app.pool(), &Event::ApproveDog { dog_id: 1 }, None, None)
+        .await
+        .unwrap();
+    sqlx::query("UPDATE log SET created_at = '2024-10-31 23:59:59' WHERE action = 'upload'")
+        .execute(app.pool())
+        .await
+        .unwrap();
+
+    let pool = app.pool();
+    let events = |filter: LogFilter| async move {
+        db::log::search(pool, &filter, 10)
+            .await
+            .unwrap()
+            .iter()
+            .map(|entry| entry.event().unwrap())
+            .collect::<Vec<_>>()
+    };
+
+    assert_eq!(
+        events(LogFilter::default()).await,
+        [
+            Event::ApproveDog { dog_id: 1 },
+            Event::NameDog { dog_id: 1 },
+            Event::Upload { dog_id: 1 }
+        ]
+    );
+    assert_eq!(
+        events(LogFilter {
+            user_id: Some(user_id),
+            action: Some("upload".to_string()),
+            ..Default::default()
+        })
+        .await,
+        [Event::Upload { dog_id: 1 }]
+    );
+    assert_eq!(
+        events(LogFilter {
+            client_ip: Some("203.0.113.7".to_string()),
+            ..Default::default()
+        })
+        .await,
+        [Event::Upload { dog_id: 1 }]
+    );
+    let october_31 = NaiveDate::from_ymd_opt(2024, 10, 31);
+    assert_eq!(
+        events(LogFilter {
+            from: october_31,
+            to: october_31,
+            ..Default::default()
+        })
+        .await,
+        [Event::Upload { dog_id: 1 }]
+    );
+    assert_eq!(
+        events(LogFilter {
+            from: NaiveDate::from_ymd_opt(2024, 11, 1),
+            user_id: Some(user_id),
+            ..Default::default()
+        })
+        .await,
+        [Event::NameDog { dog_id: 1 }]
+    );
+}
@@ -0,0 +1,645 @@
+mod common;
+
+use axum::http::{Method, StatusCode};
+use common::{total_xp, TestApp};
+
+struct CurrentMatch {
+    id: i64,
+    dog_a_id: i64,
+    dog_b_id: i64,
+}
+
+async fn current_match(app: &TestApp, user_id: i64) -> CurrentMatch {
+    let (id, dog_a_id, dog_b_id): (i64, i64, i64) = sqlx::query_as(
+        "SELECT id, dog_a_id, dog_b_id FROM match WHERE user_id = $1 AND status = '…'",
+    )
+    .bind(user_id)
+    .fetch_one(app.pool())
+    .await
+    .unwrap();
+    CurrentMatch {
+        id,
+        dog_a_id,
+        dog_b_id,
+    }
+}
+
+async fn rating(app: &TestApp, dog_id: i64, rating_type: &str) -> i64 {
+    sqlx::query_scalar("SELECT value FROM rating WHERE dog_id = $1 AND type = $2")
+        .bind(dog_id)
+        .bind(rating_type)
+        .fetch_one(app.pool())
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn first_visit_creates_a_user_and_a_match() {
+    let app = TestApp::new().await;
+    app.add_dogs(3).await;
+    let mut client = app.client();
+
+    let response = client.get("/").await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert!(response.body.contains("Pick your favorite"));
+    assert!(client.cookie.is_some());
+
+    let user_id = client.user_id().await;
+    let dog_match = current_match(&app, user_id).await;
+    assert!(response
+        .body
+        .contains(&format!("/pick-winner/{}", dog_match.dog_a_id)));
+    assert!(response
+        .body
+        .contains(&format!("/pick-winner/{}", dog_match.dog_b_id)));
+
+    // coming back shows the same pairing instead of making a new one
+    client.get("/").await;
+    let match_count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM match WHERE user_id = $1")
+        .bind(user_id)
+        .fetch_one(app.pool())
+        .await
+        .unwrap();
+    assert_eq!(match_count, 1);
+}
+
+#[tokio::test]
+async fn picking_a_winner_moves_ratings_and_grants_xp() {
+    let app = TestApp::new().await;
+    app.add_dogs(3).await;
+    let mut client = app.client();
+    client.get("/").await;
+    let user_id = client.user_id().await;
+    let dog_match = current_match(&app, user_id).await;
+
+    let response = client
+        .post(&format!("/pick-winner/{}", dog_match.dog_a_id))
+        .await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert!(response.body.contains(" xp"));
+
+    let status: String = sqlx::query_scalar("SELECT status FROM match WHERE id = $1")
+        .bind(dog_match.id)
+        .fetch_one(app.pool())
+        .await
+        .unwrap();
+    assert_eq!(status, ">");
+
+    for rating_type in ["overall", "personal"] {
+        assert!(rating(&app, dog_match.dog_a_id, rating_type).await > 1000);
+        assert!(rating(&app, dog_match.dog_b_id, rating_type).await < 1000);
+    }
+
+    let (change_a, change_b): (i64, i64) = sqlx::query_as(
+        "SELECT elo_change_overall_a, elo_change_overall_b FROM match WHERE id = $1",
+    )
+    .bind(dog_match.id)
+    .fetch_one(app.pool())
+    .await
+    .unwrap();
+    assert_eq!(
+        change_a,
+        rating(&app, dog_match.dog_a_id, "overall").await - 1000
+    );
+    assert_eq!(
+        change_b,
+        rating(&app, dog_match.dog_b_id, "overall").await - 1000
+    );
+
+    assert!(total_xp(app.pool(), user_id).await > 0);
+
+    // and the board moves on to a new pairing
+    let next_match = current_match(&app, user_id).await;
+    assert_ne!(next_match.id, dog_match.id);
+}
+
+#[tokio::test]
+async fn a_tie_leaves_equal_ratings_alone() {
+    let app = TestApp::new().await;
+    app.add_dogs(2).await;
+    let mut client = app.client();
+    client.get("/").await;
+    let user_id = client.user_id().await;
+    let dog_match = current_match(&app, user_id).await;
+
+    client.post("/pick-winner/tie").await;
+
+    assert_eq!(rating(&app, dog_match.dog_a_id, "overall").await, 1000);
+    assert_eq!(rating(&app, dog_match.dog_b_id, "overall").await, 1000);
+}
+
+#[tokio::test]
+async fn picking_a_dog_that_is_not_in_the_match_does_nothing() {
+    let app = TestApp::new().await;
+    let dog_ids = app.add_dogs(3).await;
+    let mut client = app.client();
+    client.get("/").await;
+    let user_id = client.user_id().await;
+    let dog_match = current_match(&app, user_id).await;
+    let outsider = dog_ids
+        .iter()
+        .find(|id| **id != dog_match.dog_a_id && **id != dog_match.dog_b_id)
+        .unwrap();
+
+    client.post(&format!("/pick-winner/{}", outsider)).await;
+
+    assert_eq!(current_match(&app, user_id).await.id, dog_match.id);
+    assert_eq!(total_xp(app.pool(), user_id).await, 0);
+}
+
+#[tokio::test]
+async fn running_out_of_dogs_shows_the_win_screen() {
+    let app = TestApp::new().await;
+    app.add_dogs(2).await;
+    let mut client = app.client();
+    client.get("/").await;
+
+    let response = client.post("/pick-winner/tie").await;
+
+    assert!(response.body.contains("You've won!"));
+}
+
+#[tokio::test]
+async fn the_board_can_be_played_without_a_mouse() {
+    let app = TestApp::new().await;
+    let dog_ids = app.add_dogs(2).await;
+    sqlx::query("UPDATE dog SET name = 'Biscuit' WHERE id = $1")
+        .bind(dog_ids[0])
+        .execute(app.pool())
+        .await
+        .unwrap();
+    let mut client = app.client();
+
+    let response = client.get("/").await;
+    assert!(response.body.contains(r#"aria-keyshortcuts="ArrowLeft A""#));
+    assert!(response
+        .body
+        .contains(r#"aria-keyshortcuts="ArrowRight D""#));
+    assert!(response.body.contains(r#"aria-keyshortcuts="Space""#));
+    // focus goes back to the heading after a swap
+    assert!(response.body.contains(r#"id="game-board-heading""#));
+    // the photos are described by the dogs' names, when they have one
+    assert!(response.body.contains(r#"alt="Biscuit""#));
+    assert!(response.body.contains(r#"aria-label="Pick Biscuit""#));
+    assert!(response.body.contains(r#"alt="A dog with no name""#));
+
+    let response = client.post("/pick-winner/tie").await;
+    assert!(response.body.contains("You've won!"));
+    assert!(response.body.contains(r#"id="game-board-heading""#));
+}
+
+#[tokio::test]
+async fn xp_gains_are_announced_to_screen_readers() {
+    let app = TestApp::new().await;
+    app.add_dogs(3).await;
+    let mut client = app.client();
+
+    let response = client.get("/").await;
+    assert!(response.body.contains(r#"id="announcer""#));
+    // nothing to announce before the first pick
+    assert!(!response.body.contains("hx-swap-oob"));
+
+    client.htmx = true;
+    let response = client.post("/pick-winner/tie").await;
+    assert!(response.body.contains(r#"hx-swap-oob="innerHTML""#));
+    assert!(response.body.contains("+1 xp."));
+}
+
+#[tokio::test]
+async fn skipping_a_pairing_moves_nothing() {
+    let app = TestApp::new().await;
+    app.add_dogs(3).await;
+    let mut client = app.client();
+    let response = client.get("/").await;
+    assert!(response.body.contains("/pick-winner/skip"));
+    let user_id = client.user_id().await;
+    let dog_match = current_match(&app, user_id).await;
+
+    let response = client.post("/pick-winner/skip").await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert!(!response.body.contains("/undo-pick"));
+    assert_eq!(status(&app, dog_match.id).await, "-");
+    let ratings: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM rating")
+        .fetch_one(app.pool())
+        .await
+        .unwrap();
+    assert_eq!(ratings, 0);
+    assert_eq!(total_xp(app.pool(), user_id).await, 0);
+    assert_ne!(current_match(&app, user_id).await.id, dog_match.id);
+}
+
+#[tokio::test]
+async fn skipped_pairings_come_back_once_the_rest_are_done() {
+    let app = TestApp::new().await;
+    app.add_dogs(3).await;
+    let mut client = app.client();
+    client.get("/").await;
+    let user_id = client.user_id().await;
+    let skipped = current_match(&app, user_id).await;
+    client.post("/pick-winner/skip").await;
+
+    let mut shown = vec![];
+    loop {
+        let dog_match = current_match(&app, user_id).await;
+        shown.push(dog_match.id);
+        let response = client
+            .post(&format!("/pick-winner/{}", dog_match.dog_a_id))
+            .await;
+        if response.body.contains("You've won!") {
+            break;
+        }
+    }
+
+    // the other two pairings first, then the skipped one in the same match as before
+    assert_eq!(shown.len(), 3);
+    assert_eq!(shown[2], skipped.id);
+    assert_eq!(status(&app, skipped.id).await, ">");
+}
+
+#[tokio::test]
+async fn naming_a_dog() {
+    let app = TestApp::new().await;
+    let dog_ids = app.add_dogs(1).await;
+    let mut client = app.client();
+    let user_id = client.user_id().await;
+    let dog_id = dog_ids[0].to_string();
+
+    let response = client
+        .form(
+            Method::PATCH,
+            "/name-dog",
+            &[("dog_id", &dog_id), ("new_name", " Biscuit ")],
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert!(response.body.contains("Biscuit"));
+
+    let (name, namer_id): (String, i64) =
+        sqlx::query_as("SELECT name, namer_id FROM dog WHERE id = $1")
+            .bind(dog_ids[0])
+            .fetch_one(app.pool())
+            .await
+            .unwrap();
+    assert_eq!(name, "Biscuit");
+    assert_eq!(namer_id, user_id);
+    assert_eq!(total_xp(app.pool(), user_id).await, 200);
+
+    // no renaming
+    let response = client
+        .form(
+            Method::PATCH,
+            "/name-dog",
+            &[("dog_id", &dog_id), ("new_name", "Waffles")],
+        )
+        .await;
+    assert!(response.body.contains("Biscuit already has a name, silly."));
+    assert_eq!(total_xp(app.pool(), user_id).await, 200);
+}
+
+#[tokio::test]
+async fn names_must_be_unique_and_not_jeff() {
+    let app = TestApp::new().await;
+    let dog_ids = app.add_dogs(2).await;
+    let mut client = app.client();
+    client.get("/").await;
+
+    client
+        .form(
+            Method::PATCH,
+            "/name-dog",
+            &[("dog_id", &dog_ids[0].to_string()), ("new_name", "Biscuit")],
+        )
+        .await;
+    let response = client
+        .form(
+            Method::PATCH,
+            "/name-dog",
+            &[("dog_id", &dog_ids[1].to_string()), ("new_name", "Biscuit")],
+        )
+        .await;
+    assert!(response.body.contains("something more original"));
+
+    let response = client
+        .form(
+            Method::PATCH,
+            "/name-dog",
+            &[("dog_id", &dog_ids[1].to_string()), ("new_name", "Jeff")],
+        )
+        .await;
+    assert!(response.body.contains("don't name him Jeff"));
+}
+
+#[tokio::test]
+async fn leaderboard_lists_rated_dogs() {
+    let app = TestApp::new().await;
+    app.add_dogs(2).await;
+    let mut client = app.client();
+    client.get("/").await;
+    let user_id = client.user_id().await;
+    let dog_match = current_match(&app, user_id).await;
+    client
+        .post(&format!("/pick-winner/{}", dog_match.dog_b_id))
+        .await;
+
+    let response = client.get("/leaderboard/top/overall").await;
+    assert_eq!(response.status, StatusCode::OK);
+    let winner_image = format!("/img/{}/", dog_match.dog_b_id);
+    let loser_image = format!("/img/{}/", dog_match.dog_a_id);
+    assert!(response.body.find(&winner_image).unwrap() < response.body.find(&loser_image).unwrap());
+}
+
+#[tokio::test]
+async fn always_picking_the_left_dog_gets_flagged() {
+    let app = TestApp::new().await;
+    app.add_dogs(7).await;
+    let mut client = app.client();
+    client.get("/").await;
+    let user_id = client.user_id().await;
+    for _ in 0..20 {
+        // taking a while over each one, so only the side they pick looks off
+        sqlx::query("UPDATE match SET created_at = $1 WHERE user_id = $2 AND status = '…'")
+            .bind(top_doggo::db::minutes_ago(1))
+            .bind(user_id)
+            .execute(app.pool())
+            .await
+            .unwrap();
+        let dog_id = client.current_dog_a().await;
+        client.post(&format!("/pick-winner/{}", dog_id)).await;
+    }
+
+    let trust: i64 = sqlx::query_scalar(r#"SELECT trust FROM "user" WHERE id = $1"#)
+        .bind(user_id)
+        .fetch_one(app.pool())
+        .await
+        .unwrap();
+    assert_eq!(trust, 50);
+    let last_vote_trust: i64 = sqlx::query_scalar(
+        "SELECT trust FROM match WHERE user_id = $1 AND status <> '…' ORDER BY id DESC LIMIT 1",
+    )
+    .bind(user_id)
+    .fetch_one(app.pool())
+    .await
+    .unwrap();
+    assert_eq!(last_vote_trust, trust);
+
+    let reasons: Vec<String> = sqlx::query_scalar(
+        "SELECT details FROM log WHERE action = 'vote-flagged' AND user_id = $1",
+    )
+    .bind(user_id)
+    .fetch_all(app.pool())
+    .await
+    .unwrap();
+    assert!(reasons
+        .iter()
+        .any(|reason| reason.contains("dog on the left")));
+}
+
+#[tokio::test]
+async fn forged_addresses_dont_hide_a_crowded_ip() {
+    let app = TestApp::new().await;
+    app.add_dogs(3).await;
+    let mut clients = vec![];
+    for i in 0..10 {
+        let mut client = app.client();
+        // only the last address, the one the proxy adds, is real
+        client.client_ip = Some(format!("10.0.0.{}, 203.0.113.9", i));
+        client.get("/").await;
+        clients.push(client);
+    }
+    let client = clients.last_mut().unwrap();
+    client.post("/pick-winner/tie").await;
+
+    let user_id = client.user_id().await;
+    let trust: i64 = sqlx::query_scalar(r#"SELECT trust FROM "user" WHERE id = $1"#)
+        .bind(user_id)
+        .fetch_one(app.pool())
+        .await
+        .unwrap();
+    assert_eq!(trust, 70);
+}
+
+#[tokio::test]
+async fn quarantined_votes_only_move_personal_ratings() {
+    let app = TestApp::new().await;
+    app.add_dogs(2).await;
+    let mut client = app.client();
+    client.get("/").await;
+    let user_id = client.user_id().await;
+    top_doggo::admin::quarantine_user(app.pool(), user_id)
+        .await
+        .unwrap();
+    let dog_match = current_match(&app, user_id).await;
+
+    client
+        .post(&format!("/pick-winner/{}", dog_match.dog_a_id))
+        .await;
+
+    assert_eq!(rating(&app, dog_match.dog_a_id, "overall").await, 1000);
+    assert_eq!(rating(&app, dog_match.dog_b_id, "overall").await, 1000);
+    assert!(rating(&app, dog_match.dog_a_id, "personal").await > 1000);
+}
+
+async fn status(app: &TestApp, match_id: i64) -> String {
+    sqlx::query_scalar("SELECT status FROM match WHERE id = $1")
+        .bind(match_id)
+        .fetch_one(app.pool())
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn undoing_a_pick_puts_everything_back() {
+    let app = TestApp::new().await;
+    app.add_dogs(3).await;
+    let mut client = app.client();
+    client.get("/").await;
+    let user_id = client.user_id().await;
+    let dog_match = current_match(&app, user_id).await;
+    let xp_before = total_xp(app.pool(), user_id).await;
+
+    let response = client
+        .post(&format!("/pick-winner/{}", dog_match.dog_a_id))
+        .await;
+    assert!(response.body.contains("/undo-pick"));
+    assert!(total_xp(app.pool(), user_id).await > xp_before);
+
+    let response = client.post("/undo-pick").await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert!(!response.body.contains("/undo-pick"));
+    assert_eq!(status(&app, dog_match.id).await, "…");
+    for rating_type in ["overall", "personal"] {
+        assert_eq!(rating(&app, dog_match.dog_a_id, rating_type).await, 1000);
+        assert_eq!(rating(&app, dog_match.dog_b_id, rating_type).await, 1000);
+    }
+    assert_eq!(total_xp(app.pool(), user_id).await, xp_before);
+
+    // the same pairing is back up, and the one that came after it is gone
+    assert_eq!(current_match(&app, user_id).await.id, dog_match.id);
+    let match_count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM match WHERE user_id = $1")
+        .bind(user_id)
+        .fetch_one(app.pool())
+        .await
+        .unwrap();
+    assert_eq!(match_count, 1);
+
+    // and it can be picked again
+    client
+        .post(&format!("/pick-winner/{}", dog_match.dog_b_id))
+        .await;
+    assert_eq!(status(&app, dog_match.id).await, "<");
+    assert!(rating(&app, dog_match.dog_b_id, "overall").await > 1000);
+}
+
+#[tokio::test]
+async fn a_double_submitted_pick_only_counts_once() {
+    let app = TestApp::new().await;
+    app.add_dogs(3).await;
+    let mut client = app.client();
+    client.get("/").await;
+    let user_id = client.user_id().await;
+    let dog_match = current_match(&app, user_id).await;
+    let xp_before = total_xp(app.pool(), user_id).await;
+
+    // the same pick from two tabs at once
+    let mut other_tab = app.client();
+    other_tab.cookie = client.cookie.clone();
+    other_tab.csrf_token = client.csrf_token.clone();
+    let pick = format!("/pick-winner/{}", dog_match.dog_a_id);
+    tokio::join!(client.post(&pick), other_tab.post(&pick));
+
+    let resolved: i64 =
+        sqlx::query_scalar("SELECT COUNT(*) FROM match WHERE user_id = $1 AND status <> '…'")
+            .bind(user_id)
+            .fetch_one(app.pool())
+            .await
+            .unwrap();
+    assert_eq!(resolved, 1);
+
+    // so undoing it puts everything back
+    client.post("/undo-pick").await;
+    for rating_type in ["overall", "personal"] {
+        assert_eq!(rating(&app, dog_match.dog_a_id, rating_type).await, 1000);
+        assert_eq!(rating(&app, dog_match.dog_b_id, rating_type).await, 1000);
+    }
+    assert_eq!(total_xp(app.pool(), user_id).await, xp_before);
+}
+
+#[tokio::test]
+async fn a_double_tapped_undo_only_takes_back_the_one_pick() {
+    let app = TestApp::new().await;
+    app.add_dogs(3).await;
+    let mut client = app.client();
+    client.get("/").await;
+    let user_id = client.user_id().await;
+    let first = current_match(&app, user_id).await;
+    client
+        .post(&format!("/pick-winner/{}", first.dog_a_id))
+        .await;
+    let after_first = (
+        rating(&app, first.dog_a_id, "overall").await,
+        total_xp(app.pool(), user_id).await,
+    );
+    let second = current_match(&app, user_id).await;
+    let response = client
+        .post(&format!("/pick-winner/{}", second.dog_a_id))
+        .await;
+    assert!(response.body.contains("pick_id"));
+
+    // two tabs at once, then the button again once the first has gone through
+    let mut other_tab = app.client();
+    other_tab.cookie = client.cookie.clone();
+    other_tab.csrf_token = client.csrf_token.clone();
+    let pick_id = second.id.to_string();
+    let undo = [("pick_id", pick_id.as_str())];
+    tokio::join!(
+        client.form(Method::POST, "/undo-pick", &undo),
+        other_tab.form(Method::POST, "/undo-pick", &undo)
+    );
+    client.form(Method::POST, "/undo-pick", &undo).await;
+
+    assert_eq!(status(&app, second.id).await, "…");
+    assert_eq!(status(&app, first.id).await, ">");
+    assert_eq!(
+        (
+            rating(&app, first.dog_a_id, "overall").await,
+            total_xp(app.pool(), user_id).await,
+        ),
+        after_first
+    );
+}
+
+#[tokio::test]
+async fn picks_other_votes_built_on_stay() {
+    let app = TestApp::new().await;
+    app.add_dogs(2).await;
+    let mut client = app.client();
+    client.get("/").await;
+    let user_id = client.user_id().await;
+    let dog_match = current_match(&app, user_id).await;
+    client
+        .post(&format!("/pick-winner/{}", dog_match.dog_a_id))
+        .await;
+
+    // somebody else votes on the same dogs straight after
+    let mut someone_else = app.client();
+    someone_else.get("/").await;
+    let their_match = current_match(&app, someone_else.user_id().await).await;
+    someone_else
+        .post(&format!("/pick-winner/{}", their_match.dog_a_id))
+        .await;
+    let winner_rating = rating(&app, dog_match.dog_a_id, "overall").await;
+    let xp = total_xp(app.pool(), user_id).await;
+
+    client.htmx = true;
+    let response = client.post("/undo-pick").await;
+    assert_eq!(response.status, StatusCode::BAD_REQUEST);
+    assert!(response.body.contains("Too late to undo"));
+    assert_eq!(status(&app, dog_match.id).await, ">");
+    assert_eq!(
+        rating(&app, dog_match.dog_a_id, "overall").await,
+        winner_rating
+    );
+    assert_eq!(total_xp(app.pool(), user_id).await, xp);
+}
+
+#[tokio::test]
+async fn undoing_with_nothing_to_undo_leaves_the_board_alone() {
+    let app = TestApp::new().await;
+    app.add_dogs(3).await;
+    let mut client = app.client();
+    client.get("/").await;
+    let user_id = client.user_id().await;
+    let dog_match = current_match(&app, user_id).await;
+
+    let response = client.post("/undo-pick").await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert!(response
+        .body
+        .contains(&format!("/pick-winner/{}", dog_match.dog_a_id)));
+    assert_eq!(current_match(&app, user_id).await.id, dog_match.id);
+    assert_eq!(total_xp(app.pool(), user_id).await, 0);
+}
+
+#[tokio::test]
+async fn undoing_a_pick_keeps_the_next_pairing_skipped() {
+    let app = TestApp::new().await;
+    app.add_dogs(3).await;
+    let mut client = app.client();
+    client.get("/").await;
+    let user_id = client.user_id().await;
+    let skipped = current_match(&app, user_id).await;
+    client.post("/pick-winner/skip").await;
+
+    // decide the other two, after which the skipped one comes back
+    for _ in 0..2 {
+        let dog_match = current_match(&app, user_id).await;
+        client
+            .post(&format!("/pick-winner/{}", dog_match.dog_a_id))
+            .await;
+    }
+    assert_eq!(current_match(&app, user_id).await.id, skipped.id);
+
+    client.post("/undo-pick").await;
+    assert_ne!(current_match(&app, user_id).await.id, skipped.id);
+    assert_eq!(status(&app, skipped.id).await, "-");
+}
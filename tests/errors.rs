@@ -0,0 +1,47 @@
+mod common;
+
+use axum::http::StatusCode;
+use common::TestApp;
+
+#[tokio::test]
+async fn unknown_pages_get_a_friendly_404() {
+    let app = TestApp::new().await;
+    let mut client = app.client();
+
+    let response = client.get("/no-such-page").await;
+    assert_eq!(response.status, StatusCode::NOT_FOUND);
+    assert!(response.body.contains("Ruh-roh, nothing here"));
+    assert!(response.body.contains("Back to the dog show"));
+    assert!(response.body.contains("id=\"error-toast\""));
+}
+
+#[tokio::test]
+async fn htmx_requests_get_an_error_toast() {
+    let app = TestApp::new().await;
+    let mut client = app.client();
+    client.htmx = true;
+
+    let response = client.get("/no-such-page").await;
+    assert_eq!(response.status, StatusCode::NOT_FOUND);
+    assert_eq!(response.headers["hx-retarget"], "#error-toast");
+    assert!(response.body.contains("role=\"alert\""));
+    assert!(response
+        .body
+        .contains("We couldn't find what you were looking for."));
+    assert!(!response.body.contains("<html"));
+}
+
+#[tokio::test]
+async fn database_trouble_gets_an_error_page() {
+    let app = TestApp::new().await;
+    let mut client = app.client();
+    assert_eq!(client.get("/").await.status, StatusCode::OK);
+
+    app.pool().close().await;
+
+    let response = client.get("/").await;
+    assert_eq!(response.status, StatusCode::SERVICE_UNAVAILABLE);
+    assert!(response.body.contains("try again in a moment"));
+    // the cause stays in the logs
+    assert!(!response.body.to_lowercase().contains("pool"));
+}
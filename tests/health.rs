@@ -0,0 +1,20 @@
+mod common;
+
+use axum::http::StatusCode;
+use common::TestApp;
+
+#[tokio::test]
+async fn probes_do_not_create_users() {
+    let app = TestApp::new().await;
+    let mut client = app.client();
+
+    assert_eq!(client.get("/healthz").await.status, StatusCode::OK);
+    assert_eq!(client.get("/readyz").await.status, StatusCode::OK);
+
+    assert!(client.cookie.is_none());
+    let user_count: i64 = sqlx::query_scalar(r#"SELECT COUNT(*) FROM "user""#)
+        .fetch_one(app.pool())
+        .await
+        .unwrap();
+    assert_eq!(user_count, 0);
+}
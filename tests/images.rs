@@ -0,0 +1,128 @@
+mod common;
+
+use axum::http::{header, StatusCode};
+use common::TestApp;
+use image::{ImageFormat, RgbImage};
+
+/// A dog whose photo is a `width` by `height` JPEG
+async fn dog_with_photo(app: &TestApp, width: u32, height: u32) -> i64 {
+    let dog_id = app.add_dogs(1).await[0];
+    let image_url: String = sqlx::query_scalar("SELECT image_url FROM dog WHERE id = $1")
+        .bind(dog_id)
+        .fetch_one(app.pool())
+        .await
+        .unwrap();
+    let photo = RgbImage::from_fn(width, height, |x, y| image::Rgb([x as u8, y as u8, 128]));
+    let file_name = image_url.trim_start_matches("/images/");
+    photo
+        .save_with_format(app.state.images_dir.join(file_name), ImageFormat::Jpeg)
+        .unwrap();
+    dog_id
+}
+
+#[tokio::test]
+async fn photos_come_resized_in_the_format_the_browser_takes() {
+    let app = TestApp::new().await;
+    let dog_id = dog_with_photo(&app, 400, 300).await;
+    let mut client = app.client();
+
+    for (accept, content_type, format) in [
+        ("image/webp,*/*", "image/webp", ImageFormat::WebP),
+        ("*/*", "image/jpeg", ImageFormat::Jpeg),
+        ("image/avif,image/webp,*/*", "image/avif", ImageFormat::Avif),
+    ] {
+        client.headers = vec![("Accept", accept.to_string())];
+        let response = client.get(&format!("/img/{}/160", dog_id)).await;
+        assert_eq!(response.status, StatusCode::OK);
+        assert_eq!(response.headers[header::CONTENT_TYPE], content_type);
+        assert_eq!(response.headers[header::VARY], "accept");
+        assert_eq!(image::guess_format(&response.bytes).unwrap(), format);
+        if format != ImageFormat::Avif {
+            let resized = image::load_from_memory(&response.bytes).unwrap();
+            assert_eq!((resized.width(), resized.height()), (160, 160));
+        }
+    }
+
+    // square, and never bigger than the original
+    client.headers = vec![("Accept", "image/webp".to_string())];
+    let response = client.get(&format!("/img/{}/960", dog_id)).await;
+    assert_eq!(response.status, StatusCode::OK);
+    let resized = image::load_from_memory(&response.bytes).unwrap();
+    assert_eq!((resized.width(), resized.height()), (300, 300));
+
+    // copies are kept for next time
+    assert!(app
+        .state
+        .image_cache_dir
+        .join(format!("{}-160.webp", dog_id))
+        .exists());
+
+    // and nobody got a session out of it
+    assert!(client.cookie.is_none());
+}
+
+#[tokio::test]
+async fn only_known_sizes_of_approved_dogs() {
+    let app = TestApp::new().await;
+    let dog_id = dog_with_photo(&app, 50, 50).await;
+    let mut client = app.client();
+
+    assert_eq!(
+        client.get(&format!("/img/{}/161", dog_id)).await.status,
+        StatusCode::NOT_FOUND
+    );
+    assert_eq!(
+        client.get(&format!("/img/{}/160", dog_id + 1)).await.status,
+        StatusCode::NOT_FOUND
+    );
+
+    sqlx::query("UPDATE dog SET approved = FALSE WHERE id = $1")
+        .bind(dog_id)
+        .execute(app.pool())
+        .await
+        .unwrap();
+    assert_eq!(
+        client.get(&format!("/img/{}/160", dog_id)).await.status,
+        StatusCode::NOT_FOUND
+    );
+}
+
+#[tokio::test]
+async fn photos_that_cant_be_resized_fall_back_to_the_original() {
+    let app = TestApp::new().await;
+    let dog_id = app.add_dogs(1).await[0];
+    let image_url: String = sqlx::query_scalar("SELECT image_url FROM dog WHERE id = $1")
+        .bind(dog_id)
+        .fetch_one(app.pool())
+        .await
+        .unwrap();
+    std::fs::write(
+        app.state
+            .images_dir
+            .join(image_url.trim_start_matches("/images/")),
+        b"not a photo",
+    )
+    .unwrap();
+
+    let response = app.client().get(&format!("/img/{}/320", dog_id)).await;
+    assert_eq!(response.status, StatusCode::TEMPORARY_REDIRECT);
+    assert_eq!(response.headers[header::LOCATION], image_url.as_str());
+}
+
+#[tokio::test]
+async fn pages_link_the_resized_copies() {
+    let app = TestApp::new().await;
+    let dog_ids = app.add_dogs(2).await;
+    let mut client = app.client();
+
+    let board = client.get("/").await.body;
+    assert!(board.contains(&format!("/img/{}/320 320w", dog_ids[0])));
+    assert!(board.contains("sizes=\""));
+    assert!(board.contains("width=\"640\""));
+    assert!(!board.contains("src=\"/images/"));
+
+    client.vote_until_done().await;
+    let leaderboard = client.get("/leaderboard/top/overall").await.body;
+    assert!(leaderboard.contains(&format!("/img/{}/160 160w", dog_ids[1])));
+    assert!(leaderboard.contains("loading=\"lazy\""));
+}
@@ -0,0 +1,268 @@
+mod common;
+
+use axum::http::{header, Method, StatusCode};
+use common::{total_xp, TestApp};
+use top_doggo::{db, routers::doggo::elo::recompute_ratings};
+
+fn magic_link_path(body: &str) -> String {
+    let start = body.find("/login?token=").unwrap();
+    let end = start + body[start..].find('"').unwrap();
+    body[start..end].to_string()
+}
+
+#[tokio::test]
+async fn signing_up_with_a_magic_link() {
+    let app = TestApp::new().await;
+    let mut client = app.client();
+    let user_id = client.user_id().await;
+
+    let response = client
+        .form(
+            Method::POST,
+            "/send-magic-link",
+            &[("email_address", "dogfan@example.com")],
+        )
+        .await;
+    assert!(response.body.contains("Email sent to dogfan@example.com"));
+
+    let emails = app.state.mailer.captured();
+    assert_eq!(emails.len(), 1);
+    assert_eq!(emails[0].to, "dogfan@example.com");
+
+    let response = client.get(&magic_link_path(&emails[0].body)).await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert!(response.body.contains("Successfully verified your email!"));
+
+    let email: Option<String> = sqlx::query_scalar(r#"SELECT email FROM "user" WHERE id = $1"#)
+        .bind(user_id)
+        .fetch_one(app.pool())
+        .await
+        .unwrap();
+    assert_eq!(email.as_deref(), Some("dogfan@example.com"));
+    assert_eq!(total_xp(app.pool(), user_id).await, 2000);
+
+    let response = client.get("/me").await;
+    assert!(response
+        .body
+        .contains("logged in with the email dogfan@example.com"));
+}
+
+#[tokio::test]
+async fn logging_in_on_another_device() {
+    let app = TestApp::new().await;
+    let mut phone = app.client();
+    let phone_user_id = phone.user_id().await;
+    phone
+        .form(
+            Method::POST,
+            "/send-magic-link",
+            &[("email_address", "dogfan@example.com")],
+        )
+        .await;
+    phone
+        .get(&magic_link_path(&app.state.mailer.captured()[0].body))
+        .await;
+
+    let mut laptop = app.client();
+    let laptop_user_id = laptop.user_id().await;
+    assert_ne!(laptop_user_id, phone_user_id);
+    laptop
+        .form(
+            Method::POST,
+            "/send-magic-link",
+            &[("email_address", "dogfan@example.com")],
+        )
+        .await;
+
+    let response = laptop
+        .get(&magic_link_path(&app.state.mailer.captured()[1].body))
+        .await;
+    assert!(response.headers.contains_key(header::SET_COOKIE));
+    assert_eq!(laptop.user_id().await, phone_user_id);
+}
+
+#[tokio::test]
+async fn bogus_magic_links_are_rejected() {
+    let app = TestApp::new().await;
+    let mut client = app.client();
+
+    let response = client.get("/login?token=not-a-real-token").await;
+
+    assert_eq!(response.status, StatusCode::TEMPORARY_REDIRECT);
+    assert_eq!(
+        response.headers.get(header::LOCATION).unwrap(),
+        "/sorry?reason=expired_or_does_not_exist"
+    );
+}
+
+#[tokio::test]
+async fn invalid_email_addresses_are_rejected() {
+    let app = TestApp::new().await;
+    let mut client = app.client();
+    client.get("/me").await;
+
+    let response = client
+        .form(
+            Method::POST,
+            "/send-magic-link",
+            &[("email_address", "not an email")],
+        )
+        .await;
+
+    assert!(response.body.contains("Invalid Email"));
+    assert!(app.state.mailer.captured().is_empty());
+}
+
+async fn sign_up(app: &TestApp, client: &mut common::Client, email: &str) {
+    client
+        .form(
+            Method::POST,
+            "/send-magic-link",
+            &[("email_address", email)],
+        )
+        .await;
+    let body = app.state.mailer.captured().last().unwrap().body.clone();
+    client.get(&magic_link_path(&body)).await;
+}
+
+fn delete_link_path(body: &str) -> String {
+    let start = body.find("/me/delete?token=").unwrap();
+    let end = start + body[start..].find('"').unwrap();
+    body[start..end].to_string()
+}
+
+#[tokio::test]
+async fn downloading_my_data() {
+    let app = TestApp::new().await;
+    app.add_dogs(3).await;
+    let mut client = app.client();
+    client.vote_until_done().await;
+    sign_up(&app, &mut client, "dogfan@example.com").await;
+
+    let response = client.get("/me/data").await;
+
+    assert_eq!(response.status, StatusCode::OK);
+    assert!(response.headers[header::CONTENT_DISPOSITION]
+        .to_str()
+        .unwrap()
+        .starts_with("attachment"));
+    let data: serde_json::Value = serde_json::from_str(&response.body).unwrap();
+    assert_eq!(data["user"]["email"], "dogfan@example.com");
+    assert_eq!(data["matches"].as_array().unwrap().len(), 3);
+    assert!(!data["personal_ratings"].as_array().unwrap().is_empty());
+    assert_eq!(data["magic_links"][0]["purpose"], "log-in");
+    assert!(!response.body.contains("token"));
+}
+
+#[tokio::test]
+async fn deleting_an_account_with_a_magic_link() {
+    let app = TestApp::new().await;
+    app.add_dogs(3).await;
+    let mut keeper = app.client();
+    keeper.vote_until_done().await;
+    let mut leaver = app.client();
+    leaver.vote_until_done().await;
+    let leaver_id = leaver.user_id().await;
+    sign_up(&app, &mut leaver, "leaver@example.com").await;
+
+    // an account with an email can't skip the email
+    leaver
+        .form(Method::POST, "/me/delete/confirm", &[("token", "")])
+        .await;
+    assert_eq!(leaver.user_id().await, leaver_id);
+
+    leaver.form(Method::POST, "/me/delete", &[]).await;
+    let email = app.state.mailer.captured().last().unwrap().clone();
+    assert_eq!(email.to, "leaver@example.com");
+    let link = delete_link_path(&email.body);
+    // a delete link isn't a log in link
+    let response = app
+        .client()
+        .get(&link.replace("/me/delete", "/login"))
+        .await;
+    assert_eq!(response.status, StatusCode::TEMPORARY_REDIRECT);
+
+    // following the link only asks
+    let response = leaver.get(&link).await;
+    assert!(response.body.contains("Delete your account?"));
+    let users: i64 = sqlx::query_scalar(r#"SELECT COUNT(*) FROM "user" WHERE id = $1"#)
+        .bind(leaver_id)
+        .fetch_one(app.pool())
+        .await
+        .unwrap();
+    assert_eq!(users, 1);
+
+    let token = link.split('=').nth(1).unwrap();
+    let response = leaver
+        .form(Method::POST, "/me/delete/confirm", &[("token", token)])
+        .await;
+    assert!(response.body.contains("Your account has been deleted."));
+
+    let users: i64 = sqlx::query_scalar(r#"SELECT COUNT(*) FROM "user" WHERE id = $1"#)
+        .bind(leaver_id)
+        .fetch_one(app.pool())
+        .await
+        .unwrap();
+    assert_eq!(users, 0);
+    let matches: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM match WHERE user_id = $1")
+        .bind(leaver_id)
+        .fetch_one(app.pool())
+        .await
+        .unwrap();
+    assert_eq!(matches, 0);
+    let mentions: i64 = sqlx::query_scalar(
+        r"SELECT COUNT(*) FROM log WHERE user_id = $1 OR details LIKE $2 ESCAPE '\'",
+    )
+    .bind(leaver_id)
+    .bind(db::containing("leaver@example.com"))
+    .fetch_one(app.pool())
+    .await
+    .unwrap();
+    assert_eq!(mentions, 0);
+
+    // the overall ratings are what the keeper's votes alone would give
+    let ratings = || async {
+        sqlx::query_as::<_, (i64, i64)>(
+            "SELECT dog_id, value FROM rating WHERE type = 'overall' ORDER BY dog_id",
+        )
+        .fetch_all(app.pool())
+        .await
+        .unwrap()
+    };
+    let after_deleting = ratings().await;
+    let mut conn = app.pool().acquire().await.unwrap();
+    recompute_ratings(&mut conn, None).await.unwrap();
+    drop(conn);
+    assert_eq!(ratings().await, after_deleting);
+
+    // and the leaver's browser starts over as somebody new
+    leaver.get("/me").await;
+    assert_ne!(leaver.user_id().await, leaver_id);
+}
+
+#[tokio::test]
+async fn deleting_an_anonymous_account() {
+    let app = TestApp::new().await;
+    app.add_dogs(2).await;
+    let mut client = app.client();
+    client.vote_until_done().await;
+    let user_id = client.user_id().await;
+
+    let response = client.form(Method::POST, "/me/delete", &[]).await;
+    assert!(response.body.contains("Delete your account?"));
+    assert!(app.state.mailer.captured().is_empty());
+
+    client.form(Method::POST, "/me/delete/confirm", &[]).await;
+
+    let users: i64 = sqlx::query_scalar(r#"SELECT COUNT(*) FROM "user" WHERE id = $1"#)
+        .bind(user_id)
+        .fetch_one(app.pool())
+        .await
+        .unwrap();
+    assert_eq!(users, 0);
+    let overall: Vec<i64> = sqlx::query_scalar("SELECT value FROM rating WHERE type = 'overall'")
+        .fetch_all(app.pool())
+        .await
+        .unwrap();
+    assert!(overall.iter().all(|rating| *rating == 1000));
+}
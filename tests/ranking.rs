@@ -0,0 +1,286 @@
+mod common;
+
+use axum::http::Method;
+use common::{total_xp, Client, TestApp};
+use top_doggo::admin::{self, export};
+
+async fn current_ranking(app: &TestApp, user_id: i64) -> (i64, Vec<i64>) {
+    let ranking_id: i64 =
+        sqlx::query_scalar("SELECT id FROM ranking WHERE user_id = $1 AND status = '…'")
+            .bind(user_id)
+            .fetch_one(app.pool())
+            .await
+            .unwrap();
+    let dog_ids: Vec<i64> = sqlx::query_scalar(
+        "SELECT dog_id FROM ranking_place WHERE ranking_id = $1 ORDER BY position",
+    )
+    .bind(ranking_id)
+    .fetch_all(app.pool())
+    .await
+    .unwrap();
+    (ranking_id, dog_ids)
+}
+
+async fn submit(client: &mut Client, ranking_id: i64, order: &[i64]) -> String {
+    let order = order
+        .iter()
+        .map(i64::to_string)
+        .collect::<Vec<_>>()
+        .join(",");
+    client
+        .form(
+            Method::POST,
+            "/rank",
+            &[("ranking_id", &ranking_id.to_string()), ("order", &order)],
+        )
+        .await
+        .body
+}
+
+async fn count(app: &TestApp, query: &str) -> i64 {
+    sqlx::query_scalar(query)
+        .fetch_one(app.pool())
+        .await
+        .unwrap()
+}
+
+async fn overall_rating(app: &TestApp, dog_id: i64) -> i64 {
+    sqlx::query_scalar("SELECT value FROM rating WHERE dog_id = $1 AND type = 'overall'")
+        .bind(dog_id)
+        .fetch_one(app.pool())
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn a_ranking_counts_as_a_win_for_every_dog_over_the_ones_below_it() {
+    let app = TestApp::new().await;
+    app.add_dogs(4).await;
+    let mut client = app.client();
+
+    let response = client.get("/rank").await;
+    assert!(response.body.contains("Rank your favorites"));
+    let user_id = client.user_id().await;
+    let (ranking_id, mut order) = current_ranking(&app, user_id).await;
+    assert_eq!(order.len(), 4);
+    order.reverse();
+
+    let body = submit(&mut client, ranking_id, &order).await;
+    assert!(body.contains("xp."));
+
+    let matches: Vec<(i64, i64, String)> = sqlx::query_as(
+        "SELECT dog_a_id, dog_b_id, status FROM match WHERE user_id = $1 AND ranking_id = $2",
+    )
+    .bind(user_id)
+    .bind(ranking_id)
+    .fetch_all(app.pool())
+    .await
+    .unwrap();
+    assert_eq!(matches.len(), 6);
+    let place = |dog_id: i64| order.iter().position(|id| *id == dog_id).unwrap();
+    for (dog_a_id, dog_b_id, status) in matches {
+        match status.as_str() {
+            ">" => assert!(place(dog_a_id) < place(dog_b_id)),
+            "<" => assert!(place(dog_b_id) < place(dog_a_id)),
+            _ => panic!("{} isn't a win", status),
+        }
+    }
+
+    assert!(overall_rating(&app, order[0]).await > 1000);
+    assert!(overall_rating(&app, order[3]).await < 1000);
+    assert!(total_xp(app.pool(), user_id).await > 0);
+
+    let places: Vec<(i64, i64)> = sqlx::query_as(
+        "SELECT dog_id, place FROM ranking_place WHERE ranking_id = $1 ORDER BY place",
+    )
+    .bind(ranking_id)
+    .fetch_all(app.pool())
+    .await
+    .unwrap();
+    assert_eq!(
+        places,
+        order
+            .iter()
+            .enumerate()
+            .map(|(i, dog_id)| (*dog_id, i as i64 + 1))
+            .collect::<Vec<_>>()
+    );
+}
+
+#[tokio::test]
+async fn picking_how_many_dogs_to_rank() {
+    let app = TestApp::new().await;
+    app.add_dogs(6).await;
+    let mut client = app.client();
+    let user_id = client.user_id().await;
+
+    client.get("/rank?size=3").await;
+    let (first_ranking_id, dog_ids) = current_ranking(&app, user_id).await;
+    assert_eq!(dog_ids.len(), 3);
+
+    // coming back shows the same dogs
+    client.get("/rank").await;
+    assert_eq!(current_ranking(&app, user_id).await.0, first_ranking_id);
+
+    client.get("/rank?size=9").await;
+    let (_, dog_ids) = current_ranking(&app, user_id).await;
+    assert_eq!(dog_ids.len(), 5);
+    assert_eq!(count(&app, "SELECT COUNT(*) FROM ranking").await, 1);
+}
+
+/// The dog ids on the ranking board, in the order they're shown
+fn shown_order(body: &str) -> Vec<i64> {
+    body.split("data-dog-id=\"")
+        .skip(1)
+        .map(|rest| rest[..rest.find('"').unwrap()].parse().unwrap())
+        .collect()
+}
+
+#[tokio::test]
+async fn rankings_are_shown_in_the_order_they_were_shuffled_into() {
+    let app = TestApp::new().await;
+    app.add_dogs(5).await;
+    let mut client = app.client();
+
+    let body = client.get("/rank?size=5").await.body;
+    let user_id = client.user_id().await;
+    let (ranking_id, dog_ids) = current_ranking(&app, user_id).await;
+    assert_eq!(shown_order(&body), dog_ids);
+
+    // the order sticks to the positions, not to the dogs' ids
+    for (i, dog_id) in dog_ids.iter().rev().enumerate() {
+        sqlx::query("UPDATE ranking_place SET position = $1 WHERE ranking_id = $2 AND dog_id = $3")
+            .bind(i as i64 + 1)
+            .bind(ranking_id)
+            .bind(dog_id)
+            .execute(app.pool())
+            .await
+            .unwrap();
+    }
+    let body = client.get("/rank").await.body;
+    let mut reversed = dog_ids.clone();
+    reversed.reverse();
+    assert_eq!(shown_order(&body), reversed);
+}
+
+#[tokio::test]
+async fn rankings_that_leave_out_a_dog_or_come_twice_do_nothing() {
+    let app = TestApp::new().await;
+    app.add_dogs(3).await;
+    let mut client = app.client();
+    client.get("/rank").await;
+    let user_id = client.user_id().await;
+    let (ranking_id, order) = current_ranking(&app, user_id).await;
+
+    submit(&mut client, ranking_id, &order[..2]).await;
+    submit(&mut client, ranking_id, &[order[0], order[0], order[1]]).await;
+    assert_eq!(count(&app, "SELECT COUNT(*) FROM match").await, 0);
+
+    submit(&mut client, ranking_id, &order).await;
+    let xp = total_xp(app.pool(), user_id).await;
+    submit(&mut client, ranking_id, &order).await;
+    assert_eq!(count(&app, "SELECT COUNT(*) FROM match").await, 3);
+    assert_eq!(total_xp(app.pool(), user_id).await, xp);
+}
+
+#[tokio::test]
+async fn rankings_only_use_pairings_the_user_has_not_voted_on() {
+    let app = TestApp::new().await;
+    app.add_dogs(3).await;
+    let mut client = app.client();
+    client.get("/").await;
+    client.post("/pick-winner/tie").await;
+
+    let response = client.get("/rank").await;
+
+    assert!(response.body.contains("You've ranked every dog you can!"));
+    assert_eq!(count(&app, "SELECT COUNT(*) FROM ranking").await, 0);
+}
+
+#[tokio::test]
+async fn pairings_voted_on_since_the_ranking_was_put_up_are_left_alone() {
+    let app = TestApp::new().await;
+    app.add_dogs(3).await;
+    let mut client = app.client();
+    client.get("/rank").await;
+    let user_id = client.user_id().await;
+    let (ranking_id, order) = current_ranking(&app, user_id).await;
+    client.get("/").await;
+    client.post("/pick-winner/tie").await;
+
+    // the tie, and the pairing put up after it
+    assert_eq!(count(&app, "SELECT COUNT(*) FROM match").await, 2);
+
+    submit(&mut client, ranking_id, &order).await;
+
+    assert_eq!(count(&app, "SELECT COUNT(*) FROM match").await, 3);
+    assert_eq!(
+        count(
+            &app,
+            "SELECT COUNT(*) FROM match WHERE ranking_id IS NOT NULL"
+        )
+        .await,
+        1
+    );
+}
+
+#[tokio::test]
+async fn rankings_cannot_be_undone_one_pair_at_a_time() {
+    let app = TestApp::new().await;
+    app.add_dogs(3).await;
+    let mut client = app.client();
+    client.get("/rank").await;
+    let user_id = client.user_id().await;
+    let (ranking_id, order) = current_ranking(&app, user_id).await;
+    submit(&mut client, ranking_id, &order).await;
+
+    client.post("/undo-pick").await;
+
+    assert_eq!(
+        count(
+            &app,
+            "SELECT COUNT(*) FROM match WHERE status IN ('>', '<', '=')"
+        )
+        .await,
+        3
+    );
+}
+
+#[tokio::test]
+async fn rankings_go_along_with_exports_and_deleted_users() {
+    let app = TestApp::new().await;
+    app.add_dogs(5).await;
+    let mut client = app.client();
+    client.get("/rank?size=3").await;
+    let user_id = client.user_id().await;
+    let (ranking_id, order) = current_ranking(&app, user_id).await;
+    submit(&mut client, ranking_id, &order).await;
+    // the one that comes up next isn't submitted yet
+    client.get("/rank?size=3").await;
+
+    let mut exported = vec![];
+    let counts = export::export(app.pool(), &mut exported, false)
+        .await
+        .unwrap();
+    assert_eq!(counts.rankings, 2);
+    assert_eq!(counts.matches, 3);
+    let staging = TestApp::new().await;
+    assert_eq!(
+        export::import(staging.pool(), exported.as_slice())
+            .await
+            .unwrap(),
+        counts
+    );
+    assert_eq!(
+        count(&staging, "SELECT COUNT(*) FROM ranking_place").await,
+        count(&app, "SELECT COUNT(*) FROM ranking_place").await
+    );
+    assert_eq!(
+        current_ranking(&staging, user_id).await,
+        current_ranking(&app, user_id).await
+    );
+
+    admin::delete_user(app.pool(), user_id).await.unwrap();
+    assert_eq!(count(&app, "SELECT COUNT(*) FROM ranking").await, 0);
+    assert_eq!(count(&app, "SELECT COUNT(*) FROM ranking_place").await, 0);
+}
@@ -0,0 +1,136 @@
+mod common;
+
+use axum::http::StatusCode;
+use common::TestApp;
+use top_doggo::rate_limit::{Action, Budget, Limits, RateLimiter, RateLimits};
+
+fn votes_limited_to(user: Budget, ip: Budget) -> RateLimits {
+    RateLimits {
+        vote: Limits { user, ip },
+        ..RateLimits::unlimited()
+    }
+}
+
+async fn resolved_matches(app: &TestApp) -> i64 {
+    sqlx::query_scalar("SELECT COUNT(*) FROM match WHERE status <> '…'")
+        .fetch_one(app.pool())
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn voting_too_fast_gets_a_toast() {
+    let app =
+        TestApp::with_rate_limits(votes_limited_to(Budget::new(3, 60), Budget::UNLIMITED)).await;
+    app.add_dogs(5).await;
+    let mut client = app.client();
+    client.htmx = true;
+    client.get("/").await;
+
+    for _ in 0..3 {
+        assert_eq!(client.post("/pick-winner/tie").await.status, StatusCode::OK);
+    }
+    let response = client.post("/pick-winner/tie").await;
+    assert_eq!(response.status, StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(response.headers["hx-retarget"], "#error-toast");
+    assert_eq!(response.headers["retry-after"], "20");
+    assert!(response.body.contains("Slow down there!"));
+    assert_eq!(resolved_matches(&app).await, 3);
+
+    // other pages, and other people, aren't held up
+    assert_eq!(client.get("/dedication").await.status, StatusCode::OK);
+    let mut someone_else = app.client();
+    someone_else.get("/").await;
+    assert_eq!(
+        someone_else.post("/pick-winner/tie").await.status,
+        StatusCode::OK
+    );
+}
+
+#[tokio::test]
+async fn fresh_cookies_share_their_ip_address_budget() {
+    let app =
+        TestApp::with_rate_limits(votes_limited_to(Budget::UNLIMITED, Budget::new(2, 60))).await;
+    app.add_dogs(3).await;
+
+    let mut statuses = vec![];
+    for _ in 0..3 {
+        let mut client = app.client();
+        client.client_ip = Some("203.0.113.7".to_string());
+        client.get("/").await;
+        statuses.push(client.post("/pick-winner/tie").await.status);
+    }
+    assert_eq!(
+        statuses,
+        [
+            StatusCode::OK,
+            StatusCode::OK,
+            StatusCode::TOO_MANY_REQUESTS
+        ]
+    );
+
+    let mut elsewhere = app.client();
+    elsewhere.client_ip = Some("198.51.100.1".to_string());
+    elsewhere.get("/").await;
+    assert_eq!(
+        elsewhere.post("/pick-winner/tie").await.status,
+        StatusCode::OK
+    );
+}
+
+#[tokio::test]
+async fn saved_buckets_survive_a_restart() {
+    let app = TestApp::new().await;
+    let limits = votes_limited_to(Budget::new(1, 60 * 60), Budget::UNLIMITED);
+
+    let limiter = RateLimiter::new(limits.clone(), Some(app.pool().clone()));
+    assert!(limiter.check(Action::Vote, Some(1), None).await.is_ok());
+    assert!(limiter.check(Action::Vote, Some(1), None).await.is_err());
+
+    let restarted = RateLimiter::new(limits.clone(), Some(app.pool().clone()));
+    assert!(restarted.check(Action::Vote, Some(1), None).await.is_err());
+    assert!(restarted.check(Action::Vote, Some(2), None).await.is_ok());
+
+    // without saving, a restart starts everybody over
+    let forgetful = RateLimiter::new(limits, None);
+    assert!(forgetful.check(Action::Vote, Some(1), None).await.is_ok());
+}
+
+#[tokio::test]
+async fn scripts_cant_dodge_the_ip_budget_by_dropping_cookies_or_forging_addresses() {
+    let app =
+        TestApp::with_rate_limits(votes_limited_to(Budget::new(100, 60), Budget::new(2, 60))).await;
+    app.add_dogs(3).await;
+    let mut script = app.client();
+    script.get("/").await;
+
+    let users = || async {
+        sqlx::query_scalar::<_, i64>(r#"SELECT COUNT(*) FROM "user""#)
+            .fetch_one(app.pool())
+            .await
+            .unwrap()
+    };
+    let mut statuses = vec![];
+    for i in 0..3 {
+        // the proxy adds the address it really saw to the end
+        script.client_ip = Some(format!("10.0.0.{}, 203.0.113.7", i));
+        script.cookie = None;
+        statuses.push(script.post("/pick-winner/tie").await.status);
+    }
+    assert_eq!(
+        statuses,
+        [
+            StatusCode::OK,
+            StatusCode::OK,
+            StatusCode::TOO_MANY_REQUESTS
+        ]
+    );
+    // the one that was turned away didn't get a user made for it
+    let before = users().await;
+    script.cookie = None;
+    assert_eq!(
+        script.post("/pick-winner/tie").await.status,
+        StatusCode::TOO_MANY_REQUESTS
+    );
+    assert_eq!(users().await, before);
+}
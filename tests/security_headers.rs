@@ -0,0 +1,56 @@
+mod common;
+
+use axum::http::{header, StatusCode};
+use common::TestApp;
+
+#[tokio::test]
+async fn responses_come_with_security_headers() {
+    let app = TestApp::new().await;
+    let mut client = app.client();
+
+    for uri in ["/", "/no-such-page", "/healthz", "/favicon.ico"] {
+        let response = client.get(uri).await;
+        let csp = response.headers[header::CONTENT_SECURITY_POLICY]
+            .to_str()
+            .unwrap();
+        assert!(csp.contains("script-src 'self' https://plausible.parkerbedlan.com;"));
+        assert!(!csp.contains("unpkg"));
+        assert!(response.headers[header::STRICT_TRANSPORT_SECURITY]
+            .to_str()
+            .unwrap()
+            .starts_with("max-age="));
+        assert_eq!(response.headers[header::X_CONTENT_TYPE_OPTIONS], "nosniff");
+        assert_eq!(
+            response.headers[header::REFERRER_POLICY],
+            "strict-origin-when-cross-origin"
+        );
+        assert!(response.headers.contains_key("permissions-policy"));
+    }
+}
+
+#[tokio::test]
+async fn scripts_are_self_hosted_under_hashed_urls() {
+    let app = TestApp::new().await;
+    let mut client = app.client();
+
+    let page = client.get("/").await.body;
+    assert!(!page.contains("unpkg.com"));
+    // nothing inline, so the policy doesn't need 'unsafe-inline' for scripts
+    assert!(!page.contains("<script>"));
+
+    let start = page.find("/js/top-doggo.").unwrap();
+    let end = start + page[start..].find('"').unwrap();
+    let script_url = &page[start..end];
+    assert_ne!(script_url, "/js/top-doggo.js");
+
+    let response = client.get(script_url).await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(
+        response.body,
+        std::fs::read_to_string("assets/js/top-doggo.js").unwrap()
+    );
+
+    // a stale hash still finds the file
+    let response = client.get("/js/top-doggo.00000000.js").await;
+    assert_eq!(response.status, StatusCode::OK);
+}
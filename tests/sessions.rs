@@ -0,0 +1,120 @@
+mod common;
+
+use common::{TestApp, TEST_SESSION_KEY};
+use top_doggo::{db, session_cookie::SessionKeys};
+
+async fn users(app: &TestApp) -> i64 {
+    sqlx::query_scalar(r#"SELECT COUNT(*) FROM "user""#)
+        .fetch_one(app.pool())
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn only_a_hash_of_the_token_is_stored() {
+    let app = TestApp::new().await;
+    let mut client = app.client();
+    client.get("/").await;
+
+    let cookie = client.cookie.clone().unwrap();
+    let (token, _signature) = cookie.split_once('=').unwrap().1.split_once('.').unwrap();
+    let stored: Vec<String> = sqlx::query_scalar("SELECT token FROM session")
+        .fetch_all(app.pool())
+        .await
+        .unwrap();
+    assert_eq!(stored.len(), 1);
+    assert_ne!(stored[0], token);
+
+    // and the cookie keeps working
+    let user_id = client.user_id().await;
+    client.get("/dedication").await;
+    assert_eq!(client.user_id().await, user_id);
+    assert_eq!(users(&app).await, 1);
+}
+
+#[tokio::test]
+async fn tampered_cookies_get_a_fresh_user() {
+    let app = TestApp::new().await;
+    let mut client = app.client();
+    let user_id = client.user_id().await;
+
+    // swapping the token for somebody else's means the signature no longer matches
+    let mut someone_else = app.client();
+    someone_else.user_id().await;
+    let (their_token, _) = someone_else
+        .cookie
+        .as_ref()
+        .unwrap()
+        .split_once('.')
+        .unwrap();
+    let (_, my_signature) = client.cookie.as_ref().unwrap().split_once('.').unwrap();
+    client.cookie = Some(format!("{}.{}", their_token, my_signature));
+
+    client.get("/dedication").await;
+    let new_user_id = client.user_id().await;
+    assert_ne!(new_user_id, user_id);
+    assert_ne!(new_user_id, someone_else.user_id().await);
+}
+
+#[tokio::test]
+async fn sessions_from_before_signing_are_carried_over() {
+    let app = TestApp::new().await;
+    let mut client = app.client();
+    let user_id = client.user_id().await;
+
+    let legacy_token = uuid::Uuid::new_v4().to_string();
+    sqlx::query("INSERT INTO session (token, user_id) VALUES ($1, $2)")
+        .bind(&legacy_token)
+        .bind(user_id)
+        .execute(app.pool())
+        .await
+        .unwrap();
+    // the next time the app starts
+    db::migrate(app.pool()).await.unwrap();
+    let stored_as_is: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM session WHERE token = $1")
+        .bind(&legacy_token)
+        .fetch_one(app.pool())
+        .await
+        .unwrap();
+    assert_eq!(stored_as_is, 0);
+
+    // a page firing off several requests at once with the old cookie keeps them all signed in
+    let legacy_cookie = format!("best_doggo_auth_token={}", legacy_token);
+    let mut returning = app.client();
+    returning.cookie = Some(legacy_cookie.clone());
+    let mut other_tab = app.client();
+    other_tab.cookie = Some(legacy_cookie.clone());
+    tokio::join!(returning.get("/dedication"), other_tab.get("/dedication"));
+    assert_ne!(returning.cookie.as_deref(), Some(legacy_cookie.as_str()));
+    assert_eq!(returning.cookie, other_tab.cookie);
+    assert_eq!(returning.user_id().await, user_id);
+    assert_eq!(other_tab.user_id().await, user_id);
+    assert_eq!(users(&app).await, 1);
+}
+
+#[tokio::test]
+async fn rotating_keys_keeps_everybody_signed_in() {
+    let app = TestApp::new().await;
+    let mut client = app.client();
+    let user_id = client.user_id().await;
+    let old_cookie = client.cookie.clone();
+
+    let new_key = "the key everything gets signed with from now on";
+    let app = app.with_session_keys(SessionKeys::new(&[new_key, TEST_SESSION_KEY]).unwrap());
+    let mut client = app.client();
+    client.cookie = old_cookie.clone();
+    client.get("/dedication").await;
+    assert_ne!(client.cookie, old_cookie);
+    assert_eq!(client.user_id().await, user_id);
+
+    // once the old key is dropped, only re-signed cookies still work
+    let app = app.with_session_keys(SessionKeys::new(&[new_key]).unwrap());
+    let mut resigned = app.client();
+    resigned.cookie = client.cookie.clone();
+    resigned.get("/dedication").await;
+    assert_eq!(resigned.user_id().await, user_id);
+    let mut stale = app.client();
+    stale.cookie = old_cookie;
+    stale.get("/dedication").await;
+    assert_ne!(stale.user_id().await, user_id);
+}
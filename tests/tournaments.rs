@@ -0,0 +1,447 @@
+mod common;
+
+use axum::http::{Method, StatusCode};
+use common::{Client, TestApp};
+use top_doggo::{
+    admin::{self, export},
+    routers::tournaments::bracket::Format,
+};
+
+/// Dogs rated 1400, 1300, 1200, ... so the first one is seed 1
+async fn rated_dogs(app: &TestApp, count: usize) -> Vec<i64> {
+    let dog_ids = app.add_dogs(count).await;
+    for (i, dog_id) in dog_ids.iter().enumerate() {
+        sqlx::query("INSERT INTO rating (type, dog_id, value) VALUES ('overall', $1, $2)")
+            .bind(dog_id)
+            .bind(1400 - 100 * i as i64)
+            .execute(app.pool())
+            .await
+            .unwrap();
+    }
+    dog_ids
+}
+
+/// (match id, dog a, dog b) for the matches open to votes, in bracket order
+async fn open_matches(app: &TestApp, tournament_id: i64) -> Vec<(i64, i64, i64)> {
+    sqlx::query_as(
+        "SELECT id, dog_a_id, dog_b_id FROM bracket_match WHERE tournament_id = $1 AND opens_at IS NOT NULL AND winner_id IS NULL ORDER BY number",
+    )
+    .bind(tournament_id)
+    .fetch_all(app.pool())
+    .await
+    .unwrap()
+}
+
+/// Ends the voting on every open match
+async fn close_voting(app: &TestApp, closes_at: &str) {
+    sqlx::query(
+        "UPDATE bracket_match SET closes_at = $1 WHERE opens_at IS NOT NULL AND winner_id IS NULL",
+    )
+    .bind(closes_at)
+    .execute(app.pool())
+    .await
+    .unwrap();
+}
+
+async fn vote(client: &mut Client, tournament_id: i64, match_id: i64, dog_id: i64) -> StatusCode {
+    client
+        .post(&format!(
+            "/tournaments/{}/matches/{}/vote/{}",
+            tournament_id, match_id, dog_id
+        ))
+        .await
+        .status
+}
+
+/// A user who has made enough picks on the game board to vote in tournaments
+async fn voter(app: &TestApp) -> Client {
+    let mut client = app.client();
+    let user_id = client.user_id().await;
+    let dog_ids: Vec<i64> = sqlx::query_scalar("SELECT id FROM dog ORDER BY id")
+        .fetch_all(app.pool())
+        .await
+        .unwrap();
+    let pairs = dog_ids
+        .iter()
+        .flat_map(|&a| dog_ids.iter().map(move |&b| (a, b)))
+        .filter(|(a, b)| a != b)
+        .take(10);
+    for (dog_a_id, dog_b_id) in pairs {
+        sqlx::query(
+            "INSERT INTO match (user_id, dog_a_id, dog_b_id, status) VALUES ($1, $2, $3, '>')",
+        )
+        .bind(user_id)
+        .bind(dog_a_id)
+        .bind(dog_b_id)
+        .execute(app.pool())
+        .await
+        .unwrap();
+    }
+    client
+}
+
+async fn champion(app: &TestApp, tournament_id: i64) -> Option<i64> {
+    sqlx::query_scalar("SELECT champion_id FROM tournament WHERE id = $1")
+        .bind(tournament_id)
+        .fetch_one(app.pool())
+        .await
+        .unwrap()
+}
+
+async fn count(app: &TestApp, query: &str) -> i64 {
+    sqlx::query_scalar(query)
+        .fetch_one(app.pool())
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn the_top_rated_dogs_are_seeded_into_the_bracket() {
+    let app = TestApp::new().await;
+    let dogs = rated_dogs(&app, 5).await;
+
+    let tournament_id =
+        admin::start_tournament(app.pool(), "Spring Showdown", Format::Single, 4, 24)
+            .await
+            .unwrap();
+
+    let seeds: Vec<(i64, i64)> = sqlx::query_as(
+        "SELECT dog_id, seed FROM tournament_entry WHERE tournament_id = $1 ORDER BY seed",
+    )
+    .bind(tournament_id)
+    .fetch_all(app.pool())
+    .await
+    .unwrap();
+    assert_eq!(
+        seeds,
+        vec![(dogs[0], 1), (dogs[1], 2), (dogs[2], 3), (dogs[3], 4)]
+    );
+    let first_round: Vec<(i64, i64)> = open_matches(&app, tournament_id)
+        .await
+        .into_iter()
+        .map(|(_, dog_a_id, dog_b_id)| (dog_a_id, dog_b_id))
+        .collect();
+    assert_eq!(first_round, vec![(dogs[0], dogs[3]), (dogs[1], dogs[2])]);
+    assert_eq!(count(&app, "SELECT COUNT(*) FROM bracket_match").await, 3);
+
+    let mut client = app.client();
+    let response = client.get(&format!("/tournaments/{}", tournament_id)).await;
+    assert!(response.body.contains("Spring Showdown"));
+    assert!(response.body.contains("Semifinals"));
+    assert!(client
+        .get("/tournaments")
+        .await
+        .body
+        .contains("Spring Showdown"));
+}
+
+#[tokio::test]
+async fn tournaments_need_enough_dogs_and_a_real_size() {
+    let app = TestApp::new().await;
+    rated_dogs(&app, 5).await;
+
+    assert!(
+        admin::start_tournament(app.pool(), "Too big", Format::Single, 8, 24)
+            .await
+            .is_err()
+    );
+    assert!(
+        admin::start_tournament(app.pool(), "Lopsided", Format::Single, 5, 24)
+            .await
+            .is_err()
+    );
+    assert!(
+        admin::start_tournament(app.pool(), " ", Format::Single, 4, 24)
+            .await
+            .is_err()
+    );
+    assert_eq!(count(&app, "SELECT COUNT(*) FROM tournament").await, 0);
+}
+
+#[tokio::test]
+async fn the_dog_with_more_votes_goes_through_and_the_winner_is_crowned() {
+    let app = TestApp::new().await;
+    let dogs = rated_dogs(&app, 4).await;
+    let tournament_id = admin::start_tournament(app.pool(), "Pupper Bowl", Format::Single, 4, 24)
+        .await
+        .unwrap();
+    let first_round = open_matches(&app, tournament_id).await;
+
+    // the underdog gets two votes, and a change of heart doesn't count twice
+    let page = format!("/tournaments/{}", tournament_id);
+    let mut fan = voter(&app).await;
+    fan.get(&page).await;
+    let mut other_fan = voter(&app).await;
+    other_fan.get(&page).await;
+    assert_eq!(
+        vote(&mut fan, tournament_id, first_round[0].0, dogs[0]).await,
+        StatusCode::OK
+    );
+    assert_eq!(
+        vote(&mut fan, tournament_id, first_round[0].0, dogs[3]).await,
+        StatusCode::OK
+    );
+    assert_eq!(
+        vote(&mut other_fan, tournament_id, first_round[0].0, dogs[3]).await,
+        StatusCode::OK
+    );
+    assert_eq!(count(&app, "SELECT COUNT(*) FROM bracket_vote").await, 2);
+
+    // nobody voted in the other one, so the better seed goes through
+    close_voting(&app, &top_doggo::db::minutes_ago(1)).await;
+    fan.get(&page).await;
+    let final_match = open_matches(&app, tournament_id).await;
+    assert_eq!(final_match.len(), 1);
+    let (final_id, dog_a_id, dog_b_id) = final_match[0];
+    assert_eq!((dog_a_id, dog_b_id), (dogs[3], dogs[1]));
+
+    assert_eq!(
+        vote(&mut fan, tournament_id, final_id, dogs[3]).await,
+        StatusCode::OK
+    );
+    close_voting(&app, &top_doggo::db::minutes_ago(1)).await;
+    let response = fan.get(&page).await;
+    assert!(response.body.contains("is the champion!"));
+    assert_eq!(champion(&app, tournament_id).await, Some(dogs[3]));
+
+    let leaderboard = fan.get("/leaderboard/top/overall").await;
+    assert!(leaderboard.body.contains("Won a tournament"));
+}
+
+#[tokio::test]
+async fn votes_only_count_while_the_match_is_open() {
+    let app = TestApp::new().await;
+    let dogs = rated_dogs(&app, 4).await;
+    let tournament_id = admin::start_tournament(app.pool(), "Pupper Bowl", Format::Single, 4, 24)
+        .await
+        .unwrap();
+    let first_round = open_matches(&app, tournament_id).await;
+    let mut client = voter(&app).await;
+    client.get(&format!("/tournaments/{}", tournament_id)).await;
+
+    // a dog that isn't in the match
+    assert_eq!(
+        vote(&mut client, tournament_id, first_round[0].0, dogs[1]).await,
+        StatusCode::NOT_FOUND
+    );
+
+    close_voting(&app, &top_doggo::db::minutes_ago(1)).await;
+    assert_eq!(
+        vote(&mut client, tournament_id, first_round[0].0, dogs[3]).await,
+        StatusCode::BAD_REQUEST
+    );
+    assert_eq!(count(&app, "SELECT COUNT(*) FROM bracket_vote").await, 0);
+}
+
+#[tokio::test]
+async fn quarantined_and_flagged_users_votes_do_not_count() {
+    let app = TestApp::new().await;
+    let dogs = rated_dogs(&app, 4).await;
+    let tournament_id = admin::start_tournament(app.pool(), "Pupper Bowl", Format::Single, 4, 24)
+        .await
+        .unwrap();
+    let first_round = open_matches(&app, tournament_id).await;
+    let page = format!("/tournaments/{}", tournament_id);
+    let mut client = voter(&app).await;
+    client.get(&page).await;
+    vote(&mut client, tournament_id, first_round[0].0, dogs[3]).await;
+    admin::quarantine_user(app.pool(), client.user_id().await)
+        .await
+        .unwrap();
+    let mut flagged = voter(&app).await;
+    flagged.get(&page).await;
+    vote(&mut flagged, tournament_id, first_round[0].0, dogs[3]).await;
+    top_doggo::db::users::set_trust(app.pool(), flagged.user_id().await, 70)
+        .await
+        .unwrap();
+
+    close_voting(&app, &top_doggo::db::minutes_ago(1)).await;
+    client.get(&page).await;
+
+    let winner_id: Option<i64> =
+        sqlx::query_scalar("SELECT winner_id FROM bracket_match WHERE id = $1")
+            .bind(first_round[0].0)
+            .fetch_one(app.pool())
+            .await
+            .unwrap();
+    assert_eq!(winner_id, Some(dogs[0]));
+}
+
+#[tokio::test]
+async fn users_have_to_play_a_while_before_they_can_vote() {
+    let app = TestApp::new().await;
+    let dogs = rated_dogs(&app, 4).await;
+    let tournament_id = admin::start_tournament(app.pool(), "Pupper Bowl", Format::Single, 4, 24)
+        .await
+        .unwrap();
+    let first_round = open_matches(&app, tournament_id).await;
+    let page = format!("/tournaments/{}", tournament_id);
+
+    // a script with fresh cookies gets no say
+    let mut newcomer = app.client();
+    let body = newcomer.get(&page).await.body;
+    assert!(body.contains("Pick 10 more winners on the game board"));
+    assert!(!body.contains("Vote for"));
+    assert_eq!(
+        vote(&mut newcomer, tournament_id, first_round[0].0, dogs[3]).await,
+        StatusCode::FORBIDDEN
+    );
+    assert_eq!(count(&app, "SELECT COUNT(*) FROM bracket_vote").await, 0);
+
+    let mut regular = voter(&app).await;
+    let body = regular.get(&page).await.body;
+    assert!(!body.contains("more winners on the game board"));
+    assert!(body.contains("Vote for"));
+    assert_eq!(
+        vote(&mut regular, tournament_id, first_round[0].0, dogs[3]).await,
+        StatusCode::OK
+    );
+}
+
+#[tokio::test]
+async fn a_double_elimination_bracket_plays_out_on_schedule_without_anybody_looking() {
+    let app = TestApp::new().await;
+    let dogs = rated_dogs(&app, 4).await;
+    let tournament_id =
+        admin::start_tournament(app.pool(), "Second Chances", Format::Double, 4, 24)
+            .await
+            .unwrap();
+    assert_eq!(count(&app, "SELECT COUNT(*) FROM bracket_match").await, 6);
+
+    // every window after the first round's starts when the one before it closed, so a month later
+    // the whole thing is over
+    close_voting(&app, &top_doggo::db::days_ago(30)).await;
+    let response = app
+        .client()
+        .get(&format!("/tournaments/{}", tournament_id))
+        .await;
+
+    assert!(response.body.contains("Losers bracket"));
+    assert!(response.body.contains("Grand final"));
+    assert_eq!(champion(&app, tournament_id).await, Some(dogs[0]));
+    assert_eq!(
+        count(
+            &app,
+            "SELECT COUNT(*) FROM bracket_match WHERE winner_id IS NULL"
+        )
+        .await,
+        0
+    );
+}
+
+#[tokio::test]
+async fn double_elimination_brackets_of_every_size_show_their_rounds() {
+    for (size, losers_rounds) in [(8, 4), (32, 8)] {
+        let app = TestApp::new().await;
+        rated_dogs(&app, size).await;
+        let tournament_id =
+            admin::start_tournament(app.pool(), "Big Bracket", Format::Double, size, 24)
+                .await
+                .unwrap();
+
+        let response = app
+            .client()
+            .get(&format!("/tournaments/{}", tournament_id))
+            .await;
+        assert_eq!(response.status, StatusCode::OK);
+        // the losers bracket has more rounds than the winners bracket
+        assert!(response.body.contains(&format!("Round {}", losers_rounds)));
+        assert!(response.body.contains("Winners final"));
+        assert!(response.body.contains("Grand final"));
+    }
+}
+
+#[tokio::test]
+async fn admins_can_start_tournaments_from_the_browser() {
+    let app = TestApp::new().await;
+    rated_dogs(&app, 8).await;
+    let mut client = app.client();
+    let user_id = client.user_id().await;
+    sqlx::query(r#"UPDATE "user" SET email = 'admin@example.com' WHERE id = $1"#)
+        .bind(user_id)
+        .execute(app.pool())
+        .await
+        .unwrap();
+
+    let response = client
+        .form(
+            Method::POST,
+            "/admin/tournaments",
+            &[
+                ("name", "Fall Frenzy"),
+                ("format", "double"),
+                ("size", "8"),
+                ("window_hours", "12"),
+            ],
+        )
+        .await;
+
+    assert!(response.body.contains("Started tournament"));
+    let (format, window_hours): (String, i64) =
+        sqlx::query_as("SELECT format, window_hours FROM tournament")
+            .fetch_one(app.pool())
+            .await
+            .unwrap();
+    assert_eq!((format.as_str(), window_hours), ("double", 12));
+    assert_eq!(count(&app, "SELECT COUNT(*) FROM bracket_match").await, 14);
+    assert_eq!(
+        count(
+            &app,
+            "SELECT COUNT(*) FROM log WHERE action = 'start-tournament'"
+        )
+        .await,
+        1
+    );
+
+    // and nobody else can
+    let mut stranger = app.client();
+    stranger.get("/").await;
+    let response = stranger
+        .form(
+            Method::POST,
+            "/admin/tournaments",
+            &[
+                ("name", "Hostile Takeover"),
+                ("format", "single"),
+                ("size", "4"),
+                ("window_hours", "1"),
+            ],
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn tournament_votes_go_along_with_exports_and_deleted_users() {
+    let app = TestApp::new().await;
+    let dogs = rated_dogs(&app, 4).await;
+    let tournament_id = admin::start_tournament(app.pool(), "Pupper Bowl", Format::Single, 4, 24)
+        .await
+        .unwrap();
+    let first_round = open_matches(&app, tournament_id).await;
+    let mut client = voter(&app).await;
+    client.get(&format!("/tournaments/{}", tournament_id)).await;
+    vote(&mut client, tournament_id, first_round[0].0, dogs[3]).await;
+    let user_id = client.user_id().await;
+
+    let mut exported = vec![];
+    let counts = export::export(app.pool(), &mut exported, false)
+        .await
+        .unwrap();
+    assert_eq!(counts.tournaments, 1);
+    assert_eq!(counts.bracket_votes, 1);
+    let staging = TestApp::new().await;
+    assert_eq!(
+        export::import(staging.pool(), exported.as_slice())
+            .await
+            .unwrap(),
+        counts
+    );
+    assert_eq!(
+        count(&staging, "SELECT COUNT(*) FROM bracket_match").await,
+        3
+    );
+
+    admin::delete_user(app.pool(), user_id).await.unwrap();
+    assert_eq!(count(&app, "SELECT COUNT(*) FROM bracket_vote").await, 0);
+}
@@ -0,0 +1,90 @@
+mod common;
+
+use axum::http::StatusCode;
+use common::{total_xp, TestApp};
+use top_doggo::db;
+
+const FAKE_JPEG: &[u8] = b"\xFF\xD8\xFF\xE0 not really a dog";
+
+#[tokio::test]
+async fn uploading_a_dog_saves_it_for_approval() {
+    let app = TestApp::new().await;
+    let mut client = app.client();
+    let user_id = client.user_id().await;
+
+    let response = client
+        .multipart(
+            "/upload",
+            &[("new_dog_name", "Pancake")],
+            &[("new_dog_photo", "pancake.jpg", "image/jpeg", FAKE_JPEG)],
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert!(response.body.contains("Thanks for adding your dog!"));
+
+    let (id, image_url, name, approved): (i64, String, String, db::Flag) = sqlx::query_as(
+        "SELECT id, image_url, name, CASE WHEN approved THEN 1 ELSE 0 END FROM dog WHERE namer_id = $1",
+    )
+    .bind(user_id)
+    .fetch_one(app.pool())
+    .await
+    .unwrap();
+    assert_eq!(image_url, format!("/images/{}.jpg", id));
+    assert_eq!(name, "Pancake");
+    assert!(!approved.0);
+
+    let saved = std::fs::read(app.state.unapproved_dir.join(format!("{}.jpg", id))).unwrap();
+    assert_eq!(saved, FAKE_JPEG);
+
+    // 1000 for the upload plus 200 for naming it
+    assert_eq!(total_xp(app.pool(), user_id).await, 1200);
+
+    let emails = app.state.mailer.captured();
+    assert_eq!(emails.len(), 1);
+    assert_eq!(emails[0].to, "admin@example.com");
+}
+
+#[tokio::test]
+async fn unapproved_dogs_stay_off_the_game_board() {
+    let app = TestApp::new().await;
+    let mut client = app.client();
+    client
+        .multipart(
+            "/upload",
+            &[("new_dog_name", "")],
+            &[("new_dog_photo", "a.jpg", "image/jpeg", FAKE_JPEG)],
+        )
+        .await;
+    client
+        .multipart(
+            "/upload",
+            &[("new_dog_name", "")],
+            &[("new_dog_photo", "b.jpg", "image/jpeg", FAKE_JPEG)],
+        )
+        .await;
+
+    let response = client.get("/").await;
+    assert!(response.body.contains("You've won!"));
+}
+
+#[tokio::test]
+async fn uploads_must_be_images() {
+    let app = TestApp::new().await;
+    let mut client = app.client();
+    client.get("/upload").await;
+
+    let response = client
+        .multipart(
+            "/upload",
+            &[("new_dog_name", "")],
+            &[("new_dog_photo", "notes.txt", "text/plain", b"woof")],
+        )
+        .await;
+    assert!(response.body.contains("Must be an image"));
+
+    let dog_count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM dog")
+        .fetch_one(app.pool())
+        .await
+        .unwrap();
+    assert_eq!(dog_count, 0);
+}
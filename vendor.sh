@@ -0,0 +1,25 @@
+#!/bin/sh
+# downloads htmx into assets/vendor, for `just vendor` and the Dockerfile.
+# the hash is sha384 in base64, the same as an integrity attribute, and a file that doesn't
+# match is never moved into place. bump the version here and in layout.rs together
+set -eu
+
+HTMX_URL=https://unpkg.com/htmx.org@2.0.0/dist/htmx.min.js
+HTMX_SHA384=wS5l5IKJBvK6sPTKa2WZ1js3d947pvWXbPJ1OmWfEuxLgeHcEbjUUA5i9V5ZkpCw
+
+fetch() {
+    url=$1
+    file=$2
+    expected=$3
+    curl -sSfL "$url" -o "$file.partial"
+    actual=$(openssl dgst -sha384 -binary "$file.partial" | openssl base64 -A)
+    if [ "$actual" != "$expected" ]; then
+        echo "$url has sha384 $actual, expected '$expected'" >&2
+        rm -f "$file.partial"
+        exit 1
+    fi
+    mv "$file.partial" "$file"
+}
+
+mkdir -p assets/vendor
+fetch "$HTMX_URL" assets/vendor/htmx.min.js "$HTMX_SHA384"